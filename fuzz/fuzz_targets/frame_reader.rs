@@ -0,0 +1,75 @@
+//! Arbitrary byte streams fed into the `FrameReader`, read in chunks of the
+//! sizes the first bytes give, with read timeouts in between like a serial
+//! port. Run with `cargo fuzz run frame_reader`.
+
+#![no_main]
+
+use std::io::{self, Read};
+
+use libfuzzer_sys::fuzz_target;
+use mmcp_client_cli::{describe, describe_json, reader::FrameReader, ChecksumAlgorithm, Frame};
+
+/// A stream returning `data` in chunks, a chunk size of 0 is a read timeout
+struct Chunked<'a> {
+    sizes: &'a [u8],
+    data: &'a [u8],
+}
+
+impl Read for Chunked<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let size = match self.sizes.split_first() {
+            Some((size, sizes)) => {
+                self.sizes = sizes;
+                *size as usize
+            }
+            None => buf.len(),
+        };
+        if size == 0 && !self.data.is_empty() {
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+
+        let n = size.min(buf.len()).min(self.data.len());
+        buf[..n].copy_from_slice(&self.data[..n]);
+        self.data = &self.data[n..];
+        Ok(n)
+    }
+}
+
+fuzz_target!(|input: &[u8]| {
+    let Some((&header, input)) = input.split_first() else {
+        return;
+    };
+    let (sizes, data) = input.split_at((header as usize % 16).min(input.len()));
+    let verify = header & 0x10 != 0;
+
+    let mut reader = FrameReader::new(Chunked { sizes, data });
+    if verify {
+        reader = reader.verify(ChecksumAlgorithm::Crc8);
+    }
+
+    let mut frames = 0;
+    loop {
+        match reader.read_frame() {
+            Ok(frame) => {
+                assert!(frame[0] == 0 && frame[15] == 0);
+                if verify {
+                    assert_eq!(ChecksumAlgorithm::Crc8.compute(&frame[1..14]), frame[14]);
+                }
+                assert_eq!(<[u8; 16]>::from(Frame::from(frame)), frame);
+                assert_eq!(
+                    Frame::from(frame).checksum_ok(ChecksumAlgorithm::Crc8),
+                    describe_json(&frame, ChecksumAlgorithm::Crc8).ends_with("true}")
+                );
+                describe(&frame, ChecksumAlgorithm::Crc8);
+                frames += 1;
+            }
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => (),
+            Err(e) => {
+                assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+                break;
+            }
+        }
+        assert!(reader.discarded().len() <= 4096);
+    }
+    assert!(frames * 16 <= data.len());
+});
@@ -0,0 +1,82 @@
+//! Command aliases from the configuration.
+//!
+//! An alias in the `[aliases]` table, e.g. `blink3 = "set-led on --count 3"`,
+//! stands for its words in place of the subcommand. Aliases are expanded once
+//! before the command line is parsed, so they can't refer to other aliases,
+//! and they never shadow a built in subcommand.
+
+use std::ffi::OsString;
+
+use clap::{Arg, CommandFactory};
+
+use crate::{config::split_words, config::Config, CliArgs};
+
+/// Replace an alias in the subcommand position by its words
+pub fn expand(mut args: Vec<OsString>) -> Result<Vec<OsString>, String> {
+    // A broken configuration is reported once the arguments are parsed
+    let config = Config::load(config_path(&args).as_deref()).unwrap_or_default();
+    let aliases = match config.table("aliases") {
+        Some(aliases) if !aliases.is_empty() => aliases,
+        _ => return Ok(args),
+    };
+
+    let cmd = CliArgs::command();
+    let takes_value = |arg: &Arg| {
+        arg.get_num_args().is_some_and(|n| n.min_values() > 0) && !arg.is_require_equals_set()
+    };
+
+    let mut i = 1;
+    while i < args.len() {
+        let word = args[i].to_string_lossy().into_owned();
+        if word == "--" {
+            break;
+        } else if let Some(long) = word.strip_prefix("--") {
+            if !long.contains('=')
+                && cmd
+                    .get_arguments()
+                    .any(|a| a.get_long() == Some(long) && takes_value(a))
+            {
+                i += 1;
+            }
+        } else if let Some(short) = word.strip_prefix('-') {
+            let mut chars = short.chars();
+            if let (Some(c), None) = (chars.next(), chars.next()) {
+                if cmd
+                    .get_arguments()
+                    .any(|a| a.get_short() == Some(c) && takes_value(a))
+                {
+                    i += 1;
+                }
+            }
+        } else if cmd
+            .get_subcommands()
+            .any(|s| s.get_name() == word || s.get_all_aliases().any(|a| a == word))
+        {
+            break;
+        } else if let Some(value) = aliases.get(&word) {
+            let line = value
+                .as_str()
+                .ok_or_else(|| format!("Alias `{}` has to be a string", word))?;
+            let words = split_words(line).map_err(|e| format!("Alias `{}`: {}", word, e))?;
+            args.splice(i..=i, words.into_iter().map(OsString::from));
+            break;
+        }
+        i += 1;
+    }
+
+    Ok(args)
+}
+
+fn config_path(args: &[OsString]) -> Option<std::path::PathBuf> {
+    let mut args = args.iter().map(|a| a.to_string_lossy());
+    while let Some(arg) = args.next() {
+        if arg == "--config" {
+            return args.next().map(|p| p.into_owned().into());
+        }
+        if let Some(path) = arg.strip_prefix("--config=") {
+            return Some(path.into());
+        }
+    }
+
+    None
+}
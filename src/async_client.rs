@@ -0,0 +1,229 @@
+//! A client of the bus for async code, independent of the runtime.
+//!
+//! [`AsyncMmcpClient`] moves the port to a thread of its own, which performs
+//! the operations one after another in the order they were started. Every
+//! operation returns a [`Reply`], a future completed by that thread, so
+//! executors are never blocked by the port. Clones share the thread, which
+//! ends once the last clone is dropped.
+
+use std::{
+    future::Future,
+    io::{self, Read, Write},
+    pin::Pin,
+    sync::{mpsc, Arc, Mutex},
+    task::{Context, Poll, Waker},
+    thread,
+    time::Duration,
+};
+
+pub use crate::link::EXCHANGE_TIMEOUT;
+use crate::{link::Link, ChecksumAlgorithm, LedState, MsgBuilder};
+
+type Job<P> = Box<dyn FnOnce(&mut Link<P>) + Send>;
+
+/// Exchanges of frames with the devices of a port, cheap to clone
+pub struct AsyncMmcpClient<P> {
+    jobs: mpsc::Sender<Job<P>>,
+}
+
+impl<P> Clone for AsyncMmcpClient<P> {
+    fn clone(&self) -> Self {
+        AsyncMmcpClient {
+            jobs: self.jobs.clone(),
+        }
+    }
+}
+
+impl<P: Read + Write + Send + 'static> AsyncMmcpClient<P> {
+    /// A client of the bus behind `port`, e.g. one of the
+    /// [`crate::transport`]s, whose read timeout is the one of responses
+    pub fn new(port: P) -> Self {
+        Self::spawn(Link::new(port, ChecksumAlgorithm::Sum, false))
+    }
+
+    /// Like [`AsyncMmcpClient::new`], building requests with the checksum
+    /// of `algorithm` and skipping responses with another one
+    pub fn with_checksum(port: P, algorithm: ChecksumAlgorithm) -> Self {
+        Self::spawn(Link::new(port, algorithm, true))
+    }
+
+    fn spawn(mut link: Link<P>) -> Self {
+        let (jobs, queue) = mpsc::channel::<Job<P>>();
+        thread::spawn(move || {
+            for job in queue {
+                job(&mut link);
+            }
+        });
+        AsyncMmcpClient { jobs }
+    }
+
+    /// Fail exchanges whose response didn't arrive within `timeout`, while
+    /// frames of other devices keep the port busy. Applies to all clones and
+    /// operations started later, defaults to [`EXCHANGE_TIMEOUT`]
+    pub fn exchange_timeout(self, timeout: Duration) -> Self {
+        // Nothing to await, the operation can't fail
+        drop(self.run(move |link| {
+            link.timeout = timeout;
+            Ok(())
+        }));
+        self
+    }
+
+    /// Send the request without awaiting a response, e.g. to a group
+    pub fn send(&self, builder: MsgBuilder) -> Reply<()> {
+        self.run(move |link| link.send(builder))
+    }
+
+    /// Send the request and return the response of the addressed device.
+    /// Frames of other devices or opcodes, like pushed telemetry, are
+    /// skipped
+    pub fn exchange(&self, builder: MsgBuilder) -> Reply<[u8; 16]> {
+        self.run(move |link| link.exchange(builder))
+    }
+
+    /// Round trip time of an echo of the device
+    pub fn ping(&self, to: u8) -> Reply<Duration> {
+        self.run(move |link| link.ping(to))
+    }
+
+    /// Switch LED `index` of the device, 0 on boards with a single one
+    pub fn set_led(&self, to: u8, index: u8, state: LedState) -> Reply<()> {
+        self.run(move |link| link.set_led(to, index, state))
+    }
+
+    /// Whether LED `index` of the device is on
+    pub fn get_led(&self, to: u8, index: u8) -> Reply<bool> {
+        self.run(move |link| link.get_led(to, index))
+    }
+
+    /// Number of button presses the device counted
+    pub fn read_button_presses(&self, to: u8) -> Reply<u8> {
+        self.run(move |link| link.read_button_presses(to))
+    }
+
+    /// Store `data` in `slot` of the device storage, operations started
+    /// later wait until it is stored
+    pub fn flash(&self, to: u8, slot: u8, data: Vec<u8>) -> Reply<()> {
+        self.run(move |link| link.flash(to, slot, &data))
+    }
+
+    fn run<T, F>(&self, operation: F) -> Reply<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Link<P>) -> io::Result<T> + Send + 'static,
+    {
+        let reply = Reply(Arc::new(Mutex::new(State {
+            result: None,
+            waker: None,
+        })));
+        let state = Arc::clone(&reply.0);
+        let job: Job<P> = Box::new(move |link| {
+            let result = operation(link);
+            let mut state = state.lock().unwrap_or_else(|e| e.into_inner());
+            state.result = Some(result);
+            if let Some(waker) = state.waker.take() {
+                waker.wake();
+            }
+        });
+
+        if self.jobs.send(job).is_err() {
+            // The thread died from a panic of an operation
+            reply.0.lock().unwrap_or_else(|e| e.into_inner()).result = Some(Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "The thread of the port has ended",
+            )));
+        }
+        reply
+    }
+}
+
+struct State<T> {
+    result: Option<io::Result<T>>,
+    waker: Option<Waker>,
+}
+
+/// The result of an operation, once the thread of the port performed it
+pub struct Reply<T>(Arc<Mutex<State<T>>>);
+
+impl<T> Future for Reply<T> {
+    type Output = io::Result<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        match state.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "mock"))]
+mod tests {
+    use std::{sync::mpsc::Sender, task::Wake};
+
+    use super::*;
+    use crate::{
+        transport::{answer, Mock},
+        L7Sdu, Opcode,
+    };
+
+    /// Wakes the test by a channel, no runtime needed
+    struct Notify(Mutex<Sender<()>>);
+
+    impl Wake for Notify {
+        fn wake(self: Arc<Self>) {
+            let _ = self.0.lock().unwrap().send(());
+        }
+    }
+
+    fn block_on<T>(mut reply: Reply<T>) -> io::Result<T> {
+        let (woken, wait) = mpsc::channel();
+        let waker = Waker::from(Arc::new(Notify(Mutex::new(woken))));
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            if let Poll::Ready(result) = Pin::new(&mut reply).poll(&mut cx) {
+                return result;
+            }
+            wait.recv().unwrap();
+        }
+    }
+
+    fn echo(request: &[u8; 16]) -> Option<[u8; 16]> {
+        let mut sdu = L7Sdu::default();
+        sdu.copy_from_slice(&request[6..14]);
+        Some(answer(request, sdu, ChecksumAlgorithm::Sum))
+    }
+
+    #[test]
+    fn exchanges_in_order() {
+        let client = AsyncMmcpClient::new(Mock::new(echo));
+        let first = client.exchange(MsgBuilder::new(5, Opcode::Echo, *b"first..."));
+        let second = client.exchange(MsgBuilder::new(6, Opcode::Echo, *b"second.."));
+        assert_eq!(&block_on(second).unwrap()[6..14], b"second..");
+        assert_eq!(&block_on(first).unwrap()[6..14], b"first...");
+    }
+
+    #[test]
+    fn unanswered_exchange_times_out() {
+        let client = AsyncMmcpClient::new(Mock::new(|_: &[u8; 16]| None))
+            .exchange_timeout(Duration::from_millis(50));
+        let error = block_on(client.read_button_presses(5)).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn frames_of_other_devices_are_skipped() {
+        let mut mock = Mock::new(echo);
+        mock.push(answer(
+            &MsgBuilder::new(9, Opcode::Uptime, L7Sdu::default()).build(),
+            L7Sdu::default(),
+            ChecksumAlgorithm::Sum,
+        ));
+        let client = AsyncMmcpClient::new(mock);
+        let response = block_on(client.exchange(MsgBuilder::new(5, Opcode::Echo, *b"abcdefgh")));
+        assert_eq!(response.unwrap()[2], 5);
+    }
+}
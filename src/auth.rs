@@ -0,0 +1,300 @@
+//! Frame authentication for firmwares built with the secured frame format.
+//!
+//! An authenticated frame carries a truncated HMAC-SHA256 tag in the first
+//! [`TAG_LEN`] bytes of the SDU. The tag is computed over the header fields
+//! (to, from, version, hops, opcode) followed by the remaining SDU bytes, so
+//! only the last `8 - TAG_LEN` bytes of the SDU are available as payload.
+//!
+//! With replay protection the tag is shortened by one byte, which instead
+//! carries the low byte of a monotonic frame counter. The full 32 bit counter
+//! is part of the authenticated data, between the header and the payload.
+//!
+//! Opcodes whose payload needs the SDU bytes of the tag can't be
+//! authenticated, see [`authenticates`]. Echoes and peeks only read, they
+//! are sent and answered without a tag even if a key is known. Pokes, the
+//! button configuration, the digits of the display and the text of the LCD
+//! change the device, they are refused while a key is known, see
+//! [`unsigned_write`]. Transfers move their sequence numbers behind the tag
+//! instead.
+//!
+//! Whether a response carries a tag follows from its request, a response
+//! has to answer the opcode of the request, or be the rejection of it, which
+//! is authenticated like the request.
+
+use hmac::{Hmac, Mac};
+use serialport::ErrorKind;
+use sha2::{Digest, Sha256};
+
+use crate::{MsgBuilder, Opcode};
+
+/// Number of SDU bytes reserved for authentication data
+pub const TAG_LEN: usize = 4;
+
+/// Tag length when the last reserved byte carries the frame counter
+const COUNTER_TAG_LEN: usize = TAG_LEN - 1;
+
+/// Whether frames of the opcode carry a tag, opcodes unknown to this version
+/// do and their payload has to leave the SDU bytes of the tag free
+pub fn authenticates(opcode: Opcode) -> bool {
+    match opcode {
+        Opcode::Echo
+        | Opcode::Peek
+        | Opcode::PokeU8
+        | Opcode::PokeU16
+        | Opcode::PokeU32
+        | Opcode::ConfigureButton
+        | Opcode::DisplayNumber
+        | Opcode::LcdWrite => false,
+        Opcode::SetLed
+        | Opcode::ReadButtonPresses
+        | Opcode::Capabilities
+        | Opcode::Status
+        | Opcode::Uptime
+        | Opcode::LinkStats
+        | Opcode::GroupJoin
+        | Opcode::GroupLeave
+        | Opcode::Rejected
+        | Opcode::Pair
+        | Opcode::RotateKey
+        | Opcode::KeyStatus
+        | Opcode::ReadUid
+        | Opcode::TransferData
+        | Opcode::TransferEnd
+        | Opcode::TransferOpen
+        | Opcode::TransferRead
+        | Opcode::Subscribe
+        | Opcode::Ping
+        | Opcode::GetLed
+        | Opcode::Beep
+        | Opcode::LcdCommit
+        | Opcode::SetServo
+        | Opcode::SetRelay
+        | Opcode::GetRelays
+        | Opcode::Unknown(_) => true,
+    }
+}
+
+/// Whether the opcode changes the device without being authenticated, which
+/// a device holding a key mustn't be asked to do
+pub fn unsigned_write(opcode: Opcode) -> bool {
+    matches!(
+        opcode,
+        Opcode::PokeU8
+            | Opcode::PokeU16
+            | Opcode::PokeU32
+            | Opcode::ConfigureButton
+            | Opcode::DisplayNumber
+            | Opcode::LcdWrite
+    )
+}
+
+/// Sign the message by writing the truncated tag (and the low byte of
+/// `counter`, if given) into the start of its SDU.
+///
+/// Fails if the SDU bytes reserved for the tag are already in use.
+pub fn sign(
+    msg: &mut MsgBuilder,
+    key: &[u8],
+    counter: Option<u32>,
+) -> Result<(), serialport::Error> {
+    if msg.l7_sdu[..TAG_LEN].iter().any(|&b| b != 0) {
+        return Err(serialport::Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "payload does not fit into an authenticated frame (only the last {} SDU bytes are usable)",
+                8 - TAG_LEN
+            ),
+        ));
+    }
+
+    let header = [
+        msg.to.0,
+        msg.from.0,
+        msg.version,
+        msg.hops,
+        msg.opcode.into(),
+    ];
+    let mac = mac(key, &header, counter, &msg.l7_sdu[TAG_LEN..]);
+    match counter {
+        Some(counter) => {
+            msg.l7_sdu[..COUNTER_TAG_LEN].copy_from_slice(&mac[..COUNTER_TAG_LEN]);
+            msg.l7_sdu[COUNTER_TAG_LEN] = counter as u8;
+        }
+        None => msg.l7_sdu[..TAG_LEN].copy_from_slice(&mac[..TAG_LEN]),
+    }
+
+    Ok(())
+}
+
+/// Verify the tag of a received 16 byte frame.
+///
+/// With replay protection `last_counter` is the highest counter accepted from
+/// the device so far. The frame counter is reconstructed as the next value
+/// above it matching the transmitted low byte and returned on success, frames
+/// authenticating with an older counter are rejected as replayed.
+pub fn verify(
+    frame: &[u8; 16],
+    key: &[u8],
+    last_counter: Option<u32>,
+) -> Result<Option<u32>, serialport::Error> {
+    let header = &frame[1..6];
+    let payload = &frame[6 + TAG_LEN..14];
+
+    let last = match last_counter {
+        Some(last) => last,
+        None => {
+            let expected = mac(key, header, None, payload);
+            let received = &frame[6..6 + TAG_LEN];
+            if !tag_eq(&expected[..TAG_LEN], received) {
+                return Err(auth_error(format!(
+                    "authentication failed: response tag {:02x?} does not match expected {:02x?}",
+                    received,
+                    &expected[..TAG_LEN]
+                )));
+            }
+
+            return Ok(None);
+        }
+    };
+
+    let received = &frame[6..6 + COUNTER_TAG_LEN];
+    let mut counter = (last & !0xff) | frame[6 + COUNTER_TAG_LEN] as u32;
+    if counter <= last {
+        counter = counter.wrapping_add(0x100);
+    }
+
+    let expected = mac(key, header, Some(counter), payload);
+    if tag_eq(&expected[..COUNTER_TAG_LEN], received) {
+        return Ok(Some(counter));
+    }
+
+    if let Some(stale) = counter.checked_sub(0x100) {
+        let replayed = mac(key, header, Some(stale), payload);
+        if tag_eq(&replayed[..COUNTER_TAG_LEN], received) {
+            return Err(auth_error(format!(
+                "authentication failed: stale frame counter {} (last accepted {}), the response was replayed",
+                stale, last
+            )));
+        }
+    }
+
+    Err(auth_error(format!(
+        "authentication failed: response tag {:02x?} does not match expected {:02x?} for counter {}",
+        received,
+        &expected[..COUNTER_TAG_LEN],
+        counter
+    )))
+}
+
+fn mac(key: &[u8], header: &[u8], counter: Option<u32>, payload: &[u8]) -> [u8; 32] {
+    let counter = counter.map(u32::to_be_bytes);
+    let counter: &[u8] = match &counter {
+        Some(counter) => counter,
+        None => &[],
+    };
+    hmac_sha256(key, &[header, counter, payload])
+}
+
+/// Compare without short circuiting to not leak the position of the first mismatch
+fn tag_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+fn auth_error(description: String) -> serialport::Error {
+    serialport::Error::new(ErrorKind::Io(std::io::ErrorKind::InvalidData), description)
+}
+
+/// HMAC of the concatenation of `parts`, which are hashed in place instead
+/// of being copied together, as done for every authenticated frame
+pub fn hmac_sha256(key: &[u8], parts: &[&[u8]]) -> [u8; 32] {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any length");
+    for part in parts {
+        mac.update(part);
+    }
+    mac.finalize().into_bytes().into()
+}
+
+pub fn sha256(data: &[u8]) -> [u8; 32] {
+    Sha256::digest(data).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Address, L7Sdu};
+
+    const KEY: &[u8] = b"0123456789abcdef";
+
+    /// A response of device 5 signed with `counter`
+    fn response(opcode: Opcode, sdu: L7Sdu, counter: Option<u32>) -> [u8; 16] {
+        let mut msg = MsgBuilder {
+            to: Address::HOST,
+            from: Address(5),
+            ..MsgBuilder::new(0, opcode, sdu)
+        };
+        sign(&mut msg, KEY, counter).unwrap();
+        msg.build()
+    }
+
+    #[test]
+    fn tags_cover_header_and_payload() {
+        let frame = response(Opcode::GetLed, [0, 0, 0, 0, 0, 0, 1, 1], None);
+        assert_eq!(
+            frame[6..10],
+            hmac_sha256(KEY, &[&frame[1..6], &[0, 0, 1, 1]])[..TAG_LEN]
+        );
+        assert_eq!(verify(&frame, KEY, None).unwrap(), None);
+
+        for byte in [1, 2, 3, 4, 5, 6, 9, 10, 13] {
+            let mut tampered = frame;
+            tampered[byte] ^= 1;
+            let error = verify(&tampered, KEY, None).unwrap_err();
+            assert!(
+                error.description.contains("authentication failed"),
+                "{}",
+                byte
+            );
+        }
+        assert!(verify(&frame, b"another key", None).is_err());
+    }
+
+    #[test]
+    fn payloads_leave_the_tag_free() {
+        let mut msg = MsgBuilder::new(5, Opcode::Unknown(200), [1, 0, 0, 0, 0, 0, 0, 0]);
+        let error = sign(&mut msg, KEY, None).unwrap_err();
+        assert!(error.description.contains("only the last 4 SDU bytes"));
+    }
+
+    #[test]
+    fn counters() {
+        let frame = response(Opcode::Uptime, [0, 0, 0, 0, 0, 0, 0, 9], Some(0x1_02ff));
+        assert_eq!(frame[9], 0xff);
+        assert_eq!(verify(&frame, KEY, Some(0x1_02f0)).unwrap(), Some(0x1_02ff));
+        // The low byte wrapped around since the last accepted counter
+        let frame = response(Opcode::Uptime, [0; 8], Some(0x1_0301));
+        assert_eq!(verify(&frame, KEY, Some(0x1_02ff)).unwrap(), Some(0x1_0301));
+
+        let error = verify(&frame, KEY, Some(0x1_0301)).unwrap_err();
+        assert!(error.description.contains("stale frame counter 66305"));
+        // Too old to be a replay of the frames since the last counter
+        let error = verify(&frame, KEY, Some(0x1_0500)).unwrap_err();
+        assert!(error.description.contains("does not match"));
+    }
+
+    #[test]
+    fn opcodes_needing_the_whole_sdu_are_unauthenticated() {
+        assert!(authenticates(Opcode::SetLed));
+        assert!(authenticates(Opcode::TransferData));
+        assert!(authenticates(Opcode::Unknown(200)));
+        for opcode in [Opcode::Echo, Opcode::Peek, Opcode::LcdWrite] {
+            assert!(!authenticates(opcode));
+        }
+
+        // Only reads go out without a tag
+        assert!(unsigned_write(Opcode::PokeU32));
+        assert!(unsigned_write(Opcode::LcdWrite));
+        assert!(!unsigned_write(Opcode::Peek));
+        assert!(!unsigned_write(Opcode::Echo));
+        assert!(!unsigned_write(Opcode::SetLed));
+    }
+}
@@ -0,0 +1,52 @@
+//! Base64 (RFC 4648) of frames embedded in text like JSON or chat messages.
+//!
+//! Encoding uses the standard alphabet with padding. Decoding also accepts
+//! the URL safe alphabet and missing padding, as frames are copied from all
+//! kinds of places.
+
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+pub fn encode(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bits = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |bits, (i, b)| bits | (*b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            match i <= chunk.len() {
+                true => out.push(ALPHABET[((bits >> (18 - 6 * i)) & 0x3f) as usize] as char),
+                false => out.push('='),
+            }
+        }
+    }
+    out
+}
+
+pub fn decode(s: &str) -> Result<Vec<u8>, String> {
+    let invalid = || format!("`{}` is not valid base64", s);
+    let digits = s.trim_end_matches('=');
+    if digits.len() % 4 == 1 {
+        return Err(invalid());
+    }
+
+    let mut out = Vec::with_capacity(digits.len() * 3 / 4);
+    let (mut bits, mut len) = (0u32, 0);
+    for c in digits.bytes() {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            _ => return Err(invalid()),
+        };
+        bits = bits << 6 | value as u32;
+        len += 6;
+        if len >= 8 {
+            len -= 8;
+            out.push((bits >> len) as u8);
+        }
+    }
+    Ok(out)
+}
@@ -0,0 +1,122 @@
+//! Bit error rate test of the physical link.
+//!
+//! Pseudorandom SDUs are sent with an opcode the device echoes, and every
+//! response is compared bit by bit with the expected echo, which is the
+//! request with `to` and `from` swapped.
+
+use std::{
+    io::Write,
+    time::{Duration, Instant},
+};
+
+use clap::Args;
+
+use crate::{
+    error::Error, parse_duration, parse_u8, pipeline, rng::Rng, sdu::Sdu, session::Session, L7Sdu,
+    MsgBuilder, Opcode,
+};
+
+#[derive(Args, Debug, Clone)]
+pub struct BerTest {
+    /// How long to stream frames, e.g. `30s`
+    #[arg(long, default_value = "10s", value_parser = parse_duration)]
+    duration: Duration,
+    /// Opcode the device echoes the SDU of
+    #[arg(long, default_value_t = Opcode::Echo.into(), value_parser = parse_u8)]
+    opcode: u8,
+    /// Seed of the payloads, the same seed sends the same payloads
+    #[arg(long, default_value_t = 1)]
+    seed: u64,
+    /// Frames to send before awaiting the response to the first, see
+    /// [`crate::pipeline`]
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..))]
+    window: u16,
+}
+
+#[derive(Debug, Default)]
+struct Counts {
+    sent: u64,
+    received: u64,
+    errored: u64,
+    lost: u64,
+    bit_errors: u64,
+}
+
+pub fn run(session: &mut Session, test: &BerTest) -> Result<(), Error> {
+    let mut rng = Rng::new(Some(test.seed));
+    let mut counts = Counts::default();
+    let start = Instant::now();
+
+    let template = session.builder(Opcode::from_byte(test.opcode), L7Sdu::default());
+    let algorithm = session.args.checksum();
+    let requests = std::iter::from_fn(|| {
+        (start.elapsed() < test.duration).then(|| {
+            MsgBuilder {
+                l7_sdu: L7Sdu::from_u64_be(rng.next()),
+                ..template
+            }
+            .build_with(algorithm)
+        })
+    });
+
+    pipeline::dispatch(
+        session,
+        requests,
+        test.window as usize,
+        |request, response| {
+            counts.sent += 1;
+            let frame = match response {
+                Some(frame) => frame,
+                None => {
+                    counts.lost += 1;
+                    return;
+                }
+            };
+            counts.received += 1;
+
+            // The echo has `to` and `from` swapped, which leaves the sum unchanged
+            let mut expected = *request;
+            expected.swap(1, 2);
+            let bit_errors: u32 = frame
+                .iter()
+                .zip(expected)
+                .map(|(a, b)| (a ^ b).count_ones())
+                .sum();
+            if bit_errors > 0 {
+                counts.errored += 1;
+                counts.bit_errors += bit_errors as u64;
+            }
+        },
+    )?;
+
+    let elapsed = start.elapsed().as_secs_f64();
+    let bits = counts.received * 16 * 8;
+    writeln!(
+        session.out,
+        "Frames: {} sent, {} received, {} with errors, {} lost",
+        counts.sent, counts.received, counts.errored, counts.lost
+    )?;
+    writeln!(
+        session.out,
+        "Bits: {} compared, {} errors",
+        bits, counts.bit_errors
+    )?;
+    writeln!(
+        session.out,
+        "Bit error rate: {:.3e}",
+        counts.bit_errors as f64 / bits.max(1) as f64
+    )?;
+    writeln!(
+        session.out,
+        "Frame error rate: {:.3e}",
+        (counts.errored + counts.lost) as f64 / counts.sent.max(1) as f64
+    )?;
+    writeln!(
+        session.out,
+        "Throughput: {:.1} frames/s over {:.1}s",
+        counts.sent as f64 / elapsed,
+        elapsed
+    )?;
+
+    Ok(())
+}
@@ -0,0 +1,109 @@
+//! Configuration of how a device reads its buttons.
+//!
+//! The configure button opcode carries flags of the settings to change in
+//! SDU byte 3, bit 0 for the debounce time, bit 1 for the trigger and bit 2
+//! for the pull resistors. The trigger is given in byte 4, 0 for rising, 1
+//! for falling and 2 for both edges, the pull resistors in byte 5, 0 for
+//! none, 1 for pull-ups and 2 for pull-downs. The debounce time is given in
+//! milli seconds in the last two SDU bytes (big endian). The device keeps
+//! the settings whose flag is not set.
+
+use clap::{ArgGroup, Args, ValueEnum};
+
+use crate::L7Sdu;
+
+const DEBOUNCE: u8 = 1 << 0;
+const TRIGGER: u8 = 1 << 1;
+const PULL: u8 = 1 << 2;
+
+#[derive(Args, Debug, Clone, Copy)]
+#[command(group(ArgGroup::new("settings").required(true).multiple(true)))]
+pub struct ConfigureButton {
+    /// Time in milli seconds the level of a button has to be stable to count
+    /// as a press
+    #[arg(long, group = "settings")]
+    debounce_ms: Option<u16>,
+    /// Edges of the level counting as a press
+    #[arg(long, value_enum, group = "settings")]
+    trigger: Option<Trigger>,
+    /// Resistors pulling the inputs of the buttons
+    #[arg(long, value_enum, group = "settings")]
+    pull: Option<Pull>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Trigger {
+    Rising,
+    Falling,
+    Both,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Pull {
+    Up,
+    Down,
+    None,
+}
+
+impl ConfigureButton {
+    pub fn as_sdu(&self) -> L7Sdu {
+        let mut sdu = L7Sdu::default();
+        if let Some(debounce) = self.debounce_ms {
+            sdu[3] |= DEBOUNCE;
+            sdu[6..].copy_from_slice(&debounce.to_be_bytes());
+        }
+        if let Some(trigger) = self.trigger {
+            sdu[3] |= TRIGGER;
+            sdu[4] = match trigger {
+                Trigger::Rising => 0,
+                Trigger::Falling => 1,
+                Trigger::Both => 2,
+            };
+        }
+        if let Some(pull) = self.pull {
+            sdu[3] |= PULL;
+            sdu[5] = match pull {
+                Pull::None => 0,
+                Pull::Up => 1,
+                Pull::Down => 2,
+            };
+        }
+        sdu
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn settings() {
+        let all = ConfigureButton {
+            debounce_ms: Some(0x0132),
+            trigger: Some(Trigger::Both),
+            pull: Some(Pull::Down),
+        };
+        assert_eq!(all.as_sdu(), [0, 0, 0, 0b111, 2, 2, 0x01, 0x32]);
+
+        let trigger = ConfigureButton {
+            debounce_ms: None,
+            trigger: Some(Trigger::Falling),
+            pull: None,
+        };
+        assert_eq!(trigger.as_sdu(), [0, 0, 0, TRIGGER, 1, 0, 0, 0]);
+
+        // Only the flag tells the default settings from kept ones
+        let defaults = ConfigureButton {
+            debounce_ms: Some(0),
+            trigger: Some(Trigger::Rising),
+            pull: Some(Pull::None),
+        };
+        assert_eq!(defaults.as_sdu(), [0, 0, 0, 0b111, 0, 0, 0, 0]);
+        let pull = ConfigureButton {
+            debounce_ms: None,
+            trigger: None,
+            pull: Some(Pull::Up),
+        };
+        assert_eq!(pull.as_sdu(), [0, 0, 0, PULL, 0, 1, 0, 0]);
+    }
+}
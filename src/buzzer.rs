@@ -0,0 +1,64 @@
+//! Beeps of the buzzer of a device, to find a board among many.
+//!
+//! The beep opcode carries the frequency in Hz in SDU bytes 4 and 5 and the
+//! duration in milli seconds in the last two SDU bytes, both big endian.
+
+use std::time::Duration;
+
+use clap::Args;
+
+use crate::{parse_duration, L7Sdu};
+
+#[derive(Args, Debug, Clone, Copy)]
+pub struct Beep {
+    /// Frequency of the tone in Hz
+    #[arg(long, default_value_t = 2000, value_parser = clap::value_parser!(u16).range(1..))]
+    freq: u16,
+    /// How long to beep, e.g. `500ms`
+    #[arg(long, default_value = "200ms", value_parser = parse_length)]
+    duration: Duration,
+}
+
+impl Beep {
+    pub fn as_sdu(&self) -> L7Sdu {
+        let mut sdu = L7Sdu::default();
+        sdu[4..6].copy_from_slice(&self.freq.to_be_bytes());
+        sdu[6..].copy_from_slice(&(self.duration.as_millis() as u16).to_be_bytes());
+        sdu
+    }
+}
+
+fn parse_length(s: &str) -> Result<Duration, String> {
+    let duration = parse_duration(s)?;
+    match duration.as_millis() {
+        1..=65_535 => Ok(duration),
+        _ => Err(format!("`{}` is no duration from 1ms to 65.535s", s)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tones() {
+        let beep = Beep {
+            freq: 2000,
+            duration: Duration::from_millis(200),
+        };
+        assert_eq!(beep.as_sdu(), [0, 0, 0, 0, 0x07, 0xd0, 0x00, 0xc8]);
+        let beep = Beep {
+            freq: u16::MAX,
+            duration: Duration::from_millis(65_535),
+        };
+        assert_eq!(beep.as_sdu(), [0, 0, 0, 0, 0xff, 0xff, 0xff, 0xff]);
+    }
+
+    #[test]
+    fn lengths() {
+        assert_eq!(parse_length("1"), Ok(Duration::from_millis(1)));
+        assert_eq!(parse_length("65535ms"), Ok(Duration::from_millis(65_535)));
+        assert!(parse_length("0").is_err());
+        assert!(parse_length("66s").is_err());
+    }
+}
@@ -0,0 +1,131 @@
+//! Features a device supports, as told by the capability query opcode.
+//!
+//! The device answers the query with a bit mask in the last two SDU bytes
+//! (big endian), bit 0 for the LED, 1 for the buttons, 2 for the ADC, 3 for
+//! the EEPROM and 4 for the bootloader. Further bits are reserved.
+
+use crate::{error::Error, sdu::Response, session::Session, Command, L7Sdu, Opcode};
+
+/// A feature of a device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capability {
+    Led,
+    Buttons,
+    Adc,
+    Eeprom,
+    Bootloader,
+}
+
+impl Capability {
+    pub const ALL: [Capability; 5] = [
+        Capability::Led,
+        Capability::Buttons,
+        Capability::Adc,
+        Capability::Eeprom,
+        Capability::Bootloader,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Capability::Led => "LED",
+            Capability::Buttons => "buttons",
+            Capability::Adc => "ADC",
+            Capability::Eeprom => "EEPROM",
+            Capability::Bootloader => "bootloader",
+        }
+    }
+
+    fn bit(self) -> u16 {
+        1 << self as u16
+    }
+
+    /// The capability a command needs the device to have, if any
+    pub fn required(cmd: &Command) -> Option<Capability> {
+        match cmd {
+            Command::SetLed(_) | Command::GetLed(_) => Some(Capability::Led),
+            Command::ReadButtonPresses | Command::ConfigureButton(_) => Some(Capability::Buttons),
+            _ => None,
+        }
+    }
+}
+
+/// Bit mask of the capabilities of a device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities(pub u16);
+
+impl Capabilities {
+    /// The bit mask in the answer of the capability query
+    pub fn parse(msg: &[u8; 16]) -> Self {
+        Capabilities(u16::from_be_bytes([msg.sdu_u8(6), msg.sdu_u8(7)]))
+    }
+
+    pub fn has(self, capability: Capability) -> bool {
+        self.0 & capability.bit() != 0
+    }
+
+    /// Bits of capabilities not known here
+    pub fn unknown(self) -> u16 {
+        let known = Capability::ALL.iter().fold(0, |mask, c| mask | c.bit());
+        self.0 & !known
+    }
+}
+
+/// Ask the device for its capabilities, returning its answer
+fn query_frame(session: &mut Session) -> Result<[u8; 16], serialport::Error> {
+    let mut msg = [0u8; 16];
+    session.transact(
+        session.builder(Opcode::Capabilities, L7Sdu::default()),
+        &mut msg,
+    )?;
+    Ok(msg)
+}
+
+/// Ask the device for its capabilities
+pub fn query(session: &mut Session) -> Result<Capabilities, serialport::Error> {
+    query_frame(session).map(|msg| Capabilities::parse(&msg))
+}
+
+pub fn run(session: &mut Session) -> Result<Vec<[u8; 16]>, Error> {
+    let msg = query_frame(session)?;
+    session.capabilities = Some(Some(Capabilities::parse(&msg)));
+    Ok(vec![msg])
+}
+
+/// Warn if the device lacks the capability `cmd` needs. The capabilities
+/// are queried once per session, a device not answering the query is
+/// assumed to support everything.
+pub fn check(session: &mut Session, cmd: &Command) -> Result<(), Error> {
+    let capability = match Capability::required(cmd) {
+        Some(capability) => capability,
+        None => return Ok(()),
+    };
+
+    if session.capabilities.is_none() {
+        session.capabilities = Some(match query(session) {
+            Ok(capabilities) => Some(capabilities),
+            Err(e)
+                if e.kind == serialport::ErrorKind::Io(std::io::ErrorKind::TimedOut)
+                    || session.rejection.is_some() =>
+            {
+                eprintln!(
+                    "WARNING: Device {} didn't tell its capabilities",
+                    session.id
+                );
+                None
+            }
+            Err(e) => return Err(e.into()),
+        });
+    }
+
+    if let Some(Some(capabilities)) = session.capabilities {
+        if !capabilities.has(capability) {
+            eprintln!(
+                "WARNING: Device {} has no {}, the command may fail",
+                session.id,
+                capability.name()
+            );
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,148 @@
+//! Proxy between two serial ports injecting transmission errors, to test the
+//! retry logic of clients and the robustness of firmware.
+//!
+//! Bytes are forwarded in both directions in frames of 16 bytes. Each frame
+//! may independently be dropped, duplicated or get a single bit flipped, and
+//! all frames can be held back by an extra latency. A partial frame followed
+//! by silence is forwarded as it is.
+
+use std::{thread, time::Duration};
+
+use clap::Args;
+use serialport::SerialPort;
+
+use crate::{
+    describe, emulator::parse_percent, error::Error, parse_duration, rng::Rng, ChecksumAlgorithm,
+    CliArgs,
+};
+
+#[derive(Args, Debug, Clone)]
+pub struct ChaosProxy {
+    /// Serial port on the other side of the proxy
+    port: String,
+    /// Percentage of frames with a single flipped bit
+    #[arg(long, default_value = "0", value_parser = parse_percent)]
+    flip: u8,
+    /// Percentage of frames left out
+    #[arg(long, default_value = "0", value_parser = parse_percent)]
+    drop: u8,
+    /// Percentage of frames sent twice
+    #[arg(long, default_value = "0", value_parser = parse_percent)]
+    duplicate: u8,
+    /// Extra latency of every frame, e.g. `50ms`
+    #[arg(long, value_parser = parse_duration)]
+    latency: Option<Duration>,
+    /// Seed of the injected errors, to reproduce a run
+    #[arg(long)]
+    seed: Option<u64>,
+}
+
+pub fn run(args: &CliArgs, proxy: &ChaosProxy) -> Result<(), Error> {
+    let a = crate::open(args)?;
+    let mut other = args.clone();
+    other.device = Some(proxy.port.clone());
+    let b = crate::open(&other)?;
+
+    let mut rng = Rng::new(proxy.seed);
+    let (a_to_b, b_to_a) = (
+        Direction {
+            from: a.try_clone()?,
+            to: b.try_clone()?,
+            label: ">>",
+            rng: Rng::new(Some(rng.next())),
+            checksum: args.checksum(),
+        },
+        Direction {
+            from: b,
+            to: a,
+            label: "<<",
+            rng: Rng::new(Some(rng.next())),
+            checksum: args.checksum(),
+        },
+    );
+
+    eprintln!(
+        "Proxying {} <-> {}",
+        args.device.as_deref().unwrap_or_default(),
+        proxy.port
+    );
+    let echo = args.echo;
+    let forward = {
+        let proxy = proxy.clone();
+        thread::spawn(move || a_to_b.run(&proxy, echo))
+    };
+    let result = b_to_a.run(proxy, echo);
+    forward.join().expect("proxy thread panicked")?;
+    result
+}
+
+struct Direction {
+    from: Box<dyn SerialPort>,
+    to: Box<dyn SerialPort>,
+    /// Marker of the direction in echoed output
+    label: &'static str,
+    rng: Rng,
+    /// Algorithm of the checksums of echoed frames
+    checksum: ChecksumAlgorithm,
+}
+
+impl Direction {
+    fn run(mut self, proxy: &ChaosProxy, echo: bool) -> Result<(), Error> {
+        let mut buf = Vec::new();
+        let mut chunk = [0u8; 64];
+        loop {
+            match self.from.read(&mut chunk) {
+                Ok(n) => buf.extend_from_slice(&chunk[..n]),
+                Err(e) if e.kind() == std::io::ErrorKind::TimedOut => {
+                    if !buf.is_empty() {
+                        self.to.write_all(&buf)?;
+                        buf.clear();
+                    }
+                    continue;
+                }
+                Err(e) => return Err(e.into()),
+            }
+
+            while buf.len() >= 16 {
+                let mut frame = [0u8; 16];
+                frame.copy_from_slice(&buf[..16]);
+                buf.drain(..16);
+                if self.rng.percent(proxy.drop) {
+                    if echo {
+                        eprintln!("{} dropped {}", self.label, describe(&frame, self.checksum));
+                    }
+                    continue;
+                }
+                if self.rng.percent(proxy.flip) {
+                    let bit = self.rng.below(16 * 8) as usize;
+                    frame[bit / 8] ^= 1 << (bit % 8);
+                    if echo {
+                        eprintln!("{} flipped bit {} of byte {}", self.label, bit % 8, bit / 8);
+                    }
+                }
+                let copies = if self.rng.percent(proxy.duplicate) {
+                    if echo {
+                        eprintln!(
+                            "{} duplicated {}",
+                            self.label,
+                            describe(&frame, self.checksum)
+                        );
+                    }
+                    2
+                } else {
+                    1
+                };
+
+                if let Some(latency) = proxy.latency {
+                    thread::sleep(latency);
+                }
+                for _ in 0..copies {
+                    self.to.write_all(&frame)?;
+                }
+                if echo {
+                    eprintln!("{} {}", self.label, describe(&frame, self.checksum));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,62 @@
+//! Checksums of messages computed offline, for crafting frames by hand or
+//! with other tools.
+
+use std::io::Write;
+
+use clap::{Args, ValueEnum};
+use serialport::ErrorKind;
+
+use crate::{
+    output::{Format, Output, Value},
+    parse_bytes, ChecksumAlgorithm,
+};
+
+#[derive(Args, Debug, Clone)]
+pub struct Checksum {
+    /// The 13 bytes from the destination to the end of the SDU as values or
+    /// a single hex string. A whole frame of 16 bytes is accepted too.
+    #[arg(required = true)]
+    bytes: Vec<String>,
+}
+
+pub fn run(checksum: &Checksum, out: &mut Output) -> Result<(), serialport::Error> {
+    let bytes = parse_bytes(&checksum.bytes)
+        .map_err(|e| serialport::Error::new(ErrorKind::InvalidInput, e))?;
+    let covered = match bytes.len() {
+        13 => &bytes[..],
+        // With the frame marker in front, and the checksum and marker after
+        14 | 16 => &bytes[1..14],
+        n => {
+            return Err(serialport::Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "The checksum covers the 13 bytes of header and SDU, got {}",
+                    n
+                ),
+            ))
+        }
+    };
+
+    let sums = ChecksumAlgorithm::value_variants()
+        .iter()
+        .map(|algorithm| (algorithm, algorithm.compute(covered)));
+    match out.format() {
+        Format::Text => {
+            for (algorithm, sum) in sums {
+                let name = format!("{:?}", algorithm).to_lowercase();
+                writeln!(out, "{:<5} 0x{:02x} ({})", name, sum, sum)?;
+            }
+        }
+        _ => {
+            let fields: Vec<(String, Value)> = sums
+                .map(|(algorithm, sum)| {
+                    let name = format!("{:?}", algorithm).to_lowercase();
+                    (name, Value::Int(sum.into()))
+                })
+                .collect();
+            out.record(&fields)?;
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,262 @@
+//! A blocking client of the bus shared by threads.
+//!
+//! [`MmcpClient`] owns the port behind a mutex and clones share it, so any
+//! number of threads may talk to devices on the same port. An exchange holds
+//! the lock from writing the request until its response arrived, the frames
+//! of concurrent exchanges never interleave.
+
+use std::{
+    io::{self, Read, Write},
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
+
+pub use crate::link::EXCHANGE_TIMEOUT;
+use crate::{link::Link, ChecksumAlgorithm, LedState, MsgBuilder};
+
+/// Exchanges of frames with the devices of a port, cheap to clone
+pub struct MmcpClient<P> {
+    link: Arc<Mutex<Link<P>>>,
+}
+
+impl<P> Clone for MmcpClient<P> {
+    fn clone(&self) -> Self {
+        MmcpClient {
+            link: Arc::clone(&self.link),
+        }
+    }
+}
+
+impl<P: Read + Write> MmcpClient<P> {
+    /// A client of the bus behind `port`, e.g. one of the
+    /// [`crate::transport`]s, whose read timeout is the one of responses
+    pub fn new(port: P) -> Self {
+        MmcpClient {
+            link: Arc::new(Mutex::new(Link::new(port, ChecksumAlgorithm::Sum, false))),
+        }
+    }
+
+    /// Like [`MmcpClient::new`], building requests with the checksum of
+    /// `algorithm` and skipping responses with another one
+    pub fn with_checksum(port: P, algorithm: ChecksumAlgorithm) -> Self {
+        MmcpClient {
+            link: Arc::new(Mutex::new(Link::new(port, algorithm, true))),
+        }
+    }
+
+    /// Fail exchanges whose response didn't arrive within `timeout`, while
+    /// frames of other devices keep the port busy. Applies to all clones,
+    /// defaults to [`EXCHANGE_TIMEOUT`]
+    pub fn exchange_timeout(self, timeout: Duration) -> Self {
+        self.lock().timeout = timeout;
+        self
+    }
+
+    /// Send the request without awaiting a response, e.g. to a group
+    pub fn send(&self, builder: MsgBuilder) -> io::Result<()> {
+        self.lock().send(builder)
+    }
+
+    /// Send the request and return the response of the addressed device.
+    /// Frames of other devices or opcodes, like pushed telemetry, are
+    /// skipped
+    pub fn exchange(&self, builder: MsgBuilder) -> io::Result<[u8; 16]> {
+        self.lock().exchange(builder)
+    }
+
+    /// Round trip time of an echo of the device
+    pub fn ping(&self, to: u8) -> io::Result<Duration> {
+        self.lock().ping(to)
+    }
+
+    /// Switch LED `index` of the device, 0 on boards with a single one
+    pub fn set_led(&self, to: u8, index: u8, state: LedState) -> io::Result<()> {
+        self.lock().set_led(to, index, state)
+    }
+
+    /// Whether LED `index` of the device is on
+    pub fn get_led(&self, to: u8, index: u8) -> io::Result<bool> {
+        self.lock().get_led(to, index)
+    }
+
+    /// Number of button presses the device counted
+    pub fn read_button_presses(&self, to: u8) -> io::Result<u8> {
+        self.lock().read_button_presses(to)
+    }
+
+    /// Store `data` in `slot` of the device storage, other exchanges wait
+    /// until it is stored
+    pub fn flash(&self, to: u8, slot: u8, data: &[u8]) -> io::Result<()> {
+        self.lock().flash(to, slot, data)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Link<P>> {
+        // A panic while holding the lock leaves at most a partial frame
+        // behind, which the reader drops
+        self.link.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(all(test, feature = "mock"))]
+mod tests {
+    use std::{io::Read, time::Instant};
+
+    use super::*;
+    use crate::{
+        transport::{answer, Mock},
+        Address, L7Sdu, LedState, Opcode,
+    };
+
+    fn echo(request: &[u8; 16]) -> Option<[u8; 16]> {
+        let mut sdu = L7Sdu::default();
+        sdu.copy_from_slice(&request[6..14]);
+        Some(answer(request, sdu, ChecksumAlgorithm::Sum))
+    }
+
+    /// A frame of device `from` nobody asked for
+    fn foreign(from: u8) -> [u8; 16] {
+        MsgBuilder {
+            to: Address::HOST,
+            from: Address(from),
+            ..MsgBuilder::new(0, Opcode::Uptime, L7Sdu::default())
+        }
+        .build()
+    }
+
+    #[test]
+    fn exchange() {
+        let client = MmcpClient::new(Mock::new(echo));
+        let builder = MsgBuilder::new(5, Opcode::Echo, *b"abcdefgh");
+        let response = client.exchange(builder).unwrap();
+        assert_eq!(response[2], 5);
+        assert_eq!(&response[6..14], b"abcdefgh");
+        assert!(client.ping(5).is_ok());
+    }
+
+    #[test]
+    fn leds_are_switched_by_index() {
+        let mut leds = [false; 2];
+        let device = move |request: &[u8; 16]| {
+            let led = &mut leds[request[12] as usize];
+            match Opcode::from_byte(request[5]) {
+                Opcode::SetLed => *led = request[13] == 1 || (request[13] == 2 && !*led),
+                Opcode::GetLed => (),
+                _ => return None,
+            }
+            Some(answer(
+                request,
+                [0, 0, 0, 0, 0, 0, 0, *led as u8],
+                ChecksumAlgorithm::Sum,
+            ))
+        };
+        let client = MmcpClient::new(Mock::new(device));
+
+        client.set_led(5, 1, LedState::On).unwrap();
+        assert!(!client.get_led(5, 0).unwrap());
+        assert!(client.get_led(5, 1).unwrap());
+        client.set_led(5, 0, LedState::Toggle).unwrap();
+        client.set_led(5, 1, LedState::Toggle).unwrap();
+        assert!(client.get_led(5, 0).unwrap());
+        assert!(!client.get_led(5, 1).unwrap());
+        client.set_led(5, 0, LedState::Off).unwrap();
+        assert!(!client.get_led(5, 0).unwrap());
+    }
+
+    #[test]
+    fn responses_are_verified_with_the_checksum_of_the_client() {
+        let crc8_echo = |request: &[u8; 16]| {
+            let mut sdu = L7Sdu::default();
+            sdu.copy_from_slice(&request[6..14]);
+            Some(answer(request, sdu, ChecksumAlgorithm::Crc8))
+        };
+        let client = MmcpClient::with_checksum(Mock::new(crc8_echo), ChecksumAlgorithm::Crc8);
+        assert!(client.ping(5).is_ok());
+
+        let client = MmcpClient::with_checksum(Mock::new(echo), ChecksumAlgorithm::Crc8)
+            .exchange_timeout(Duration::from_millis(50));
+        let error = client.ping(5).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn unanswered_exchange_times_out() {
+        let client = MmcpClient::new(Mock::new(|_: &[u8; 16]| None));
+        let error = client.read_button_presses(5).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn frames_of_other_devices_are_skipped() {
+        let mut mock = Mock::new(echo);
+        mock.push(foreign(9));
+        mock.push(foreign(5));
+        let client = MmcpClient::new(mock);
+        let response = client
+            .exchange(MsgBuilder::new(5, Opcode::Echo, *b"abcdefgh"))
+            .unwrap();
+        assert_eq!((response[2], response[5]), (5, u8::from(Opcode::Echo)));
+    }
+
+    #[test]
+    fn flash_sends_lost_chunks_again() {
+        let (mut stored, mut lost) = (Vec::new(), false);
+        let device = move |request: &[u8; 16]| {
+            let mut sdu = L7Sdu::default();
+            match Opcode::from_byte(request[5]) {
+                Opcode::TransferOpen => (),
+                Opcode::TransferData => {
+                    let seq = u16::from_be_bytes([request[6], request[7]]) as usize;
+                    if seq == 2 && !lost {
+                        lost = true;
+                        return None;
+                    }
+                    // Chunks out of order are dropped
+                    if seq == stored.len() / 6 {
+                        stored.extend_from_slice(&request[8..14]);
+                    }
+                    sdu[..2].copy_from_slice(&((stored.len() / 6) as u16).to_be_bytes());
+                }
+                Opcode::TransferEnd => {
+                    let len =
+                        u32::from_be_bytes([request[10], request[11], request[12], request[13]]);
+                    assert_eq!(&stored[..len as usize], b"chunks of data, some lost");
+                }
+                _ => return None,
+            }
+            Some(answer(request, sdu, ChecksumAlgorithm::Sum))
+        };
+        let client = MmcpClient::new(Mock::new(device));
+        client.flash(5, 1, b"chunks of data, some lost").unwrap();
+    }
+
+    /// A bus where another device pushes frames without end
+    struct Chatty;
+
+    impl Read for Chatty {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let frame = foreign(9);
+            let n = buf.len().min(16);
+            buf[..n].copy_from_slice(&frame[..n]);
+            Ok(n)
+        }
+    }
+
+    impl Write for Chatty {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn foreign_frames_dont_extend_the_exchange() {
+        let client = MmcpClient::new(Chatty).exchange_timeout(Duration::from_millis(50));
+        let start = Instant::now();
+        let error = client.read_button_presses(5).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,41 @@
+//! Wall clock or virtual clock for delays and log timestamps.
+
+use std::{
+    thread,
+    time::{Duration, SystemTime},
+};
+
+#[derive(Debug, Clone, Copy)]
+pub enum Clock {
+    Real,
+    /// Sleeps return at once and advance the clock ahead of the wall clock
+    /// instead, so logs keep the relative timing
+    Virtual {
+        ahead: Duration,
+    },
+}
+
+impl Clock {
+    pub fn new(virtual_time: bool) -> Self {
+        match virtual_time {
+            true => Clock::Virtual {
+                ahead: Duration::ZERO,
+            },
+            false => Clock::Real,
+        }
+    }
+
+    pub fn now(&self) -> SystemTime {
+        match self {
+            Clock::Real => SystemTime::now(),
+            Clock::Virtual { ahead } => SystemTime::now() + *ahead,
+        }
+    }
+
+    pub fn sleep(&mut self, duration: Duration) {
+        match self {
+            Clock::Real => thread::sleep(crate::deadline::limit(duration)),
+            Clock::Virtual { ahead } => *ahead += duration,
+        }
+    }
+}
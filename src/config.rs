@@ -0,0 +1,358 @@
+//! Configuration file support.
+//!
+//! The configuration is read from `--config`, or from `mmcp/config.toml` in
+//! the user's configuration directory if that exists. Only the subset of TOML
+//! this tool needs is understood: `[table]` headers, `key = value` pairs with
+//! string, integer, boolean and array values, and `#` comments.
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serialport::ErrorKind;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    Bool(bool),
+    Array(Vec<Value>),
+}
+
+impl Value {
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_integer(&self) -> Option<i64> {
+        match self {
+            Value::Integer(i) => Some(*i),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Value]> {
+        match self {
+            Value::Array(a) => Some(a),
+            _ => None,
+        }
+    }
+}
+
+pub type Table = BTreeMap<String, Value>;
+
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    /// Path the configuration was loaded from, if any
+    pub path: Option<PathBuf>,
+    /// All tables by their full header name, top level keys live in `""`
+    pub tables: BTreeMap<String, Table>,
+}
+
+impl Config {
+    /// Load the configuration from `path`, or from the default location.
+    ///
+    /// A missing file at the default location yields an empty configuration.
+    pub fn load(path: Option<&Path>) -> Result<Self, serialport::Error> {
+        let (path, required) = match path {
+            Some(p) => (p.to_path_buf(), true),
+            None => match default_path() {
+                Some(p) => (p, false),
+                None => return Ok(Self::default()),
+            },
+        };
+
+        let src = match fs::read_to_string(&path) {
+            Ok(src) => src,
+            Err(e) if !required && e.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(Self::default())
+            }
+            Err(e) => {
+                return Err(serialport::Error::new(
+                    ErrorKind::Io(e.kind()),
+                    format!("Could not read config {}: {}", path.display(), e),
+                ))
+            }
+        };
+
+        let mut config = Self::parse(&src).map_err(|(line, msg)| {
+            serialport::Error::new(
+                ErrorKind::InvalidInput,
+                format!("{}:{}: {}", path.display(), line, msg),
+            )
+        })?;
+        config.path = Some(path);
+        Ok(config)
+    }
+
+    /// Parse configuration source, errors carry the line number
+    pub fn parse(src: &str) -> Result<Self, (usize, String)> {
+        let mut config = Self::default();
+        let mut current = String::new();
+        config.tables.insert(current.clone(), Table::new());
+
+        let mut lines = src.lines().enumerate();
+        while let Some((idx, line)) = lines.next() {
+            let line_no = idx + 1;
+            let mut line = strip_comment(line).trim().to_owned();
+            if line.is_empty() {
+                continue;
+            }
+
+            if line.starts_with('[') {
+                let name = line
+                    .strip_prefix('[')
+                    .and_then(|l| l.strip_suffix(']'))
+                    .ok_or((line_no, "Malformed table header".to_owned()))?;
+                current = name
+                    .split('.')
+                    .map(|part| unquote_key(part.trim()))
+                    .collect::<Vec<_>>()
+                    .join(".");
+                config.tables.entry(current.clone()).or_default();
+                continue;
+            }
+
+            // Arrays may span several lines
+            while bracket_depth(&line) > 0 {
+                match lines.next() {
+                    Some((_, next)) => {
+                        line.push(' ');
+                        line.push_str(strip_comment(next).trim());
+                    }
+                    None => return Err((line_no, "Unterminated array".to_owned())),
+                }
+            }
+
+            let (key, value) = line
+                .split_once('=')
+                .ok_or((line_no, "Expected `key = value`".to_owned()))?;
+            let key = unquote_key(key.trim());
+            let (value, rest) = parse_value(value.trim()).map_err(|e| (line_no, e))?;
+            if !rest.trim().is_empty() {
+                return Err((line_no, format!("Unexpected trailing `{}`", rest.trim())));
+            }
+
+            config
+                .tables
+                .get_mut(&current)
+                .expect("current table is always present")
+                .insert(key, value);
+        }
+
+        Ok(config)
+    }
+
+    pub fn table(&self, name: &str) -> Option<&Table> {
+        self.tables.get(name)
+    }
+
+    pub fn get(&self, table: &str, key: &str) -> Option<&Value> {
+        self.tables.get(table).and_then(|t| t.get(key))
+    }
+
+    /// Resolve a path from the configuration, `~/` is expanded and relative
+    /// paths are taken relative to the configuration file.
+    pub fn resolve_path(&self, path: &str) -> PathBuf {
+        if let Some(rest) = path.strip_prefix("~/") {
+            if let Some(home) = home_dir() {
+                return home.join(rest);
+            }
+        }
+
+        let path = PathBuf::from(path);
+        match self.path.as_ref().and_then(|p| p.parent()) {
+            Some(dir) if path.is_relative() => dir.join(path),
+            _ => path,
+        }
+    }
+}
+
+/// Split a command line into words, single and double quotes group words
+pub fn split_words(line: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut word = None::<String>;
+    let mut quote = None;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (None, c) if c.is_whitespace() => words.extend(word.take()),
+            (None, '"' | '\'') => {
+                quote = Some(c);
+                word.get_or_insert_with(String::new);
+            }
+            (Some(q), c) if c == q => quote = None,
+            (Some('"') | None, '\\') => match chars.next() {
+                Some(c) => word.get_or_insert_with(String::new).push(c),
+                None => return Err(format!("Trailing `\\` in `{}`", line)),
+            },
+            (_, c) => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+
+    if quote.is_some() {
+        return Err(format!("Unterminated quote in `{}`", line));
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+/// Directory holding the configuration and other local state
+pub fn config_dir() -> Option<PathBuf> {
+    if let Some(dir) = std::env::var_os("XDG_CONFIG_HOME").filter(|d| !d.is_empty()) {
+        return Some(PathBuf::from(dir).join("mmcp"));
+    }
+    if cfg!(windows) {
+        if let Some(dir) = std::env::var_os("APPDATA") {
+            return Some(PathBuf::from(dir).join("mmcp"));
+        }
+    }
+
+    home_dir().map(|home| home.join(".config").join("mmcp"))
+}
+
+pub fn default_path() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("config.toml"))
+}
+
+fn home_dir() -> Option<PathBuf> {
+    std::env::var_os("HOME")
+        .or_else(|| std::env::var_os("USERPROFILE"))
+        .map(PathBuf::from)
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = None;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match (in_string, c) {
+            (Some('"'), '\\') if !escaped => {
+                escaped = true;
+                continue;
+            }
+            (Some(q), c) if c == q && !escaped => in_string = None,
+            (None, '"' | '\'') => in_string = Some(c),
+            (None, '#') => return &line[..i],
+            _ => (),
+        }
+        escaped = false;
+    }
+
+    line
+}
+
+fn bracket_depth(line: &str) -> i32 {
+    let value = match line.split_once('=') {
+        Some((_, v)) => v,
+        None => return 0,
+    };
+
+    let mut depth = 0;
+    let mut in_string = None;
+    for c in value.chars() {
+        match (in_string, c) {
+            (Some(q), c) if c == q => in_string = None,
+            (None, '"' | '\'') => in_string = Some(c),
+            (None, '[') => depth += 1,
+            (None, ']') => depth -= 1,
+            _ => (),
+        }
+    }
+
+    depth
+}
+
+fn unquote_key(key: &str) -> String {
+    key.trim_matches(|c| c == '"' || c == '\'').to_owned()
+}
+
+/// Parse one value from the start of `s`, returning it and the unparsed rest
+fn parse_value(s: &str) -> Result<(Value, &str), String> {
+    if let Some(rest) = s.strip_prefix('"') {
+        let mut out = String::new();
+        let mut chars = rest.char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => return Ok((Value::String(out), &rest[i + 1..])),
+                '\\' => match chars.next().map(|(_, c)| c) {
+                    Some('n') => out.push('\n'),
+                    Some('t') => out.push('\t'),
+                    Some('r') => out.push('\r'),
+                    Some('\\') => out.push('\\'),
+                    Some('"') => out.push('"'),
+                    Some(c) => return Err(format!("Unknown escape sequence `\\{}`", c)),
+                    None => break,
+                },
+                c => out.push(c),
+            }
+        }
+
+        return Err("Unterminated string".to_owned());
+    }
+
+    if let Some(rest) = s.strip_prefix('\'') {
+        let end = rest.find('\'').ok_or("Unterminated string")?;
+        return Ok((Value::String(rest[..end].to_owned()), &rest[end + 1..]));
+    }
+
+    if let Some(mut rest) = s.strip_prefix('[') {
+        let mut items = Vec::new();
+        loop {
+            rest = rest.trim_start();
+            if let Some(r) = rest.strip_prefix(']') {
+                return Ok((Value::Array(items), r));
+            }
+
+            let (item, r) = parse_value(rest)?;
+            items.push(item);
+            rest = r.trim_start();
+            if let Some(r) = rest.strip_prefix(',') {
+                rest = r;
+            } else if !rest.starts_with(']') {
+                return Err("Expected `,` or `]` in array".to_owned());
+            }
+        }
+    }
+
+    let end = s
+        .find(|c: char| c == ',' || c == ']' || c.is_whitespace())
+        .unwrap_or(s.len());
+    let (token, rest) = s.split_at(end);
+    let value = match token {
+        "true" => Value::Bool(true),
+        "false" => Value::Bool(false),
+        _ => Value::Integer(parse_integer(token).ok_or(format!("Invalid value `{}`", token))?),
+    };
+
+    Ok((value, rest))
+}
+
+fn parse_integer(token: &str) -> Option<i64> {
+    let token = token.replace('_', "");
+    let (negative, digits) = match token.strip_prefix('-') {
+        Some(d) => (true, d),
+        None => (false, token.strip_prefix('+').unwrap_or(&token)),
+    };
+
+    let value = if let Some(hex) = digits.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(bin) = digits.strip_prefix("0b") {
+        i64::from_str_radix(bin, 2).ok()?
+    } else {
+        digits.parse().ok()?
+    };
+
+    Some(if negative { -value } else { value })
+}
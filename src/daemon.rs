@@ -0,0 +1,447 @@
+//! Daemon serving commands over a socket, so several programs of a gateway
+//! can share one bus.
+//!
+//! Every line a client sends is a command written like in a macro, e.g.
+//! `set-led on` or `send --to 7 --opcode 101`. The daemon answers with a line
+//! per response frame followed by `ok` or `error: <message>`.
+//!
+//! Clients are served at the same time, each by a thread of its own, while
+//! the commands are executed one after another on the port. A client has
+//! one command in flight at most, so queuing them in order of arrival takes
+//! turns between clients and a busy one can't starve the others. Commands
+//! waiting for the port longer than `--request-timeout` fail without being
+//! executed. Up to `--max-clients` are served at once, further ones get an
+//! error and are disconnected, as are clients sending lines longer than
+//! [`MAX_LINE`] bytes.
+//!
+//! The daemon doesn't authenticate clients, so it only serves commands of
+//! the bus. Commands reading or writing files, managing keys or the memory
+//! of the device, and those running for long are refused, as are raw frames
+//! and `send` with opcodes of those. Without `--listen` it listens on the
+//! socket `mmcp-<id>.sock` in `$XDG_RUNTIME_DIR`, or the temporary
+//! directory, and TCP addresses other machines can reach need
+//! `--allow-remote`.
+//!
+//! On Windows the daemon listens on a named pipe, `mmcp-<id>` by default,
+//! see [`crate::pipe`].
+//!
+//! Under systemd the socket passed with socket activation is used instead of
+//! `--listen`, and readiness is reported through `NOTIFY_SOCKET` for services
+//! of `Type=notify`. SIGTERM and SIGINT end the daemon after the current
+//! command, closing the port and the trace file.
+
+#[cfg(unix)]
+use std::env;
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{TcpListener, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{self, RecvTimeoutError},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+#[cfg(unix)]
+use std::os::unix::net::UnixListener;
+
+use clap::Args;
+use serialport::ErrorKind;
+
+#[cfg(windows)]
+use crate::pipe::PipeListener;
+use crate::{describe, error::Error, macros, session::Session, signals, Command, Opcode};
+
+#[derive(Args, Debug, Clone)]
+pub struct Daemon {
+    /// Address to listen on, `host:port`, `unix:<path>` or on Windows
+    /// `pipe:<name>`. Defaults to a socket only local users can reach
+    #[arg(long)]
+    listen: Option<String>,
+    /// Listen on TCP addresses other than loopback. Anyone reaching them can
+    /// control the bus, the daemon doesn't authenticate clients
+    #[arg(long)]
+    allow_remote: bool,
+    /// Time a command may wait for the port behind those of other clients,
+    /// e.g. `--request-timeout=30s`
+    #[arg(long, default_value = "10s", value_parser = crate::parse_duration)]
+    request_timeout: Duration,
+    /// Clients to serve at the same time, further ones are turned away
+    #[arg(long, default_value_t = 16, value_parser = clap::value_parser!(u16).range(1..))]
+    max_clients: u16,
+}
+
+/// Longest line of a command, in bytes with the line break
+pub const MAX_LINE: usize = 4096;
+
+enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+    #[cfg(windows)]
+    Pipe(PipeListener),
+}
+
+/// Clients being served and their limit
+#[derive(Clone)]
+struct Clients {
+    served: Arc<AtomicUsize>,
+    max: usize,
+}
+
+/// A command of a client, waiting for the port
+struct Request {
+    line: String,
+    queued: Instant,
+    reply: mpsc::Sender<String>,
+}
+
+pub fn run(session: &mut Session, daemon: &Daemon) -> Result<(), Error> {
+    let (mut listener, address) = match activated() {
+        Some(listener) => (listener, "the socket from systemd".to_owned()),
+        None => {
+            let address = daemon
+                .listen
+                .clone()
+                .unwrap_or_else(|| default_address(session.id));
+            (bind(&address, daemon.allow_remote)?, address)
+        }
+    };
+    signals::handle();
+
+    match &listener {
+        Listener::Tcp(l) => l.set_nonblocking(true)?,
+        #[cfg(unix)]
+        Listener::Unix(l) => l.set_nonblocking(true)?,
+        // Instances of the pipe wait for clients without blocking
+        #[cfg(windows)]
+        Listener::Pipe(_) => (),
+    }
+    notify("READY=1");
+    eprintln!("Serving commands for device {} on {}", session.id, address);
+
+    let (requests, queue) = mpsc::channel();
+    let clients = Clients {
+        served: Arc::new(AtomicUsize::new(0)),
+        max: daemon.max_clients as usize,
+    };
+    // Accepting polls, so a signal is noticed without a client
+    while !signals::stopping() {
+        let accepted = match &mut listener {
+            Listener::Tcp(l) => l.accept().and_then(|(stream, _)| {
+                stream.set_nonblocking(false)?;
+                stream.set_read_timeout(Some(Duration::from_millis(200)))?;
+                spawn(stream, requests.clone(), &clients);
+                Ok(())
+            }),
+            #[cfg(unix)]
+            Listener::Unix(l) => l.accept().and_then(|(stream, _)| {
+                stream.set_nonblocking(false)?;
+                stream.set_read_timeout(Some(Duration::from_millis(200)))?;
+                spawn(stream, requests.clone(), &clients);
+                Ok(())
+            }),
+            #[cfg(windows)]
+            Listener::Pipe(l) => l
+                .accept()
+                .map(|stream| spawn(stream, requests.clone(), &clients)),
+        };
+        match accepted {
+            // Further clients may be waiting already
+            Ok(()) => continue,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => (),
+            Err(e) => eprintln!("Could not accept a client: {}", e),
+        }
+
+        match queue.recv_timeout(Duration::from_millis(100)) {
+            Ok(request) => execute(session, request, daemon.request_timeout),
+            Err(RecvTimeoutError::Timeout) => (),
+            // The daemon keeps a sender of its own
+            Err(RecvTimeoutError::Disconnected) => unreachable!(),
+        }
+    }
+
+    notify("STOPPING=1");
+    eprintln!("Shutting down");
+    Ok(())
+}
+
+/// Serve a client by a thread of its own, unless the limit of clients is
+/// served already
+fn spawn<S: Read + Write + Send + 'static>(
+    mut stream: S,
+    requests: mpsc::Sender<Request>,
+    clients: &Clients,
+) {
+    if clients.served.fetch_add(1, Ordering::AcqRel) >= clients.max {
+        clients.served.fetch_sub(1, Ordering::AcqRel);
+        eprintln!("Turned a client away, {} are served", clients.max);
+        let _ = writeln!(
+            stream,
+            "error: The daemon serves {} clients at most, try again later",
+            clients.max
+        );
+        return;
+    }
+
+    let served = clients.served.clone();
+    thread::spawn(move || {
+        // A client going away doesn't end the daemon
+        if let Err(e) = serve(stream, requests) {
+            eprintln!("Client failed: {}", e);
+        }
+        served.fetch_sub(1, Ordering::AcqRel);
+    });
+}
+
+/// Queue the commands of one client until it disconnects, answering each
+/// once it was executed
+fn serve<S: Read + Write>(stream: S, requests: mpsc::Sender<Request>) -> io::Result<()> {
+    let mut stream = BufReader::new(stream);
+    let mut line = String::new();
+    while !signals::stopping() {
+        let rest = (MAX_LINE - line.len()) as u64;
+        match (&mut stream).take(rest).read_line(&mut line) {
+            Ok(0) => return Ok(()),
+            Ok(_) if line.len() >= MAX_LINE && !line.ends_with('\n') => {
+                let e = format!("Lines are limited to {} bytes", MAX_LINE);
+                writeln!(stream.get_mut(), "error: {}", e)?;
+                return Err(io::Error::new(io::ErrorKind::InvalidData, e));
+            }
+            Ok(_) => (),
+            // Partial lines stay in `line` until the rest arrives
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                continue
+            }
+            Err(e) => return Err(e),
+        }
+        let request = line.trim().to_owned();
+        line.clear();
+        if request.is_empty() {
+            continue;
+        }
+
+        let (reply, answer) = mpsc::channel();
+        let queued = requests.send(Request {
+            line: request,
+            queued: Instant::now(),
+            reply,
+        });
+        // Both fail only once the daemon is shutting down
+        match queued.ok().and_then(|_| answer.recv().ok()) {
+            Some(reply) => stream.get_mut().write_all(reply.as_bytes())?,
+            None => return Ok(()),
+        }
+    }
+
+    Ok(())
+}
+
+/// Execute the command of a client on the port and send it the answer
+fn execute(session: &mut Session, request: Request, timeout: Duration) {
+    let result = match macros::parse(&request.line) {
+        _ if request.queued.elapsed() > timeout => Err(format!(
+            "Waited longer than {:?} for the port, it is busy with other clients",
+            timeout
+        )),
+        Ok(cmd) if !allowed(&cmd) => Err(format!(
+            "`{}` is not served by the daemon, it may only run commands of the bus",
+            request.line
+        )),
+        Ok(cmd) => crate::perform(session, &cmd).map_err(|e| e.to_string()),
+        Err(e) => Err(e),
+    };
+    let mut reply = String::new();
+    match result {
+        Ok(responses) => {
+            for frame in &responses {
+                reply.push_str(&describe(frame, session.args.checksum()));
+                reply.push('\n');
+            }
+            reply.push_str("ok\n");
+        }
+        Err(e) => reply.push_str(&format!("error: {}\n", e.replace('\n', " "))),
+    }
+    // The client may have disconnected meanwhile
+    let _ = request.reply.send(reply);
+}
+
+/// Whether clients may run the command. Clients aren't authenticated, so
+/// files, keys and the memory of the device are out of their reach
+fn allowed(cmd: &Command) -> bool {
+    match cmd {
+        Command::Send(send) => !restricted(send.opcode),
+        // Every frame of the bytes, a short one has no opcode to check
+        Command::Raw(raw) => raw
+            .bytes
+            .concat()
+            .chunks(16)
+            .all(|frame| frame.get(5).is_none_or(|&opcode| !restricted(opcode))),
+        Command::SetLed(_)
+        | Command::GetLed(_)
+        | Command::ReadButtonPresses
+        | Command::ConfigureButton(_)
+        | Command::Beep(_)
+        | Command::DisplayNumber(_)
+        | Command::LcdWrite(_)
+        | Command::SetServo(_)
+        | Command::SetRelay(_)
+        | Command::GetRelays
+        | Command::ReadUid
+        | Command::Capabilities
+        | Command::Status
+        | Command::Uptime(_)
+        | Command::Stats(_)
+        | Command::Group(_)
+        | Command::ListOpcodes(_) => true,
+        _ => false,
+    }
+}
+
+/// Opcodes managing keys, transfers and memory, which clients can't send
+fn restricted(opcode: u8) -> bool {
+    matches!(
+        Opcode::from_byte(opcode),
+        Opcode::Pair
+            | Opcode::RotateKey
+            | Opcode::TransferData
+            | Opcode::TransferEnd
+            | Opcode::TransferOpen
+            | Opcode::TransferRead
+            | Opcode::Peek
+            | Opcode::PokeU8
+            | Opcode::PokeU16
+            | Opcode::PokeU32
+    )
+}
+
+/// The address without `--listen`, only reachable by local users
+#[cfg(unix)]
+fn default_address(id: u8) -> String {
+    let dir = env::var_os("XDG_RUNTIME_DIR")
+        .map(std::path::PathBuf::from)
+        .unwrap_or_else(env::temp_dir);
+    format!("unix:{}", dir.join(format!("mmcp-{}.sock", id)).display())
+}
+
+#[cfg(not(unix))]
+fn default_address(id: u8) -> String {
+    format!("pipe:mmcp-{}", id)
+}
+
+fn bind(address: &str, allow_remote: bool) -> Result<Listener, serialport::Error> {
+    let error = |e: io::Error| {
+        serialport::Error::new(
+            ErrorKind::Io(e.kind()),
+            format!("Could not listen on {}: {}", address, e),
+        )
+    };
+
+    #[cfg(unix)]
+    if let Some(path) = address.strip_prefix("unix:") {
+        use std::os::unix::{fs::FileTypeExt, net::UnixStream};
+
+        // The socket of a daemon which was killed, nobody accepts on it
+        let stale = std::fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_socket())
+            && UnixStream::connect(path).is_err();
+        if stale {
+            std::fs::remove_file(path).map_err(error)?;
+        }
+        return UnixListener::bind(path).map(Listener::Unix).map_err(error);
+    }
+    #[cfg(windows)]
+    if let Some(name) = address.strip_prefix("pipe:") {
+        return PipeListener::bind(name).map(Listener::Pipe).map_err(error);
+    }
+
+    let addresses: Vec<_> = address.to_socket_addrs().map_err(error)?.collect();
+    if !allow_remote && addresses.iter().any(|a| !a.ip().is_loopback()) {
+        return Err(serialport::Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "{} can be reached by other machines, pass --allow-remote to listen on it",
+                address
+            ),
+        ));
+    }
+    TcpListener::bind(&*addresses)
+        .map(Listener::Tcp)
+        .map_err(error)
+}
+
+/// The socket passed by systemd socket activation, if any
+#[cfg(unix)]
+fn activated() -> Option<Listener> {
+    use std::os::fd::FromRawFd;
+
+    let pid: u32 = env::var("LISTEN_PID").ok()?.parse().ok()?;
+    let fds: u32 = env::var("LISTEN_FDS").ok()?.parse().ok()?;
+    if pid != std::process::id() || fds == 0 {
+        return None;
+    }
+    // Hooks started by the daemon must not take the socket for theirs
+    env::remove_var("LISTEN_PID");
+    env::remove_var("LISTEN_FDS");
+
+    // Passed descriptors start after stdin, stdout and stderr
+    let fd = 3;
+    // SAFETY: sockaddr_storage is plain data and large enough for any address
+    let mut address: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+    let mut len = std::mem::size_of_val(&address) as libc::socklen_t;
+    // SAFETY: The pointers are valid for the given length
+    let result = unsafe {
+        libc::getsockname(
+            fd,
+            &mut address as *mut libc::sockaddr_storage as *mut libc::sockaddr,
+            &mut len,
+        )
+    };
+    if result != 0 {
+        return None;
+    }
+
+    // SAFETY: systemd passes the descriptor to this process to own
+    Some(match address.ss_family as libc::c_int {
+        libc::AF_UNIX => Listener::Unix(unsafe { UnixListener::from_raw_fd(fd) }),
+        _ => Listener::Tcp(unsafe { TcpListener::from_raw_fd(fd) }),
+    })
+}
+
+#[cfg(not(unix))]
+fn activated() -> Option<Listener> {
+    None
+}
+
+/// Report a state change to systemd, if started by it
+#[cfg(unix)]
+fn notify(state: &str) {
+    if let Some(path) = env::var_os("NOTIFY_SOCKET") {
+        use std::os::unix::net::UnixDatagram;
+
+        let sent = UnixDatagram::unbound().and_then(|socket| {
+            let path = path.to_string_lossy();
+            match path.strip_prefix('@') {
+                #[cfg(target_os = "linux")]
+                Some(name) => {
+                    use std::os::{linux::net::SocketAddrExt, unix::net::SocketAddr};
+                    let address = SocketAddr::from_abstract_name(name)?;
+                    socket.send_to_addr(state.as_bytes(), &address)
+                }
+                _ => socket.send_to(state.as_bytes(), &*path),
+            }
+        });
+        if let Err(e) = sent {
+            eprintln!("Could not notify systemd of {}: {}", state, e);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn notify(_state: &str) {}
@@ -0,0 +1,62 @@
+//! The `--deadline` of an invocation.
+//!
+//! Waits of the session, opening the port and locking it are cut short at
+//! the deadline and then fail with a timeout, long running modes stop like
+//! on SIGTERM, see [`crate::signals`]. The invocation ends through its
+//! normal shutdown, so output and trace files are completed and statistics
+//! printed before the deadline is reported.
+
+use std::{
+    io,
+    sync::OnceLock,
+    time::{Duration, Instant},
+};
+
+use serialport::ErrorKind;
+
+/// When the invocation has to be done and the duration it was given
+static DEADLINE: OnceLock<(Instant, Duration)> = OnceLock::new();
+
+/// Start the deadline of `duration` from now
+pub fn set(duration: Duration) {
+    let _ = DEADLINE.set((Instant::now() + duration, duration));
+}
+
+/// Whether the deadline passed
+pub fn expired() -> bool {
+    DEADLINE.get().is_some_and(|(at, _)| Instant::now() >= *at)
+}
+
+/// Time left until the deadline, if there is one. Never zero, which means
+/// no timeout for sockets
+pub fn remaining() -> Option<Duration> {
+    DEADLINE.get().map(|(at, _)| {
+        at.saturating_duration_since(Instant::now())
+            .max(Duration::from_millis(1))
+    })
+}
+
+/// `wait`, but not past the deadline
+pub fn limit(wait: Duration) -> Duration {
+    match DEADLINE.get() {
+        Some((at, _)) => wait.min(at.saturating_duration_since(Instant::now())),
+        None => wait,
+    }
+}
+
+/// Fail once the deadline passed
+pub fn check() -> Result<(), serialport::Error> {
+    match expired() {
+        true => Err(error()),
+        false => Ok(()),
+    }
+}
+
+/// The error of a wait cut short by the deadline
+pub fn error() -> serialport::Error {
+    let duration = DEADLINE.get().map(|(_, duration)| *duration);
+    serialport::Error::new(
+        ErrorKind::Io(io::ErrorKind::TimedOut),
+        format!("The deadline of {:?} expired", duration.unwrap_or_default()),
+    )
+}
@@ -0,0 +1,84 @@
+//! Comparison of two sessions recorded with `--trace-file`.
+
+use std::{io::Write, path::PathBuf};
+
+use clap::Args;
+use serialport::ErrorKind;
+
+use crate::{
+    output::Output,
+    trace::{self, Entry},
+};
+
+#[derive(Args, Debug, Clone)]
+pub struct Diff {
+    /// Trace file of the first session
+    a: PathBuf,
+    /// Trace file of the second session
+    b: PathBuf,
+}
+
+/// Print every frame that differs between the sessions, timestamps are
+/// ignored. Fails if the sessions differ.
+pub fn run(diff: &Diff, out: &mut Output) -> Result<(), serialport::Error> {
+    let a = trace::read(&diff.a)?;
+    let b = trace::read(&diff.b)?;
+
+    let mut differences = 0;
+    for i in 0..a.len().max(b.len()) {
+        match (a.get(i), b.get(i)) {
+            (Some(x), Some(y)) if x.direction == y.direction && x.bytes == y.bytes => (),
+            (x, y) => {
+                differences += 1;
+                writeln!(out, "@ frame {}", i + 1)?;
+                writeln!(out, "- {}", render(x))?;
+                writeln!(out, "+ {}", render(y))?;
+                if let (Some(x), Some(y)) = (x, y) {
+                    writeln!(out, "  {}", markers(x, y))?;
+                }
+            }
+        }
+    }
+
+    if differences > 0 {
+        return Err(serialport::Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "The sessions differ in {} of {} frames",
+                differences,
+                a.len().max(b.len())
+            ),
+        ));
+    }
+
+    writeln!(out, "The sessions are identical ({} frames)", a.len())?;
+    Ok(())
+}
+
+pub fn render(entry: Option<&Entry>) -> String {
+    match entry {
+        Some(entry) => {
+            let hex: Vec<String> = entry.bytes.iter().map(|b| format!("{:02x}", b)).collect();
+            format!("{} {}", entry.direction.as_str(), hex.join(" "))
+        }
+        None => "(missing)".to_owned(),
+    }
+}
+
+/// Underline the direction and the bytes that differ
+pub fn markers(x: &Entry, y: &Entry) -> String {
+    let mut line = String::from(if x.direction == y.direction {
+        "  "
+    } else {
+        "^^"
+    });
+    for i in 0..x.bytes.len().max(y.bytes.len()) {
+        line.push_str(if x.bytes.get(i) == y.bytes.get(i) {
+            "   "
+        } else {
+            " ^^"
+        });
+    }
+
+    line.trim_end().to_owned()
+}
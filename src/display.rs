@@ -0,0 +1,119 @@
+//! Numbers on the seven-segment display of a device.
+//!
+//! The display number opcode carries the segments of up to seven digits in
+//! SDU bytes 1 to 7, the rightmost digit last, with bit 0 for segment a up
+//! to bit 6 for segment g. SDU byte 0 holds the position of the rightmost
+//! digit of the frame, counted from the right end of the display. Wider
+//! numbers take several frames, starting with the rightmost digits. The
+//! frame at position 0 clears the display and bit 7 of byte 0 marks the
+//! last frame, once it arrived the device shows the number.
+
+use clap::Args;
+
+use crate::{session::Session, L7Sdu, Opcode};
+
+/// Segments of the digits 0 to 9
+const DIGITS: [u8; 10] = [0x3f, 0x06, 0x5b, 0x4f, 0x66, 0x6d, 0x7d, 0x07, 0x7f, 0x6f];
+/// Segment g alone
+const MINUS: u8 = 0x40;
+const LAST: u8 = 1 << 7;
+/// Digits in the SDU of a frame
+const PER_FRAME: usize = 7;
+
+#[derive(Args, Debug, Clone, Copy)]
+pub struct DisplayNumber {
+    /// Number to show, e.g. `-42`
+    #[arg(allow_negative_numbers = true)]
+    pub number: i64,
+}
+
+/// Show the number, returning the answers to its frames
+pub fn run(
+    session: &mut Session,
+    display: &DisplayNumber,
+) -> Result<Vec<[u8; 16]>, serialport::Error> {
+    let mut responses = Vec::new();
+    for sdu in frames(display.number) {
+        let mut msg = [0u8; 16];
+        session.transact(session.builder(Opcode::DisplayNumber, sdu), &mut msg)?;
+        if !session.args.no_response {
+            responses.push(msg);
+        }
+    }
+    Ok(responses)
+}
+
+/// The SDUs showing `number`, in the order they are sent
+fn frames(number: i64) -> Vec<L7Sdu> {
+    // The segments of the digits from the right
+    let mut segments: Vec<u8> = number
+        .unsigned_abs()
+        .to_string()
+        .bytes()
+        .rev()
+        .map(|digit| DIGITS[(digit - b'0') as usize])
+        .collect();
+    if number < 0 {
+        segments.push(MINUS);
+    }
+
+    let chunks = segments.chunks(PER_FRAME);
+    let count = chunks.len();
+    chunks
+        .enumerate()
+        .map(|(i, chunk)| {
+            let mut sdu = L7Sdu::default();
+            sdu[0] = (i * PER_FRAME) as u8;
+            if i + 1 == count {
+                sdu[0] |= LAST;
+            }
+            for (to, segments) in sdu[1..].iter_mut().rev().zip(chunk) {
+                *to = *segments;
+            }
+            sdu
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn single_frame() {
+        assert_eq!(frames(0), [[LAST, 0, 0, 0, 0, 0, 0, DIGITS[0]]]);
+        assert_eq!(
+            frames(-42),
+            [[LAST, 0, 0, 0, 0, MINUS, DIGITS[4], DIGITS[2]]]
+        );
+        assert_eq!(
+            frames(1_234_567),
+            [[LAST, 0x06, 0x5b, 0x4f, 0x66, 0x6d, 0x7d, 0x07]]
+        );
+    }
+
+    #[test]
+    fn rightmost_digits_first() {
+        let [first, last] = frames(-12_345_678)[..] else {
+            panic!("{:?}", frames(-12_345_678));
+        };
+        let digits = |s: &str| {
+            s.bytes()
+                .map(|d| DIGITS[(d - b'0') as usize])
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(first[0], 0);
+        assert_eq!(first[1..], digits("2345678"));
+        assert_eq!(last, [LAST | 7, 0, 0, 0, 0, 0, MINUS, DIGITS[1]]);
+
+        let frames = frames(i64::MIN);
+        assert_eq!(frames.len(), 3);
+        assert_eq!(
+            frames.iter().map(|sdu| sdu[0]).collect::<Vec<_>>(),
+            [0, 7, LAST | 14]
+        );
+        // 19 digits and the minus
+        assert_eq!(frames[2][1..3], [0, MINUS]);
+        assert_eq!(frames[2][3..], digits("92233"));
+    }
+}
@@ -0,0 +1,245 @@
+//! Device emulator answering frames according to a rules file, to reproduce
+//! field scenarios without hardware.
+//!
+//! Every non empty line of the rules file that isn't a `#` comment is a rule
+//! of the form
+//!
+//! `[opcode <op>] [to <id>] [sdu <pattern>] => <reply> [delay <duration>] [corrupt <n>%] [drop <n>%]`
+//!
+//! where the part before `=>` matches requests, an SDU pattern is written
+//! like for `--expect-sdu`. The reply is one of
+//!
+//! - `echo`, the request with `to` and `from` swapped,
+//! - `sdu <hex>`, the same with the 8 given SDU bytes,
+//! - `raw <hex>`, exactly the given bytes,
+//! - `none`, no reply at all.
+//!
+//! `delay` holds the reply back, `corrupt` sends the given percentage of
+//! replies with a bad checksum and `drop` leaves them out. The first matching
+//! rule applies, requests matching no rule and requests with a bad checksum
+//! are ignored like a device would. With `--virtual-time` delays only show in
+//! the timestamps of the trace file.
+
+use std::{fs, path::PathBuf, time::Duration};
+
+use clap::Args;
+use serialport::ErrorKind;
+
+use crate::{
+    clock::Clock,
+    config::split_words,
+    describe,
+    error::Error,
+    expect::{parse_sdu_pattern, SduPattern},
+    parse_duration, parse_hex,
+    poll::{Event, Poller},
+    reader::FrameReader,
+    rng::Rng,
+    sdu::Response,
+    trace::{Direction, Trace},
+    Address, ChecksumAlgorithm, CliArgs, MsgBuilder, Opcode,
+};
+
+#[derive(Args, Debug, Clone)]
+pub struct Emulate {
+    /// Path of the rules file
+    rules: PathBuf,
+    /// Seed for corrupted and dropped replies, to reproduce a run
+    #[arg(long)]
+    seed: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rule {
+    opcode: Option<u8>,
+    to: Option<u8>,
+    sdu: Option<SduPattern>,
+    reply: Reply,
+    delay: Duration,
+    /// Percentage of replies sent with a bad checksum
+    corrupt: u8,
+    /// Percentage of replies left out
+    drop: u8,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reply {
+    Echo,
+    Sdu([u8; 8]),
+    Raw(Vec<u8>),
+    None,
+}
+
+impl Rule {
+    pub fn matches(&self, frame: &[u8; 16]) -> bool {
+        self.opcode.is_none_or(|op| op == frame[5])
+            && self.to.is_none_or(|to| to == frame[1])
+            && self.sdu.is_none_or(|sdu| sdu.matches(&frame[6..14]))
+    }
+
+    /// The reply to a matching request with the checksum of `algorithm`, if
+    /// any
+    pub fn reply(&self, frame: &[u8; 16], algorithm: ChecksumAlgorithm) -> Option<Vec<u8>> {
+        let sdu = match &self.reply {
+            Reply::Echo => frame.sdu(),
+            Reply::Sdu(sdu) => *sdu,
+            Reply::Raw(bytes) => return Some(bytes.clone()),
+            Reply::None => return None,
+        };
+
+        let reply = MsgBuilder {
+            to: Address(frame[2]),
+            from: Address(frame[1]),
+            hops: frame[4],
+            version: frame[3],
+            opcode: Opcode::from_byte(frame[5]),
+            l7_sdu: sdu,
+        };
+        Some(reply.build_with(algorithm).to_vec())
+    }
+}
+
+pub fn run(args: &CliArgs, emulate: &Emulate) -> Result<(), Error> {
+    let src = fs::read_to_string(&emulate.rules).map_err(|e| {
+        serialport::Error::new(
+            ErrorKind::Io(e.kind()),
+            format!("Could not read rules {}: {}", emulate.rules.display(), e),
+        )
+    })?;
+    let rules = parse(&src).map_err(|(line, msg)| {
+        serialport::Error::new(
+            ErrorKind::InvalidInput,
+            format!("{}:{}: {}", emulate.rules.display(), line, msg),
+        )
+    })?;
+
+    let serial = crate::open(args)?;
+    let mut trace = Trace::from_args(args)?;
+    let mut clock = Clock::new(args.virtual_time);
+    let mut rng = Rng::new(emulate.seed);
+    eprintln!("Emulating with {} rules", rules.len());
+
+    let mut poller = Poller::new(FrameReader::new(serial))?;
+    loop {
+        let frame = match poller.next()? {
+            Event::Frame(frame) => frame,
+            // The reader dropped the partial frame, if any
+            Event::Idle | Event::Timer(_) => continue,
+            Event::Stop => return Ok(()),
+        };
+
+        if let Some(t) = trace.as_mut() {
+            t.log(clock.now(), Direction::Rx, &frame)?;
+        }
+        if args.echo {
+            eprintln!("RX {}", describe(&frame, args.checksum()));
+        }
+
+        if args.checksum().compute(&frame[1..14]) != frame[14]
+            || args.id.is_some_and(|id| id != frame[1])
+        {
+            continue;
+        }
+        let rule = match rules.iter().find(|r| r.matches(&frame)) {
+            Some(rule) => rule,
+            None => continue,
+        };
+        let mut reply = match rule.reply(&frame, args.checksum()) {
+            Some(reply) => reply,
+            None => continue,
+        };
+        if rng.percent(rule.drop) {
+            continue;
+        }
+        if rng.percent(rule.corrupt) && reply.len() > 14 {
+            reply[14] = !reply[14];
+        }
+
+        clock.sleep(rule.delay);
+        if let Some(t) = trace.as_mut() {
+            t.log(clock.now(), Direction::Tx, &reply)?;
+        }
+        if args.echo {
+            match <[u8; 16]>::try_from(&reply[..]) {
+                Ok(frame) => eprintln!("TX {}", describe(&frame, args.checksum())),
+                Err(_) => eprintln!("TX {:02x?}", reply),
+            }
+        }
+        poller.frames.get_mut().write_all(&reply)?;
+    }
+}
+
+/// Parse the rules, errors carry the line number
+pub fn parse(src: &str) -> Result<Vec<Rule>, (usize, String)> {
+    src.lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(line_no, line)| parse_rule(line).map_err(|e| (line_no, e)))
+        .collect()
+}
+
+fn parse_rule(line: &str) -> Result<Rule, String> {
+    let usage = || {
+        format!(
+            "`{}`: Expected `[opcode <op>] [to <id>] [sdu <pattern>] => <reply>`",
+            line
+        )
+    };
+    let words = split_words(line)?;
+    let arrow = words.iter().position(|w| w == "=>").ok_or_else(usage)?;
+
+    let mut rule = Rule {
+        opcode: None,
+        to: None,
+        sdu: None,
+        reply: Reply::None,
+        delay: Duration::ZERO,
+        corrupt: 0,
+        drop: 0,
+    };
+
+    let mut conditions = words[..arrow].iter();
+    while let Some(word) = conditions.next() {
+        let value = conditions.next().ok_or_else(usage)?;
+        match word.as_str() {
+            "opcode" => rule.opcode = Some(crate::parse_u8(value)?),
+            "to" => rule.to = Some(crate::parse_u8(value)?),
+            "sdu" => rule.sdu = Some(parse_sdu_pattern(value)?),
+            _ => return Err(usage()),
+        }
+    }
+
+    let mut actions = words[arrow + 1..].iter();
+    rule.reply = match actions.next().map(String::as_str) {
+        Some("echo") => Reply::Echo,
+        Some("none") => Reply::None,
+        Some("sdu") => Reply::Sdu(crate::parse_sdu(actions.next().ok_or_else(usage)?)?),
+        Some("raw") => Reply::Raw(parse_hex(actions.next().ok_or_else(usage)?)?),
+        _ => {
+            return Err(format!(
+                "`{}`: The reply is one of `echo`, `sdu <hex>`, `raw <hex>` and `none`",
+                line
+            ))
+        }
+    };
+    while let Some(word) = actions.next() {
+        let value = actions.next().ok_or_else(usage)?;
+        match word.as_str() {
+            "delay" => rule.delay = parse_duration(value)?,
+            "corrupt" => rule.corrupt = parse_percent(value)?,
+            "drop" => rule.drop = parse_percent(value)?,
+            _ => return Err(format!("`{}`: Unknown option `{}`", line, word)),
+        }
+    }
+
+    Ok(rule)
+}
+
+pub fn parse_percent(s: &str) -> Result<u8, String> {
+    s.trim_end_matches('%')
+        .parse()
+        .ok()
+        .filter(|p| *p <= 100)
+        .ok_or_else(|| format!("`{}` is not a percentage like `10%`", s))
+}
@@ -0,0 +1,102 @@
+//! Error type of command execution, which maps failures to exit codes.
+
+use std::fmt::{self, Display};
+
+use crate::rejection::Rejection;
+
+#[derive(Debug)]
+pub enum Error {
+    /// Errors of the serial port, I/O and invalid input
+    Serial(serialport::Error),
+    /// A response didn't match what was expected of it
+    Assertion(String),
+    /// A frame broke the protocol, an error only with `--strict`
+    Protocol(String),
+    /// The device refused the request
+    Rejected(Rejection),
+    /// An error with the bytes of the frame which was cut short by it
+    Context {
+        error: Box<Error>,
+        received: Vec<u8>,
+    },
+}
+
+impl Error {
+    /// Exit code of the process when it fails with this error
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Error::Serial(_) => 1,
+            Error::Assertion(_) => 3,
+            Error::Protocol(_) => 4,
+            Error::Rejected(rejection) => rejection.exit_code(),
+            Error::Context { error, .. } => error.exit_code(),
+        }
+    }
+
+    /// Name of the kind of error for scripts, e.g. `timed_out`
+    pub fn kind(&self) -> String {
+        match self {
+            Error::Serial(e) => {
+                let kind = match e.kind {
+                    serialport::ErrorKind::Io(kind) => format!("{:?}", kind),
+                    kind => format!("{:?}", kind),
+                };
+                // CamelCase to snake_case
+                kind.chars()
+                    .enumerate()
+                    .fold(String::new(), |mut s, (i, c)| {
+                        if c.is_ascii_uppercase() && i > 0 {
+                            s.push('_');
+                        }
+                        s.push(c.to_ascii_lowercase());
+                        s
+                    })
+            }
+            Error::Assertion(_) => "assertion".to_owned(),
+            Error::Protocol(_) => "protocol".to_owned(),
+            Error::Rejected(_) => "rejected".to_owned(),
+            Error::Context { error, .. } => error.kind(),
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            Error::Serial(e) => e.description.clone(),
+            Error::Assertion(msg) | Error::Protocol(msg) => msg.clone(),
+            Error::Rejected(rejection) => rejection.to_string(),
+            Error::Context { error, .. } => error.message(),
+        }
+    }
+
+    /// Code of the operating system error behind this one, if any
+    pub fn os_error(&self) -> Option<i32> {
+        // I/O errors keep the code only in their description
+        let message = self.message();
+        let code = &message[message.rfind("(os error ")? + 10..];
+        code[..code.find(')')?].parse().ok()
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Serial(e) => write!(f, "Error({:?}): {}", e.kind, e.description),
+            Error::Assertion(msg) => write!(f, "Assertion failed: {}", msg),
+            Error::Protocol(msg) => write!(f, "Protocol violation: {}", msg),
+            Error::Rejected(rejection) => write!(f, "Rejected: {}", rejection),
+            Error::Context { error, .. } => error.fmt(f),
+        }
+    }
+}
+
+impl From<serialport::Error> for Error {
+    fn from(e: serialport::Error) -> Self {
+        Error::Serial(e)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Serial(e.into())
+    }
+}
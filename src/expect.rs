@@ -0,0 +1,95 @@
+//! Expectations on responses, which fail the run when they don't hold.
+
+use std::fmt::{self, Display};
+
+/// Pattern for the 8 SDU bytes, `None` bytes match anything
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SduPattern([Option<u8>; 8]);
+
+impl SduPattern {
+    pub fn matches(&self, sdu: &[u8]) -> bool {
+        self.0
+            .iter()
+            .zip(sdu)
+            .all(|(expected, actual)| expected.is_none_or(|e| e == *actual))
+    }
+
+    /// Check the SDU of the response against the pattern
+    pub fn check(&self, response: Option<&[u8; 16]>) -> Result<(), String> {
+        let frame = response.ok_or_else(|| format!("Expected SDU {}, got no response", self))?;
+        let sdu = &frame[6..14];
+        if self.matches(sdu) {
+            return Ok(());
+        }
+
+        Err(format!(
+            "Expected SDU {}, got {}",
+            self,
+            sdu.iter()
+                .map(|b| format!("{:02x}", b))
+                .collect::<Vec<_>>()
+                .join(":")
+        ))
+    }
+}
+
+impl Display for SduPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, byte) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(":")?;
+            }
+            match byte {
+                Some(b) => write!(f, "{:02x}", b)?,
+                None => f.write_str("xx")?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Parse an SDU pattern like `00:00:00:00:00:00:00:01`, `xx` matches any
+/// byte and `..` any number of bytes, e.g. `00..01`
+pub fn parse_sdu_pattern(s: &str) -> Result<SduPattern, String> {
+    let invalid = || {
+        format!(
+            "`{}` is not an SDU pattern like `00:xx:00:00:00:00:00:01` or `00..01`",
+            s
+        )
+    };
+    let bytes = |part: &str| -> Result<Vec<Option<u8>>, String> {
+        let digits: String = part.chars().filter(|c| !matches!(c, ':' | ' ')).collect();
+        if !digits.is_ascii() || !digits.len().is_multiple_of(2) {
+            return Err(invalid());
+        }
+        (0..digits.len())
+            .step_by(2)
+            .map(|i| match &digits[i..i + 2] {
+                "xx" | "XX" => Ok(None),
+                byte => u8::from_str_radix(byte, 16)
+                    .map(Some)
+                    .map_err(|_| invalid()),
+            })
+            .collect()
+    };
+
+    let s = s.trim().trim_start_matches("0x");
+    let pattern = match s.split_once("..") {
+        Some((head, tail)) => {
+            let (head, tail) = (bytes(head)?, bytes(tail)?);
+            if head.len() + tail.len() > 8 {
+                return Err(format!("The SDU consists of 8 bytes, `{}` has more", s));
+            }
+            let mut pattern = head;
+            pattern.resize(8 - tail.len(), None);
+            pattern.extend(tail);
+            pattern
+        }
+        None => bytes(s)?,
+    };
+
+    <[Option<u8>; 8]>::try_from(pattern)
+        .map(SduPattern)
+        .map_err(|p| format!("The SDU consists of 8 bytes, got {}", p.len()))
+}
@@ -0,0 +1,52 @@
+//! Offline decoding of frames, e.g. ones pasted from a firmware log.
+
+use std::io::Write;
+
+use clap::Args;
+use serialport::ErrorKind;
+
+use crate::{
+    output::{self, Format, Output},
+    parse_bytes,
+    sdu::Response,
+};
+
+#[derive(Args, Debug, Clone)]
+pub struct Explain {
+    /// The frame as 16 byte values, or as a single hex string like
+    /// `00:05:00:04:...` or base64
+    #[arg(required = true)]
+    frame: Vec<String>,
+}
+
+/// Print the fields of the frame, as table unless another `--format` is
+/// chosen
+pub fn run(explain: &Explain, out: &mut Output) -> Result<(), serialport::Error> {
+    let bytes = parse_bytes(&explain.frame)
+        .map_err(|e| serialport::Error::new(ErrorKind::InvalidInput, e))?;
+
+    let frame: [u8; 16] = bytes.as_slice().try_into().map_err(|_| {
+        serialport::Error::new(
+            ErrorKind::InvalidInput,
+            format!("A frame consists of 16 bytes, got {}", bytes.len()),
+        )
+    })?;
+    match out.format() {
+        Format::Text => {
+            let table = output::table(&frame, out.checksum(), out.seq_offset());
+            writeln!(out, "{}", table)?;
+            // Firmware logs seldom tell how the SDU is encoded
+            writeln!(out, "\nSDU as u64 (big endian): {}", frame.sdu_u64_be())?;
+            writeln!(
+                out,
+                "SDU as u32 (little endian): {} {}",
+                frame.sdu_u32_le(0),
+                frame.sdu_u32_le(4)
+            )?;
+            writeln!(out, "SDU as text: {:?}", frame.sdu_str())?;
+        }
+        _ => out.frame(&frame)?,
+    }
+
+    Ok(())
+}
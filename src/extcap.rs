@@ -0,0 +1,169 @@
+//! Wireshark extcap interface.
+//!
+//! Wireshark runs every executable in its extcap directory with
+//! `--extcap-interfaces` to discover capture interfaces, so placing (a link
+//! to) this binary there makes live MMCP traffic capturable. Every 16 byte
+//! frame seen on the port is written as one packet with the `USER0` link type
+//! to the pcap stream Wireshark reads from `--fifo`.
+
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::PathBuf,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use clap::Parser;
+use serialport::ErrorKind;
+
+const INTERFACE: &str = "mmcp";
+/// Link type `USER0`, a dissector for it can be configured in Wireshark
+const DLT_USER0: u32 = 147;
+
+#[derive(Parser, Debug)]
+#[command(about = "Wireshark extcap interface")]
+pub struct ExtcapArgs {
+    #[arg(long)]
+    extcap_interfaces: bool,
+    #[arg(long)]
+    extcap_version: Option<String>,
+    #[arg(long)]
+    extcap_interface: Option<String>,
+    #[arg(long)]
+    extcap_dlts: bool,
+    #[arg(long)]
+    extcap_config: bool,
+    #[arg(long)]
+    extcap_capture_filter: Option<String>,
+    #[arg(long)]
+    extcap_control_in: Option<PathBuf>,
+    #[arg(long)]
+    extcap_control_out: Option<PathBuf>,
+    #[arg(long)]
+    capture: bool,
+    #[arg(long)]
+    fifo: Option<PathBuf>,
+    #[arg(long)]
+    device: Option<String>,
+    #[arg(long, default_value_t = 115_200)]
+    baud_rate: u32,
+}
+
+/// Whether the process was started by Wireshark as an extcap program
+pub fn is_extcap_invocation() -> bool {
+    std::env::args()
+        .skip(1)
+        .any(|a| a.starts_with("--extcap-") || a == "--capture")
+}
+
+pub fn run() -> Result<(), serialport::Error> {
+    let args = ExtcapArgs::parse();
+
+    if args.extcap_interfaces {
+        println!("extcap {{version={}}}", env!("CARGO_PKG_VERSION"));
+        println!("interface {{value={}}}{{display=MMCP serial bus}}", INTERFACE);
+        return Ok(());
+    }
+
+    match args.extcap_interface.as_deref() {
+        Some(INTERFACE) => (),
+        Some(other) => {
+            return Err(serialport::Error::new(
+                ErrorKind::InvalidInput,
+                format!("Unknown extcap interface `{}`", other),
+            ))
+        }
+        None => {
+            return Err(serialport::Error::new(
+                ErrorKind::InvalidInput,
+                "--extcap-interface is required",
+            ))
+        }
+    }
+
+    if args.extcap_dlts {
+        println!("dlt {{number={}}}{{name=USER0}}{{display=MMCP}}", DLT_USER0);
+    } else if args.extcap_config {
+        print_config();
+    } else if args.capture {
+        capture(&args)?;
+    }
+
+    Ok(())
+}
+
+fn print_config() {
+    println!(
+        "arg {{number=0}}{{call=--device}}{{display=Serial port}}\
+        {{type=selector}}{{required=true}}"
+    );
+    if let Ok(ports) = serialport::available_ports() {
+        for (i, port) in ports.iter().enumerate() {
+            let default = if i == 0 { "{default=true}" } else { "" };
+            println!(
+                "value {{arg=0}}{{value={0}}}{{display={0}}}{1}",
+                port.port_name, default
+            );
+        }
+    }
+    println!(
+        "arg {{number=1}}{{call=--baud-rate}}{{display=Baud rate}}\
+        {{type=integer}}{{default=115200}}"
+    );
+}
+
+fn capture(args: &ExtcapArgs) -> Result<(), serialport::Error> {
+    let device = args.device.as_deref().ok_or_else(|| {
+        serialport::Error::new(ErrorKind::InvalidInput, "--device is required to capture")
+    })?;
+    let fifo = args.fifo.as_ref().ok_or_else(|| {
+        serialport::Error::new(ErrorKind::InvalidInput, "--fifo is required to capture")
+    })?;
+
+    let mut serial = serialport::new(device, args.baud_rate)
+        .timeout(Duration::from_millis(100))
+        .open()?;
+    let mut out = BufWriter::new(File::create(fifo)?);
+
+    // pcap global header: magic, version 2.4, UTC, accuracy, snap length, link type
+    out.write_all(&0xa1b2_c3d4u32.to_le_bytes())?;
+    out.write_all(&2u16.to_le_bytes())?;
+    out.write_all(&4u16.to_le_bytes())?;
+    out.write_all(&0i32.to_le_bytes())?;
+    out.write_all(&0u32.to_le_bytes())?;
+    out.write_all(&65_535u32.to_le_bytes())?;
+    out.write_all(&DLT_USER0.to_le_bytes())?;
+    out.flush()?;
+
+    let mut frame = [0u8; 16];
+    let mut filled = 0;
+    loop {
+        match serial.read(&mut frame[filled..]) {
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == std::io::ErrorKind::TimedOut => continue,
+            Err(e) => return Err(e.into()),
+        }
+        if filled < frame.len() {
+            continue;
+        }
+        filled = 0;
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let result = out
+            .write_all(&(now.as_secs() as u32).to_le_bytes())
+            .and_then(|_| out.write_all(&now.subsec_micros().to_le_bytes()))
+            .and_then(|_| out.write_all(&(frame.len() as u32).to_le_bytes()))
+            .and_then(|_| out.write_all(&(frame.len() as u32).to_le_bytes()))
+            .and_then(|_| out.write_all(&frame))
+            .and_then(|_| out.flush());
+
+        match result {
+            Ok(()) => (),
+            // Wireshark closes the fifo when the capture is stopped
+            Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => return Ok(()),
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
@@ -0,0 +1,224 @@
+//! Reading back the flash of a device, into a file or to compare it with an
+//! image.
+//!
+//! Memory mapped flash is read word by word with the peek debug opcode, the
+//! slots of the device storage with transfer reads, as `peek` and `download`
+//! do. A verify of memory stops at the first word which differs from the
+//! image, both report the address of the first byte which differs.
+
+use std::{fs, io::Write, ops::Range, path::PathBuf, time::Instant};
+
+use clap::Args;
+use serialport::ErrorKind;
+
+use crate::{
+    error::Error,
+    memory::{self, parse_u32, Width},
+    parse_u8,
+    session::Session,
+    transfer::{self, TransferOptions},
+};
+
+#[derive(Args, Debug, Clone)]
+pub struct DumpFlash {
+    /// File to write the flash to
+    file: PathBuf,
+    /// Addresses of the memory to read, e.g. `0x08000000..0x08010000`
+    #[arg(
+        long,
+        value_parser = parse_range,
+        required_unless_present = "slot",
+        conflicts_with = "slot"
+    )]
+    range: Option<Range<u32>>,
+    /// Slot of the device storage to read instead of memory
+    #[arg(long, value_parser = parse_u8)]
+    slot: Option<u8>,
+    #[command(flatten)]
+    width: Width,
+    #[command(flatten)]
+    options: TransferOptions,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct Verify {
+    /// Image the flash should hold
+    file: PathBuf,
+    /// Address of the memory holding the image, e.g. `0x08000000`
+    #[arg(
+        long,
+        value_parser = parse_u32,
+        required_unless_present = "slot",
+        conflicts_with = "slot"
+    )]
+    address: Option<u32>,
+    /// Slot of the device storage holding the image instead of memory
+    #[arg(long, value_parser = parse_u8)]
+    slot: Option<u8>,
+    #[command(flatten)]
+    width: Width,
+    #[command(flatten)]
+    options: TransferOptions,
+}
+
+pub fn dump(session: &mut Session, dump: &DumpFlash) -> Result<(), Error> {
+    let start = Instant::now();
+    let (bytes, source) = match (&dump.range, dump.slot) {
+        (_, Some(slot)) => (
+            transfer::read_slot(session, slot, dump.options)?,
+            format!("slot {}", slot),
+        ),
+        (Some(range), None) => {
+            let width = dump.width.width as u32;
+            let mut bytes = Vec::with_capacity(range.len());
+            for address in range.clone().step_by(width as usize) {
+                let word = memory::read(session, address, width as u8)?;
+                bytes.extend_from_slice(&word[..width.min(range.end - address) as usize]);
+            }
+            (bytes, format!("{:#010x}..{:#010x}", range.start, range.end))
+        }
+        (None, None) => unreachable!("clap requires a range or a slot"),
+    };
+
+    fs::write(&dump.file, &bytes).map_err(|e| {
+        serialport::Error::new(
+            ErrorKind::Io(e.kind()),
+            format!("Could not write {}: {}", dump.file.display(), e),
+        )
+    })?;
+    writeln!(
+        session.out,
+        "Dumped {} bytes of {} to {} in {:.1?}",
+        bytes.len(),
+        source,
+        dump.file.display(),
+        start.elapsed()
+    )?;
+
+    Ok(())
+}
+
+pub fn verify(session: &mut Session, verify: &Verify) -> Result<(), Error> {
+    let image = fs::read(&verify.file).map_err(|e| {
+        serialport::Error::new(
+            ErrorKind::Io(e.kind()),
+            format!("Could not read {}: {}", verify.file.display(), e),
+        )
+    })?;
+
+    let start = Instant::now();
+    let source = match (verify.address, verify.slot) {
+        (_, Some(slot)) => {
+            let data = transfer::read_slot(session, slot, verify.options)?;
+            if let Some(offset) = first_mismatch(&data, &image) {
+                return Err(Error::Assertion(match data.get(offset) {
+                    Some(byte) => format!(
+                        "First mismatch at offset {:#x} of slot {}: the device holds {:#04x}, \
+                        the image {:#04x}",
+                        offset, slot, byte, image[offset]
+                    ),
+                    None => format!(
+                        "Slot {} ends after {} bytes, the image holds {}",
+                        slot,
+                        data.len(),
+                        image.len()
+                    ),
+                }));
+            }
+            if data.len() > image.len() {
+                return Err(Error::Assertion(format!(
+                    "Slot {} holds {} bytes, the image only {}",
+                    slot,
+                    data.len(),
+                    image.len()
+                )));
+            }
+            format!("slot {}", slot)
+        }
+        (Some(address), None) => {
+            if address as u64 + image.len() as u64 > 1 << 32 {
+                return Err(serialport::Error::new(
+                    ErrorKind::InvalidInput,
+                    format!(
+                        "{} bytes from {:#010x} reach beyond the address space",
+                        image.len(),
+                        address
+                    ),
+                )
+                .into());
+            }
+            let width = verify.width.width as usize;
+            for (offset, expected) in (0..).step_by(width).zip(image.chunks(width)) {
+                let word_address = address + offset as u32;
+                let word = memory::read(session, word_address, width as u8)?;
+                if let Some(i) = first_mismatch(&word, expected) {
+                    return Err(Error::Assertion(format!(
+                        "First mismatch at {:#010x}: the device holds {:#04x}, the image {:#04x}",
+                        word_address + i as u32,
+                        word[i],
+                        expected[i]
+                    )));
+                }
+            }
+            format!("{:#010x}", address)
+        }
+        (None, None) => unreachable!("clap requires an address or a slot"),
+    };
+
+    writeln!(
+        session.out,
+        "Verified {} bytes at {} in {:.1?}",
+        image.len(),
+        source,
+        start.elapsed()
+    )?;
+
+    Ok(())
+}
+
+/// Offset of the first byte of `image` which `data` doesn't hold
+fn first_mismatch(data: &[u8], image: &[u8]) -> Option<usize> {
+    image
+        .iter()
+        .enumerate()
+        .position(|(i, byte)| data.get(i) != Some(byte))
+}
+
+/// Parse a range of addresses as `<start>..<end>`, the end is excluded
+fn parse_range(s: &str) -> Result<Range<u32>, String> {
+    let (start, end) = s
+        .split_once("..")
+        .ok_or_else(|| format!("`{}` is no range, use <start>..<end>", s))?;
+    let range = parse_u32(start)?..parse_u32(end)?;
+    match range.is_empty() {
+        true => Err(format!("`{}` is an empty range", s)),
+        false => Ok(range),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mismatches() {
+        assert_eq!(first_mismatch(b"abcd", b"abcd"), None);
+        assert_eq!(first_mismatch(b"abcdef", b"abcd"), None);
+        assert_eq!(first_mismatch(b"abXd", b"abcd"), Some(2));
+        assert_eq!(first_mismatch(b"ab", b"abcd"), Some(2));
+        assert_eq!(first_mismatch(b"", b""), None);
+    }
+
+    #[test]
+    fn ranges() {
+        assert_eq!(
+            parse_range("0x08000000..0x08010000"),
+            Ok(0x0800_0000..0x0801_0000)
+        );
+        assert_eq!(parse_range("16..32"), Ok(16..32));
+        assert!(parse_range("0x10").is_err());
+        assert!(parse_range("32..16").is_err());
+        assert!(parse_range("16..16").is_err());
+        assert!(parse_range("16..0x1_0000_0000").is_err());
+    }
+}
@@ -0,0 +1,68 @@
+//! Membership of devices in groups.
+//!
+//! Messages to the address of a group, see [`crate::Address::group`], reach
+//! every device which joined it, like a broadcast to a part of the bus.
+//! Devices join and leave groups by the group join and leave opcodes with
+//! the group in the last SDU byte, answered with a status in the same place,
+//! non zero if the device can't join any more groups.
+
+use std::io::Write;
+
+use clap::{Args, Subcommand};
+use serialport::ErrorKind;
+
+use crate::{
+    parse_group,
+    sdu::{Response, Sdu},
+    session::Session,
+    L7Sdu, Opcode,
+};
+
+#[derive(Args, Debug, Clone, Copy)]
+pub struct Group {
+    #[command(subcommand)]
+    action: GroupAction,
+}
+
+#[derive(Subcommand, Debug, Clone, Copy)]
+pub enum GroupAction {
+    /// Make the device take messages to the group
+    Join {
+        #[arg(value_parser = parse_group)]
+        group: u8,
+    },
+    /// Make the device ignore messages to the group
+    Leave {
+        #[arg(value_parser = parse_group)]
+        group: u8,
+    },
+}
+
+pub fn run(session: &mut Session, group: &Group) -> Result<(), serialport::Error> {
+    let (opcode, number, verb) = match group.action {
+        GroupAction::Join { group } => (Opcode::GroupJoin, group, "joined"),
+        GroupAction::Leave { group } => (Opcode::GroupLeave, group, "left"),
+    };
+
+    let mut msg = [0u8; 16];
+    let sdu = L7Sdu::from_u8(7, number);
+    session.transact(session.builder(opcode, sdu), &mut msg)?;
+    if msg.sdu_u8(7) != 0 {
+        return Err(serialport::Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "Device {} rejected group {} (status {})",
+                session.id,
+                number,
+                msg.sdu_u8(7)
+            ),
+        ));
+    }
+
+    writeln!(
+        session.out,
+        "Device {} {} group {}",
+        session.id, verb, number
+    )?;
+    Ok(())
+}
@@ -0,0 +1,146 @@
+//! gzip members of compressed trace files, compressed by flate2.
+//!
+//! Every line appended to a [`Member`] is compressed and flushed at once, so
+//! it can be written before the member is finished. The member of a process
+//! which was killed never gets its trailer, reading it ends with its last
+//! flushed line and continues with the members appended after it.
+
+use std::io::{self, Read, Write};
+
+use flate2::{read::MultiGzDecoder, write::GzEncoder, Compression, GzBuilder};
+
+/// Header of the members of [`Member`], without modification time and of
+/// an unknown OS. A member which was never finished ends where the next one
+/// starts
+const HEADER: [u8; 10] = [0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 0xff];
+
+/// A gzip member being written
+pub struct Member {
+    encoder: GzEncoder<Vec<u8>>,
+    /// Uncompressed bytes appended to the member
+    pub len: usize,
+}
+
+impl Member {
+    pub fn new() -> Self {
+        Member {
+            encoder: GzBuilder::new()
+                .operating_system(HEADER[9])
+                .write(Vec::new(), Compression::default()),
+            len: 0,
+        }
+    }
+
+    /// Compress `data`, the returned bytes continue the member up to its end
+    pub fn append(&mut self, data: &[u8]) -> Vec<u8> {
+        // Writes to memory don't fail
+        self.encoder.write_all(data).expect("writing to memory");
+        self.encoder.flush().expect("writing to memory");
+        self.len += data.len();
+        std::mem::take(self.encoder.get_mut())
+    }
+
+    /// The bytes ending the member, its checksum and length
+    pub fn finish(self) -> Vec<u8> {
+        self.encoder.finish().expect("writing to memory")
+    }
+}
+
+/// The data of all gzip members in `data`, members which were never
+/// finished included
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>, String> {
+    if !data.starts_with(&[0x1f, 0x8b]) {
+        return Err("Not gzip data".to_owned());
+    }
+
+    let mut starts: Vec<usize> = (1..data.len().saturating_sub(HEADER.len() - 1))
+        .filter(|i| data[*i..].starts_with(&HEADER))
+        .collect();
+    starts.insert(0, 0);
+    starts.push(data.len());
+
+    let mut out = Vec::new();
+    for part in starts.windows(2) {
+        let start = out.len();
+        match MultiGzDecoder::new(&data[part[0]..part[1]]).read_to_end(&mut out) {
+            Ok(_) => (),
+            // The member of a killed process, its last line may be cut
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                let end = out[start..].iter().rposition(|b| *b == b'\n');
+                out.truncate(end.map_or(start, |end| start + end + 1));
+            }
+            Err(e) => return Err(e.to_string()),
+        }
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_hex;
+
+    const LINES: &[u8] = b"\
+        2026-10-15T08:00:00.000Z TX aa 05 00 01 00 64 00 00 00 00 00 00 00 01 95 55\n\
+        2026-10-15T08:00:00.012Z RX aa 00 05 01 00 64 00 00 00 00 00 00 00 01 95 55\n";
+
+    fn member(lines: &[&[u8]]) -> Vec<u8> {
+        let mut member = Member::new();
+        let mut out: Vec<u8> = lines.iter().flat_map(|line| member.append(line)).collect();
+        out.extend(member.finish());
+        out
+    }
+
+    #[test]
+    fn round_trip() {
+        let (first, second) = LINES.split_at(LINES.len() / 2 + 1);
+        let data = member(&[first, second]);
+        assert!(data.starts_with(&HEADER));
+        assert_eq!(decompress(&data).unwrap(), LINES);
+
+        let mut data = member(&[LINES]);
+        data.extend(member(&[b"", b"last\n"]));
+        assert_eq!(decompress(&data).unwrap(), [LINES, b"last\n"].concat());
+
+        let long: Vec<u8> = (0..100_000u32)
+            .flat_map(|i| format!("{}\n", i).into_bytes())
+            .collect();
+        assert_eq!(decompress(&member(&[&long])).unwrap(), long);
+    }
+
+    #[test]
+    fn unfinished_members() {
+        let mut killed = Member::new();
+        let mut data = killed.append(LINES);
+        data.extend(killed.append(b"2026-10-15T08:00:01.000Z TX aa"));
+        assert_eq!(decompress(&data).unwrap(), LINES);
+
+        // The trace was continued by another invocation
+        data.extend(member(&[b"continued\n"]));
+        assert_eq!(decompress(&data).unwrap(), [LINES, b"continued\n"].concat());
+    }
+
+    #[test]
+    fn system_gzip() {
+        // `gzip trace.txt`, which stores the file name and modification time
+        let data = parse_hex("1f8b08088054ef68000374726163652e7478740033323032d33534d035340d31b0b0323000223d0303832885900885c4440503530503030503431069660266632243054b530553532e236c66191a45290441cc32001b479c59000cc40b9898000000").unwrap();
+        assert_eq!(decompress(&data).unwrap(), LINES);
+        let mut twice = data.clone();
+        twice.extend(&data);
+        assert_eq!(decompress(&twice).unwrap(), [LINES, LINES].concat());
+    }
+
+    #[test]
+    fn corrupt_data() {
+        assert!(decompress(LINES).is_err());
+        let data = member(&[LINES]);
+        let mut corrupt = data.clone();
+        corrupt[12] ^= 0xff;
+        assert!(decompress(&corrupt).is_err());
+        // The checksum in the trailer
+        let mut corrupt = data.clone();
+        corrupt[data.len() - 8] ^= 0xff;
+        assert!(decompress(&corrupt).is_err());
+    }
+}
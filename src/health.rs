@@ -0,0 +1,120 @@
+//! Health of a device, as reported by itself.
+//!
+//! The status opcode is answered with flags in SDU byte 4, bit 0 for a
+//! brown-out and bit 1 for a reset by the watchdog since the last query,
+//! with the number of buffer overruns in bytes 5 and 6 (big endian) and the
+//! code of the last error in byte 7, 0 if none occurred.
+//!
+//! The uptime opcode is answered with the ticks since the last reset in the
+//! last four SDU bytes (big endian), a tick lasts a millisecond unless the
+//! firmware counts in other units.
+//!
+//! The link statistics opcode reads one of the counters of the device, 0 for
+//! received frames, 1 for frames with a bad checksum and 2 for dropped
+//! frames, given in SDU byte 4. A 1 in SDU byte 5 resets the counter once
+//! read. The answer carries the counter in the last four SDU bytes (big
+//! endian).
+
+use std::time::Duration;
+
+use clap::Args;
+
+use crate::{error::Error, parse_duration, sdu::Response, session::Session, L7Sdu, Opcode};
+
+#[derive(Args, Debug, Clone, Copy)]
+pub struct Uptime {
+    /// Length of a tick of the uptime counter, e.g. `10ms`
+    #[arg(long, default_value = "1ms", value_parser = parse_duration)]
+    pub tick: Duration,
+}
+
+#[derive(Args, Debug, Clone, Copy)]
+pub struct DeviceStats {
+    /// Reset the counters of the device after reading them
+    #[arg(long)]
+    pub clear: bool,
+}
+
+const BROWN_OUT: u8 = 1 << 0;
+const WATCHDOG_RESET: u8 = 1 << 1;
+
+/// Names of the counters of the link statistics opcode, by index
+pub const COUNTERS: [&str; 3] = ["received frames", "checksum errors", "dropped frames"];
+
+/// The answer of the status opcode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Status {
+    pub brown_out: bool,
+    pub watchdog_reset: bool,
+    pub buffer_overruns: u16,
+    /// Code of the last error, 0 if none occurred
+    pub last_error: u8,
+    /// Flags without a meaning known here
+    pub unknown_flags: u8,
+}
+
+impl Status {
+    pub fn parse(msg: &[u8; 16]) -> Self {
+        let flags = msg.sdu_u8(4);
+        Status {
+            brown_out: flags & BROWN_OUT != 0,
+            watchdog_reset: flags & WATCHDOG_RESET != 0,
+            buffer_overruns: u16::from_be_bytes([msg.sdu_u8(5), msg.sdu_u8(6)]),
+            last_error: msg.sdu_u8(7),
+            unknown_flags: flags & !(BROWN_OUT | WATCHDOG_RESET),
+        }
+    }
+}
+
+/// The value in the last four SDU bytes, the ticks of the uptime opcode or a
+/// counter of the link statistics opcode
+pub fn value(msg: &[u8; 16]) -> u32 {
+    u32::from_be_bytes([msg.sdu_u8(4), msg.sdu_u8(5), msg.sdu_u8(6), msg.sdu_u8(7)])
+}
+
+pub fn status(session: &mut Session) -> Result<Vec<[u8; 16]>, Error> {
+    let mut msg = [0u8; 16];
+    session.transact(session.builder(Opcode::Status, L7Sdu::default()), &mut msg)?;
+    Ok(vec![msg])
+}
+
+pub fn uptime(session: &mut Session) -> Result<Vec<[u8; 16]>, Error> {
+    let mut msg = [0u8; 16];
+    session.transact(session.builder(Opcode::Uptime, L7Sdu::default()), &mut msg)?;
+    Ok(vec![msg])
+}
+
+/// A duration like `2d 3h 4m 5.250s`, leaving out leading zero units
+pub fn humanize(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    let units = [
+        (secs / 86_400, "d"),
+        (secs / 3_600 % 24, "h"),
+        (secs / 60 % 60, "m"),
+    ];
+    let mut out: Vec<String> = units
+        .iter()
+        .skip_while(|(value, _)| *value == 0)
+        .map(|(value, unit)| format!("{}{}", value, unit))
+        .collect();
+    out.push(format!("{}.{:03}s", secs % 60, duration.subsec_millis()));
+    out.join(" ")
+}
+
+/// Read all counters, the responses are in the order of [`COUNTERS`]
+pub fn stats(session: &mut Session, stats: &DeviceStats) -> Result<Vec<[u8; 16]>, Error> {
+    let mut responses = Vec::new();
+    for index in 0..COUNTERS.len() {
+        let mut sdu = L7Sdu::default();
+        sdu[4] = index as u8;
+        sdu[5] = stats.clear as u8;
+        let mut msg = [0u8; 16];
+        session.transact(session.builder(Opcode::LinkStats, sdu), &mut msg)?;
+        responses.push(msg);
+    }
+    if stats.clear {
+        eprintln!("Cleared the counters of device {}", session.id);
+    }
+
+    Ok(responses)
+}
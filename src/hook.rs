@@ -0,0 +1,156 @@
+//! External programs run on frames of interest.
+//!
+//! A condition like `opcode==101 && from>=10` compares fields of a frame,
+//! the fields are `to`, `from`, `version`, `hops`, `opcode` and `sdu0` to
+//! `sdu7`. The command of a hook is split into words like a macro, `{json}`
+//! in a word is replaced by the frame as JSON, which is also written to the
+//! standard input of the program. Up to `--max-hooks` programs run at the
+//! same time, frames arriving meanwhile are skipped and counted.
+
+use std::{
+    io::Write,
+    process::{Command, Stdio},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+};
+
+use serialport::ErrorKind;
+
+use crate::{config::split_words, describe_json, script::Comparison, ChecksumAlgorithm};
+
+/// Comparisons of frame fields which all have to hold
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Condition(Vec<(usize, Comparison, u8)>);
+
+impl Condition {
+    pub fn matches(&self, frame: &[u8; 16]) -> bool {
+        self.0
+            .iter()
+            .all(|(index, op, value)| op.eval(frame[*index] as u64, *value as u64))
+    }
+}
+
+pub fn parse_condition(s: &str) -> Result<Condition, String> {
+    let usage = || {
+        format!(
+            "`{}` is no condition like `opcode==101 && from>=10`, the fields are \
+            to, from, version, hops, opcode and sdu0 to sdu7",
+            s
+        )
+    };
+
+    s.split("&&")
+        .map(|clause| {
+            let clause = clause.trim();
+            let op_start = clause.find(['=', '!', '<', '>']).ok_or_else(usage)?;
+            let op_end = clause[op_start..]
+                .find(|c| !matches!(c, '=' | '!' | '<' | '>'))
+                .map_or(clause.len(), |i| op_start + i);
+            let index = match clause[..op_start].trim() {
+                "to" => 1,
+                "from" => 2,
+                "version" => 3,
+                "hops" => 4,
+                "opcode" => 5,
+                field => match field
+                    .strip_prefix("sdu")
+                    .and_then(|i| i.parse::<usize>().ok())
+                {
+                    Some(i) if i < 8 => 6 + i,
+                    _ => return Err(usage()),
+                },
+            };
+            let op = Comparison::parse(&clause[op_start..op_end]).ok_or_else(usage)?;
+            let value = crate::parse_u8(clause[op_end..].trim())?;
+            Ok((index, op, value))
+        })
+        .collect::<Result<_, _>>()
+        .map(Condition)
+}
+
+/// The program run for frames, as `--exec` of the monitor
+pub struct Hook {
+    command: String,
+    algorithm: ChecksumAlgorithm,
+    /// Programs started and not finished yet
+    running: Arc<AtomicUsize>,
+    max_running: usize,
+    /// Frames the program wasn't run for, as `max_running` were running
+    pub skipped: u64,
+    /// Whether the last frame was skipped, so a run of them is reported once
+    skipping: bool,
+}
+
+impl Hook {
+    /// The hook running `command`, `{json}` holds the checksum verified with
+    /// `algorithm`
+    pub fn new(command: &str, algorithm: ChecksumAlgorithm, max_running: usize) -> Self {
+        Self {
+            command: command.to_owned(),
+            algorithm,
+            running: Arc::new(AtomicUsize::new(0)),
+            max_running,
+            skipped: 0,
+            skipping: false,
+        }
+    }
+
+    /// Start the program for the frame without waiting for it, failures are
+    /// reported on stderr. While `max_running` programs are running the frame
+    /// is skipped
+    pub fn run(&mut self, frame: &[u8; 16]) -> Result<(), serialport::Error> {
+        let running = self.running.load(Ordering::Acquire);
+        if running >= self.max_running {
+            if !self.skipping {
+                eprintln!("Skipping the hook for frames while {} are running", running);
+            }
+            self.skipping = true;
+            self.skipped += 1;
+            return Ok(());
+        }
+        self.skipping = false;
+
+        let json = describe_json(frame, self.algorithm);
+        let invalid = |msg: String| serialport::Error::new(ErrorKind::InvalidInput, msg);
+        let words: Vec<String> = split_words(&self.command)
+            .map_err(invalid)?
+            .into_iter()
+            .map(|w| w.replace("{json}", &json))
+            .collect();
+        let (program, args) = words
+            .split_first()
+            .ok_or_else(|| invalid("The hook command is empty".to_owned()))?;
+
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .spawn()
+            .map_err(|e| {
+                serialport::Error::new(
+                    ErrorKind::Io(e.kind()),
+                    format!("Could not run hook `{}`: {}", program, e),
+                )
+            })?;
+        self.running.fetch_add(1, Ordering::AcqRel);
+        let (program, running) = (program.clone(), self.running.clone());
+        thread::spawn(move || {
+            if let Some(mut stdin) = child.stdin.take() {
+                // The program may not read its input at all
+                let _ = writeln!(stdin, "{}", json);
+            }
+            match child.wait() {
+                Ok(status) if !status.success() => {
+                    eprintln!("Hook `{}` failed: {}", program, status)
+                }
+                Err(e) => eprintln!("Hook `{}` failed: {}", program, e),
+                Ok(_) => (),
+            }
+            running.fetch_sub(1, Ordering::AcqRel);
+        });
+
+        Ok(())
+    }
+}
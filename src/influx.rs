@@ -0,0 +1,48 @@
+//! InfluxDB line protocol for long term telemetry storage.
+//!
+//! Every frame is a point of the measurement `mmcp` tagged with the sending
+//! device and the opcode. The fields are the header fields, the SDU bytes and
+//! the decoded values of known opcodes, `led` of LED and `button_presses` of
+//! button press frames.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{sdu::Response, ChecksumAlgorithm, Opcode};
+
+/// The frame as a line without the trailing newline, the checksum verified
+/// with `algorithm`
+pub fn line(frame: &[u8; 16], time: SystemTime, algorithm: ChecksumAlgorithm) -> String {
+    let mut fields = vec![
+        format!("to={}i", frame[1]),
+        format!("version={}i", frame[3]),
+        format!("hops={}i", frame[4]),
+    ];
+    fields.extend(
+        frame
+            .sdu()
+            .iter()
+            .enumerate()
+            .map(|(i, b)| format!("sdu{}={}i", i, b)),
+    );
+    fields.push(format!(
+        "checksum_ok={}",
+        algorithm.compute(&frame[1..14]) == frame[14]
+    ));
+    match Opcode::from_byte(frame[5]) {
+        Opcode::SetLed => fields.push(format!("led={}", frame.sdu_u8(7) == 1)),
+        Opcode::ReadButtonPresses => fields.push(format!("button_presses={}i", frame.sdu_u8(7))),
+        _ => (),
+    }
+
+    let nanos = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    format!(
+        "mmcp,device={},opcode={} {} {}",
+        frame[2],
+        frame[5],
+        fields.join(","),
+        nanos
+    )
+}
@@ -0,0 +1,403 @@
+//! Shared secret management for devices using authenticated frames.
+//!
+//! Keys are stored per device id in a key file, one `<id> <hex key>` pair per
+//! line. The file is referenced by `key_file` in the `[auth]` table of the
+//! configuration and defaults to `keys` next to the configuration. It is
+//! refused if other users may access it.
+//!
+//! A new key is provisioned over the pairing opcode, which the firmware only
+//! accepts while it has no key, so pairing sends it in the clear and should
+//! be done on a bus nobody else listens to. Rotating sends the new key in
+//! frames signed with the current one, encrypted with it: chunks from offset
+//! [`NONCE_OFFSET`] on carry [`NONCE_LEN`] random bytes first, the key is
+//! then XORed with HMAC-SHA256 of the current key over `mmcp-rotate` and
+//! those bytes. The nonce is wide enough that rotations with the same
+//! current key don't repeat it, so recorded frames of one rotation don't
+//! reveal the key of another. In both cases the key is transferred in chunks
+//! of three bytes with the chunk offset in front of them, and the device
+//! reports a non zero status in the last SDU byte if it rejects a chunk.
+//! Keys and nonces come from the random source of the operating system.
+
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    path::PathBuf,
+};
+
+use clap::{Args, Subcommand};
+use serialport::ErrorKind;
+
+use crate::{
+    auth, config::Config, parse_auth_key, sdu::Response, session::Session, AuthKey, L7Sdu, Opcode,
+};
+
+/// Length of the shared secret stored on the device
+pub const KEY_LEN: usize = 16;
+
+const CHUNK_LEN: usize = 3;
+
+/// Chunk offset of the nonce a rotation starts with, its further chunks
+/// follow at the offsets of their bytes like those of the key
+const NONCE_OFFSET: u8 = 0xf0;
+/// Random bytes of the nonce of a rotation
+pub const NONCE_LEN: usize = 12;
+
+#[derive(Args, Debug, Clone)]
+pub struct Key {
+    #[command(subcommand)]
+    action: KeyAction,
+}
+
+impl Key {
+    /// Whether the action shows the state of the keys, rather than changing
+    /// them
+    pub fn is_status(&self) -> bool {
+        matches!(self.action, KeyAction::Status)
+    }
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum KeyAction {
+    /// Pair an unsecured device by provisioning a new shared secret
+    Set(NewKey),
+    /// Replace the shared secret of an already paired device
+    Rotate(NewKey),
+    /// Show the locally stored key and whether the device is paired
+    Status,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct NewKey {
+    /// Key as hex string, a random key is generated if omitted
+    #[arg(long, value_parser = parse_auth_key)]
+    key: Option<AuthKey>,
+}
+
+pub fn run(session: &mut Session, key: &Key, msg: &mut [u8; 16]) -> Result<(), serialport::Error> {
+    let key_file = KeyFile::from_config(&session.config)?;
+    let id = session.id;
+    match &key.action {
+        KeyAction::Set(NewKey { key }) => {
+            let key = new_key(key.clone())?;
+            // The device has no key yet, so pairing frames are sent unauthenticated
+            let current = session.args.auth_key.take();
+            let result = transfer_key(session, Opcode::Pair, &key, msg);
+            session.args.auth_key = current;
+            result?;
+
+            key_file.set(id, &key)?;
+            session.reset_counters()?;
+            writeln!(
+                session.out,
+                "Device {} paired, key stored in {}",
+                id,
+                key_file.path.display()
+            )?;
+        }
+        KeyAction::Rotate(NewKey { key }) => {
+            if session.args.auth_key.is_none() {
+                return Err(serialport::Error::new(
+                    ErrorKind::InvalidInput,
+                    format!(
+                        "No key known for device {}, pair it with `key set` first",
+                        id
+                    ),
+                ));
+            }
+
+            let key = new_key(key.clone())?;
+            let current = session.args.auth_key.clone().expect("checked above").0;
+            let nonce = random(NONCE_LEN)?;
+            let encrypted = encrypt(&current, &nonce, &key);
+            let nonce_chunks = nonce
+                .chunks(CHUNK_LEN)
+                .enumerate()
+                .map(|(i, chunk)| (NONCE_OFFSET + (i * CHUNK_LEN) as u8, chunk));
+            transfer_chunks(session, Opcode::RotateKey, nonce_chunks, msg)?;
+            transfer_key(session, Opcode::RotateKey, &encrypted, msg)?;
+            key_file.set(id, &key)?;
+            session.reset_counters()?;
+            writeln!(
+                session.out,
+                "Key of device {} rotated, stored in {}",
+                id,
+                key_file.path.display()
+            )?;
+        }
+        KeyAction::Status => {
+            match key_file.get(id)? {
+                Some(key) => writeln!(
+                    session.out,
+                    "Local key: {} (fingerprint {})",
+                    key_file.path.display(),
+                    fingerprint(&key)
+                )?,
+                None => writeln!(session.out, "Local key: none")?,
+            }
+
+            let builder = session.builder(Opcode::KeyStatus, L7Sdu::default());
+            session.transact(builder, msg)?;
+            let paired = if msg.sdu_u8(7) != 0 {
+                "paired"
+            } else {
+                "unpaired"
+            };
+            writeln!(session.out, "Device: {}", paired)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn transfer_key(
+    session: &mut Session,
+    opcode: Opcode,
+    key: &[u8],
+    msg: &mut [u8; 16],
+) -> Result<(), serialport::Error> {
+    let chunks = key
+        .chunks(CHUNK_LEN)
+        .enumerate()
+        .map(|(i, chunk)| ((i * CHUNK_LEN) as u8, chunk));
+    transfer_chunks(session, opcode, chunks, msg)
+}
+
+/// Send chunks with their offsets, stopping at the first the device rejects
+fn transfer_chunks<'a>(
+    session: &mut Session,
+    opcode: Opcode,
+    chunks: impl IntoIterator<Item = (u8, &'a [u8])>,
+    msg: &mut [u8; 16],
+) -> Result<(), serialport::Error> {
+    for (offset, chunk) in chunks {
+        let mut sdu = L7Sdu::default();
+        sdu[auth::TAG_LEN] = offset;
+        sdu[auth::TAG_LEN + 1..auth::TAG_LEN + 1 + chunk.len()].copy_from_slice(chunk);
+
+        let builder = session.builder(opcode, sdu);
+        session.transact(builder, msg)?;
+        if msg.sdu_u8(7) != 0 {
+            return Err(serialport::Error::new(
+                ErrorKind::InvalidInput,
+                format!("Device rejected the key (status {})", msg.sdu_u8(7)),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// The new key encrypted with the current one, for the device to decrypt
+/// with the nonce sent before
+fn encrypt(current: &[u8], nonce: &[u8], key: &[u8]) -> Vec<u8> {
+    let stream = auth::hmac_sha256(current, &[b"mmcp-rotate", nonce]);
+    key.iter().zip(stream).map(|(k, s)| k ^ s).collect()
+}
+
+fn random(len: usize) -> Result<Vec<u8>, serialport::Error> {
+    let mut bytes = vec![0u8; len];
+    getrandom::getrandom(&mut bytes).map_err(|e| {
+        serialport::Error::new(
+            ErrorKind::Io(std::io::ErrorKind::Other),
+            format!(
+                "Could not generate random bytes ({}), pass a key with --key",
+                e
+            ),
+        )
+    })?;
+    Ok(bytes)
+}
+
+fn new_key(key: Option<AuthKey>) -> Result<Vec<u8>, serialport::Error> {
+    let key = match key {
+        Some(AuthKey(key)) => key,
+        None => random(KEY_LEN)?,
+    };
+
+    if key.len() != KEY_LEN {
+        return Err(serialport::Error::new(
+            ErrorKind::InvalidInput,
+            format!("Keys must be {} bytes long, got {}", KEY_LEN, key.len()),
+        ));
+    }
+
+    Ok(key)
+}
+
+fn fingerprint(key: &[u8]) -> String {
+    auth::sha256(key)[..4]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+#[derive(Debug, Clone)]
+pub struct KeyFile {
+    pub path: PathBuf,
+}
+
+impl KeyFile {
+    pub fn from_config(config: &Config) -> Result<Self, serialport::Error> {
+        let path = match config.get("auth", "key_file").and_then(|v| v.as_str()) {
+            Some(path) => config.resolve_path(path),
+            None => crate::config::config_dir()
+                .ok_or_else(|| {
+                    serialport::Error::new(
+                        ErrorKind::InvalidInput,
+                        "No configuration directory found, set `key_file` in the [auth] table",
+                    )
+                })?
+                .join("keys"),
+        };
+
+        Ok(Self { path })
+    }
+
+    /// Look up the key stored for a device id
+    pub fn get(&self, id: u8) -> Result<Option<Vec<u8>>, serialport::Error> {
+        Ok(self
+            .entries()?
+            .into_iter()
+            .find(|(entry_id, _)| *entry_id == id)
+            .map(|(_, key)| key))
+    }
+
+    /// Store the key for a device id, replacing an existing one
+    pub fn set(&self, id: u8, key: &[u8]) -> Result<(), serialport::Error> {
+        let mut entries = self.entries()?;
+        entries.retain(|(entry_id, _)| *entry_id != id);
+        entries.push((id, key.to_vec()));
+        entries.sort_by_key(|(id, _)| *id);
+
+        let contents: String = entries
+            .iter()
+            .map(|(id, key)| {
+                let hex: String = key.iter().map(|b| format!("{:02x}", b)).collect();
+                format!("{} {}\n", id, hex)
+            })
+            .collect();
+
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir).map_err(|e| self.io_error(e))?;
+        }
+
+        // Write to a new file first, a torn write would lose the keys of
+        // other devices. Created afresh, so a stale one can't leak them
+        let tmp = self.path.with_extension("tmp");
+        let _ = fs::remove_file(&tmp);
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+
+        options
+            .open(&tmp)
+            .and_then(|mut f| {
+                f.write_all(contents.as_bytes())?;
+                f.sync_all()
+            })
+            .and_then(|_| fs::rename(&tmp, &self.path))
+            .map_err(|e| self.io_error(e))
+    }
+
+    fn entries(&self) -> Result<Vec<(u8, Vec<u8>)>, serialport::Error> {
+        let contents = match fs::read_to_string(&self.path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(self.io_error(e)),
+        };
+        self.check_permissions()?;
+
+        contents
+            .lines()
+            .enumerate()
+            .map(|(i, line)| (i, line.trim()))
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+            .map(|(i, line)| {
+                line.split_once(char::is_whitespace)
+                    .and_then(|(id, key)| {
+                        Some((id.parse().ok()?, crate::parse_hex(key.trim()).ok()?))
+                    })
+                    .ok_or_else(|| {
+                        serialport::Error::new(
+                            ErrorKind::InvalidInput,
+                            format!(
+                                "{}:{}: Expected `<id> <hex key>`",
+                                self.path.display(),
+                                i + 1
+                            ),
+                        )
+                    })
+            })
+            .collect()
+    }
+
+    #[cfg(unix)]
+    fn check_permissions(&self) -> Result<(), serialport::Error> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mode = fs::metadata(&self.path)
+            .map_err(|e| self.io_error(e))?
+            .permissions()
+            .mode();
+        if mode & 0o077 != 0 {
+            return Err(serialport::Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Key file {} is accessible by other users (mode {:o}), restrict it with `chmod 600`",
+                    self.path.display(),
+                    mode & 0o777
+                ),
+            ));
+        }
+
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    fn check_permissions(&self) -> Result<(), serialport::Error> {
+        Ok(())
+    }
+
+    fn io_error(&self, e: std::io::Error) -> serialport::Error {
+        serialport::Error::new(
+            ErrorKind::Io(e.kind()),
+            format!("Key file {}: {}", self.path.display(), e),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_file_is_replaced() {
+        let dir = std::env::temp_dir().join(format!("mmcp-keys-{}", std::process::id()));
+        let file = KeyFile {
+            path: dir.join("keys"),
+        };
+        file.set(7, &[1; KEY_LEN]).unwrap();
+        file.set(3, &[2; KEY_LEN]).unwrap();
+        file.set(7, &[3; KEY_LEN]).unwrap();
+        assert_eq!(file.get(7).unwrap(), Some(vec![3; KEY_LEN]));
+        assert_eq!(file.get(3).unwrap(), Some(vec![2; KEY_LEN]));
+        assert!(!file.path.with_extension("tmp").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn rotated_keys_are_encrypted() {
+        let (current, nonce, key) = ([1; KEY_LEN], [1; NONCE_LEN], [0xaa; KEY_LEN]);
+        let encrypted = encrypt(&current, &nonce, &key);
+        assert_ne!(encrypted, key);
+        let mut other = nonce;
+        other[NONCE_LEN - 1] = 2;
+        assert_ne!(encrypted, encrypt(&current, &other, &key));
+        // The device decrypts by the same operation
+        assert_eq!(encrypt(&current, &nonce, &encrypted), key);
+    }
+}
@@ -0,0 +1,116 @@
+//! Text on the character LCD of a device.
+//!
+//! Text is written in chunks by the LCD write opcode, with the line in SDU
+//! byte 0, the column of the first character in byte 1 and up to six ASCII
+//! characters in bytes 2 to 7. Zero bytes after the characters of the last
+//! chunk are not written. Written text is shown once the LCD commit opcode
+//! arrives, so the display never shows half a message.
+
+use clap::Args;
+
+use crate::{session::Session, L7Sdu, Opcode};
+
+/// Characters in the SDU of a frame
+const PER_FRAME: usize = 6;
+
+#[derive(Args, Debug, Clone)]
+pub struct LcdWrite {
+    /// Text to write, in ASCII
+    #[arg(value_parser = parse_text)]
+    text: String,
+    /// Line of the display to write to
+    #[arg(long, default_value_t = 0)]
+    line: u8,
+}
+
+/// Write and commit the text, returning the answers to the chunks and the
+/// commit
+pub fn run(session: &mut Session, write: &LcdWrite) -> Result<Vec<[u8; 16]>, serialport::Error> {
+    let mut responses = Vec::new();
+    for sdu in chunks(write) {
+        let mut msg = [0u8; 16];
+        session.transact(session.builder(Opcode::LcdWrite, sdu), &mut msg)?;
+        responses.push(msg);
+    }
+    let mut msg = [0u8; 16];
+    session.transact(
+        session.builder(Opcode::LcdCommit, L7Sdu::default()),
+        &mut msg,
+    )?;
+    responses.push(msg);
+
+    if session.args.no_response {
+        responses.clear();
+    }
+    Ok(responses)
+}
+
+/// The SDUs writing the text, in the order they are sent
+fn chunks(write: &LcdWrite) -> Vec<L7Sdu> {
+    write
+        .text
+        .as_bytes()
+        .chunks(PER_FRAME)
+        .enumerate()
+        .map(|(i, chunk)| {
+            let mut sdu = L7Sdu::default();
+            sdu[0] = write.line;
+            sdu[1] = (i * PER_FRAME) as u8;
+            sdu[2..2 + chunk.len()].copy_from_slice(chunk);
+            sdu
+        })
+        .collect()
+}
+
+fn parse_text(s: &str) -> Result<String, String> {
+    if !s.bytes().all(|b| b.is_ascii() && b != 0) {
+        return Err(format!("`{}` contains characters other than ASCII", s));
+    }
+    // Columns are counted in a byte
+    match s.len() {
+        0..=256 => Ok(s.to_owned()),
+        _ => Err("The text is longer than 256 characters".to_owned()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(text: &str, line: u8) -> LcdWrite {
+        LcdWrite {
+            text: parse_text(text).unwrap(),
+            line,
+        }
+    }
+
+    #[test]
+    fn chunks_by_column() {
+        assert_eq!(chunks(&write("", 0)), Vec::<L7Sdu>::new());
+        assert_eq!(chunks(&write("Hi", 1)), [*b"\x01\x00Hi\0\0\0\0"]);
+        assert_eq!(
+            chunks(&write("Hello, world", 3)),
+            [*b"\x03\x00Hello,", *b"\x03\x06 world"]
+        );
+        assert_eq!(
+            chunks(&write("temperature", 0)),
+            [*b"\x00\x00temper", *b"\x00\x06ature\0"]
+        );
+    }
+
+    #[test]
+    fn columns_fit_a_byte() {
+        let longest = "x".repeat(256);
+        let chunks = chunks(&write(&longest, 0));
+        assert_eq!(chunks.len(), 43);
+        assert_eq!(chunks[42], *b"\x00\xfcxxxx\0\0");
+        assert!(parse_text(&"x".repeat(257)).is_err());
+    }
+
+    #[test]
+    fn only_ascii_text() {
+        assert!(parse_text("Grüße").is_err());
+        assert!(parse_text("a\0b").is_err());
+        assert!(parse_text("~ 100%").is_ok());
+    }
+}
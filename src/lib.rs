@@ -0,0 +1,804 @@
+//! The MMCP protocol: addresses, opcodes, and the building, decoding and
+//! checking of the 16 byte frames on the bus.
+//!
+//! The `mmcp_client_cli` binary of the `cli` feature is built on this
+//! library, without the feature the crate is only the library. Applications
+//! talking to devices themselves share a port with the blocking
+//! `client::MmcpClient` of the `sync` feature, on by default, or the
+//! `async_client::AsyncMmcpClient` of the `async` feature, over any of the
+//! [`transport`]s. SDUs are built and read by the values they carry with the
+//! traits of [`sdu`], transfers run through the sliding window of `window`,
+//! also of the `sync` feature. The protocol itself needs no dependencies.
+
+use std::{
+    fmt::{self, Display},
+    ops::RangeInclusive,
+};
+
+use sdu::Response;
+
+#[cfg(feature = "async")]
+pub mod async_client;
+#[cfg(feature = "sync")]
+pub mod client;
+#[cfg(any(feature = "sync", feature = "async"))]
+mod link;
+pub mod reader;
+pub mod sdu;
+pub mod transport;
+#[cfg(feature = "sync")]
+pub mod window;
+// The async client flashes through the window of the blocking transfers
+#[cfg(all(feature = "async", not(feature = "sync")))]
+mod window;
+
+/// The 8 bytes of a message carried for the application
+pub type L7Sdu = [u8; 8];
+
+/// Address of a participant of the bus
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
+pub struct Address(pub u8);
+
+impl Address {
+    /// The host, it sends but never receives messages
+    pub const HOST: Address = Address(0);
+    /// Devices not given an address yet
+    pub const UNASSIGNED: Address = Address(0xfe);
+    /// All devices of the bus
+    pub const BROADCAST: Address = Address(0xff);
+
+    /// First address of a group, see [`Address::group`]
+    const FIRST_GROUP: u8 = 0xf0;
+    /// Number of groups devices can join
+    pub const GROUPS: u8 = 14;
+
+    /// Address of a group, messages to it reach all devices which joined it.
+    /// `None` beyond the [`Address::GROUPS`] groups
+    pub fn group(group: u8) -> Option<Address> {
+        (group < Address::GROUPS).then(|| Address(Address::FIRST_GROUP + group))
+    }
+
+    /// The group of the address, if it is one
+    pub fn as_group(self) -> Option<u8> {
+        self.0
+            .checked_sub(Address::FIRST_GROUP)
+            .filter(|group| *group < Address::GROUPS)
+    }
+
+    /// Whether messages can't be sent to the address
+    pub fn is_reserved(self) -> bool {
+        self == Address::HOST
+    }
+}
+
+impl Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Address::UNASSIGNED => write!(f, "unassigned"),
+            Address::BROADCAST => write!(f, "broadcast"),
+            address if address.as_group().is_some() => {
+                write!(f, "group {}", address.0 - Address::FIRST_GROUP)
+            }
+            Address(address) => write!(f, "{}", address),
+        }
+    }
+}
+
+impl From<u8> for Address {
+    fn from(address: u8) -> Self {
+        Address(address)
+    }
+}
+
+impl From<Address> for u8 {
+    fn from(address: Address) -> Self {
+        address.0
+    }
+}
+
+/// Protocol versions devices understand
+pub const PROTOCOL_VERSIONS: RangeInclusive<u8> = 1..=4;
+/// Opcodes of the application, of key management, of transfers, of
+/// debugging, of telemetry and of peripherals
+pub const OPCODE_RANGES: [RangeInclusive<u8>; 6] = [
+    100..=109,
+    110..=119,
+    120..=129,
+    130..=139,
+    140..=149,
+    150..=159,
+];
+
+/// Operation of a message, responses carry the opcode of their request.
+///
+/// [`Opcode::from_byte`] takes any byte of a frame, unknown ones become
+/// [`Opcode::Unknown`], while `TryFrom<u8>` rejects them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(into = "u8"))]
+pub enum Opcode {
+    /// Switch the LED, the state is the last SDU byte, 0 for off, 1 for on
+    /// and 2 to toggle it. The byte before selects one of several LEDs
+    SetLed,
+    /// Count of the button presses in the last SDU byte of the response
+    ReadButtonPresses,
+    /// The device answers with the unchanged SDU
+    Echo,
+    /// Features of the device, see the `capabilities` command
+    Capabilities,
+    /// Health flags and the last error of the device, see the `status` command
+    Status,
+    /// Ticks since the last reset of the device
+    Uptime,
+    /// A counter of the link statistics of the device
+    LinkStats,
+    /// Make the device take messages to a group, see [`Address::group`]
+    GroupJoin,
+    /// Make the device ignore messages to a group
+    GroupLeave,
+    /// Answer to a request the device refused, with its opcode and the reason
+    /// in the last two SDU bytes
+    Rejected,
+    /// Store a new key, transferred in chunks
+    Pair,
+    /// Replace the key, transferred in chunks like for pairing
+    RotateKey,
+    /// Whether the device is paired, in the last SDU byte of the response
+    KeyStatus,
+    /// Unique id of the device, in the last four SDU bytes of the response
+    ReadUid,
+    /// A chunk of a transfer
+    TransferData,
+    /// Completes a transfer with the length of the data
+    TransferEnd,
+    /// Starts a transfer from or to a slot of the device storage
+    TransferOpen,
+    /// Requests a chunk of a slot, answered like a chunk of a transfer
+    TransferRead,
+    /// Read a word of memory
+    Peek,
+    /// Write a byte of memory
+    PokeU8,
+    /// Write a half word of memory
+    PokeU16,
+    /// Write a word of memory
+    PokeU32,
+    /// Make the device push frames of an opcode periodically
+    Subscribe,
+    /// Whether the device is alive, answered with an empty SDU
+    Ping,
+    /// State of the LED in the last SDU byte of the response, 1 if it is on.
+    /// The LED is selected like for [`Opcode::SetLed`]
+    GetLed,
+    /// Settings of the buttons, see the `configure-button` command
+    ConfigureButton,
+    /// Sound the buzzer, see the `beep` command
+    Beep,
+    /// Digits of a number, see the `display-number` command
+    DisplayNumber,
+    /// A chunk of text for the LCD, see the `lcd-write` command
+    LcdWrite,
+    /// Show the text written to the LCD
+    LcdCommit,
+    /// Position of a servo, see the `set-servo` command
+    SetServo,
+    /// Switch a relay, see the `set-relay` command
+    SetRelay,
+    /// States of all relays as bit mask in the last four SDU bytes of the
+    /// response
+    GetRelays,
+    Unknown(u8),
+}
+
+impl Opcode {
+    /// Name for humans, if the opcode is known
+    pub fn name(self) -> Option<&'static str> {
+        Some(match self {
+            Opcode::SetLed => "set LED",
+            Opcode::ReadButtonPresses => "read button presses",
+            Opcode::Echo => "echo",
+            Opcode::Capabilities => "capabilities",
+            Opcode::Status => "status",
+            Opcode::Uptime => "uptime",
+            Opcode::LinkStats => "link statistics",
+            Opcode::GroupJoin => "join group",
+            Opcode::GroupLeave => "leave group",
+            Opcode::Rejected => "rejected",
+            Opcode::Pair => "pair",
+            Opcode::RotateKey => "rotate key",
+            Opcode::KeyStatus => "key status",
+            Opcode::ReadUid => "read UID",
+            Opcode::TransferData => "transfer data",
+            Opcode::TransferEnd => "transfer end",
+            Opcode::TransferOpen => "transfer open",
+            Opcode::TransferRead => "transfer read",
+            Opcode::Peek => "peek",
+            Opcode::PokeU8 => "poke byte",
+            Opcode::PokeU16 => "poke half word",
+            Opcode::PokeU32 => "poke word",
+            Opcode::Subscribe => "subscribe",
+            Opcode::Ping => "ping",
+            Opcode::GetLed => "get LED",
+            Opcode::ConfigureButton => "configure button",
+            Opcode::Beep => "beep",
+            Opcode::DisplayNumber => "display number",
+            Opcode::LcdWrite => "LCD write",
+            Opcode::LcdCommit => "LCD commit",
+            Opcode::SetServo => "set servo",
+            Opcode::SetRelay => "set relay",
+            Opcode::GetRelays => "get relays",
+            Opcode::Unknown(_) => return None,
+        })
+    }
+
+    /// The opcode of a byte, [`Opcode::Unknown`] if none is defined for it
+    pub fn from_byte(opcode: u8) -> Self {
+        match opcode {
+            100 => Opcode::SetLed,
+            101 => Opcode::ReadButtonPresses,
+            102 => Opcode::Echo,
+            103 => Opcode::Capabilities,
+            104 => Opcode::Status,
+            105 => Opcode::Uptime,
+            106 => Opcode::LinkStats,
+            107 => Opcode::GroupJoin,
+            108 => Opcode::GroupLeave,
+            109 => Opcode::Rejected,
+            110 => Opcode::Pair,
+            111 => Opcode::RotateKey,
+            112 => Opcode::KeyStatus,
+            113 => Opcode::ReadUid,
+            120 => Opcode::TransferData,
+            121 => Opcode::TransferEnd,
+            122 => Opcode::TransferOpen,
+            123 => Opcode::TransferRead,
+            130 => Opcode::Peek,
+            131 => Opcode::PokeU8,
+            132 => Opcode::PokeU16,
+            133 => Opcode::PokeU32,
+            140 => Opcode::Subscribe,
+            141 => Opcode::Ping,
+            150 => Opcode::GetLed,
+            151 => Opcode::ConfigureButton,
+            152 => Opcode::Beep,
+            153 => Opcode::DisplayNumber,
+            154 => Opcode::LcdWrite,
+            155 => Opcode::LcdCommit,
+            156 => Opcode::SetServo,
+            157 => Opcode::SetRelay,
+            158 => Opcode::GetRelays,
+            opcode => Opcode::Unknown(opcode),
+        }
+    }
+}
+
+/// Rejects bytes no opcode is defined for
+impl TryFrom<u8> for Opcode {
+    type Error = UnknownOpcode;
+
+    fn try_from(opcode: u8) -> Result<Self, Self::Error> {
+        match Opcode::from_byte(opcode) {
+            Opcode::Unknown(opcode) => Err(UnknownOpcode(opcode)),
+            opcode => Ok(opcode),
+        }
+    }
+}
+
+/// A byte no [`Opcode`] is defined for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnknownOpcode(pub u8);
+
+impl Display for UnknownOpcode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "No opcode is defined for {}", self.0)
+    }
+}
+
+impl std::error::Error for UnknownOpcode {}
+
+/// Frames keep opcodes unknown to this version of the crate
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Opcode {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        u8::deserialize(deserializer).map(Opcode::from_byte)
+    }
+}
+
+impl From<Opcode> for u8 {
+    fn from(opcode: Opcode) -> Self {
+        match opcode {
+            Opcode::SetLed => 100,
+            Opcode::ReadButtonPresses => 101,
+            Opcode::Echo => 102,
+            Opcode::Capabilities => 103,
+            Opcode::Status => 104,
+            Opcode::Uptime => 105,
+            Opcode::LinkStats => 106,
+            Opcode::GroupJoin => 107,
+            Opcode::GroupLeave => 108,
+            Opcode::Rejected => 109,
+            Opcode::Pair => 110,
+            Opcode::RotateKey => 111,
+            Opcode::KeyStatus => 112,
+            Opcode::ReadUid => 113,
+            Opcode::TransferData => 120,
+            Opcode::TransferEnd => 121,
+            Opcode::TransferOpen => 122,
+            Opcode::TransferRead => 123,
+            Opcode::Peek => 130,
+            Opcode::PokeU8 => 131,
+            Opcode::PokeU16 => 132,
+            Opcode::PokeU32 => 133,
+            Opcode::Subscribe => 140,
+            Opcode::Ping => 141,
+            Opcode::GetLed => 150,
+            Opcode::ConfigureButton => 151,
+            Opcode::Beep => 152,
+            Opcode::DisplayNumber => 153,
+            Opcode::LcdWrite => 154,
+            Opcode::LcdCommit => 155,
+            Opcode::SetServo => 156,
+            Opcode::SetRelay => 157,
+            Opcode::GetRelays => 158,
+            Opcode::Unknown(opcode) => opcode,
+        }
+    }
+}
+
+/// State [`Opcode::SetLed`] switches an LED to, its last SDU byte
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum LedState {
+    On,
+    Off,
+    /// Switch the LED off if it is on and on otherwise
+    Toggle,
+}
+
+impl From<LedState> for u8 {
+    fn from(state: LedState) -> Self {
+        match state {
+            LedState::Off => 0,
+            LedState::On => 1,
+            LedState::Toggle => 2,
+        }
+    }
+}
+
+/// A field of a message which devices would drop it for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameError {
+    ReservedAddress(Address),
+    UnknownVersion(u8),
+    UnknownOpcode(u8),
+}
+
+impl Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FrameError::ReservedAddress(to) => {
+                write!(f, "Address {} is reserved and can't be sent to", to)
+            }
+            FrameError::UnknownVersion(version) => write!(
+                f,
+                "Protocol version {} is unknown, devices speak {} to {}",
+                version,
+                PROTOCOL_VERSIONS.start(),
+                PROTOCOL_VERSIONS.end()
+            ),
+            FrameError::UnknownOpcode(opcode) => write!(
+                f,
+                "Opcode {} is outside the defined ranges {:?}, send it with `raw`",
+                opcode, OPCODE_RANGES
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "serial")]
+impl From<FrameError> for serialport::Error {
+    fn from(e: FrameError) -> Self {
+        serialport::Error::new(serialport::ErrorKind::InvalidInput, e.to_string())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MsgBuilder {
+    pub to: Address,
+    pub from: Address,
+    pub hops: u8,
+    pub version: u8,
+    pub opcode: Opcode,
+    pub l7_sdu: [u8; 8],
+}
+
+impl MsgBuilder {
+    pub fn new(to: u8, opcode: Opcode, l7_sdu: L7Sdu) -> Self {
+        Self {
+            to: Address(to),
+            from: Address::HOST,
+            version: 4,
+            hops: 0,
+            opcode,
+            l7_sdu,
+        }
+    }
+
+    pub fn build(self) -> [u8; 16] {
+        self.build_with(ChecksumAlgorithm::Sum)
+    }
+
+    /// Build the message, unless a field would make devices drop it
+    pub fn try_build(self) -> Result<[u8; 16], FrameError> {
+        self.validate()?;
+        Ok(self.build())
+    }
+
+    /// Check the fields devices drop messages for
+    pub fn validate(&self) -> Result<(), FrameError> {
+        if self.to.is_reserved() {
+            return Err(FrameError::ReservedAddress(self.to));
+        }
+        if !PROTOCOL_VERSIONS.contains(&self.version) {
+            return Err(FrameError::UnknownVersion(self.version));
+        }
+        let opcode = u8::from(self.opcode);
+        if !OPCODE_RANGES.iter().any(|r| r.contains(&opcode)) {
+            return Err(FrameError::UnknownOpcode(opcode));
+        }
+
+        Ok(())
+    }
+
+    /// Build the message with the checksum computed by `algorithm`
+    pub fn build_with(self, algorithm: ChecksumAlgorithm) -> [u8; 16] {
+        let frame = self.build_with_checksum(0);
+        self.build_with_checksum(algorithm.compute(&frame[1..14]))
+    }
+
+    pub fn build_with_checksum(self, check_sum: u8) -> [u8; 16] {
+        [
+            0,
+            self.to.0,
+            self.from.0,
+            self.version,
+            self.hops,
+            self.opcode.into(),
+            self.l7_sdu[0],
+            self.l7_sdu[1],
+            self.l7_sdu[2],
+            self.l7_sdu[3],
+            self.l7_sdu[4],
+            self.l7_sdu[5],
+            self.l7_sdu[6],
+            self.l7_sdu[7],
+            check_sum,
+            0,
+        ]
+    }
+}
+
+/// Fields of a frame. With the `serde` feature frames and [`MsgBuilder`]s
+/// are serialized as their fields, addresses and opcodes as numbers and the
+/// SDU as 8 numbers, and unknown opcodes are kept
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Frame {
+    pub to: Address,
+    pub from: Address,
+    pub version: u8,
+    pub hops: u8,
+    pub opcode: Opcode,
+    pub sdu: L7Sdu,
+    pub checksum: u8,
+}
+
+impl Frame {
+    /// Whether the checksum is the one `algorithm` computes over the header
+    /// and SDU bytes
+    pub fn checksum_ok(&self, algorithm: ChecksumAlgorithm) -> bool {
+        let bytes: [u8; 16] = (*self).into();
+        algorithm.compute(&bytes[1..14]) == self.checksum
+    }
+}
+
+/// The fields on one line like `[5 <- 0] v4 op=SetLed sdu=00..01`, the hop
+/// count only if it isn't 0. Leading zero bytes of the SDU are shortened to
+/// `00..`
+impl Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{} <- {}] v{}", self.to, self.from, self.version)?;
+        if self.hops != 0 {
+            write!(f, " hops={}", self.hops)?;
+        }
+        match self.opcode {
+            Opcode::Unknown(op) => write!(f, " op={}", op)?,
+            op => write!(f, " op={:?}", op)?,
+        }
+
+        f.write_str(" sdu=")?;
+        let mut sdu = &self.sdu[..];
+        let zeros = sdu.iter().take_while(|b| **b == 0).count();
+        if zeros > 1 {
+            f.write_str("00..")?;
+            sdu = &sdu[zeros.min(sdu.len() - 1)..];
+        }
+        for b in sdu {
+            write!(f, "{:02x}", b)?;
+        }
+        Ok(())
+    }
+}
+
+/// The fields of a frame like its [`Frame`] followed by the checksum
+/// verified by the algorithm, `[5 <- 0] v4 op=SetLed sdu=00..01 crc=OK`, see
+/// [`describe`]
+pub struct FrameText<'a>(pub &'a [u8; 16], pub ChecksumAlgorithm);
+
+impl Display for FrameText<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let frame = Frame::from(*self.0);
+        match frame.checksum_ok(self.1) {
+            true => write!(f, "{} crc=OK", frame),
+            false => write!(f, "{} crc=BAD", frame),
+        }
+    }
+}
+
+impl From<[u8; 16]> for Frame {
+    fn from(frame: [u8; 16]) -> Self {
+        Frame {
+            to: Address(frame[1]),
+            from: Address(frame[2]),
+            version: frame[3],
+            hops: frame[4],
+            opcode: Opcode::from_byte(frame[5]),
+            sdu: frame.sdu(),
+            checksum: frame[14],
+        }
+    }
+}
+
+impl From<Frame> for [u8; 16] {
+    fn from(frame: Frame) -> Self {
+        MsgBuilder {
+            to: frame.to,
+            from: frame.from,
+            version: frame.version,
+            hops: frame.hops,
+            opcode: frame.opcode,
+            l7_sdu: frame.sdu,
+        }
+        .build_with_checksum(frame.checksum)
+    }
+}
+
+/// Describe the fields of a received frame on a single line, see
+/// [`FrameText`]
+pub fn describe(frame: &[u8; 16], algorithm: ChecksumAlgorithm) -> String {
+    FrameText(frame, algorithm).to_string()
+}
+
+/// Fields of a frame as a JSON object
+pub fn describe_json(frame: &[u8; 16], algorithm: ChecksumAlgorithm) -> String {
+    FrameJson(frame, algorithm).to_string()
+}
+
+/// Fields of a frame as a JSON object, see [`describe_json`], written without
+/// allocating
+pub struct FrameJson<'a>(pub &'a [u8; 16], pub ChecksumAlgorithm);
+
+impl Display for FrameJson<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let frame = self.0;
+        write!(
+            f,
+            "{{\"to\":{},\"from\":{},\"version\":{},\"hops\":{},\"opcode\":{},\"sdu\":[",
+            frame[1], frame[2], frame[3], frame[4], frame[5]
+        )?;
+        for (i, b) in frame.sdu().iter().enumerate() {
+            match i {
+                0 => write!(f, "{}", b)?,
+                _ => write!(f, ",{}", b)?,
+            }
+        }
+        let valid = self.1.compute(&frame[1..14]) == frame[14];
+        write!(f, "],\"checksum_ok\":{}}}", valid)
+    }
+}
+
+/// Bytes as hex pairs separated by spaces, like `00 05 00 04`, written
+/// without allocating
+pub struct Hex<'a>(pub &'a [u8]);
+
+impl Display for Hex<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, b) in self.0.iter().enumerate() {
+            match i {
+                0 => write!(f, "{:02x}", b)?,
+                _ => write!(f, " {:02x}", b)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Parse a hex string, bytes may optionally be separated by `:` or spaces
+pub fn parse_hex(s: &str) -> Result<Vec<u8>, String> {
+    let digits: String = s
+        .trim_start_matches("0x")
+        .chars()
+        .filter(|c| !matches!(c, ':' | ' '))
+        .collect();
+    if digits.is_empty()
+        || !digits.len().is_multiple_of(2)
+        || !digits.chars().all(|c| c.is_ascii_hexdigit())
+    {
+        return Err(format!("`{}` is not a valid hex string", s));
+    }
+
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&digits[i..i + 2], 16)
+                .map_err(|_| format!("`{}` is not a valid hex string", s))
+        })
+        .collect()
+}
+
+/// Checksum over the header and SDU bytes of a message
+pub fn checksum<I: IntoIterator<Item = u8>>(bytes: I) -> u8 {
+    !bytes.into_iter().fold(0u8, |i, acc| i.wrapping_add(acc))
+}
+
+/// Algorithms of the checksum byte of a message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum ChecksumAlgorithm {
+    /// One's complement of the wrapping sum of the bytes
+    Sum,
+    /// CRC-8/SMBUS, see [`crc8`]
+    Crc8,
+}
+
+impl ChecksumAlgorithm {
+    pub fn compute(self, bytes: &[u8]) -> u8 {
+        match self {
+            Self::Sum => checksum(bytes.iter().copied()),
+            Self::Crc8 => crc8(bytes.iter().copied()),
+        }
+    }
+}
+
+/// CRC-8 with the polynomial 0x07 and no reflection (CRC-8/SMBUS) over the
+/// header and SDU bytes of a message
+pub fn crc8<I: IntoIterator<Item = u8>>(bytes: I) -> u8 {
+    bytes
+        .into_iter()
+        .fold(0u8, |crc, b| CRC8_TABLE[(crc ^ b) as usize])
+}
+
+/// Remainders of all bytes, so the CRC is computed a byte at a time
+const CRC8_TABLE: [u8; 256] = {
+    let mut table = [0u8; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u8;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn serde_representation() {
+        let bytes = MsgBuilder::new(5, Opcode::SetLed, [0, 0, 0, 0, 0, 0, 1, 1]).build();
+        let frame = Frame::from(bytes);
+        let json = serde_json::to_string(&frame).unwrap();
+        assert_eq!(
+            json,
+            r#"{"to":5,"from":0,"version":4,"hops":0,"opcode":100,"sdu":[0,0,0,0,0,0,1,1],"checksum":144}"#
+        );
+        assert_eq!(serde_json::from_str::<Frame>(&json).unwrap(), frame);
+        assert_eq!(<[u8; 16]>::from(frame), bytes);
+
+        let builder: MsgBuilder = serde_json::from_str(
+            r#"{"to":7,"from":0,"hops":1,"version":2,"opcode":250,"l7_sdu":[0,0,0,0,0,0,0,9]}"#,
+        )
+        .unwrap();
+        assert_eq!(builder.opcode, Opcode::Unknown(250));
+        assert_eq!(serde_json::to_value(builder.opcode).unwrap(), 250);
+        assert_eq!(
+            serde_json::from_str::<ChecksumAlgorithm>(r#""crc8""#).unwrap(),
+            ChecksumAlgorithm::Crc8
+        );
+    }
+
+    #[test]
+    fn frames_on_one_line() {
+        let bytes = MsgBuilder::new(5, Opcode::SetLed, [0, 0, 0, 0, 0, 0, 0, 1]).build();
+        let mut frame = Frame::from(bytes);
+        assert_eq!(frame.to_string(), "[5 <- 0] v4 op=SetLed sdu=00..01");
+        assert_eq!(
+            FrameText(&bytes, ChecksumAlgorithm::Sum).to_string(),
+            "[5 <- 0] v4 op=SetLed sdu=00..01 crc=OK"
+        );
+        assert!(FrameText(&bytes, ChecksumAlgorithm::Crc8)
+            .to_string()
+            .ends_with("sdu=00..01 crc=BAD"));
+
+        frame.hops = 2;
+        frame.opcode = Opcode::Unknown(99);
+        frame.sdu = *b"\0abcdefg";
+        assert_eq!(
+            frame.to_string(),
+            "[5 <- 0] v4 hops=2 op=99 sdu=0061626364656667"
+        );
+        frame.to = Address::BROADCAST;
+        frame.sdu = [0; 8];
+        assert_eq!(
+            frame.to_string(),
+            "[broadcast <- 0] v4 hops=2 op=99 sdu=00..00"
+        );
+    }
+
+    #[test]
+    fn group_addresses() {
+        assert_eq!(Address::group(0), Some(Address(0xf0)));
+        assert_eq!(Address::group(13).and_then(Address::as_group), Some(13));
+        assert_eq!(Address::group(14), None);
+        assert_eq!(Address::group(u8::MAX), None);
+        assert_eq!(Address::UNASSIGNED.as_group(), None);
+    }
+
+    #[test]
+    fn crc8_known_vectors() {
+        assert_eq!(crc8([]), 0x00);
+        assert_eq!(crc8([0x00]), 0x00);
+        assert_eq!(crc8([0xff]), 0xf3);
+        // The check value of the CRC catalogue
+        assert_eq!(crc8(*b"123456789"), 0xf4);
+        assert_eq!(crc8([5, 0, 4, 0, 101, 0, 0, 0, 0, 0, 0, 0, 0]), 0x3b);
+    }
+
+    #[test]
+    fn opcodes_round_trip() {
+        for byte in 0..=u8::MAX {
+            let opcode = Opcode::from_byte(byte);
+            assert_eq!(u8::from(opcode), byte);
+            match Opcode::try_from(byte) {
+                Ok(known) => assert_eq!(known, opcode),
+                Err(e) => assert_eq!((opcode, e), (Opcode::Unknown(byte), UnknownOpcode(byte))),
+            }
+        }
+        assert_eq!(Opcode::try_from(113), Ok(Opcode::ReadUid));
+        assert_eq!(Opcode::try_from(141), Ok(Opcode::Ping));
+        assert_eq!(Opcode::try_from(99), Err(UnknownOpcode(99)));
+    }
+
+    #[test]
+    fn build_with_crc8() {
+        let builder = MsgBuilder::new(5, Opcode::ReadButtonPresses, L7Sdu::default());
+        assert_eq!(builder.build_with(ChecksumAlgorithm::Crc8)[14], 0x3b);
+        assert_eq!(builder.build()[14], 0x91);
+    }
+}
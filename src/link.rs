@@ -0,0 +1,211 @@
+//! The operations of the clients on a port, shared by the blocking
+//! [`crate::client`] and the [`crate::async_client`].
+//!
+//! A [`Link`] is used by one exchange at a time, the clients serialize them
+//! by a mutex or a thread owning the link.
+
+use std::{
+    io::{self, Read, Write},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    reader::FrameReader,
+    sdu::{Response, Sdu},
+    window::{self, Exchange, Window},
+    Address, ChecksumAlgorithm, L7Sdu, LedState, MsgBuilder, Opcode,
+};
+
+/// Direction of a transfer in its open frame
+const UPLOAD: u8 = 0;
+/// Offset of the sequence number in transfer frames, the clients don't tag
+/// them
+const SEQ_AT: usize = 0;
+
+/// Time an exchange waits for its response by default, however many frames
+/// of other devices arrive meanwhile
+pub const EXCHANGE_TIMEOUT: Duration = Duration::from_secs(1);
+
+pub(crate) struct Link<P> {
+    frames: FrameReader<P>,
+    checksum: ChecksumAlgorithm,
+    /// Longest time of an exchange, see [`EXCHANGE_TIMEOUT`]
+    pub timeout: Duration,
+}
+
+impl<P: Read + Write> Link<P> {
+    /// A link building requests with the checksum of `algorithm`, with
+    /// `verify` responses with another one are skipped
+    pub fn new(port: P, algorithm: ChecksumAlgorithm, verify: bool) -> Self {
+        let frames = FrameReader::new(port);
+        Link {
+            frames: if verify {
+                frames.verify(algorithm)
+            } else {
+                frames
+            },
+            checksum: algorithm,
+            timeout: EXCHANGE_TIMEOUT,
+        }
+    }
+
+    pub fn send(&mut self, builder: MsgBuilder) -> io::Result<()> {
+        builder.validate().map_err(invalid)?;
+        let frame = builder.build_with(self.checksum);
+        self.frames.get_mut().write_all(&frame)?;
+        self.frames.get_mut().flush()
+    }
+
+    /// Send the request and return the response of the addressed device.
+    /// Frames of other devices or opcodes, like pushed telemetry, are
+    /// skipped until the timeout of the link expired
+    pub fn exchange(&mut self, builder: MsgBuilder) -> io::Result<[u8; 16]> {
+        self.send(builder)?;
+        self.response(builder.to, builder.opcode)
+    }
+
+    /// The response of device `to` to a request of `opcode`, skipping other
+    /// frames until the timeout of the link expired
+    fn response(&mut self, to: Address, opcode: Opcode) -> io::Result<[u8; 16]> {
+        let (deadline, opcode) = (Instant::now() + self.timeout, u8::from(opcode));
+        loop {
+            if Instant::now() >= deadline {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!(
+                        "Device {} didn't answer opcode {} within {:?}",
+                        to, opcode, self.timeout
+                    ),
+                ));
+            }
+            let response = self.frames.read_frame()?;
+            if response[2] != to.0 {
+                continue;
+            }
+            match Opcode::from_byte(response[5]) {
+                Opcode::Rejected if response.sdu_u8(6) == opcode => {
+                    return Err(invalid(format!(
+                        "Device {} rejected opcode {} (reason {})",
+                        to,
+                        opcode,
+                        response.sdu_u8(7)
+                    )))
+                }
+                _ if response[5] == opcode => return Ok(response),
+                _ => (),
+            }
+        }
+    }
+
+    /// Round trip time of an echo of the device
+    pub fn ping(&mut self, to: u8) -> io::Result<Duration> {
+        let pattern: L7Sdu = *b"MMCPping";
+        let start = Instant::now();
+        let response = self.exchange(MsgBuilder::new(to, Opcode::Echo, pattern))?;
+        let elapsed = start.elapsed();
+        if response.sdu() != pattern {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Device {} echoed a corrupted SDU", to),
+            ));
+        }
+        Ok(elapsed)
+    }
+
+    pub fn set_led(&mut self, to: u8, index: u8, state: LedState) -> io::Result<()> {
+        let mut sdu = L7Sdu::default();
+        sdu[6] = index;
+        sdu[7] = state.into();
+        self.exchange(MsgBuilder::new(to, Opcode::SetLed, sdu))?;
+        Ok(())
+    }
+
+    pub fn get_led(&mut self, to: u8, index: u8) -> io::Result<bool> {
+        let sdu = L7Sdu::from_u8(6, index);
+        let response = self.exchange(MsgBuilder::new(to, Opcode::GetLed, sdu))?;
+        Ok(response.sdu_u8(7) == 1)
+    }
+
+    pub fn read_button_presses(&mut self, to: u8) -> io::Result<u8> {
+        let builder = MsgBuilder::new(to, Opcode::ReadButtonPresses, L7Sdu::default());
+        Ok(self.exchange(builder)?.sdu_u8(7))
+    }
+
+    /// Upload `data` into `slot` of the device storage with the chunks of a
+    /// [`Window`] in flight, resuming an interrupted upload of the same
+    /// length
+    pub fn flash(&mut self, to: u8, slot: u8, data: &[u8]) -> io::Result<()> {
+        let chunk_len = window::chunk_len(SEQ_AT);
+        let chunks = data.len().div_ceil(chunk_len);
+        let chunk_count = u16::try_from(chunks).map_err(|_| {
+            invalid(format!(
+                "{} bytes don't fit into a transfer, it holds up to {} bytes",
+                data.len(),
+                u16::MAX as usize * chunk_len
+            ))
+        })?;
+
+        let mut open = L7Sdu::default();
+        open[4] = UPLOAD;
+        open[5] = slot;
+        open[6..].copy_from_slice(&chunk_count.to_be_bytes());
+        let response = self.exchange(MsgBuilder::new(to, Opcode::TransferOpen, open))?;
+        status(&response, "slot")?;
+        let first = u16::from_be_bytes([response[10], response[11]]) as usize;
+
+        let chunks: Vec<&[u8]> = data.chunks(chunk_len).collect();
+        let request = |seq: usize| window::chunk(SEQ_AT, seq as u16, chunks[seq]);
+        // The device acknowledges all chunks before the one it expects next
+        let answer = |response: &[u8; 16], _| {
+            status(response, "chunk")?;
+            Ok(window::seq(response, SEQ_AT))
+        };
+        let mut device = Device {
+            link: self,
+            to: Address(to),
+        };
+        window::go_back_n(
+            &mut device,
+            Opcode::TransferData,
+            first..chunks.len(),
+            Window::default(),
+            request,
+            answer,
+        )?;
+
+        let mut end = L7Sdu::default();
+        end[4..].copy_from_slice(&(data.len() as u32).to_be_bytes());
+        let response = self.exchange(MsgBuilder::new(to, Opcode::TransferEnd, end))?;
+        status(&response, "data")
+    }
+}
+
+/// The frames of one device on a link, for transfers through a [`Window`]
+struct Device<'a, P> {
+    link: &'a mut Link<P>,
+    to: Address,
+}
+
+impl<P: Read + Write> Exchange for Device<'_, P> {
+    fn send(&mut self, opcode: Opcode, sdu: L7Sdu) -> io::Result<()> {
+        self.link.send(MsgBuilder::new(self.to.0, opcode, sdu))
+    }
+
+    fn receive(&mut self, opcode: Opcode) -> io::Result<Option<[u8; 16]>> {
+        self.link.response(self.to, opcode).map(Some)
+    }
+}
+
+fn status(response: &[u8; 16], what: &str) -> io::Result<()> {
+    match response.sdu_u8(7) {
+        0 => Ok(()),
+        status => Err(invalid(format!(
+            "Device rejected the {} (status {})",
+            what, status
+        ))),
+    }
+}
+
+fn invalid<E: ToString>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, e.to_string())
+}
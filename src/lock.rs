@@ -0,0 +1,146 @@
+//! Advisory locks of serial ports, so instances don't interleave frames.
+//!
+//! Before opening a port an instance locks the file `mmcp-<port>.lock` in
+//! `/run/lock`, or else the temporary directory, with `flock` and writes its
+//! PID into it. The directory is shared by all users, so instances of
+//! different users lock the same file, one which another user created is
+//! locked without writing it. Symbolic links aren't followed and the file
+//! isn't truncated, the PID padded to a fixed width overwrites the one
+//! before, so a link planted in the shared directory can't make the lock
+//! damage another file. A second instance fails with the PID of the first
+//! one or, with `--wait-for-port`, waits until it is done. Locks are held
+//! until the process exits, so the kernel releases them even after a crash.
+//! Windows opens serial ports exclusively anyway, there locking is left to
+//! the system.
+
+use std::time::Duration;
+#[cfg(unix)]
+use std::{
+    env,
+    fs::{self, File, OpenOptions},
+    io::{self, Read},
+    os::{
+        fd::AsRawFd,
+        unix::fs::{FileExt, OpenOptionsExt},
+    },
+    path::PathBuf,
+    sync::Mutex,
+    thread,
+    time::Instant,
+};
+
+#[cfg(unix)]
+use serialport::ErrorKind;
+
+/// The lock files of the ports this process opened
+#[cfg(unix)]
+static HELD: Mutex<Vec<(PathBuf, File)>> = Mutex::new(Vec::new());
+
+/// Lock `device` for this process, waiting at most `wait` for another
+/// instance to release it
+#[cfg(unix)]
+pub fn acquire(device: &str, wait: Option<Duration>) -> Result<(), serialport::Error> {
+    let path = path(device);
+    let mut held = HELD.lock().unwrap_or_else(|e| e.into_inner());
+    // Reopening a port, e.g. after the adapter was unplugged
+    if held.iter().any(|(p, _)| *p == path) {
+        return Ok(());
+    }
+
+    let error = |e: io::Error| {
+        serialport::Error::new(
+            ErrorKind::Io(e.kind()),
+            format!("Could not lock {}: {}", path.display(), e),
+        )
+    };
+    // Another user's lock file can still be locked, just not written
+    let (mut file, writable) = match OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        // The PID of the owner is read until the file is locked
+        .truncate(false)
+        .custom_flags(libc::O_NOFOLLOW)
+        .open(&path)
+    {
+        Ok(file) => (file, true),
+        Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+            let file = OpenOptions::new()
+                .read(true)
+                .custom_flags(libc::O_NOFOLLOW)
+                .open(&path)
+                .map_err(error)?;
+            (file, false)
+        }
+        Err(e) => return Err(error(e)),
+    };
+
+    let deadline = wait.map(|wait| Instant::now() + wait);
+    // SAFETY: flock only takes the descriptor of the open file
+    while unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+        let e = io::Error::last_os_error();
+        if e.kind() != io::ErrorKind::WouldBlock {
+            return Err(error(e));
+        }
+        crate::deadline::check()?;
+        match deadline {
+            Some(deadline) if Instant::now() < deadline => thread::sleep(Duration::from_millis(50)),
+            _ => return Err(in_use(device, &mut file)),
+        }
+    }
+
+    if writable {
+        // Wide enough for any PID, so nothing of the previous one is left
+        let pid = format!("{:>10}\n", std::process::id());
+        file.write_all_at(pid.as_bytes(), 0).map_err(error)?;
+    }
+    held.push((path, file));
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn acquire(_device: &str, _wait: Option<Duration>) -> Result<(), serialport::Error> {
+    Ok(())
+}
+
+/// The lock file of `device`, the same for all names of the port
+#[cfg(unix)]
+fn path(device: &str) -> PathBuf {
+    let device = fs::canonicalize(device).unwrap_or_else(|_| device.into());
+    let name: String = device
+        .to_string_lossy()
+        .trim_start_matches('/')
+        .chars()
+        .map(|c| match c.is_ascii_alphanumeric() {
+            true => c,
+            false => '-',
+        })
+        .collect();
+    dir().join(format!("mmcp-{}.lock", name))
+}
+
+/// The directory of the lock files, shared by all users
+#[cfg(unix)]
+fn dir() -> PathBuf {
+    // SAFETY: The path is a null terminated string
+    match unsafe { libc::access(c"/run/lock".as_ptr(), libc::W_OK) } {
+        0 => "/run/lock".into(),
+        _ => env::temp_dir(),
+    }
+}
+
+#[cfg(unix)]
+fn in_use(device: &str, file: &mut File) -> serialport::Error {
+    let mut pid = String::new();
+    let owner = match file.read_to_string(&mut pid) {
+        Ok(_) if !pid.trim().is_empty() => format!("PID {}", pid.trim()),
+        _ => "another instance".to_owned(),
+    };
+    serialport::Error::new(
+        ErrorKind::Io(io::ErrorKind::WouldBlock),
+        format!(
+            "Port {} in use by {}, pass --wait-for-port to wait for it",
+            device, owner
+        ),
+    )
+}
@@ -0,0 +1,81 @@
+//! Named command macros from the configuration.
+//!
+//! A macro is a list of subcommand lines in the `[macros]` table, e.g.
+//! `provision = ["set-led on", "read-button-presses"]`, which are executed in
+//! order over a single session.
+
+use clap::{Args, Parser};
+use serialport::ErrorKind;
+
+use crate::{config::split_words, error::Error, session::Session, Command};
+
+#[derive(Args, Debug, Clone)]
+pub struct RunMacro {
+    /// Name of the macro
+    name: String,
+}
+
+/// One line of a macro, parsed like the subcommand part of the command line
+#[derive(Parser, Debug)]
+#[command(no_binary_name = true)]
+struct MacroLine {
+    #[command(subcommand)]
+    cmd: Command,
+}
+
+pub fn run(session: &mut Session, run: &RunMacro) -> Result<(), Error> {
+    let lines = session
+        .config
+        .get("macros", &run.name)
+        .and_then(|v| v.as_array())
+        .ok_or_else(|| {
+            serialport::Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "No macro `{}` in the [macros] table of the configuration",
+                    run.name
+                ),
+            )
+        })?;
+
+    // Parse every line up front, so a typo doesn't leave a device half provisioned
+    let commands = lines
+        .iter()
+        .map(|line| {
+            let line = line.as_str().ok_or_else(|| {
+                macro_error(&run.name, "Macro lines have to be strings".to_owned())
+            })?;
+            parse(line)
+                .map(|cmd| (line.to_owned(), cmd))
+                .map_err(|e| macro_error(&run.name, e))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    for (line, cmd) in commands {
+        if session.args.echo {
+            eprintln!("> {}", line);
+        }
+        crate::execute(session, &cmd)?;
+    }
+
+    Ok(())
+}
+
+pub fn parse(line: &str) -> Result<Command, String> {
+    let words = split_words(line)?;
+    let cmd = MacroLine::try_parse_from(words)
+        .map_err(|e| format!("`{}`: {}", line, e.to_string().trim()))?
+        .cmd;
+    if matches!(cmd, Command::Run(_)) {
+        return Err(format!("`{}`: Macros can't run other macros", line));
+    }
+
+    Ok(cmd)
+}
+
+fn macro_error(name: &str, msg: String) -> serialport::Error {
+    serialport::Error::new(
+        ErrorKind::InvalidInput,
+        format!("Macro `{}`: {}", name, msg),
+    )
+}
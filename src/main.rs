@@ -1,174 +1,1043 @@
-use std::{process::ExitCode, time::Duration};
+use std::{
+    path::PathBuf,
+    process::ExitCode,
+    thread,
+    time::{Duration, Instant},
+};
 
-use clap::{Args, Parser, Subcommand, ValueEnum};
+use clap::{Args, CommandFactory, FromArgMatches, Parser, Subcommand};
 use serialport::SerialPort;
 
-type L7Sdu = [u8;8];
+mod aliases;
+mod auth;
+mod base64;
+mod ber;
+mod buttons;
+mod buzzer;
+mod capabilities;
+mod chaos;
+mod checksums;
+mod clock;
+mod config;
+mod daemon;
+mod deadline;
+mod diff;
+mod display;
+mod emulator;
+mod error;
+mod expect;
+mod explain;
+mod extcap;
+mod flash;
+mod groups;
+mod gzip;
+mod health;
+mod hook;
+mod influx;
+mod keys;
+mod lcd;
+mod lock;
+mod macros;
+mod memory;
+mod mesh;
+mod monitor;
+mod mqtt;
+mod opcodes;
+mod output;
+#[cfg(windows)]
+mod pipe;
+mod pipeline;
+mod playback;
+mod poll;
+mod ports;
+mod profile;
+mod registers;
+mod rejection;
+mod relay;
+mod relays;
+mod remote;
+mod replay;
+mod report;
+mod rng;
+mod script;
+mod servo;
+mod session;
+mod signals;
+mod snapshot;
+mod stats;
+mod telemetry;
+mod trace;
+mod transfer;
+mod webhook;
+mod writer;
+
+use config::Config;
+use error::Error;
+use output::Output;
+use sdu::Sdu;
+use session::Session;
+
+pub use mmcp_client_cli::{
+    checksum, crc8, describe, describe_json, parse_hex, reader, sdu, Address, ChecksumAlgorithm,
+    Frame, FrameError, FrameJson, FrameText, Hex, L7Sdu, LedState, MsgBuilder, Opcode,
+    OPCODE_RANGES, PROTOCOL_VERSIONS, window,
+};
 
 fn main() -> ExitCode {
-    let args = CliArgs::parse();
-    match serialport::new(&args.device, args.baud_rate)
-        .timeout(Duration::from_millis(args.timeout))
-        .open()
-        .and_then(|s| run(args, s))
-    {
+    if extcap::is_extcap_invocation() {
+        return match extcap::run() {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) => {
+                eprintln!("Error({:?}): {}", e.kind, e.description);
+                ExitCode::FAILURE
+            }
+        };
+    }
+
+    let matches = match aliases::expand(std::env::args_os().collect()) {
+        Ok(args) => CliArgs::command().get_matches_from(args),
+        Err(e) => {
+            eprintln!("Error(InvalidInput): {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let mut args = CliArgs::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    // Without a device the id is the first positional, a port is never a number
+    if args.id.is_none() {
+        if let Some(id) = args.device.as_deref().and_then(|d| d.parse().ok()) {
+            args.id = Some(id);
+            args.device = None;
+        }
+    }
+    let config = Config::load(args.config.as_deref())
+        .and_then(|config| profile::apply(&mut args, &matches, &config).map(|_| config));
+    let (format, device) = (args.format, args.device.clone());
+    if let Some(deadline) = args.deadline {
+        deadline::set(deadline);
+    }
+    let result = config
+        .map_err(Error::from)
+        .and_then(|config| match args.cmd {
+            Command::Diff(ref diff) => standalone(&args, |out| diff::run(diff, out)),
+            Command::Explain(ref explain) => standalone(&args, |out| explain::run(explain, out)),
+            Command::Checksum(ref checksum) => {
+                standalone(&args, |out| checksums::run(checksum, out))
+            }
+            Command::ListPorts => standalone(&args, ports::list),
+            Command::Emulate(ref emulate) => emulator::run(&args, emulate),
+            Command::ChaosProxy(ref proxy) => chaos::run(&args, proxy),
+            Command::Relay(ref relay) => relay::run(&args, relay),
+            Command::Monitor(ref monitor) => {
+                standalone(&args, |out| monitor::run(&args, monitor, out))
+            }
+            Command::MqttBridge(ref bridge) => mqtt::run(&args, bridge, &config),
+            Command::Route(ref route) => relay::route(&args, route, &config),
+            Command::Remote(ref remote) => standalone(&args, |out| remote::run(remote, out)),
+            _ => match open(&args) {
+                Ok(serial) => run(args, config, serial),
+                Err(e) => Err(e.into()),
+            },
+        });
+    // Commands cut short by the deadline may end without an error of their own
+    let result = deadline::check().map_err(Error::from).and(result);
+
+    match result {
         Ok(_) => ExitCode::SUCCESS,
         Err(e) => {
-            eprintln!("Error({:?}): {}", e.kind, e.description);
-            ExitCode::FAILURE
+            output::error(&e, format, device.as_deref(), matches.subcommand_name());
+            ExitCode::from(e.exit_code())
         }
     }
 }
 
-pub fn run(args: CliArgs, mut serial: Box<dyn SerialPort>) -> Result<(), serialport::Error> {
+/// Run a command without a session, writing its results to the output
+fn standalone<E: Into<Error>>(
+    args: &CliArgs,
+    f: impl FnOnce(&mut Output) -> Result<(), E>,
+) -> Result<(), Error> {
+    output::supports(&args.cmd, args.format)?;
+    let mut out = Output::open(args)?;
+    let result = f(&mut out).map_err(Into::into);
+    // Results up to an error are still worth keeping
+    let finished = out.finish();
+    result?;
+    Ok(finished?)
+}
+
+/// Open the serial port, retrying until the open timeout expires since
+/// USB adapters may take a while to enumerate
+pub fn open(args: &CliArgs) -> Result<Box<dyn SerialPort>, serialport::Error> {
+    let device = match &args.device {
+        Some(device) => device.clone(),
+        None => ports::only_port()?,
+    };
+    if let Some(timeout) = args.wait_for_device {
+        ports::wait_for(&device, timeout)?;
+    }
+    let device = ports::resolve(&device)?;
+    lock::acquire(&device, args.wait_for_port)?;
+
+    let deadline = Instant::now() + args.open_timeout();
+    loop {
+        deadline::check()?;
+        match serialport::new(&device, args.baud_rate)
+            .timeout(args.response_timeout())
+            .open()
+        {
+            Ok(serial) => return Ok(serial),
+            Err(e) if Instant::now() >= deadline => return Err(e),
+            Err(_) => thread::sleep(Duration::from_millis(50)),
+        }
+    }
+}
+
+pub fn run(
+    args: CliArgs,
+    config: Config,
+    serial: Box<dyn SerialPort>,
+) -> Result<(), Error> {
+    let mut session = Session::new(args, config, serial)?;
+    let cmd = session.args.cmd.clone();
+    let result = execute(&mut session, &cmd).and_then(|responses| {
+        match session.args.expect_sdu {
+            Some(pattern) => pattern.check(responses.first()).map_err(Error::Assertion),
+            None => Ok(()),
+        }
+    });
+    // A refused request fails the command with the reason of the device
+    let result = result.map_err(|error| match (&error, session.rejection.take()) {
+        (Error::Serial(_), Some(rejection)) => Error::Rejected(rejection),
+        _ => error,
+    });
+    // The bytes of a frame cut short tell a noisy line from a silent device
+    let result = result.map_err(|error| match session.received.is_empty() {
+        true => error,
+        false => Error::Context {
+            error: Box::new(error),
+            received: session.received.clone(),
+        },
+    });
+
+    if let Some(format) = session.args.stats {
+        eprintln!("{}", session.stats.render(format));
+    }
+
+    let finished = session.out.finish();
+    result?;
+    Ok(finished?)
+}
+
+/// Execute a single command within the session, print its result and compare
+/// it with its snapshot
+pub fn execute(session: &mut Session, cmd: &Command) -> Result<Vec<[u8; 16]>, Error> {
+    let responses = perform(session, cmd)?;
+    session.out.report(cmd, &responses)?;
+    if let Some(snapshots) = session.snapshots.as_mut() {
+        snapshots.check(cmd, &responses)?;
+    }
+
+    Ok(responses)
+}
+
+/// Execute a single command within the session, returning the response frames
+pub fn perform(session: &mut Session, cmd: &Command) -> Result<Vec<[u8; 16]>, Error> {
+    let args = session.args.clone();
+    let id = session.id;
+    output::supports(cmd, session.out.format())?;
+
+    if args.no_response
+        && matches!(
+            cmd,
+            Command::ReadButtonPresses
+                | Command::ReadUid
+                | Command::GetLed(_)
+                | Command::GetRelays
+                | Command::Capabilities
+                | Command::Status
+                | Command::Uptime(_)
+                | Command::Stats(_)
+                | Command::Group(_)
+                | Command::Subscribe(_)
+                | Command::ListOpcodes(_)
+                | Command::Key(_)
+                | Command::Upload(_)
+                | Command::Download(_)
+                | Command::Peek(_)
+                | Command::DumpFlash(_)
+                | Command::Verify(_)
+                | Command::ReadReg(_)
+        )
+    {
+        return Err(serialport::Error::new(
+            serialport::ErrorKind::InvalidInput,
+            "This command needs the response of the device, it can't be used with --no-response \
+            or --group",
+        )
+        .into());
+    }
+
+    if !matches!(
+        cmd,
+        Command::Run(_)
+            | Command::Script(_)
+            | Command::Replay(_)
+            | Command::BerTest(_)
+            | Command::MeshFlood(_)
+            | Command::ListOpcodes(_)
+            | Command::Subscribe(_)
+            | Command::Upload(_)
+            | Command::Download(_)
+            | Command::Peek(_)
+            | Command::Poke(_)
+            | Command::DumpFlash(_)
+            | Command::Verify(_)
+            | Command::ReadReg(_)
+            | Command::WriteReg(_)
+            | Command::Diff(_)
+            | Command::Explain(_)
+            | Command::Checksum(_)
+            | Command::ListPorts
+            | Command::Emulate(_)
+            | Command::ChaosProxy(_)
+            | Command::Relay(_)
+            | Command::Route(_)
+            | Command::Monitor(_)
+            | Command::MqttBridge(_)
+            | Command::Remote(_)
+            | Command::Daemon(_)
+    ) {
+        session.pace()?;
+    }
+    if args.check_capabilities && !args.no_response {
+        capabilities::check(session, cmd)?;
+    }
+
     let mut msg = [0u8;16];
-    match args.cmd {
-        Command::Raw(Raw { bytes }) => {
+    match cmd {
+        Command::Raw(Raw { bytes, auto_checksum }) => {
+            let mut bytes = bytes.concat();
+            if *auto_checksum {
+                match bytes.len() {
+                    14 => bytes.extend([0, 0]),
+                    16 => (),
+                    n => {
+                        return Err(serialport::Error::new(
+                            serialport::ErrorKind::InvalidInput,
+                            format!(
+                                "--auto-checksum needs the first 14 bytes of the message \
+                                (or all 16 with the checksum to replace), got {}",
+                                n
+                            ),
+                        )
+                        .into())
+                    }
+                }
+                bytes[14] = args.checksum().compute(&bytes[1..14]);
+            }
+
             if bytes.len() != 16 {
-                eprintln!(
-                    "WARNING: The message is incomplete or too long.\n\
-                    One message consists of 16 bytes"
-                );
+                violation(
+                    &args,
+                    format!("The message consists of {} bytes instead of 16", bytes.len()),
+                )?;
             }
 
-            serial.write_all(&bytes)?;
-            serial.read_exact(&mut msg)?;
+            session.write(&bytes)?;
+            if !args.no_response {
+                session.read_frame(&mut msg)?;
+            }
         }
         Command::SetLed(set_led) => {
-            let bytes = MsgBuilder::new(args.id, 100, set_led.as_sdu()).build();
-            if args.echo {
-                eprintln!("MSG: {:?}", bytes);
-            }
-            
-            serial.write_all(&bytes)?;
-            serial.read_exact(&mut msg)?;
+            let builder = session.builder(Opcode::SetLed, set_led.as_sdu());
+            session.transact(builder, &mut msg)?;
+        }
+        Command::GetLed(get_led) => {
+            let builder = session.builder(Opcode::GetLed, L7Sdu::from_u8(6, get_led.index));
+            session.transact(builder, &mut msg)?;
         }
         Command::ReadButtonPresses => {
-            let bytes = MsgBuilder::new(args.id, 101, L7Sdu::default()).build();
-            if args.echo {
-                eprintln!("MSG: {:?}", bytes);
-            }
-            
-            serial.write_all(&bytes)?;
-            serial.read_exact(&mut msg)?;
-
-            println!("Button Presses: {}", msg[13]);
+            let builder = session.builder(Opcode::ReadButtonPresses, L7Sdu::default());
+            session.transact(builder, &mut msg)?;
+        }
+        Command::ConfigureButton(configure) => {
+            let builder = session.builder(Opcode::ConfigureButton, configure.as_sdu());
+            session.transact(builder, &mut msg)?;
+        }
+        Command::Beep(beep) => {
+            let builder = session.builder(Opcode::Beep, beep.as_sdu());
+            session.transact(builder, &mut msg)?;
+        }
+        Command::DisplayNumber(number) => {
+            return display::run(session, number).map_err(Error::from)
+        }
+        Command::LcdWrite(write) => {
+            return lcd::run(session, write).map_err(Error::from)
+        }
+        Command::SetServo(servo) => {
+            return servo::run(session, servo).map_err(Error::from)
+        }
+        Command::SetRelay(set_relay) => {
+            let builder = session.builder(Opcode::SetRelay, set_relay.as_sdu());
+            session.transact(builder, &mut msg)?;
+        }
+        Command::GetRelays => return relays::get(session),
+        Command::Send(send) => {
+            let builder = MsgBuilder {
+                to: send.to.unwrap_or(Address(id)),
+                from: send.from,
+                hops: send.hops,
+                version: send.version.unwrap_or(args.protocol_version),
+                opcode: Opcode::from_byte(send.opcode),
+                l7_sdu: send.sdu.unwrap_or_default(),
+            };
+            session.transact(builder, &mut msg)?;
+        }
+        Command::ReadUid => {
+            let builder = session.builder(Opcode::ReadUid, L7Sdu::default());
+            session.transact(builder, &mut msg)?;
+        }
+        Command::Capabilities => return capabilities::run(session),
+        Command::Status => return health::status(session),
+        Command::Uptime(_) => return health::uptime(session),
+        Command::Stats(stats) => return health::stats(session, stats),
+        Command::Subscribe(subscribe) => {
+            return telemetry::subscribe(session, subscribe).map(|_| Vec::new())
+        }
+        Command::Group(group) => {
+            return groups::run(session, group).map(|_| Vec::new()).map_err(Error::from)
+        }
+        Command::ListOpcodes(list) => return opcodes::run(session, list).map(|_| Vec::new()),
+        Command::Key(key) => keys::run(session, key, &mut msg)?,
+        Command::Run(run) => return macros::run(session, run).map(|_| Vec::new()),
+        Command::Script(script) => return script::run(session, script).map(|_| Vec::new()),
+        Command::Replay(replay) => return playback::run(session, replay).map(|_| Vec::new()),
+        Command::BerTest(test) => return ber::run(session, test).map(|_| Vec::new()),
+        Command::MeshFlood(flood) => return mesh::run(session, flood).map(|_| Vec::new()),
+        Command::Upload(upload) => return transfer::upload(session, upload).map(|_| Vec::new()),
+        Command::Download(download) => {
+            return transfer::download(session, download).map(|_| Vec::new())
+        }
+        Command::Peek(peek) => return memory::peek(session, peek).map(|_| Vec::new()),
+        Command::Poke(poke) => return memory::poke(session, poke).map(|_| Vec::new()),
+        Command::DumpFlash(dump) => return flash::dump(session, dump).map(|_| Vec::new()),
+        Command::Verify(verify) => return flash::verify(session, verify).map(|_| Vec::new()),
+        Command::ReadReg(read) => return registers::read(session, read).map(|_| Vec::new()),
+        Command::WriteReg(write) => return registers::write(session, write).map(|_| Vec::new()),
+        Command::Daemon(daemon) => return daemon::run(session, daemon).map(|_| Vec::new()),
+        Command::Diff(diff) => {
+            return diff::run(diff, &mut session.out).map(|_| Vec::new()).map_err(Error::from)
+        }
+        Command::Explain(explain) => {
+            return explain::run(explain, &mut session.out).map(|_| Vec::new()).map_err(Error::from)
+        }
+        Command::Checksum(checksum) => {
+            return checksums::run(checksum, &mut session.out).map(|_| Vec::new()).map_err(Error::from)
+        }
+        Command::ListPorts => {
+            return ports::list(&mut session.out).map(|_| Vec::new()).map_err(Error::from)
+        }
+        Command::Emulate(_)
+        | Command::ChaosProxy(_)
+        | Command::Relay(_)
+        | Command::Route(_)
+        | Command::Monitor(_)
+        | Command::MqttBridge(_)
+        | Command::Remote(_) => {
+            return Err(serialport::Error::new(
+                serialport::ErrorKind::InvalidInput,
+                "The emulator, proxy, relay, monitor, bridge and remote commands can only be \
+                started on their own",
+            )
+            .into())
         }
-        Command::ReadUid => todo!(),
     }
     
+    if args.no_response {
+        return Ok(Vec::new());
+    }
+
+    let responder = match cmd {
+        Command::Raw(raw) => raw.bytes.concat().get(1).copied(),
+        Command::Send(send) => Some(send.to.map_or(id, u8::from)),
+        _ => Some(id),
+    };
+    let mut responses = vec![msg];
+    // Raw messages are never authenticated, so neither are their responses
+    let verify = !matches!(cmd, Command::Raw(_));
+    while args.read_until_timeout || responses.len() < args.expect_frames as usize {
+        let mut frame = [0u8; 16];
+        let read = if verify {
+            session.receive(Opcode::from_byte(msg[5]), &mut frame)
+        } else {
+            session.read_frame(&mut frame)
+        };
+
+        match read {
+            Ok(()) => responses.push(frame),
+            Err(e)
+                if args.read_until_timeout
+                    && e.kind == serialport::ErrorKind::Io(std::io::ErrorKind::TimedOut) =>
+            {
+                break
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+
     if args.echo {
-        eprintln!("Response: {:?}", msg);
+        for frame in &responses {
+            eprintln!(
+                "Response: {}\n{}",
+                describe(frame, args.checksum()),
+                output::table(frame, args.checksum(), transfer::seq_offset(&args))
+            );
+        }
     }
-    
+    check_responses(&args, responder, &responses)?;
+
+    Ok(responses)
+}
+
+/// Report the protocol violations of the responses, the first one is the
+/// reply of `responder`
+fn check_responses(
+    args: &CliArgs,
+    responder: Option<u8>,
+    responses: &[[u8; 16]],
+) -> Result<(), Error> {
+    let algorithm = args.checksum();
+    for (i, frame) in responses.iter().enumerate() {
+        if frame[0] != 0 || frame[15] != 0 {
+            violation(
+                args,
+                format!(
+                    "Response {} has the reserved bytes {:#04x} and {:#04x} instead of 0",
+                    i + 1,
+                    frame[0],
+                    frame[15]
+                ),
+            )?;
+        }
+        let expected = algorithm.compute(&frame[1..14]);
+        if frame[14] != expected {
+            violation(
+                args,
+                format!(
+                    "Response {} has checksum {:#04x} instead of {:#04x}",
+                    i + 1,
+                    frame[14],
+                    expected
+                ),
+            )?;
+        }
+    }
+    // Further frames may come from others, e.g. after a broadcast
+    if let (Some(responder), Some(frame)) = (responder, responses.first()) {
+        if frame[2] != responder {
+            violation(
+                args,
+                format!(
+                    "The response comes from {} instead of {}",
+                    frame[2], responder
+                ),
+            )?;
+        }
+    }
+
     Ok(())
 }
 
-#[derive(Debug, Clone, Copy)]
-pub struct MsgBuilder {
-    pub to: u8,
-    pub from: u8,
-    pub hops: u8,
-    pub version: u8,
-    pub opcode: u8,
-    pub l7_sdu: [u8; 8],
-}
-
-impl MsgBuilder {
-    pub fn new(to: u8, opcode: u8, l7_sdu: L7Sdu) -> Self {
-        Self {
-            to,
-            from: 0,
-            version: 4,
-            hops: 0,
-            opcode,
-            l7_sdu,
-        }
-    }
-
-    pub fn build(self) -> [u8; 16] {
-        let check_sum = ![self.to, self.from, self.version, self.hops, self.opcode]
-            .into_iter()
-            .chain(self.l7_sdu)
-            .fold(0u8, |i,acc| i.wrapping_add(acc));
-        
-        self.build_with_checksum(check_sum)
-    }
-
-    pub fn build_with_checksum(self, check_sum: u8) -> [u8; 16] {
-        [
-            0,
-            self.to,
-            self.from,
-            self.version,
-            self.hops,
-            self.opcode,
-            self.l7_sdu[0],
-            self.l7_sdu[1],
-            self.l7_sdu[2],
-            self.l7_sdu[3],
-            self.l7_sdu[4],
-            self.l7_sdu[5],
-            self.l7_sdu[6],
-            self.l7_sdu[7],
-            check_sum,
-            0,
-        ]
-    }
-}
-
-// fn calc_crc<I: Iterator<Item=u8>>(iter: &I) {
-    
-// }
+/// Warn of a protocol violation, or fail with `--strict`
+fn violation(args: &CliArgs, msg: String) -> Result<(), Error> {
+    match args.strict {
+        true => Err(Error::Protocol(msg)),
+        false => {
+            eprintln!("WARNING: {}", msg);
+            Ok(())
+        }
+    }
+}
 
-#[derive(Parser, Debug)]
+#[derive(Parser, Debug, Clone)]
 #[command(author, version, about, long_about = None)]
 pub struct CliArgs {
-    device: String,
-    id: u8,
+    /// Serial port of the device, `usb:<vid>:<pid>` or `name:<text>`, defaults
+    /// to the only serial port of the system. Not needed for offline commands
+    device: Option<String>,
+    /// Id of the device
+    id: Option<u8>,
     #[arg(short, long)]
     echo: bool,
     #[arg(short, long, default_value_t = 115_200)]
     baud_rate: u32,
-    /// Timeout in milli seconds
-    #[arg(short, long, default_value_t = 500)]
-    timeout: u64,
+    /// Timeout, e.g. `500ms` or `2s`, the default for all specific timeouts
+    #[arg(short, long, default_value = "500ms", value_parser = parse_duration)]
+    timeout: Duration,
+    /// Time to keep retrying to open the port
+    #[arg(long, value_parser = parse_duration)]
+    open_timeout: Option<Duration>,
+    /// Wait for the port to appear before opening it, at most the given time,
+    /// e.g. `--wait-for-device=2m`
+    #[arg(
+        long,
+        require_equals = true,
+        num_args = 0..=1,
+        default_missing_value = "60s",
+        value_parser = parse_duration
+    )]
+    wait_for_device: Option<Duration>,
+    /// Wait for other instances using the port to finish, at most the given
+    /// time, e.g. `--wait-for-port=30s`. Fails at once without it
+    #[arg(
+        long,
+        require_equals = true,
+        num_args = 0..=1,
+        default_missing_value = "60s",
+        value_parser = parse_duration
+    )]
+    wait_for_port: Option<Duration>,
+    /// Fail once the whole invocation took longer than this, including
+    /// retries and waits, e.g. `--deadline 30s`
+    #[arg(long, value_parser = parse_duration)]
+    deadline: Option<Duration>,
+    /// Time to wait for the first byte of a response
+    #[arg(long, value_parser = parse_duration)]
+    response_timeout: Option<Duration>,
+    /// Time to wait for each further byte of a frame
+    #[arg(long, value_parser = parse_duration)]
+    inter_byte_timeout: Option<Duration>,
+    /// Turn a half-duplex bus like RS-485 around for every transmission,
+    /// waiting for the guard times and switching the driver with --rts
+    #[arg(long)]
+    half_duplex: bool,
+    /// Time to wait before transmitting on a half-duplex bus, for the
+    /// device to release it, e.g. `2ms`
+    #[arg(long, default_value = "0ms", value_parser = parse_duration, requires = "half_duplex")]
+    tx_guard: Duration,
+    /// Time to keep driving a half-duplex bus after the last byte was sent,
+    /// before receiving
+    #[arg(long, default_value = "0ms", value_parser = parse_duration, requires = "half_duplex")]
+    rx_guard: Duration,
+    /// Enable the driver of the transceiver by RTS while transmitting
+    #[arg(long, requires = "half_duplex")]
+    rts: bool,
+    /// Shared secret as hex string, enables authenticated frames with a truncated HMAC.
+    /// Defaults to the key stored for the device id in the key file. Echoes and peeks stay
+    /// unauthenticated, pokes, button configurations, display digits and LCD text are refused
+    #[arg(long, value_parser = parse_auth_key)]
+    auth_key: Option<AuthKey>,
+    /// Only send the message without waiting for a response, for broadcasts
+    /// and fire-and-forget opcodes
+    #[arg(long)]
+    no_response: bool,
+    /// Send to all devices which joined this group instead of the device
+    /// id, they don't respond. Implies --no-response
+    #[arg(long, value_parser = parse_group)]
+    group: Option<u8>,
+    /// Number of response frames to read, for replies spanning several frames
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+    expect_frames: u32,
+    /// Read response frames until the timeout expires
+    #[arg(long, conflicts_with = "expect_frames")]
+    read_until_timeout: bool,
+    /// Carry a frame counter in authenticated frames and reject replayed responses
+    #[arg(long)]
+    replay_protection: bool,
+    /// Send at most this many frames per second, for slow devices and the
+    /// turnaround of half-duplex buses
+    #[arg(long, value_parser = parse_rate)]
+    rate: Option<f64>,
+    /// Pause between the commands of a macro or script, e.g. `250ms`
+    #[arg(long, value_parser = parse_duration)]
+    delay: Option<Duration>,
+    /// Fail with exit code 4 on protocol violations like bad checksums,
+    /// unexpected responders, non-zero reserved bytes or raw messages not of
+    /// 16 bytes, instead of warning of them
+    #[arg(long)]
+    strict: bool,
+    /// Ask the device for its capabilities first and warn if it lacks the
+    /// one a command needs
+    #[arg(long)]
+    check_capabilities: bool,
+    /// Fail with exit code 3 unless the SDU of the response matches, e.g.
+    /// `00:00:00:00:00:00:00:01`, `xx` matches any byte and `..` any bytes
+    #[arg(long, value_parser = expect::parse_sdu_pattern)]
+    expect_sdu: Option<expect::SduPattern>,
+    /// Write a JUnit XML report of the lines executed by a script to this file
+    #[arg(long)]
+    report: Option<PathBuf>,
+    /// Directory of golden response snapshots, responses without one are
+    /// stored and all others have to match theirs
+    #[arg(long)]
+    snapshot: Option<PathBuf>,
+    /// Overwrite snapshots with the current responses instead of failing
+    #[arg(long, requires = "snapshot")]
+    update_snapshots: bool,
+    /// Don't actually wait for delays, sleeps and recorded gaps, a virtual
+    /// clock keeps the timing in trace files instead. The guard times of a
+    /// half-duplex bus are still waited for
+    #[arg(long)]
+    virtual_time: bool,
+    /// Print link statistics when done, `--stats=json` prints them as JSON
+    #[arg(long, require_equals = true, num_args = 0..=1, default_missing_value = "human")]
+    stats: Option<stats::StatsFormat>,
+    /// Append every transmitted and received frame to this file
+    #[arg(long)]
+    trace_file: Option<PathBuf>,
+    /// Start a new trace file once the current one reaches a size like
+    /// `100MB` or an age like `1h`
+    #[arg(long, requires = "trace_file", value_parser = trace::parse_rotation)]
+    rotate: Option<trace::Rotation>,
+    /// Number of rotated trace files to keep, older ones are deleted
+    #[arg(long, requires = "rotate")]
+    keep: Option<usize>,
+    /// Compress the trace file on the fly
+    #[arg(long, requires = "trace_file", value_enum)]
+    compress: Option<trace::Compression>,
+    /// Checksum of the frames sent, responses are rejected unless theirs
+    /// matches. Without it frames carry the sum and responses aren't checked
+    #[arg(long, value_enum)]
+    checksum: Option<ChecksumAlgorithm>,
+    /// Protocol version of the frames sent
+    #[arg(long, default_value_t = 4, value_parser = parse_u8)]
+    protocol_version: u8,
+    /// Format of the response frames, `json` also reports errors as JSON
+    /// objects on stderr
+    #[arg(long, value_enum, default_value_t = output::Format::Text)]
+    format: output::Format,
+    /// Write results to this file instead of stdout
+    #[arg(long)]
+    output: Option<PathBuf>,
+    /// Append results to the --output file instead of replacing it
+    #[arg(long, requires = "output")]
+    append: bool,
+    /// Connection profile from the [profile.<name>] table of the configuration
+    #[arg(long)]
+    profile: Option<String>,
+    /// Path of the configuration file
+    #[arg(long)]
+    config: Option<PathBuf>,
     #[command(subcommand)]
     cmd: Command,
 }
 
+impl CliArgs {
+    pub fn open_timeout(&self) -> Duration {
+        self.open_timeout.unwrap_or(self.timeout)
+    }
+
+    pub fn response_timeout(&self) -> Duration {
+        self.response_timeout.unwrap_or(self.timeout)
+    }
+
+    pub fn inter_byte_timeout(&self) -> Duration {
+        self.inter_byte_timeout.unwrap_or(self.timeout)
+    }
+
+    /// The algorithm of checksums, of the frames sent and of the ones
+    /// rendered
+    pub fn checksum(&self) -> ChecksumAlgorithm {
+        self.checksum.unwrap_or(ChecksumAlgorithm::Sum)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AuthKey(Vec<u8>);
+
+fn parse_auth_key(s: &str) -> Result<AuthKey, String> {
+    parse_hex(s).map(AuthKey)
+}
+
+/// Parse a byte of a raw message, or several of them in base64
+fn parse_raw_bytes(s: &str) -> Result<Vec<u8>, String> {
+    match parse_u8(s) {
+        Ok(byte) => Ok(vec![byte]),
+        // Numbers out of range are no base64, even if they would decode
+        Err(e) if s.bytes().all(|c| c.is_ascii_digit()) => Err(e),
+        Err(_) => base64::decode(s)
+            .map_err(|_| format!("`{}` is neither a byte value nor base64", s)),
+    }
+}
+
+/// Parse bytes given as values like `5` or `0x05`, or as a single hex or
+/// base64 string
+pub fn parse_bytes(values: &[String]) -> Result<Vec<u8>, String> {
+    match values {
+        [s] => parse_hex(s).or_else(|e| base64::decode(s).map_err(|_| e)),
+        values => values.iter().map(|s| parse_u8(s)).collect(),
+    }
+}
+
 #[derive(Subcommand, Debug, Clone)]
 pub enum Command {
     Raw(Raw),
     SetLed(SetLed),
+    /// Print whether the LED is on, as the device reports it
+    GetLed(GetLed),
     ReadButtonPresses,
+    /// Change how the device reads its buttons, like their debounce time,
+    /// trigger and pull resistors
+    ConfigureButton(buttons::ConfigureButton),
+    /// Sound the buzzer of the device, to find it among others
+    Beep(buzzer::Beep),
+    /// Show a number on the seven-segment display of the device
+    DisplayNumber(display::DisplayNumber),
+    /// Write text to a line of the LCD of the device
+    LcdWrite(lcd::LcdWrite),
+    /// Move a servo of the device to a position, or sweep it through a range
+    SetServo(servo::SetServo),
+    /// Switch a relay of an expansion node
+    SetRelay(relays::SetRelay),
+    /// Print which relays of an expansion node are on
+    GetRelays,
+    /// Print the unique id of the device
     ReadUid,
+    /// List the features the device supports
+    Capabilities,
+    /// Report the health of the device, like resets by the watchdog
+    Status,
+    /// Print the time since the last reset of the device
+    Uptime(health::Uptime),
+    /// Print the counters of received, corrupted and dropped frames the
+    /// device keeps, to compare them with --stats
+    Stats(health::DeviceStats),
+    /// Let the device push the values of an opcode periodically and print
+    /// them until interrupted
+    Subscribe(telemetry::Subscribe),
+    /// Add the device to a group or remove it, to address all devices of
+    /// the group with --group
+    Group(groups::Group),
+    /// Probe which opcodes the device answers
+    ListOpcodes(opcodes::ListOpcodes),
+    /// Send a message built from its individual fields
+    Send(SendMsg),
+    /// Run a macro from the [macros] table of the configuration
+    Run(macros::RunMacro),
+    /// Execute a script of commands and directives over one session
+    Script(script::Script),
+    /// Compare two sessions recorded with --trace-file frame by frame
+    Diff(diff::Diff),
+    /// Decode a frame without a device, naming its fields and opcode
+    Explain(explain::Explain),
+    /// Compute the checksum of the header and SDU of a message with every
+    /// algorithm
+    Checksum(checksums::Checksum),
+    /// List the serial ports of the system with their adapter names
+    ListPorts,
+    /// Send the frames of a session recorded with --trace-file again and
+    /// compare the responses
+    Replay(playback::Replay),
+    /// Measure the bit and frame error rate of the link with an echo opcode
+    BerTest(ber::BerTest),
+    /// Flood the mesh with frames of varying hop counts and log which paths
+    /// deliver responses
+    MeshFlood(mesh::MeshFlood),
+    /// Store a file in a slot of the device storage, sending chunks it
+    /// missed again
+    Upload(transfer::Upload),
+    /// Read a slot of the device storage into a file
+    Download(transfer::Download),
+    /// Print memory of the device as hex dump, through the debug opcodes
+    Peek(memory::Peek),
+    /// Write memory of the device, through the debug opcodes
+    Poke(memory::Poke),
+    /// Read flash of the device into a file, from memory or a storage slot
+    DumpFlash(flash::DumpFlash),
+    /// Compare flash of the device with an image, reporting the first address
+    /// which differs
+    Verify(flash::Verify),
+    /// Read a register by its name in the register map and decode its fields
+    ReadReg(registers::ReadReg),
+    /// Write a register by its name in the register map
+    WriteReg(registers::WriteReg),
+    /// Emulate a device on the serial port, answering frames by a rules file
+    Emulate(emulator::Emulate),
+    /// Forward frames between the serial port and another one, injecting
+    /// bit flips, dropped and duplicated frames and latency
+    ChaosProxy(chaos::ChaosProxy),
+    /// Forward frames between two serial bus segments
+    Relay(relay::Relay),
+    /// Route frames between the segments of the [routes] tables of the
+    /// configuration
+    Route(relay::Route),
+    /// Print the frames on the bus without sending anything
+    Monitor(monitor::Monitor),
+    /// Publish the frames on the bus to an MQTT broker and switch LEDs
+    /// through it
+    MqttBridge(mqtt::MqttBridge),
+    /// Serve commands to the device over a socket
+    Daemon(daemon::Daemon),
+    /// Execute commands by a running daemon, which keeps the port open
+    Remote(remote::Remote),
+    /// Manage the shared secret of devices using authenticated frames
+    Key(keys::Key),
 }
 
 #[derive(Args, Debug, Clone)]
 pub struct Raw {
-    bytes: Vec<u8>,
+    /// Bytes of the message, several of them may be given at once in
+    /// base64, e.g. `AAUEAGYAAAAAAAAAAJAA`
+    #[arg(value_parser = parse_raw_bytes)]
+    bytes: Vec<Vec<u8>>,
+    /// Compute and fill in the checksum byte, only the first 14 bytes have to be given
+    #[arg(long)]
+    auto_checksum: bool,
+}
+
+#[derive(Args, Debug, Clone, Copy)]
+pub struct SendMsg {
+    /// Destination address, defaults to the device id. `broadcast`,
+    /// `unassigned` and `group:<n>` name the special addresses
+    #[arg(long, value_parser = parse_address)]
+    to: Option<Address>,
+    #[arg(long, default_value = "0", value_parser = parse_address)]
+    from: Address,
+    #[arg(long, value_parser = parse_u8)]
+    opcode: u8,
+    /// Protocol version, defaults to --protocol-version
+    #[arg(long, value_parser = parse_u8)]
+    version: Option<u8>,
+    #[arg(long, default_value_t = 0, value_parser = parse_u8)]
+    hops: u8,
+    /// The 8 SDU bytes as hex string, e.g. `00:00:00:00:00:00:00:01`, or a
+    /// single value like `text:abc`, `u64:1000` or `u32le@4:1000`
+    #[arg(long, value_parser = parse_sdu)]
+    sdu: Option<L7Sdu>,
+}
+
+/// Parse an address, a byte value or the name of a special address
+fn parse_address(s: &str) -> Result<Address, String> {
+    match s {
+        "broadcast" => Ok(Address::BROADCAST),
+        "unassigned" => Ok(Address::UNASSIGNED),
+        s if s.starts_with("group:") => {
+            Address::group(parse_u8(&s[6..])?).ok_or_else(|| no_group(&s[6..]))
+        }
+        s => parse_u8(s).map(Address),
+    }
+}
+
+/// Parse the number of a group
+pub fn parse_group(s: &str) -> Result<u8, String> {
+    let group = parse_u8(s)?;
+    Address::group(group).map(|_| group).ok_or_else(|| no_group(s))
+}
+
+fn no_group(s: &str) -> String {
+    format!("`{}` is no group, there are {} of them", s, Address::GROUPS)
+}
+
+/// Parse a byte given in decimal or as `0x` prefixed hex
+fn parse_u8(s: &str) -> Result<u8, String> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u8::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+    .map_err(|_| format!("`{}` is not a byte value (0-255 or 0x00-0xff)", s))
+}
+
+/// Parse a positive rate like `20` or `0.5`
+fn parse_rate(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(rate) if rate > 0.0 && rate.is_finite() => Ok(rate),
+        _ => Err(format!("`{}` is no positive number of frames per second", s)),
+    }
+}
+
+/// Parse a duration like `250ms`, `30s`, `1m30s` or `1h`, bare numbers are
+/// milli seconds
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let invalid = || format!("`{}` is not a duration like `250ms`, `30s` or `1m30s`", s);
+    if let Ok(ms) = s.parse::<u64>() {
+        return Ok(Duration::from_millis(ms));
+    }
+
+    let mut total = Duration::ZERO;
+    let mut rest = s.trim();
+    if rest.is_empty() {
+        return Err(invalid());
+    }
+    while !rest.is_empty() {
+        let digits = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+        let value: u64 = rest[..digits].parse().map_err(|_| invalid())?;
+        rest = &rest[digits..];
+        let unit = rest.find(|c: char| c.is_ascii_digit()).unwrap_or(rest.len());
+        let part = match &rest[..unit] {
+            "ms" => Duration::from_millis(value),
+            "s" => Duration::from_secs(value),
+            "m" => Duration::from_secs(value.checked_mul(60).ok_or_else(invalid)?),
+            "h" => Duration::from_secs(value.checked_mul(3_600).ok_or_else(invalid)?),
+            _ => return Err(invalid()),
+        };
+        total = total.checked_add(part).ok_or_else(invalid)?;
+        rest = &rest[unit..];
+    }
+
+    Ok(total)
+}
+
+/// Parse an SDU given as hex string, or as a single value like `text:abc`,
+/// `u64:1000` (big endian) or `u32le@4:1000` (little endian at offset 4)
+fn parse_sdu(s: &str) -> Result<L7Sdu, String> {
+    let invalid = || format!("`{}` is no SDU like `u64:1000` or `u32le@4:1000`", s);
+    if let Some(text) = s.strip_prefix("text:") {
+        L7Sdu::from_str_padded(text)
+    } else if let Some(value) = s.strip_prefix("u64:") {
+        value.parse().map(L7Sdu::from_u64_be).map_err(|_| invalid())
+    } else if let Some(value) = s.strip_prefix("u32le@") {
+        let (offset, value) = value.split_once(':').ok_or_else(invalid)?;
+        match (offset.parse::<usize>(), value.parse()) {
+            (Ok(offset), Ok(value)) if offset <= 4 => Ok(L7Sdu::from_u32_le(offset, value)),
+            _ => Err(invalid()),
+        }
+    } else {
+        L7Sdu::from_hex(s)
+    }
 }
 
 #[derive(Args, Debug, Clone, Copy)]
 pub struct SetLed {
     on: LedState,
+    /// LED to switch, for boards with several
+    #[arg(long, default_value_t = 0)]
+    index: u8,
 }
 
 impl SetLed {
     pub fn as_sdu(self) -> [u8;8] {
-        let mut sdu = L7Sdu::default();
-        if self.on == LedState::On {
-            sdu[7] = 1;
-        }
-        
+        let mut sdu = L7Sdu::from_u8(7, self.on.into());
+        sdu[6] = self.index;
         sdu
     }
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
-pub enum LedState {
-    On,
-    Off,
+#[derive(Args, Debug, Clone, Copy)]
+pub struct GetLed {
+    /// LED to query, for boards with several
+    #[arg(long, default_value_t = 0)]
+    index: u8,
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("250"), Ok(Duration::from_millis(250)));
+        assert_eq!(parse_duration("250ms"), Ok(Duration::from_millis(250)));
+        assert_eq!(parse_duration("1m30s"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("2h"), Ok(Duration::from_secs(7_200)));
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration(" ").is_err());
+        assert!(parse_duration("10d").is_err());
+        assert!(parse_duration("s").is_err());
+    }
+
+    #[test]
+    fn durations_overflowing() {
+        assert!(parse_duration(&format!("{}h", u64::MAX / 1_000)).is_err());
+        assert!(parse_duration(&format!("{}m", u64::MAX)).is_err());
+        assert!(parse_duration(&format!("{}s{}s", u64::MAX, u64::MAX)).is_err());
+    }
+}
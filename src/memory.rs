@@ -0,0 +1,162 @@
+//! Memory access through the debug opcodes of firmwares in bring-up.
+//!
+//! Every frame accesses one word at the address in the first four SDU bytes
+//! (big endian). A peek carries the access width (1, 2 or 4) in SDU byte 4
+//! and is answered with the address and the bytes read, in memory order,
+//! from SDU byte 4 on. A poke carries the bytes to write in the same place,
+//! the width is told by its opcode. Words wider than a byte are accessed at
+//! once, as registers often require.
+
+use std::io::Write;
+
+use clap::Args;
+use serialport::ErrorKind;
+
+use crate::{error::Error, parse_hex, session::Session, L7Sdu, Opcode};
+
+#[derive(Args, Debug, Clone)]
+pub struct Peek {
+    /// Address to read from, e.g. `0x20000000`
+    #[arg(value_parser = parse_u32)]
+    address: u32,
+    /// Number of bytes to read, defaults to one word
+    #[arg(value_parser = parse_u32)]
+    len: Option<u32>,
+    #[command(flatten)]
+    width: Width,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct Poke {
+    /// Address to write to, e.g. `0x40021000`
+    #[arg(value_parser = parse_u32)]
+    address: u32,
+    /// Bytes to write in memory order as hex strings, e.g. `01:00:00:00`
+    #[arg(required = true, value_parser = parse_hex)]
+    bytes: Vec<Vec<u8>>,
+    #[command(flatten)]
+    width: Width,
+}
+
+#[derive(Args, Debug, Clone, Copy)]
+pub struct Width {
+    /// Bytes accessed at once: 1, 2 or 4
+    #[arg(long, default_value_t = 4, value_parser = parse_width)]
+    pub width: u8,
+}
+
+pub fn peek(session: &mut Session, peek: &Peek) -> Result<(), Error> {
+    let width = peek.width.width as u32;
+    let len = peek.len.unwrap_or(width);
+    if peek.address as u64 + len as u64 > 1 << 32 {
+        return Err(serialport::Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "{} bytes from {:#010x} reach beyond the address space",
+                len, peek.address
+            ),
+        )
+        .into());
+    }
+
+    // Grown as words arrive, the length is only bounded by the address space
+    let mut bytes = Vec::new();
+    for offset in (0..len).step_by(width as usize) {
+        let word = read(session, peek.address + offset, width as u8)?;
+        bytes.extend_from_slice(&word[..width.min(len - offset) as usize]);
+    }
+
+    for (i, line) in bytes.chunks(16).enumerate() {
+        let hex: Vec<String> = line.iter().map(|b| format!("{:02x}", b)).collect();
+        let text: String = line
+            .iter()
+            .map(|&b| match b.is_ascii_graphic() || b == b' ' {
+                true => b as char,
+                false => '.',
+            })
+            .collect();
+        writeln!(
+            session.out,
+            "{:08x}  {:<47}  |{}|",
+            peek.address.wrapping_add(i as u32 * 16),
+            hex.join(" "),
+            text
+        )?;
+    }
+
+    Ok(())
+}
+
+pub fn poke(session: &mut Session, poke: &Poke) -> Result<(), Error> {
+    let width = poke.width.width as usize;
+    let bytes = poke.bytes.concat();
+    if !bytes.len().is_multiple_of(width) {
+        return Err(serialport::Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "{} bytes can't be written in words of {} bytes",
+                bytes.len(),
+                width
+            ),
+        )
+        .into());
+    }
+
+    for (i, word) in bytes.chunks(width).enumerate() {
+        write(session, poke.address.wrapping_add((i * width) as u32), word)?;
+    }
+
+    Ok(())
+}
+
+/// Read a word of `width` bytes, in memory order
+pub fn read(session: &mut Session, address: u32, width: u8) -> Result<Vec<u8>, Error> {
+    let mut sdu = L7Sdu::default();
+    sdu[..4].copy_from_slice(&address.to_be_bytes());
+    sdu[4] = width;
+
+    let mut msg = [0u8; 16];
+    session.transact(session.builder(Opcode::Peek, sdu), &mut msg)?;
+    if msg[6..10] != address.to_be_bytes() {
+        return Err(Error::Protocol(format!(
+            "Read {:#010x}, the device answered for {:#010x}",
+            address,
+            u32::from_be_bytes([msg[6], msg[7], msg[8], msg[9]])
+        )));
+    }
+    Ok(msg[10..10 + width as usize].to_vec())
+}
+
+/// Write a word given in memory order, its length is the access width
+pub fn write(session: &mut Session, address: u32, word: &[u8]) -> Result<(), Error> {
+    let opcode = match word.len() {
+        1 => Opcode::PokeU8,
+        2 => Opcode::PokeU16,
+        _ => Opcode::PokeU32,
+    };
+    let mut sdu = L7Sdu::default();
+    sdu[..4].copy_from_slice(&address.to_be_bytes());
+    sdu[4..4 + word.len()].copy_from_slice(word);
+
+    let mut msg = [0u8; 16];
+    session.transact(session.builder(opcode, sdu), &mut msg)?;
+    Ok(())
+}
+
+/// Parse a 32 bit value given in decimal or as `0x` prefixed hex
+pub fn parse_u32(s: &str) -> Result<u32, String> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(&hex.replace('_', ""), 16),
+        None => s.parse(),
+    }
+    .map_err(|_| format!("`{}` is not a 32 bit value", s))
+}
+
+fn parse_width(s: &str) -> Result<u8, String> {
+    match s {
+        "1" => Ok(1),
+        "2" => Ok(2),
+        "4" => Ok(4),
+        _ => Err(format!("`{}` is no access width, use 1, 2 or 4", s)),
+    }
+}
@@ -0,0 +1,135 @@
+//! Flood test of multi-hop forwarding in the device mesh.
+//!
+//! Every target address is sent a frame for each hop count up to
+//! `--max-hops`, with an opcode the devices echo. All responses arriving
+//! until the response timeout are logged, so duplicates delivered by several
+//! relays show up too. Frames carry no route, so the paths are told apart by
+//! the responding device and the hop count of its response.
+
+use std::{collections::BTreeMap, io::Write, ops::RangeInclusive, time::Instant};
+
+use clap::Args;
+use serialport::ClearBuffer;
+
+use crate::{
+    describe, error::Error, parse_u8, relay::parse_address_range, session::Session, Address,
+    MsgBuilder, Opcode,
+};
+
+#[derive(Args, Debug, Clone)]
+pub struct MeshFlood {
+    /// Addresses to flood, e.g. `5` or `10-20`, defaults to the device id
+    #[arg(long = "target", value_parser = parse_address_range)]
+    targets: Vec<RangeInclusive<u8>>,
+    /// Highest hop count to send frames with
+    #[arg(long, default_value_t = 3, value_parser = parse_u8)]
+    max_hops: u8,
+    /// Opcode the devices echo the SDU of
+    #[arg(long, default_value_t = Opcode::Echo.into(), value_parser = parse_u8)]
+    opcode: u8,
+    /// How often to send each frame
+    #[arg(long, default_value_t = 1)]
+    rounds: u16,
+}
+
+/// Outcome of the frames to one target with one hop count
+#[derive(Debug, Default)]
+struct Paths {
+    delivered: u16,
+    /// Responses beyond the first of a frame
+    duplicates: u16,
+    /// Responding device and hop count of its response
+    seen: BTreeMap<(u8, u8), u16>,
+}
+
+pub fn run(session: &mut Session, flood: &MeshFlood) -> Result<(), Error> {
+    let targets: Vec<u8> = match flood.targets.is_empty() {
+        true => vec![session.id],
+        false => flood.targets.iter().flat_map(|r| r.clone()).collect(),
+    };
+
+    let mut results: BTreeMap<(u8, u8), Paths> = BTreeMap::new();
+    for round in 0..flood.rounds {
+        for &target in &targets {
+            for hops in 0..=flood.max_hops {
+                session.pace()?;
+                // The SDU tells the responses to this frame from late ones
+                let [r0, r1] = round.to_be_bytes();
+                let request = MsgBuilder {
+                    to: Address(target),
+                    hops,
+                    ..session.builder(
+                        Opcode::from_byte(flood.opcode),
+                        [target, hops, r0, r1, 0, 0, 0, 0],
+                    )
+                }
+                .build_with(session.args.checksum());
+                session.write(&request)?;
+
+                let start = Instant::now();
+                let paths = results.entry((target, hops)).or_default();
+                let mut responses = 0;
+                loop {
+                    let mut frame = [0u8; 16];
+                    match session.read_frame(&mut frame) {
+                        Ok(()) if frame[6..14] == request[6..14] => {
+                            responses += 1;
+                            *paths.seen.entry((frame[2], frame[4])).or_default() += 1;
+                            writeln!(
+                                session.out,
+                                "to={} hops={}: {:?} {}",
+                                target,
+                                hops,
+                                start.elapsed(),
+                                describe(&frame, session.args.checksum())
+                            )?;
+                        }
+                        Ok(()) => eprintln!(
+                            "Ignoring late response {}",
+                            describe(&frame, session.args.checksum())
+                        ),
+                        Err(e)
+                            if e.kind
+                                == serialport::ErrorKind::Io(std::io::ErrorKind::TimedOut) =>
+                        {
+                            session.serial.clear(ClearBuffer::Input)?;
+                            break;
+                        }
+                        Err(e) => return Err(e.into()),
+                    }
+                }
+
+                if responses == 0 {
+                    writeln!(session.out, "to={} hops={}: no response", target, hops)?;
+                } else {
+                    paths.delivered += 1;
+                    paths.duplicates += responses - 1;
+                }
+            }
+        }
+    }
+
+    writeln!(session.out)?;
+    for ((target, hops), paths) in &results {
+        let seen: Vec<String> = paths
+            .seen
+            .iter()
+            .map(|((from, hops), n)| format!("{}@{}x{}", from, hops, n))
+            .collect();
+        writeln!(
+            session.out,
+            "to={} hops={}: {}/{} delivered, {} duplicates, responders (from@hops x count): {}",
+            target,
+            hops,
+            paths.delivered,
+            flood.rounds,
+            paths.duplicates,
+            match seen.is_empty() {
+                true => "-".to_owned(),
+                false => seen.join(" "),
+            }
+        )?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,218 @@
+//! Passive monitor printing every frame on the bus.
+//!
+//! Lines are printed like in a trace file, frames can be narrowed down to a
+//! conversation with filters on the addresses and the opcode. Filters of
+//! different fields must all match, a repeated filter matches if any of its
+//! values does. Several ports can be monitored at once, e.g. both segments
+//! of a bridge. Ctrl-C ends the monitor, completing the output and trace
+//! files.
+//!
+//! With `--exec` a program is run for every shown frame matching
+//! `--on-match`, see [`crate::hook`], and with `--webhook` the frame is
+//! POSTed to an HTTP endpoint, see [`crate::webhook`].
+//!
+//! `--influx` prints the frames in InfluxDB line protocol instead, see
+//! [`crate::influx`], or writes them to the given HTTP endpoint, e.g.
+//! `http://localhost:8086/api/v2/write?org=lab&bucket=mmcp`.
+
+use std::{
+    io::Write,
+    ops::RangeInclusive,
+    sync::mpsc::{self, RecvTimeoutError},
+    thread,
+    time::SystemTime,
+};
+
+use clap::{ArgGroup, Args};
+
+use crate::{
+    error::Error,
+    hook::{parse_condition, Condition, Hook},
+    influx,
+    output::{Format, Output},
+    poll::{self, Event, Poller},
+    reader::FrameReader,
+    relay::parse_address_range,
+    signals,
+    trace::{Direction, Timestamp, Trace},
+    webhook::{self, parse_url, Url, Webhook},
+    CliArgs, FrameText, Hex,
+};
+
+#[derive(Args, Debug, Clone)]
+#[command(group(ArgGroup::new("actions").multiple(true)))]
+pub struct Monitor {
+    /// Only show frames from these addresses, e.g. `5` or `10-20`
+    #[arg(long, value_parser = parse_address_range)]
+    filter_from: Vec<RangeInclusive<u8>>,
+    /// Only show frames to these addresses, e.g. `5` or `10-20`
+    #[arg(long, value_parser = parse_address_range)]
+    filter_to: Vec<RangeInclusive<u8>>,
+    /// Only show frames with these opcodes, e.g. `100` or `100-102`
+    #[arg(long, value_parser = parse_address_range)]
+    filter_opcode: Vec<RangeInclusive<u8>>,
+    /// Only run `--exec` and `--webhook` for frames matching this condition,
+    /// e.g. `opcode==101 && from>=10`
+    #[arg(long, value_parser = parse_condition, requires = "actions")]
+    on_match: Option<Condition>,
+    /// Program to run for matching frames, `{json}` is replaced by the frame
+    /// as JSON, which is also written to its stdin
+    #[arg(long, group = "actions")]
+    exec: Option<String>,
+    /// Programs of `--exec` to run at the same time, further matching frames
+    /// are skipped until one finished
+    #[arg(
+        long,
+        default_value_t = 8,
+        requires = "exec",
+        value_parser = clap::value_parser!(u16).range(1..)
+    )]
+    max_hooks: u16,
+    /// POST matching frames as JSON to this http:// URL
+    #[arg(long, value_parser = parse_url, group = "actions")]
+    webhook: Option<Url>,
+    /// Print frames in InfluxDB line protocol, or write them to this http://
+    /// URL with `--influx=<url>`
+    #[arg(long, value_parser = parse_url, num_args = 0..=1, require_equals = true)]
+    influx: Option<Option<Url>>,
+    /// Further port to monitor, frames of all ports are shown in one stream
+    /// tagged by their port
+    #[arg(long = "device", value_name = "PORT")]
+    devices: Vec<String>,
+}
+
+impl Monitor {
+    pub fn matches(&self, frame: &[u8; 16]) -> bool {
+        let any = |filter: &[RangeInclusive<u8>], value: u8| {
+            filter.is_empty() || filter.iter().any(|r| r.contains(&value))
+        };
+        any(&self.filter_from, frame[2])
+            && any(&self.filter_to, frame[1])
+            && any(&self.filter_opcode, frame[5])
+    }
+}
+
+pub fn run(args: &CliArgs, monitor: &Monitor, out: &mut Output) -> Result<(), Error> {
+    let mut ports: Vec<Option<String>> = monitor.devices.iter().cloned().map(Some).collect();
+    if args.device.is_some() || ports.is_empty() {
+        ports.insert(0, args.device.clone());
+    }
+    let mut trace = Trace::from_args(args)?;
+    let mut hook = monitor
+        .exec
+        .as_deref()
+        .map(|command| Hook::new(command, args.checksum(), monitor.max_hooks as usize));
+    let mut webhook = monitor
+        .webhook
+        .clone()
+        .map(|url| Webhook::start(url, "application/json"));
+    let mut influx_endpoint = monitor
+        .influx
+        .clone()
+        .flatten()
+        .map(|url| Webhook::start(url, "text/plain; charset=utf-8"));
+
+    // Every port is read by its own thread, their frames meet in one stream
+    let (tx, rx) = mpsc::channel();
+    let mut names = Vec::new();
+    for (i, port) in ports.into_iter().enumerate() {
+        let serial = crate::open(&CliArgs {
+            device: port.clone(),
+            ..args.clone()
+        })?;
+        let name = port.unwrap_or_else(|| serial.name().unwrap_or_default());
+        names.push(name.clone());
+        let mut poller = Poller::new(FrameReader::new(serial))?;
+        let (tx, monitor) = (tx.clone(), monitor.clone());
+        thread::spawn(move || loop {
+            let frame = match poller.next() {
+                Ok(Event::Frame(frame)) => frame,
+                Ok(Event::Idle | Event::Timer(_)) => {
+                    if !poller.frames.discarded().is_empty() {
+                        eprintln!(
+                            "{}: Discarding incomplete frame {:02x?}",
+                            name,
+                            poller.frames.discarded()
+                        );
+                    }
+                    continue;
+                }
+                Ok(Event::Stop) => return,
+                Err(e) => {
+                    let _ = tx.send(Err(e));
+                    return;
+                }
+            };
+            if !poller.frames.discarded().is_empty() {
+                eprintln!(
+                    "{}: Skipped {:02x?} to find the next frame",
+                    name,
+                    poller.frames.discarded()
+                );
+            }
+            if monitor.matches(&frame) && tx.send(Ok((i, frame))).is_err() {
+                return;
+            }
+        });
+    }
+    drop(tx);
+
+    loop {
+        let (port, frame) = match rx.recv_timeout(poll::INTERVAL) {
+            Ok(seen) => seen?,
+            Err(RecvTimeoutError::Timeout) if !signals::stopping() => continue,
+            Err(_) => break,
+        };
+
+        let now = SystemTime::now();
+        if let Some(t) = trace.as_mut() {
+            t.log(now, Direction::Rx, &frame)?;
+        }
+        if let Some(endpoint) = &mut influx_endpoint {
+            endpoint.send(influx::line(&frame, now, args.checksum()));
+        } else if monitor.influx.is_some() {
+            writeln!(out, "{}", influx::line(&frame, now, args.checksum()))?;
+        } else if args.format != Format::Text {
+            out.frame(&frame)?;
+        } else {
+            write!(out, "{} ", Timestamp(now))?;
+            // Frames of several ports are told apart by the port
+            if names.len() > 1 {
+                write!(out, "{} ", names[port])?;
+            }
+            writeln!(
+                out,
+                "{} | {}",
+                Hex(&frame),
+                FrameText(&frame, args.checksum())
+            )?;
+        }
+
+        if monitor.on_match.as_ref().is_none_or(|c| c.matches(&frame)) {
+            if let Some(hook) = &mut hook {
+                hook.run(&frame)?;
+            }
+            if let Some(webhook) = &mut webhook {
+                webhook.send(crate::describe_json(&frame, args.checksum()));
+            }
+        }
+    }
+
+    for endpoint in webhook.iter().chain(&influx_endpoint) {
+        if endpoint.dropped > 0 {
+            eprintln!(
+                "Dropped {} frames for {} while {} were waiting",
+                endpoint.dropped,
+                endpoint.url,
+                webhook::QUEUE
+            );
+        }
+    }
+    if let Some(hook) = hook.filter(|hook| hook.skipped > 0) {
+        eprintln!(
+            "Skipped the hook for {} frames while --max-hooks were running",
+            hook.skipped
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,374 @@
+//! Bridge between the bus and an MQTT broker.
+//!
+//! Every frame with a valid checksum is published as JSON to
+//! `<prefix>/<from>/frame`, see [`crate::describe_json`], button press
+//! responses additionally as count to `<prefix>/<from>/button_presses`.
+//! Publishing `ON` or `OFF` to `<prefix>/<id>/led/set` switches the LED of a
+//! device, signed like by the other commands if a key is known for it. Once
+//! the device answered, the new state is published to
+//! `<prefix>/<id>/led/state`.
+//!
+//! With `--ha-discovery` Home Assistant discovery payloads for the LED
+//! (switch) and the buttons (sensor) are published for every device when its
+//! first frame is seen, so nodes appear in Home Assistant by themselves.
+//!
+//! Only plain MQTT 3.1.1 without authentication and QoS 0 is spoken.
+
+use std::{
+    collections::{btree_map::Entry, BTreeMap, BTreeSet},
+    io::{self, Read, Write},
+    net::TcpStream,
+    sync::{mpsc, Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use clap::Args;
+use serialport::ErrorKind;
+
+use crate::{
+    config::Config,
+    describe, describe_json,
+    error::Error,
+    poll::{Event, Poller},
+    reader::FrameReader,
+    sdu::Response,
+    session::Session,
+    signals, CliArgs, LedState, Opcode, SetLed,
+};
+
+/// Seconds the broker waits for a packet before dropping the connection
+const KEEP_ALIVE: u16 = 60;
+
+#[derive(Args, Debug, Clone)]
+pub struct MqttBridge {
+    /// Address of the broker, `host` or `host:port`
+    broker: String,
+    /// Prefix of the topics of the devices
+    #[arg(long, default_value = "mmcp")]
+    prefix: String,
+    /// Client id at the broker
+    #[arg(long, default_value = "mmcp-bridge")]
+    client_id: String,
+    /// Publish Home Assistant discovery payloads for detected devices
+    #[arg(long)]
+    ha_discovery: bool,
+    /// Topic prefix Home Assistant reads discovery payloads from
+    #[arg(long, default_value = "homeassistant")]
+    discovery_prefix: String,
+}
+
+/// Connection to the broker, shared by the threads of the bridge
+#[derive(Clone)]
+struct Broker(Arc<Mutex<TcpStream>>);
+
+impl Broker {
+    fn send(&self, packet: &[u8]) -> io::Result<()> {
+        self.0
+            .lock()
+            .expect("broker lock poisoned")
+            .write_all(packet)
+    }
+
+    fn publish(&self, topic: &str, payload: &str, retain: bool) -> io::Result<()> {
+        let mut body = string(topic);
+        body.extend_from_slice(payload.as_bytes());
+        self.send(&packet(0x30 | retain as u8, &body))
+    }
+}
+
+/// An LED switched through the broker
+struct LedCommand {
+    id: u8,
+    state: LedState,
+    /// Payload of the command, published as state once the device answered
+    payload: String,
+}
+
+pub fn run(args: &CliArgs, bridge: &MqttBridge, config: &Config) -> Result<(), Error> {
+    let serial = crate::open(args)?;
+    let port = serial.try_clone()?;
+
+    let address = match bridge.broker.contains(':') {
+        true => bridge.broker.clone(),
+        false => format!("{}:1883", bridge.broker),
+    };
+    let mut stream = TcpStream::connect(&address)?;
+    let mut connect = string("MQTT");
+    // Protocol level 4 is MQTT 3.1.1, flag 0x02 a clean session
+    connect.extend([4, 0x02]);
+    connect.extend(KEEP_ALIVE.to_be_bytes());
+    connect.extend(string(&bridge.client_id));
+    stream.write_all(&packet(0x10, &connect))?;
+    match read_packet(&mut stream)? {
+        (0x20, body) if body.get(1) == Some(&0) => (),
+        (_, body) => {
+            return Err(serialport::Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Broker {} refused the connection, return code {:?}",
+                    address,
+                    body.get(1)
+                ),
+            )
+            .into())
+        }
+    }
+    let mut incoming = stream.try_clone()?;
+    let broker = Broker(Arc::new(Mutex::new(stream)));
+
+    let filter = format!("{}/+/led/set", bridge.prefix);
+    let mut subscribe = 1u16.to_be_bytes().to_vec();
+    subscribe.extend(string(&filter));
+    subscribe.push(0);
+    broker.send(&packet(0x82, &subscribe))?;
+    eprintln!("Bridging to {}, LEDs are set through {}", address, filter);
+
+    let prefix = bridge.prefix.clone();
+    let checksum = args.checksum();
+    let (failed, failure) = mpsc::channel();
+    let (commands, requested) = mpsc::channel();
+    thread::spawn(move || {
+        let result = handle_commands(&mut incoming, &prefix, &commands);
+        if let Err(e) = result {
+            // The bridge shuts down like on SIGTERM and reports the error
+            let _ = failed.send(e);
+            signals::stop();
+        }
+    });
+
+    let mut detected = BTreeSet::new();
+    // Sessions of the devices commanded, with their keys and counters
+    let mut sessions = BTreeMap::new();
+    let mut pending = BTreeMap::new();
+    let mut poller = Poller::new(FrameReader::new(serial).verify(checksum))?;
+    let ping = poller.every(Duration::from_secs(KEEP_ALIVE as u64 / 2));
+    loop {
+        for command in requested.try_iter() {
+            let LedCommand { id, state, payload } = command;
+            let session = match sessions.entry(id) {
+                Entry::Occupied(session) => session.into_mut(),
+                Entry::Vacant(entry) => {
+                    // Traces and output stay with the bridge
+                    let device_args = CliArgs {
+                        id: Some(id),
+                        group: None,
+                        trace_file: None,
+                        output: None,
+                        ..args.clone()
+                    };
+                    match Session::new(device_args, config.clone(), port.try_clone()?) {
+                        Ok(session) => entry.insert(session),
+                        Err(e) => {
+                            eprintln!("Can't switch the LED of device {}: {}", id, e);
+                            continue;
+                        }
+                    }
+                }
+            };
+            let sdu = SetLed {
+                on: state,
+                index: 0,
+            }
+            .as_sdu();
+            match session.frame(session.builder(Opcode::SetLed, sdu)) {
+                Ok(frame) => session.write(&frame)?,
+                Err(e) => {
+                    eprintln!("Can't switch the LED of device {}: {}", id, e);
+                    continue;
+                }
+            }
+            pending.insert(id, payload);
+        }
+
+        let frame = match poller.next()? {
+            Event::Frame(frame) => frame,
+            Event::Timer(timer) if timer == ping => {
+                broker.send(&[0xc0, 0])?;
+                continue;
+            }
+            // The reader dropped the partial frame, if any
+            Event::Idle | Event::Timer(_) => continue,
+            Event::Stop => {
+                if let Ok(e) = failure.try_recv() {
+                    return Err(serialport::Error::new(
+                        ErrorKind::Io(e.kind()),
+                        format!("Connection to the broker failed: {}", e),
+                    )
+                    .into());
+                }
+                broker.send(&[0xe0, 0])?;
+                return Ok(());
+            }
+        };
+        if args.echo {
+            eprintln!("RX {}", describe(&frame, checksum));
+        }
+
+        let from = frame[2];
+        if let (Some(session), Some(payload)) = (sessions.get_mut(&from), pending.get(&from)) {
+            let answer = matches!(
+                Opcode::from_byte(frame[5]),
+                Opcode::SetLed | Opcode::Rejected
+            );
+            match session.check_response(Opcode::SetLed, &frame) {
+                Ok(()) => {
+                    let topic = format!("{}/{}/led/state", bridge.prefix, from);
+                    broker.publish(&topic, payload, true)?;
+                    pending.remove(&from);
+                }
+                Err(e) if answer => {
+                    eprintln!("Device {} didn't switch its LED: {}", from, e);
+                    pending.remove(&from);
+                }
+                // Other frames of the device, like pushed telemetry
+                Err(_) => (),
+            }
+        }
+        if bridge.ha_discovery && detected.insert(from) {
+            discover(&broker, bridge, from)?;
+        }
+        broker.publish(
+            &format!("{}/{}/frame", bridge.prefix, from),
+            &describe_json(&frame, checksum),
+            false,
+        )?;
+        if Opcode::from_byte(frame[5]) == Opcode::ReadButtonPresses {
+            broker.publish(
+                &format!("{}/{}/button_presses", bridge.prefix, from),
+                &frame.sdu_u8(7).to_string(),
+                false,
+            )?;
+        }
+    }
+}
+
+/// Pass the LED commands of the broker on to the bridge
+fn handle_commands(
+    incoming: &mut TcpStream,
+    prefix: &str,
+    commands: &mpsc::Sender<LedCommand>,
+) -> io::Result<()> {
+    loop {
+        let (header, body) = read_packet(incoming)?;
+        if header & 0xf0 != 0x30 || body.len() < 2 {
+            continue;
+        }
+        let topic_len = u16::from_be_bytes([body[0], body[1]]) as usize;
+        let topic = String::from_utf8_lossy(body.get(2..2 + topic_len).unwrap_or_default());
+        // QoS 0 publishes carry no packet id, the payload follows the topic
+        let payload = String::from_utf8_lossy(body.get(2 + topic_len..).unwrap_or_default());
+
+        let id = topic
+            .strip_prefix(prefix)
+            .and_then(|t| t.strip_prefix('/'))
+            .and_then(|t| t.strip_suffix("/led/set"))
+            .and_then(|id| id.parse::<u8>().ok());
+        let id = match id {
+            Some(id) => id,
+            None => continue,
+        };
+        let state = match payload.trim() {
+            "ON" => LedState::On,
+            "OFF" => LedState::Off,
+            _ => {
+                eprintln!("Ignoring LED command {:?} for device {}", payload, id);
+                continue;
+            }
+        };
+
+        let payload = payload.trim().to_owned();
+        // The bridge is shutting down once it dropped the receiver
+        if commands.send(LedCommand { id, state, payload }).is_err() {
+            return Ok(());
+        }
+    }
+}
+
+/// Publish the Home Assistant discovery payloads of a device
+fn discover(broker: &Broker, bridge: &MqttBridge, id: u8) -> io::Result<()> {
+    let device = format!(
+        "\"device\":{{\"identifiers\":[\"mmcp_{id}\"],\"name\":\"MMCP node {id}\"}}",
+        id = id
+    );
+    let prefix = &bridge.prefix;
+    broker.publish(
+        &format!("{}/switch/mmcp_{}_led/config", bridge.discovery_prefix, id),
+        &format!(
+            "{{\"name\":\"LED\",\"unique_id\":\"mmcp_{id}_led\",\
+            \"command_topic\":\"{prefix}/{id}/led/set\",\
+            \"state_topic\":\"{prefix}/{id}/led/state\",{device}}}",
+            id = id,
+            prefix = prefix,
+            device = device
+        ),
+        true,
+    )?;
+    broker.publish(
+        &format!(
+            "{}/sensor/mmcp_{}_buttons/config",
+            bridge.discovery_prefix, id
+        ),
+        &format!(
+            "{{\"name\":\"Button presses\",\"unique_id\":\"mmcp_{id}_buttons\",\
+            \"state_topic\":\"{prefix}/{id}/button_presses\",{device}}}",
+            id = id,
+            prefix = prefix,
+            device = device
+        ),
+        true,
+    )?;
+    eprintln!("Published discovery payloads of device {}", id);
+
+    Ok(())
+}
+
+/// A packet with the fixed header byte and the remaining length
+fn packet(header: u8, body: &[u8]) -> Vec<u8> {
+    let mut packet = vec![header];
+    let mut len = body.len();
+    loop {
+        let byte = (len % 128) as u8;
+        len /= 128;
+        if len == 0 {
+            packet.push(byte);
+            break;
+        }
+        packet.push(byte | 0x80);
+    }
+    packet.extend_from_slice(body);
+    packet
+}
+
+/// A string prefixed with its length
+fn string(s: &str) -> Vec<u8> {
+    let mut bytes = (s.len() as u16).to_be_bytes().to_vec();
+    bytes.extend_from_slice(s.as_bytes());
+    bytes
+}
+
+fn read_packet(stream: &mut TcpStream) -> io::Result<(u8, Vec<u8>)> {
+    let mut byte = [0u8; 1];
+    stream.read_exact(&mut byte)?;
+    let header = byte[0];
+
+    let (mut len, mut shift) = (0usize, 0);
+    loop {
+        stream.read_exact(&mut byte)?;
+        len |= ((byte[0] & 0x7f) as usize) << shift;
+        if byte[0] & 0x80 == 0 {
+            break;
+        }
+        shift += 7;
+        if shift > 21 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Invalid packet length",
+            ));
+        }
+    }
+
+    let mut body = vec![0u8; len];
+    stream.read_exact(&mut body)?;
+    Ok((header, body))
+}
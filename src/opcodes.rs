@@ -0,0 +1,80 @@
+//! Probing which opcodes a firmware answers.
+//!
+//! Firmwares have no way to enumerate their opcodes, so every opcode of
+//! [`crate::OPCODE_RANGES`] is sent with a zero SDU and the ones answered
+//! with the same opcode are listed. Known opcodes which change the device,
+//! like switching the LED or writing memory, are left out unless
+//! `--include-writes` is given, the others are harmless with a zero SDU.
+//! Unknown opcodes are probed as well, firmwares drop or reject those they
+//! don't know.
+
+use std::io::Write;
+
+use clap::Args;
+use serialport::{ClearBuffer, ErrorKind};
+
+use crate::{error::Error, rejection::Reason, session::Session, L7Sdu, Opcode, OPCODE_RANGES};
+
+#[derive(Args, Debug, Clone, Copy)]
+pub struct ListOpcodes {
+    /// Also probe the known opcodes which change the device, e.g. poking
+    /// memory at address 0
+    #[arg(long)]
+    include_writes: bool,
+}
+
+pub fn run(session: &mut Session, list: &ListOpcodes) -> Result<(), Error> {
+    let mut skipped = Vec::new();
+    for opcode in OPCODE_RANGES.iter().cloned().flatten() {
+        if !list.include_writes && changes_device(Opcode::from_byte(opcode)) {
+            skipped.push(opcode);
+            continue;
+        }
+
+        let mut msg = [0u8; 16];
+        let builder = session.builder(Opcode::from_byte(opcode), L7Sdu::default());
+        let answered = match (session.transact(builder, &mut msg), session.rejection) {
+            (Ok(()), _) => true,
+            // A late response to the opcode before
+            (Err(_), None) if session.stray.is_some() => false,
+            // Refused for other reasons than not knowing it, e.g. a bad parameter
+            (Err(_), Some(rejection)) => rejection.reason != Reason::UnknownOpcode,
+            (Err(e), None) if e.kind == ErrorKind::Io(std::io::ErrorKind::TimedOut) => {
+                session.serial.clear(ClearBuffer::Input)?;
+                false
+            }
+            (Err(e), None) => return Err(e.into()),
+        };
+        if answered {
+            let name = Opcode::from_byte(opcode).name().unwrap_or("unknown");
+            writeln!(session.out, "{:>3}  {}", opcode, name)?;
+        }
+    }
+
+    if !skipped.is_empty() {
+        eprintln!(
+            "Skipped {:?}, they change the device, probe them with --include-writes",
+            skipped
+        );
+    }
+    Ok(())
+}
+
+/// Whether the opcode changes the state of the device even with a zero SDU
+fn changes_device(opcode: Opcode) -> bool {
+    matches!(
+        opcode,
+        Opcode::SetLed
+            | Opcode::DisplayNumber
+            | Opcode::SetServo
+            | Opcode::SetRelay
+            | Opcode::Pair
+            | Opcode::RotateKey
+            | Opcode::TransferData
+            | Opcode::TransferEnd
+            | Opcode::TransferOpen
+            | Opcode::PokeU8
+            | Opcode::PokeU16
+            | Opcode::PokeU32
+    )
+}
@@ -0,0 +1,731 @@
+//! Rendering of results for humans or for other programs.
+//!
+//! Results go to stdout, or with `--output` to a file. The file is written
+//! next to its destination and moved into place when the command is done,
+//! so it never holds a partial result. With `--append` results are appended
+//! instead, a line at a time, like lines of commands running until they are
+//! interrupted, e.g. `monitor`.
+//!
+//! Response frames are rendered here in the `--format` chosen, so all
+//! commands print them alike. `text` and `table` are meant for humans, the
+//! other formats render a frame per line, so output streamed by `monitor`
+//! stays parseable when cut off. `cbor` is binary, a sequence of maps
+//! (RFC 8742) with the SDU as byte string, each written at once.
+//!
+//! Commands decoding their responses, like `status`, render the decoded
+//! values in `json`, `csv`, `yaml` and `cbor` and their frames in the other
+//! formats. Summaries, listings and dumps are text only, other formats are
+//! refused for them, see [`supports`].
+
+use std::{
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::PathBuf,
+};
+
+use clap::ValueEnum;
+use serialport::ErrorKind;
+
+use crate::{
+    base64,
+    capabilities::{Capabilities, Capability},
+    describe,
+    error::Error,
+    health::{self, Status},
+    rejection::Reason,
+    relays,
+    sdu::Response,
+    transfer, ChecksumAlgorithm, CliArgs, Command, FrameJson, FrameText, Hex, Opcode,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// Decoded for humans
+    Text,
+    /// A table of the fields per frame, see [`table`]
+    Table,
+    /// An object per frame
+    Json,
+    /// The raw bytes per frame
+    Hex,
+    /// A row per frame, after a header
+    Csv,
+    /// A sequence item per frame
+    Yaml,
+    /// The raw bytes per frame in base64
+    Base64,
+    /// A binary CBOR map per frame
+    Cbor,
+}
+
+impl Format {
+    /// The name like given to `--format`
+    pub fn name(self) -> &'static str {
+        match self {
+            Format::Text => "text",
+            Format::Table => "table",
+            Format::Json => "json",
+            Format::Hex => "hex",
+            Format::Csv => "csv",
+            Format::Yaml => "yaml",
+            Format::Base64 => "base64",
+            Format::Cbor => "cbor",
+        }
+    }
+}
+
+/// A decoded value of the results of a command, see [`Output::record`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Bool(bool),
+    Int(u64),
+    Float(f64),
+    List(Vec<u64>),
+}
+
+/// Scalars as in JSON and YAML, lists as JSON array
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Int(n) => write!(f, "{}", n),
+            Value::Float(x) => write!(f, "{}", x),
+            Value::List(items) => {
+                let items: Vec<String> = items.iter().map(u64::to_string).collect();
+                write!(f, "[{}]", items.join(","))
+            }
+        }
+    }
+}
+
+/// The decoded values of the responses of commands which have any
+fn fields(cmd: &Command, responses: &[[u8; 16]]) -> Option<Vec<(String, Value)>> {
+    let field = |name: &str, value| (name.to_owned(), value);
+    Some(match (cmd, responses) {
+        (Command::Status, [msg, ..]) => {
+            let status = Status::parse(msg);
+            vec![
+                field("brown_out", Value::Bool(status.brown_out)),
+                field("watchdog_reset", Value::Bool(status.watchdog_reset)),
+                field("buffer_overruns", Value::Int(status.buffer_overruns.into())),
+                field("last_error", Value::Int(status.last_error.into())),
+            ]
+        }
+        (Command::Uptime(uptime), [msg, ..]) => {
+            let ticks = health::value(msg);
+            let mut fields = vec![field("ticks", Value::Int(ticks.into()))];
+            // A tick too long to count the ticks in a duration leaves the ticks alone
+            if let Some(duration) = uptime.tick.checked_mul(ticks) {
+                fields.push(field("seconds", Value::Float(duration.as_secs_f64())));
+            }
+            fields
+        }
+        (Command::Stats(_), [received, checksum_errors, dropped, ..]) => vec![
+            field("received", Value::Int(health::value(received).into())),
+            field(
+                "checksum_errors",
+                Value::Int(health::value(checksum_errors).into()),
+            ),
+            field("dropped", Value::Int(health::value(dropped).into())),
+        ],
+        (Command::GetRelays, [msg, ..]) => {
+            let on = relays::on(msg).into_iter().map(u64::from).collect();
+            vec![field("on", Value::List(on))]
+        }
+        (Command::Capabilities, [msg, ..]) => {
+            let capabilities = Capabilities::parse(msg);
+            let mut fields: Vec<(String, Value)> = Capability::ALL
+                .iter()
+                .map(|c| (c.name().to_lowercase(), Value::Bool(capabilities.has(*c))))
+                .collect();
+            fields.push(field(
+                "unknown_bits",
+                Value::Int(capabilities.unknown().into()),
+            ));
+            fields
+        }
+        _ => return None,
+    })
+}
+
+/// Destination of the results of a command
+pub struct Output {
+    sink: Sink,
+    /// Bytes of the line not complete yet
+    line: Vec<u8>,
+    format: Format,
+    /// Algorithm the checksums of rendered frames are verified with
+    checksum: ChecksumAlgorithm,
+    /// Offset of the sequence number in the SDU of transfer frames
+    seq_at: usize,
+    /// Whether the CSV header was written already
+    header: bool,
+}
+
+enum Sink {
+    Stdout,
+    File {
+        file: File,
+        path: PathBuf,
+        /// Path written until the output is finished, if it replaces `path`
+        partial: Option<PathBuf>,
+    },
+}
+
+impl Output {
+    pub fn open(args: &CliArgs) -> Result<Self, serialport::Error> {
+        let path = match &args.output {
+            Some(path) => path.clone(),
+            None => {
+                return Ok(Self {
+                    seq_at: transfer::seq_offset(args),
+                    ..Self::stdout(args.format, args.checksum())
+                })
+            }
+        };
+        let streaming = matches!(args.cmd, Command::Monitor(_));
+        let partial = match args.append || streaming {
+            true => None,
+            false => {
+                let mut partial = path.clone().into_os_string();
+                partial.push(".partial");
+                Some(PathBuf::from(partial))
+            }
+        };
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(args.append)
+            .write(true)
+            .truncate(!args.append)
+            .open(partial.as_ref().unwrap_or(&path))
+            .map_err(|e| {
+                serialport::Error::new(
+                    ErrorKind::Io(e.kind()),
+                    format!("Could not open output {}: {}", path.display(), e),
+                )
+            })?;
+        let file_len = file.metadata().map_or(0, |m| m.len());
+
+        Ok(Self {
+            sink: Sink::File {
+                file,
+                path,
+                partial,
+            },
+            line: Vec::new(),
+            format: args.format,
+            checksum: args.checksum(),
+            seq_at: transfer::seq_offset(args),
+            // Appended rows go below the header written before
+            header: args.append && file_len > 0,
+        })
+    }
+
+    pub fn stdout(format: Format, checksum: ChecksumAlgorithm) -> Self {
+        Self {
+            sink: Sink::Stdout,
+            line: Vec::new(),
+            format,
+            checksum,
+            seq_at: 0,
+            header: false,
+        }
+    }
+
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    pub fn checksum(&self) -> ChecksumAlgorithm {
+        self.checksum
+    }
+
+    pub fn seq_offset(&self) -> usize {
+        self.seq_at
+    }
+
+    /// Render the responses of a command
+    pub fn report(&mut self, cmd: &Command, responses: &[[u8; 16]]) -> io::Result<()> {
+        match self.format {
+            Format::Text => return self.text(cmd, responses),
+            Format::Json | Format::Csv | Format::Yaml | Format::Cbor => {
+                if let Some(fields) = fields(cmd, responses) {
+                    return self.record(&fields);
+                }
+            }
+            Format::Table | Format::Hex | Format::Base64 => (),
+        }
+        responses.iter().try_for_each(|frame| self.frame(frame))
+    }
+
+    /// Render the responses of a command for humans
+    fn text(&mut self, cmd: &Command, responses: &[[u8; 16]]) -> io::Result<()> {
+        match (cmd, responses) {
+            (Command::ReadButtonPresses, [msg, ..]) => {
+                writeln!(self, "Button Presses: {}", msg.sdu_u8(7))?
+            }
+            (Command::ReadUid, [msg, ..]) => {
+                writeln!(self, "UID: {:08x}", msg.sdu_u64_be() as u32)?
+            }
+            (Command::GetLed(get_led), [msg, ..]) => {
+                let state = if msg.sdu_u8(7) == 1 { "on" } else { "off" };
+                match get_led.index {
+                    0 => writeln!(self, "LED: {}", state)?,
+                    index => writeln!(self, "LED {}: {}", index, state)?,
+                }
+            }
+            (Command::Status, [msg, ..]) => return self.status(Status::parse(msg)),
+            (Command::Uptime(uptime), [msg, ..]) => {
+                let ticks = health::value(msg);
+                return match uptime.tick.checked_mul(ticks) {
+                    Some(duration) => {
+                        writeln!(self, "{} ({} ticks)", health::humanize(duration), ticks)
+                    }
+                    None => writeln!(self, "{} ticks", ticks),
+                };
+            }
+            (Command::Stats(_), [_, _, _, ..]) => {
+                for (name, counter) in health::COUNTERS.iter().zip(responses) {
+                    writeln!(self, "{:<16} {}", name, health::value(counter))?;
+                }
+                return Ok(());
+            }
+            (Command::GetRelays, [msg, ..]) => {
+                let on: Vec<String> = relays::on(msg).iter().map(u8::to_string).collect();
+                return match on.is_empty() {
+                    true => writeln!(self, "Relays on: none"),
+                    false => writeln!(self, "Relays on: {}", on.join(", ")),
+                };
+            }
+            (Command::Capabilities, [msg, ..]) => {
+                return self.capabilities(Capabilities::parse(msg))
+            }
+            // The answers only acknowledge the digits, text or positions
+            (Command::DisplayNumber(_) | Command::LcdWrite(_) | Command::SetServo(_), _) => {
+                return Ok(())
+            }
+            _ => (),
+        }
+        if responses.len() > 1 {
+            for (i, frame) in responses.iter().enumerate() {
+                writeln!(self, "Frame {}: {}", i + 1, describe(frame, self.checksum))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn status(&mut self, status: Status) -> io::Result<()> {
+        let yes_no = |set: bool| if set { "yes" } else { "no" };
+        writeln!(self, "brown-out        {}", yes_no(status.brown_out))?;
+        writeln!(self, "watchdog reset   {}", yes_no(status.watchdog_reset))?;
+        writeln!(self, "buffer overruns  {}", status.buffer_overruns)?;
+        match status.last_error {
+            0 => writeln!(self, "last error       none")?,
+            code => writeln!(self, "last error       {}", code)?,
+        }
+        if status.unknown_flags != 0 {
+            writeln!(self, "unknown flags    {:#04x}", status.unknown_flags)?;
+        }
+        Ok(())
+    }
+
+    fn capabilities(&mut self, capabilities: Capabilities) -> io::Result<()> {
+        for capability in Capability::ALL {
+            let supported = match capabilities.has(capability) {
+                true => "yes",
+                false => "no",
+            };
+            writeln!(self, "{:<10}  {}", capability.name(), supported)?;
+        }
+        if capabilities.unknown() != 0 {
+            writeln!(self, "unknown bits {:#06x}", capabilities.unknown())?;
+        }
+        Ok(())
+    }
+
+    /// Render decoded values like a frame in the formats for programs, an
+    /// object, a row after a header, a sequence item or a CBOR map
+    pub fn record(&mut self, fields: &[(String, Value)]) -> io::Result<()> {
+        match self.format {
+            Format::Json => {
+                let fields: Vec<String> = fields
+                    .iter()
+                    .map(|(name, value)| format!("{}:{}", json_string(name), value))
+                    .collect();
+                writeln!(self, "{{{}}}", fields.join(","))
+            }
+            Format::Csv => {
+                if !self.header {
+                    self.header = true;
+                    let names: Vec<&str> = fields.iter().map(|(name, _)| name.as_str()).collect();
+                    writeln!(self, "{}", names.join(","))?;
+                }
+                let values: Vec<String> = fields
+                    .iter()
+                    .map(|(_, value)| match value {
+                        // Lists stay in their column
+                        Value::List(items) => {
+                            let items: Vec<String> = items.iter().map(u64::to_string).collect();
+                            items.join(" ")
+                        }
+                        value => value.to_string(),
+                    })
+                    .collect();
+                writeln!(self, "{}", values.join(","))
+            }
+            Format::Yaml => {
+                let fields: Vec<String> = fields
+                    .iter()
+                    .map(|(name, value)| match value {
+                        Value::List(items) => {
+                            let items: Vec<String> = items.iter().map(u64::to_string).collect();
+                            format!("{}: [{}]", name, items.join(", "))
+                        }
+                        value => format!("{}: {}", name, value),
+                    })
+                    .collect();
+                writeln!(self, "- {{{}}}", fields.join(", "))
+            }
+            Format::Cbor => {
+                let mut out = Vec::new();
+                cbor_head(&mut out, 5, fields.len() as u64);
+                for (name, value) in fields {
+                    cbor_head(&mut out, 3, name.len() as u64);
+                    out.extend_from_slice(name.as_bytes());
+                    match value {
+                        Value::Bool(b) => out.push(if *b { 0xf5 } else { 0xf4 }),
+                        Value::Int(n) => cbor_head(&mut out, 0, *n),
+                        Value::Float(f) => {
+                            out.push(0xfb);
+                            out.extend_from_slice(&f.to_be_bytes());
+                        }
+                        Value::List(items) => {
+                            cbor_head(&mut out, 4, items.len() as u64);
+                            for item in items {
+                                cbor_head(&mut out, 0, *item);
+                            }
+                        }
+                    }
+                }
+                self.write_all(&out)?;
+                self.flush()
+            }
+            format => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("{} renders frames, these results have none", format.name()),
+            )),
+        }
+    }
+
+    /// Render a single frame
+    pub fn frame(&mut self, frame: &[u8; 16]) -> io::Result<()> {
+        let valid = self.checksum.compute(&frame[1..14]) == frame[14];
+        let sdu = frame.sdu();
+        match self.format {
+            Format::Text => writeln!(self, "{}", FrameText(frame, self.checksum)),
+            // A blank line separates the tables of several frames
+            Format::Table => writeln!(self, "{}\n", table(frame, self.checksum, self.seq_at)),
+            Format::Json => writeln!(self, "{}", FrameJson(frame, self.checksum)),
+            Format::Hex => writeln!(self, "{}", Hex(frame)),
+            Format::Csv => {
+                if !self.header {
+                    self.header = true;
+                    writeln!(self, "to,from,version,hops,opcode,sdu,checksum_ok")?;
+                }
+                write!(
+                    self,
+                    "{},{},{},{},{},",
+                    frame[1], frame[2], frame[3], frame[4], frame[5]
+                )?;
+                for b in sdu {
+                    write!(self, "{:02x}", b)?;
+                }
+                writeln!(self, ",{}", valid)
+            }
+            Format::Base64 => writeln!(self, "{}", base64::encode(frame)),
+            Format::Cbor => {
+                self.write_all(&cbor(frame, valid))?;
+                // Maps may contain no newline to complete them
+                self.flush()
+            }
+            Format::Yaml => {
+                write!(
+                    self,
+                    "- {{to: {}, from: {}, version: {}, hops: {}, opcode: {}, sdu: [",
+                    frame[1], frame[2], frame[3], frame[4], frame[5]
+                )?;
+                for (i, b) in sdu.iter().enumerate() {
+                    match i {
+                        0 => write!(self, "{}", b)?,
+                        _ => write!(self, ", {}", b)?,
+                    }
+                }
+                writeln!(self, "], checksum_ok: {}}}", valid)
+            }
+        }
+    }
+
+    /// Move the results into place, the output is done afterwards
+    pub fn finish(&mut self) -> Result<(), serialport::Error> {
+        self.flush()?;
+        if let Sink::File {
+            file,
+            path,
+            partial: Some(partial),
+        } = &mut self.sink
+        {
+            file.sync_all()?;
+            fs::rename(&*partial, &*path).map_err(|e| {
+                serialport::Error::new(
+                    ErrorKind::Io(e.kind()),
+                    format!("Could not write output {}: {}", path.display(), e),
+                )
+            })?;
+            self.sink = Sink::Stdout;
+        }
+
+        Ok(())
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let file = match &mut self.sink {
+            Sink::Stdout => return io::stdout().write(buf),
+            Sink::File { file, .. } => file,
+        };
+        self.line.extend_from_slice(buf);
+        // Complete lines are written at once, so appended lines never tear
+        if let Some(end) = self.line.iter().rposition(|b| *b == b'\n') {
+            file.write_all(&self.line[..=end])?;
+            self.line.drain(..=end);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.sink {
+            Sink::Stdout => io::stdout().flush(),
+            Sink::File { file, .. } => {
+                file.write_all(&self.line)?;
+                self.line.clear();
+                file.flush()
+            }
+        }
+    }
+}
+
+/// Fail unless the command renders its results in `format`. Summaries,
+/// listings and dumps are written for humans only
+pub fn supports(cmd: &Command, format: Format) -> Result<(), serialport::Error> {
+    let text_only = match cmd {
+        Command::Key(key) => key.is_status(),
+        Command::BerTest(_)
+        | Command::MeshFlood(_)
+        | Command::Group(_)
+        | Command::ListOpcodes(_)
+        | Command::Upload(_)
+        | Command::Download(_)
+        | Command::Peek(_)
+        | Command::DumpFlash(_)
+        | Command::Verify(_)
+        | Command::ReadReg(_)
+        | Command::Replay(_)
+        | Command::Diff(_)
+        | Command::ListPorts
+        | Command::Remote(_) => true,
+        _ => false,
+    };
+    match text_only && format != Format::Text {
+        true => Err(serialport::Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "--format {} is not supported by this command, it writes text only",
+                format.name()
+            ),
+        )),
+        false => Ok(()),
+    }
+}
+
+/// Print an error to stderr
+pub fn error(e: &Error, format: Format, device: Option<&str>, command: Option<&str>) {
+    match format {
+        Format::Json => {
+            let received = match e {
+                Error::Context { received, .. } => {
+                    let bytes: Vec<String> = received.iter().map(|b| b.to_string()).collect();
+                    format!("[{}]", bytes.join(","))
+                }
+                _ => "null".to_owned(),
+            };
+            eprintln!(
+                "{{\"kind\":{},\"message\":{},\"os_error\":{},\"exit_code\":{},\
+                \"context\":{{\"device\":{},\"command\":{},\"received\":{}}}}}",
+                json_string(&e.kind()),
+                json_string(&e.message()),
+                e.os_error().map_or("null".to_owned(), |c| c.to_string()),
+                e.exit_code(),
+                device.map_or("null".to_owned(), json_string),
+                command.map_or("null".to_owned(), json_string),
+                received
+            );
+        }
+        _ => eprintln!("{}", e),
+    }
+}
+
+/// Fields of a frame as aligned table of their offset, bytes, value and
+/// meaning, the checksum verified with `algorithm`. Transfer sequence numbers
+/// are read at SDU offset `seq_at`, see [`transfer::seq_offset`].
+pub fn table(frame: &[u8; 16], algorithm: ChecksumAlgorithm, seq_at: usize) -> String {
+    let expected = algorithm.compute(&frame[1..14]);
+    let marker = |b: u8| match b {
+        0 => "frame marker".to_owned(),
+        b => format!("frame marker, expected 00 not {:02x}", b),
+    };
+
+    let mut rows = vec![
+        ("start".to_owned(), marker(frame[0])),
+        ("to".to_owned(), "destination address".to_owned()),
+        ("from".to_owned(), "source address".to_owned()),
+        ("version".to_owned(), "protocol version".to_owned()),
+        ("hops".to_owned(), "hops travelled".to_owned()),
+        (
+            "opcode".to_owned(),
+            Opcode::from_byte(frame[5])
+                .name()
+                .unwrap_or("unknown opcode")
+                .to_owned(),
+        ),
+    ];
+    for i in 0..8 {
+        let meaning = match (Opcode::from_byte(frame[5]), i) {
+            (Opcode::SetLed, 7) => match frame.sdu_u8(7) {
+                0 => "LED off".to_owned(),
+                1 => "LED on".to_owned(),
+                2 => "toggle LED".to_owned(),
+                state => format!("unknown LED state {}", state),
+            },
+            (Opcode::SetLed | Opcode::GetLed, 6) => format!("LED {}", frame.sdu_u8(6)),
+            (Opcode::GetLed, 7) => {
+                format!("LED {}", if frame.sdu_u8(7) == 1 { "on" } else { "off" })
+            }
+            (Opcode::ReadButtonPresses, 7) => format!("{} button presses", frame.sdu_u8(7)),
+            (Opcode::KeyStatus, 7) => match frame.sdu_u8(7) {
+                0 => "device unpaired".to_owned(),
+                _ => "device paired".to_owned(),
+            },
+            (Opcode::Rejected, 6) => match Opcode::from_byte(frame.sdu_u8(6)).name() {
+                Some(name) => format!("rejected {}", name),
+                None => "rejected opcode".to_owned(),
+            },
+            (Opcode::Rejected, 7) => Reason::from(frame.sdu_u8(7)).to_string(),
+            (Opcode::TransferData, i) if i == seq_at => format!(
+                "sequence number {}",
+                u16::from_be_bytes([frame[6 + seq_at], frame[7 + seq_at]])
+            ),
+            _ => String::new(),
+        };
+        rows.push((format!("sdu[{}]", i), meaning));
+    }
+    rows.push((
+        "checksum".to_owned(),
+        match frame[14] == expected {
+            true => "ok".to_owned(),
+            false => format!("bad, expected {:02x}", expected),
+        },
+    ));
+    rows.push(("end".to_owned(), marker(frame[15])));
+
+    let mut out = format!(
+        "{:<9} {:>6}  {:<3} {:>5}  {}",
+        "field", "offset", "hex", "value", "meaning"
+    );
+    for (offset, ((field, meaning), byte)) in rows.iter().zip(frame).enumerate() {
+        let row = format!(
+            "{:<9} {:>6}  {:<3} {:>5}  {}",
+            field,
+            offset,
+            format!("{:02x}", byte),
+            byte,
+            meaning
+        );
+        out.push('\n');
+        out.push_str(row.trim_end());
+    }
+    out
+}
+
+/// Append the initial bytes of a CBOR item of the major type with the
+/// argument `n`, a length or an unsigned value
+fn cbor_head(out: &mut Vec<u8>, major: u8, n: u64) {
+    let major = major << 5;
+    match n {
+        0..=23 => out.push(major | n as u8),
+        24..=0xff => out.extend([major | 24, n as u8]),
+        0x100..=0xffff => {
+            out.push(major | 25);
+            out.extend_from_slice(&(n as u16).to_be_bytes());
+        }
+        0x1_0000..=0xffff_ffff => {
+            out.push(major | 26);
+            out.extend_from_slice(&(n as u32).to_be_bytes());
+        }
+        _ => {
+            out.push(major | 27);
+            out.extend_from_slice(&n.to_be_bytes());
+        }
+    }
+}
+
+/// Fields of a frame as CBOR map
+fn cbor(frame: &[u8; 16], valid: bool) -> Vec<u8> {
+    // Major type 5 (map) with 7 pairs
+    let mut out = vec![0xa7];
+    let key = |out: &mut Vec<u8>, name: &str| {
+        // Major type 3 (text string), short names fit into the initial byte
+        out.push(0x60 | name.len() as u8);
+        out.extend_from_slice(name.as_bytes());
+    };
+    for (name, value) in ["to", "from", "version", "hops", "opcode"]
+        .iter()
+        .zip(&frame[1..6])
+    {
+        key(&mut out, name);
+        // Major type 0 (unsigned), values from 24 on take a byte
+        if *value >= 24 {
+            out.push(0x18);
+        }
+        out.push(*value);
+    }
+    key(&mut out, "sdu");
+    // Major type 2 (byte string) of 8 bytes
+    out.push(0x48);
+    out.extend_from_slice(&frame.sdu());
+    key(&mut out, "checksum_ok");
+    out.push(if valid { 0xf5 } else { 0xf4 });
+    out
+}
+
+/// A string as JSON string literal
+pub fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
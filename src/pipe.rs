@@ -0,0 +1,252 @@
+//! Named pipes on Windows, the counterpart of Unix sockets for the daemon.
+//!
+//! A pipe `pipe:<name>` is `\\.\pipe\<name>`, unless the name is a full
+//! path already. Only the user running the daemon and the system may open
+//! it, clients of other machines are rejected. Creating the first instance
+//! fails if another process owns the name, so nobody can take the pipe of a
+//! daemon before it.
+
+use std::{
+    ffi::c_void,
+    fs::File,
+    io::{self, Read, Write},
+    os::windows::{
+        ffi::OsStrExt,
+        io::{AsRawHandle, FromRawHandle, OwnedHandle, RawHandle},
+    },
+    ptr, thread,
+    time::{Duration, Instant},
+};
+
+const PIPE_ACCESS_DUPLEX: u32 = 0x3;
+const FILE_FLAG_FIRST_PIPE_INSTANCE: u32 = 0x0008_0000;
+const PIPE_WAIT: u32 = 0x0;
+const PIPE_NOWAIT: u32 = 0x1;
+const PIPE_REJECT_REMOTE_CLIENTS: u32 = 0x8;
+const PIPE_UNLIMITED_INSTANCES: u32 = 255;
+const BUFFER_SIZE: u32 = 4096;
+const ERROR_BROKEN_PIPE: i32 = 109;
+const ERROR_PIPE_CONNECTED: i32 = 535;
+const ERROR_PIPE_LISTENING: i32 = 536;
+const SDDL_REVISION_1: u32 = 1;
+/// Full access for the system and the owner, the user creating the pipe
+const OWNER_ONLY: &str = "D:P(A;;GA;;;SY)(A;;GA;;;OW)";
+
+/// How long a read waits for data, so the daemon notices signals
+const READ_TIMEOUT: Duration = Duration::from_millis(200);
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+#[repr(C)]
+struct SecurityAttributes {
+    length: u32,
+    descriptor: *mut c_void,
+    inherit: i32,
+}
+
+#[link(name = "kernel32")]
+extern "system" {
+    fn CreateNamedPipeW(
+        name: *const u16,
+        open_mode: u32,
+        pipe_mode: u32,
+        max_instances: u32,
+        out_buffer_size: u32,
+        in_buffer_size: u32,
+        default_timeout: u32,
+        security: *const SecurityAttributes,
+    ) -> RawHandle;
+    fn ConnectNamedPipe(pipe: RawHandle, overlapped: *mut c_void) -> i32;
+    fn SetNamedPipeHandleState(
+        pipe: RawHandle,
+        mode: *const u32,
+        max_collection_count: *const u32,
+        collect_data_timeout: *const u32,
+    ) -> i32;
+    fn PeekNamedPipe(
+        pipe: RawHandle,
+        buffer: *mut c_void,
+        buffer_size: u32,
+        read: *mut u32,
+        available: *mut u32,
+        left_of_message: *mut u32,
+    ) -> i32;
+    fn LocalFree(memory: *mut c_void) -> *mut c_void;
+}
+
+#[link(name = "advapi32")]
+extern "system" {
+    fn ConvertStringSecurityDescriptorToSecurityDescriptorW(
+        sddl: *const u16,
+        revision: u32,
+        descriptor: *mut *mut c_void,
+        size: *mut u32,
+    ) -> i32;
+}
+
+/// The path of the pipe `name`
+pub fn path(name: &str) -> String {
+    match name.starts_with(r"\\") {
+        true => name.to_owned(),
+        false => format!(r"\\.\pipe\{}", name),
+    }
+}
+
+fn wide(s: &str) -> Vec<u16> {
+    std::ffi::OsStr::new(s)
+        .encode_wide()
+        .chain(Some(0))
+        .collect()
+}
+
+/// Instances of a named pipe waiting for clients
+pub struct PipeListener {
+    name: Vec<u16>,
+    descriptor: *mut c_void,
+    /// The instance the next client connects to
+    waiting: OwnedHandle,
+}
+
+impl PipeListener {
+    pub fn bind(name: &str) -> io::Result<Self> {
+        let mut descriptor = ptr::null_mut();
+        // SAFETY: The SDDL is null terminated, the descriptor is freed on drop
+        let converted = unsafe {
+            ConvertStringSecurityDescriptorToSecurityDescriptorW(
+                wide(OWNER_ONLY).as_ptr(),
+                SDDL_REVISION_1,
+                &mut descriptor,
+                ptr::null_mut(),
+            )
+        };
+        if converted == 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let name = wide(&path(name));
+        let waiting = match create(&name, descriptor, FILE_FLAG_FIRST_PIPE_INSTANCE) {
+            Ok(waiting) => waiting,
+            Err(e) => {
+                // SAFETY: Allocated by the conversion above
+                unsafe { LocalFree(descriptor) };
+                return Err(e);
+            }
+        };
+        Ok(PipeListener {
+            name,
+            descriptor,
+            waiting,
+        })
+    }
+
+    /// The next client, [`io::ErrorKind::WouldBlock`] if none is waiting
+    pub fn accept(&mut self) -> io::Result<Pipe> {
+        // SAFETY: The handle is a pipe instance in non blocking mode
+        if unsafe { ConnectNamedPipe(self.waiting.as_raw_handle(), ptr::null_mut()) } == 0 {
+            let e = io::Error::last_os_error();
+            match e.raw_os_error() {
+                Some(ERROR_PIPE_CONNECTED) => (),
+                Some(ERROR_PIPE_LISTENING) => return Err(io::ErrorKind::WouldBlock.into()),
+                _ => return Err(e),
+            }
+        }
+
+        let next = create(&self.name, self.descriptor, 0)?;
+        let connected = std::mem::replace(&mut self.waiting, next);
+        // SAFETY: Only the mode is set, the other pointers may be null
+        let blocking = unsafe {
+            SetNamedPipeHandleState(
+                connected.as_raw_handle(),
+                &PIPE_WAIT,
+                ptr::null(),
+                ptr::null(),
+            )
+        };
+        if blocking == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Pipe(File::from(connected)))
+    }
+}
+
+impl Drop for PipeListener {
+    fn drop(&mut self) {
+        // SAFETY: Allocated by the conversion in `bind`
+        unsafe { LocalFree(self.descriptor) };
+    }
+}
+
+/// A new instance of the pipe, not blocking while waiting for a client
+fn create(name: &[u16], descriptor: *mut c_void, flags: u32) -> io::Result<OwnedHandle> {
+    let security = SecurityAttributes {
+        length: std::mem::size_of::<SecurityAttributes>() as u32,
+        descriptor,
+        inherit: 0,
+    };
+    // SAFETY: The name is null terminated and the attributes outlive the call
+    let handle = unsafe {
+        CreateNamedPipeW(
+            name.as_ptr(),
+            PIPE_ACCESS_DUPLEX | flags,
+            PIPE_NOWAIT | PIPE_REJECT_REMOTE_CLIENTS,
+            PIPE_UNLIMITED_INSTANCES,
+            BUFFER_SIZE,
+            BUFFER_SIZE,
+            0,
+            &security,
+        )
+    };
+    // INVALID_HANDLE_VALUE
+    if handle as isize == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: A valid handle owned by nobody else
+    Ok(unsafe { OwnedHandle::from_raw_handle(handle) })
+}
+
+/// The connection of a client, reads time out like those of sockets
+pub struct Pipe(File);
+
+impl Read for Pipe {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let started = Instant::now();
+        loop {
+            let mut available = 0;
+            // SAFETY: Only the number of available bytes is written
+            let peeked = unsafe {
+                PeekNamedPipe(
+                    self.0.as_raw_handle(),
+                    ptr::null_mut(),
+                    0,
+                    ptr::null_mut(),
+                    &mut available,
+                    ptr::null_mut(),
+                )
+            };
+            if peeked == 0 {
+                let e = io::Error::last_os_error();
+                return match e.raw_os_error() {
+                    // The client disconnected
+                    Some(ERROR_BROKEN_PIPE) => Ok(0),
+                    _ => Err(e),
+                };
+            }
+            if available > 0 {
+                return self.0.read(buf);
+            }
+            if started.elapsed() >= READ_TIMEOUT {
+                return Err(io::ErrorKind::TimedOut.into());
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+impl Write for Pipe {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
@@ -0,0 +1,68 @@
+//! Pipelined dispatch of many requests.
+//!
+//! Up to `window` requests are sent before the response to the first one is
+//! read, so the latency of USB serial adapters is paid once per window
+//! instead of once per frame. Frames carry no sequence number, responses are
+//! matched to the oldest request in flight to the responding device with
+//! the same opcode. Requests before the matched one are answered no more and
+//! count as lost, as a device answers in order.
+
+use std::collections::VecDeque;
+
+use serialport::ClearBuffer;
+
+use crate::{error::Error, session::Session};
+
+/// Send `requests`, keeping up to `window` of them in flight, and hand every
+/// request with its response, `None` if it got lost, to `handle`
+pub fn dispatch(
+    session: &mut Session,
+    requests: impl IntoIterator<Item = [u8; 16]>,
+    window: usize,
+    mut handle: impl FnMut(&[u8; 16], Option<&[u8; 16]>),
+) -> Result<(), Error> {
+    let mut requests = requests.into_iter().peekable();
+    let mut in_flight: VecDeque<[u8; 16]> = VecDeque::with_capacity(window);
+    // The requests of a window go out in a single write
+    session.buffer_writes(true)?;
+
+    while requests.peek().is_some() || !in_flight.is_empty() {
+        while in_flight.len() < window.max(1) {
+            match requests.next() {
+                Some(request) => {
+                    session.write(&request)?;
+                    in_flight.push_back(request);
+                }
+                None => break,
+            }
+        }
+
+        let mut frame = [0u8; 16];
+        match session.read_frame(&mut frame) {
+            Ok(()) => {
+                // A response nothing fits is a corrupted one to the oldest request
+                let answered = in_flight
+                    .iter()
+                    .position(|r| r[1] == frame[2] && r[5] == frame[5])
+                    .unwrap_or(0);
+                for lost in in_flight.drain(..answered) {
+                    handle(&lost, None);
+                }
+                if let Some(request) = in_flight.pop_front() {
+                    handle(&request, Some(&frame));
+                }
+            }
+            Err(e) if e.kind == serialport::ErrorKind::Io(std::io::ErrorKind::TimedOut) => {
+                // Drop the remains of a partial frame so the next one lines up
+                session.serial.clear(ClearBuffer::Input)?;
+                if let Some(request) = in_flight.pop_front() {
+                    handle(&request, None);
+                }
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    session.buffer_writes(false)?;
+    Ok(())
+}
@@ -0,0 +1,88 @@
+//! Replay of sessions recorded with `--trace-file`.
+//!
+//! The transmitted frames of the recording are sent again with their recorded
+//! gaps, and every response is compared with the recorded one. With
+//! `--virtual-time` the gaps aren't waited for, but the timestamps of a new
+//! trace file keep the recorded timing.
+
+use std::{io::Write, path::PathBuf};
+
+use clap::Args;
+
+use crate::{
+    clock::Clock,
+    diff,
+    error::Error,
+    session::Session,
+    trace::{self, Direction, Entry},
+};
+
+#[derive(Args, Debug, Clone)]
+pub struct Replay {
+    /// Trace file of the recorded session
+    path: PathBuf,
+}
+
+pub fn run(session: &mut Session, replay: &Replay) -> Result<(), Error> {
+    let entries = trace::read(&replay.path)?;
+
+    let mut previous = None;
+    let mut responses = 0;
+    let mut mismatches = 0;
+    for (i, entry) in entries.iter().enumerate() {
+        let time = trace::parse_timestamp(&entry.timestamp);
+        if let (Some(previous), Some(time)) = (previous, time) {
+            let gap = time.duration_since(previous).unwrap_or_default();
+            // Real responses take their own time, only a virtual clock has to
+            // be advanced to their recorded time
+            if entry.direction == Direction::Tx || matches!(session.clock, Clock::Virtual { .. }) {
+                session.sleep(gap)?;
+            }
+        }
+        previous = time.or(previous);
+
+        match entry.direction {
+            Direction::Tx => session.write(&entry.bytes)?,
+            Direction::Rx => {
+                responses += 1;
+                let mut frame = [0u8; 16];
+                let actual = match session.read_frame(&mut frame) {
+                    Ok(()) => Some(Entry {
+                        timestamp: String::new(),
+                        direction: Direction::Rx,
+                        bytes: frame.to_vec(),
+                    }),
+                    Err(e) if e.kind == serialport::ErrorKind::Io(std::io::ErrorKind::TimedOut) => {
+                        None
+                    }
+                    Err(e) => return Err(e.into()),
+                };
+
+                if actual.as_ref().is_none_or(|a| a.bytes != entry.bytes) {
+                    mismatches += 1;
+                    writeln!(session.out, "@ frame {}", i + 1)?;
+                    writeln!(session.out, "- {}", diff::render(Some(entry)))?;
+                    writeln!(session.out, "+ {}", diff::render(actual.as_ref()))?;
+                    if let Some(actual) = &actual {
+                        writeln!(session.out, "  {}", diff::markers(entry, actual))?;
+                    }
+                }
+            }
+        }
+    }
+
+    if mismatches > 0 {
+        return Err(Error::Assertion(format!(
+            "{} of {} responses differ from the recording",
+            mismatches, responses
+        )));
+    }
+
+    writeln!(
+        session.out,
+        "Replayed {} frames, all {} responses match",
+        entries.len(),
+        responses
+    )?;
+    Ok(())
+}
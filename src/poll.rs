@@ -0,0 +1,69 @@
+//! Waiting for frames in long running modes.
+//!
+//! A [`Poller`] reads the port with a short timeout instead of blocking until
+//! the next frame arrives, so between reads it notices SIGTERM and SIGINT,
+//! see [`crate::signals`], and timers which are due, like keep alives.
+
+use std::{
+    io,
+    time::{Duration, Instant},
+};
+
+use serialport::SerialPort;
+
+use crate::{reader::FrameReader, signals};
+
+/// Longest time a read blocks
+pub const INTERVAL: Duration = Duration::from_millis(100);
+
+pub enum Event {
+    Frame([u8; 16]),
+    /// Nothing arrived within the interval, a partial frame was dropped
+    Idle,
+    /// The timer of this index is due
+    Timer(usize),
+    /// SIGTERM or SIGINT arrived
+    Stop,
+}
+
+pub struct Poller {
+    pub frames: FrameReader<Box<dyn SerialPort>>,
+    /// Period and next due time of the timers
+    timers: Vec<(Duration, Instant)>,
+}
+
+impl Poller {
+    pub fn new(mut frames: FrameReader<Box<dyn SerialPort>>) -> Result<Self, serialport::Error> {
+        frames.get_mut().set_timeout(INTERVAL)?;
+        signals::handle();
+        Ok(Poller {
+            frames,
+            timers: Vec::new(),
+        })
+    }
+
+    /// Add a timer due every `period` from now on, returning its index
+    pub fn every(&mut self, period: Duration) -> usize {
+        self.timers.push((period, Instant::now() + period));
+        self.timers.len() - 1
+    }
+
+    /// Wait for the next frame, due timer or signal, whichever comes first
+    pub fn next(&mut self) -> io::Result<Event> {
+        if signals::stopping() {
+            return Ok(Event::Stop);
+        }
+        let now = Instant::now();
+        if let Some(i) = self.timers.iter().position(|(_, due)| *due <= now) {
+            // Timers missed while busy are due only once
+            self.timers[i].1 = now + self.timers[i].0;
+            return Ok(Event::Timer(i));
+        }
+
+        match self.frames.read_frame() {
+            Ok(frame) => Ok(Event::Frame(frame)),
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => Ok(Event::Idle),
+            Err(e) => Err(e),
+        }
+    }
+}
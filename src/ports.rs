@@ -0,0 +1,189 @@
+//! Discovery of the serial ports of the system.
+//!
+//! Besides port names, devices can be selected as `usb:<vid>:<pid>` with the
+//! hex USB vendor and product id of the adapter, or as `name:<text>` by a part
+//! of the adapter's manufacturer or product name. On Windows the product name
+//! is the friendly name shown by the Device Manager, which unlike the COM
+//! number stays the same when the adapter is plugged into another socket.
+
+use std::{
+    io::Write,
+    path::Path,
+    thread,
+    time::{Duration, Instant},
+};
+
+use serialport::{ErrorKind, SerialPortInfo, SerialPortType, UsbPortInfo};
+
+use crate::output::Output;
+
+/// Name of the only serial port of the system, for when no device is given
+pub fn only_port() -> Result<String, serialport::Error> {
+    let mut ports = serialport::available_ports()?;
+    match ports.len() {
+        0 => Err(serialport::Error::new(
+            ErrorKind::NoDevice,
+            "No serial port found, connect the device or pass its port as DEVICE",
+        )),
+        1 => {
+            let port = ports.remove(0);
+            eprintln!("Using {}, the only serial port", describe(&port));
+            Ok(port.port_name)
+        }
+        _ => {
+            let list: Vec<String> = ports.iter().map(|p| format!("  {}", describe(p))).collect();
+            Err(serialport::Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Several serial ports found, pass the one of the device as DEVICE:\n{}",
+                    list.join("\n")
+                ),
+            ))
+        }
+    }
+}
+
+/// Name of the port with what is known about the hardware behind it
+pub fn describe(port: &SerialPortInfo) -> String {
+    match &port.port_type {
+        SerialPortType::UsbPort(usb) => {
+            let mut out = format!("{} (USB {:04x}:{:04x}", port.port_name, usb.vid, usb.pid);
+            for s in [&usb.manufacturer, &usb.product].into_iter().flatten() {
+                out.push(' ');
+                out.push_str(s);
+            }
+            out.push(')');
+            out
+        }
+        SerialPortType::PciPort => format!("{} (PCI)", port.port_name),
+        SerialPortType::BluetoothPort => format!("{} (Bluetooth)", port.port_name),
+        SerialPortType::Unknown => port.port_name.clone(),
+    }
+}
+
+/// Port name of a device, resolving selectors
+pub fn resolve(device: &str) -> Result<String, serialport::Error> {
+    if !is_selector(device) {
+        return Ok(device.to_owned());
+    }
+
+    let mut matching = select(device, serialport::available_ports()?)?;
+    match matching.len() {
+        0 => Err(serialport::Error::new(
+            ErrorKind::NoDevice,
+            format!("No serial port matches `{}`", device),
+        )),
+        1 => Ok(matching.remove(0).port_name),
+        _ => {
+            let list: Vec<String> = matching
+                .iter()
+                .map(|p| format!("  {}", describe(p)))
+                .collect();
+            Err(serialport::Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Several serial ports match `{}`:\n{}",
+                    device,
+                    list.join("\n")
+                ),
+            ))
+        }
+    }
+}
+
+/// Print all serial ports of the system
+pub fn list(out: &mut Output) -> Result<(), serialport::Error> {
+    let ports = serialport::available_ports()?;
+    if ports.is_empty() {
+        writeln!(out, "No serial ports found")?;
+    }
+    for port in ports {
+        match &port.port_type {
+            SerialPortType::UsbPort(usb) => match &usb.serial_number {
+                Some(serial) => writeln!(out, "{} serial {}", describe(&port), serial)?,
+                None => writeln!(out, "{}", describe(&port))?,
+            },
+            _ => writeln!(out, "{}", describe(&port))?,
+        }
+    }
+
+    Ok(())
+}
+
+/// Block until the device is present, for at most `timeout`
+pub fn wait_for(device: &str, timeout: Duration) -> Result<(), serialport::Error> {
+    let start = Instant::now();
+    let mut announced = false;
+    while find(device)?.is_none() {
+        crate::deadline::check()?;
+        if start.elapsed() >= timeout {
+            return Err(serialport::Error::new(
+                ErrorKind::NoDevice,
+                format!("`{}` didn't appear within {:?}", device, timeout),
+            ));
+        }
+        if !announced {
+            eprintln!("Waiting for {} to appear", device);
+            announced = true;
+        }
+        thread::sleep(crate::deadline::limit(Duration::from_millis(100)));
+    }
+
+    Ok(())
+}
+
+/// Port name of the device if it is present
+fn find(device: &str) -> Result<Option<String>, serialport::Error> {
+    let ports = serialport::available_ports()?;
+    if is_selector(device) {
+        return Ok(select(device, ports)?
+            .into_iter()
+            .next()
+            .map(|p| p.port_name));
+    }
+
+    let present = Path::new(device).exists() || ports.iter().any(|p| p.port_name == device);
+    Ok(present.then(|| device.to_owned()))
+}
+
+fn is_selector(device: &str) -> bool {
+    device.starts_with("usb:") || device.starts_with("name:")
+}
+
+/// The ports matching a `usb:` or `name:` selector
+fn select(
+    device: &str,
+    ports: Vec<SerialPortInfo>,
+) -> Result<Vec<SerialPortInfo>, serialport::Error> {
+    let matches: Box<dyn Fn(&UsbPortInfo) -> bool> =
+        if let Some(selector) = device.strip_prefix("usb:") {
+            let (vid, pid) = selector
+                .split_once(':')
+                .and_then(|(v, p)| {
+                    Some((
+                        u16::from_str_radix(v, 16).ok()?,
+                        u16::from_str_radix(p, 16).ok()?,
+                    ))
+                })
+                .ok_or_else(|| {
+                    serialport::Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("`{}` is not a USB selector like `usb:0403:6001`", device),
+                    )
+                })?;
+            Box::new(move |usb| usb.vid == vid && usb.pid == pid)
+        } else {
+            let name = device.trim_start_matches("name:").to_lowercase();
+            Box::new(move |usb| {
+                [&usb.manufacturer, &usb.product]
+                    .into_iter()
+                    .flatten()
+                    .any(|s| s.to_lowercase().contains(&name))
+            })
+        };
+
+    Ok(ports
+        .into_iter()
+        .filter(|p| matches!(&p.port_type, SerialPortType::UsbPort(usb) if matches(usb)))
+        .collect())
+}
@@ -0,0 +1,105 @@
+//! Connection profiles from the configuration.
+//!
+//! A profile is a `[profile.<name>]` table bundling the settings of one kind
+//! of board, e.g.
+//!
+//! ```toml
+//! [profile.bench-rig]
+//! device = "/dev/ttyUSB0"
+//! id = 5
+//! baud_rate = 57600
+//! timeout = "1s"
+//! version = 3
+//! checksum = "crc8"
+//! ```
+//!
+//! and is selected with `--profile bench-rig`. Options given on the command
+//! line take precedence over the profile. Unknown keys are rejected, as a
+//! misspelled setting would silently talk to a board with the wrong settings.
+
+use std::time::Duration;
+
+use clap::{parser::ValueSource, ArgMatches, ValueEnum};
+use serialport::ErrorKind;
+
+use crate::{
+    config::{Config, Value},
+    parse_duration, ChecksumAlgorithm, CliArgs,
+};
+
+/// Apply the settings of the selected profile to the arguments not given on
+/// the command line
+pub fn apply(
+    args: &mut CliArgs,
+    matches: &ArgMatches,
+    config: &Config,
+) -> Result<(), serialport::Error> {
+    let name = match &args.profile {
+        Some(name) => name.clone(),
+        None => return Ok(()),
+    };
+    let table = config.table(&format!("profile.{}", name)).ok_or_else(|| {
+        serialport::Error::new(
+            ErrorKind::InvalidInput,
+            format!("No profile `{}` in the configuration", name),
+        )
+    })?;
+
+    let explicit = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+    for (key, value) in table {
+        let invalid = || {
+            serialport::Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Profile `{}`: Invalid value {:?} for `{}`",
+                    name, value, key
+                ),
+            )
+        };
+        let byte = |value: &Value| {
+            value
+                .as_integer()
+                .and_then(|i| u8::try_from(i).ok())
+                .ok_or_else(invalid)
+        };
+
+        match key.as_str() {
+            "device" if args.device.is_none() => {
+                args.device = Some(value.as_str().ok_or_else(invalid)?.to_owned())
+            }
+            "id" if args.id.is_none() => args.id = Some(byte(value)?),
+            "baud_rate" if !explicit("baud_rate") => {
+                args.baud_rate = value
+                    .as_integer()
+                    .and_then(|i| u32::try_from(i).ok())
+                    .ok_or_else(invalid)?
+            }
+            "timeout" if !explicit("timeout") => {
+                // Milli seconds or a duration like `2s`
+                args.timeout = match value.as_str() {
+                    Some(s) => parse_duration(s).map_err(|_| invalid())?,
+                    None => value
+                        .as_integer()
+                        .and_then(|i| u64::try_from(i).ok())
+                        .map(Duration::from_millis)
+                        .ok_or_else(invalid)?,
+                }
+            }
+            "version" if !explicit("protocol_version") => args.protocol_version = byte(value)?,
+            "checksum" if args.checksum.is_none() => {
+                let name = value.as_str().ok_or_else(invalid)?;
+                args.checksum =
+                    Some(ChecksumAlgorithm::from_str(name, true).map_err(|_| invalid())?)
+            }
+            "device" | "id" | "baud_rate" | "timeout" | "version" | "checksum" => (),
+            _ => {
+                return Err(serialport::Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("Profile `{}`: Unknown setting `{}`", name, key),
+                ))
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,124 @@
+//! Decoding frames from a stream of bytes.
+//!
+//! A read may return any part of a frame, [`FrameReader`] collects the bytes
+//! until a frame is complete. A partial frame followed by a read timeout is
+//! noise and dropped. Windows of 16 bytes without the start and end marker,
+//! or with a wrong checksum if one is verified, are skipped byte by byte
+//! until the stream is aligned to frames again.
+//!
+//! The bytes may come from anywhere, like a bus with a misbehaving device or
+//! a socket, so no input makes the reader panic, hold more than a few frames
+//! in memory or take more than linear time.
+
+use std::io::{self, Read};
+
+use crate::ChecksumAlgorithm;
+
+/// Most bytes kept by [`FrameReader::discarded`], a stream of garbage
+/// would grow them without bound
+const MAX_DISCARDED: usize = 4096;
+
+pub struct FrameReader<R> {
+    inner: R,
+    buf: Vec<u8>,
+    verify: Option<ChecksumAlgorithm>,
+    discarded: Vec<u8>,
+}
+
+impl<R: Read> FrameReader<R> {
+    pub fn new(inner: R) -> Self {
+        FrameReader {
+            inner,
+            buf: Vec::new(),
+            verify: None,
+            discarded: Vec::new(),
+        }
+    }
+
+    /// Skip frames whose checksum byte isn't the one of `algorithm`
+    pub fn verify(mut self, algorithm: ChecksumAlgorithm) -> Self {
+        self.verify = Some(algorithm);
+        self
+    }
+
+    /// The underlying stream, e.g. to answer on the same port
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    /// Bytes dropped while looking for the last frame or before the last
+    /// timeout, the first 4096 of them
+    pub fn discarded(&self) -> &[u8] {
+        &self.discarded
+    }
+
+    /// The next complete frame. Read timeouts are returned after dropping a
+    /// partial frame, so callers may go on reading
+    pub fn read_frame(&mut self) -> io::Result<[u8; 16]> {
+        self.discarded.clear();
+        let mut chunk = [0u8; 64];
+        loop {
+            let mut skipped = 0;
+            let mut found = None;
+            while self.buf.len() - skipped >= 16 {
+                let mut frame = [0u8; 16];
+                frame.copy_from_slice(&self.buf[skipped..skipped + 16]);
+                if self.is_frame(&frame) {
+                    found = Some(frame);
+                    break;
+                }
+                skipped += 1;
+            }
+            self.discard(skipped);
+            if let Some(frame) = found {
+                self.buf.drain(..16);
+                return Ok(frame);
+            }
+
+            match self.inner.read(&mut chunk) {
+                Ok(0) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "The stream ended",
+                    ))
+                }
+                Ok(n) => self.buf.extend_from_slice(&chunk[..n]),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => {
+                    if e.kind() == io::ErrorKind::TimedOut {
+                        self.discard(self.buf.len());
+                    }
+                    return Err(e);
+                }
+            }
+        }
+    }
+
+    /// Drop the first `n` bytes of the buffer
+    fn discard(&mut self, n: usize) {
+        let keep = n.min(MAX_DISCARDED - self.discarded.len());
+        self.discarded.extend_from_slice(&self.buf[..keep]);
+        self.buf.drain(..n);
+    }
+
+    fn is_frame(&self, frame: &[u8; 16]) -> bool {
+        frame[0] == 0
+            && frame[15] == 0
+            && self
+                .verify
+                .is_none_or(|algorithm| algorithm.compute(&frame[1..14]) == frame[14])
+    }
+}
+
+/// Frames until the end of the stream, read errors including timeouts are
+/// passed on
+impl<R: Read> Iterator for FrameReader<R> {
+    type Item = io::Result<[u8; 16]>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.read_frame() {
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => None,
+            result => Some(result),
+        }
+    }
+}
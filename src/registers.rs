@@ -0,0 +1,255 @@
+//! Registers accessed by name through a register map.
+//!
+//! The map is a file in the format of the configuration, given with `--map`
+//! or by `register_map` in the `[debug]` table of the configuration:
+//!
+//! ```toml
+//! # Order of the bytes of registers wider than one, "little" or "big"
+//! byte_order = "little"
+//!
+//! [registers.STATUS]
+//! address = 0x40000000
+//! # Access width in bytes, defaults to 4
+//! width = 4
+//!
+//! [registers.STATUS.fields]
+//! READY = 0
+//! MODE = "5:4"
+//! ```
+//!
+//! Fields are single bits or inclusive bit ranges from the highest bit down,
+//! read values are decoded into them. Registers are read and written with
+//! the debug memory opcodes, see [`crate::memory`].
+
+use std::{collections::BTreeMap, io::Write, path::PathBuf};
+
+use clap::Args;
+use serialport::ErrorKind;
+
+use crate::{
+    config::{Config, Value},
+    error::Error,
+    memory::{self, parse_u32},
+    session::Session,
+};
+
+#[derive(Args, Debug, Clone)]
+pub struct ReadReg {
+    /// Name of the register in the map, or its address
+    register: String,
+    /// Register map, defaults to `register_map` of the configuration
+    #[arg(long)]
+    map: Option<PathBuf>,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct WriteReg {
+    /// Name of the register in the map, or its address
+    register: String,
+    /// Value to write, e.g. `0x03`
+    #[arg(value_parser = parse_u32)]
+    value: u32,
+    /// Register map, defaults to `register_map` of the configuration
+    #[arg(long)]
+    map: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RegisterMap {
+    registers: BTreeMap<String, Register>,
+    big_endian: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Register {
+    address: u32,
+    width: u8,
+    /// Name, highest and lowest bit of the fields, lowest field first
+    fields: Vec<(String, u8, u8)>,
+}
+
+impl RegisterMap {
+    /// Load the map given by `--map` or the configuration, an address
+    /// needs no map
+    pub fn load(path: Option<&PathBuf>, config: &Config) -> Result<Self, serialport::Error> {
+        let path = match path {
+            Some(path) => path.clone(),
+            None => match config.get("debug", "register_map").and_then(Value::as_str) {
+                Some(path) => config.resolve_path(path),
+                None => return Ok(Self::default()),
+            },
+        };
+        let map = Config::load(Some(&path))?;
+        let invalid = |msg: String| {
+            serialport::Error::new(
+                ErrorKind::InvalidInput,
+                format!("Register map {}: {}", path.display(), msg),
+            )
+        };
+
+        let big_endian = match map.get("", "byte_order").and_then(Value::as_str) {
+            None | Some("little") => false,
+            Some("big") => true,
+            Some(order) => return Err(invalid(format!("Unknown byte order `{}`", order))),
+        };
+        let mut registers = BTreeMap::new();
+        for (table, values) in &map.tables {
+            let name = match table.strip_prefix("registers.") {
+                Some(name) if !name.contains('.') => name,
+                _ => continue,
+            };
+            let address = values
+                .get("address")
+                .and_then(Value::as_integer)
+                .and_then(|a| u32::try_from(a).ok())
+                .ok_or_else(|| invalid(format!("`{}` needs a 32 bit `address`", name)))?;
+            let width = match values.get("width").map(|w| w.as_integer()) {
+                None => 4,
+                Some(Some(w @ (1 | 2 | 4))) => w as u8,
+                Some(_) => return Err(invalid(format!("`{}` has no width of 1, 2 or 4", name))),
+            };
+
+            let mut fields = Vec::new();
+            for (field, bits) in map
+                .table(&format!("{}.fields", table))
+                .into_iter()
+                .flatten()
+            {
+                let (high, low) = match bits {
+                    Value::Integer(bit) => (*bit, *bit),
+                    Value::String(range) => {
+                        let (high, low) = range.split_once(':').unwrap_or(("", ""));
+                        (
+                            high.trim().parse().unwrap_or(-1),
+                            low.trim().parse().unwrap_or(-1),
+                        )
+                    }
+                    _ => (-1, -1),
+                };
+                if !(0..width as i64 * 8).contains(&high) || !(0..=high).contains(&low) {
+                    return Err(invalid(format!(
+                        "Field `{}.{}` has to be a bit or a range like \"5:4\" within the register",
+                        name, field
+                    )));
+                }
+                fields.push((field.clone(), high as u8, low as u8));
+            }
+            fields.sort_by_key(|(_, _, low)| *low);
+
+            registers.insert(
+                name.to_owned(),
+                Register {
+                    address,
+                    width,
+                    fields,
+                },
+            );
+        }
+
+        Ok(Self {
+            registers,
+            big_endian,
+        })
+    }
+
+    /// The register of a name in the map or an address, with its name
+    fn resolve(&self, register: &str) -> Result<(String, Register), serialport::Error> {
+        if let Some(known) = self.registers.get(register) {
+            return Ok((register.to_owned(), known.clone()));
+        }
+        let address = parse_u32(register).map_err(|_| {
+            serialport::Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "`{}` is neither an address nor in the register map",
+                    register
+                ),
+            )
+        })?;
+
+        // Prefer the name over a bare address
+        Ok(
+            match self.registers.iter().find(|(_, r)| r.address == address) {
+                Some((name, known)) => (name.clone(), known.clone()),
+                None => (
+                    format!("{:#010x}", address),
+                    Register {
+                        address,
+                        width: 4,
+                        fields: Vec::new(),
+                    },
+                ),
+            },
+        )
+    }
+}
+
+pub fn read(session: &mut Session, read: &ReadReg) -> Result<(), Error> {
+    let map = RegisterMap::load(read.map.as_ref(), &session.config)?;
+    let (name, register) = map.resolve(&read.register)?;
+
+    let mut bytes = memory::read(session, register.address, register.width)?;
+    if !map.big_endian {
+        bytes.reverse();
+    }
+    let value = bytes.iter().fold(0u32, |v, b| v << 8 | *b as u32);
+
+    let label = match map.registers.contains_key(&name) {
+        true => format!("{} ({:#010x})", name, register.address),
+        false => name,
+    };
+    writeln!(
+        session.out,
+        "{} = {:#0width$x}",
+        label,
+        value,
+        width = 2 + 2 * register.width as usize
+    )?;
+    let align = register
+        .fields
+        .iter()
+        .map(|(f, _, _)| f.len())
+        .max()
+        .unwrap_or(0);
+    for (field, high, low) in &register.fields {
+        let bits = (value >> low) & (u32::MAX >> (31 - (high - low)));
+        let range = match high == low {
+            true => format!("bit {}", low),
+            false => format!("bits {}:{}", high, low),
+        };
+        writeln!(
+            session.out,
+            "  {:<align$} = {:#x} ({})",
+            field,
+            bits,
+            range,
+            align = align
+        )?;
+    }
+
+    Ok(())
+}
+
+pub fn write(session: &mut Session, write: &WriteReg) -> Result<(), Error> {
+    let map = RegisterMap::load(write.map.as_ref(), &session.config)?;
+    let (name, register) = map.resolve(&write.register)?;
+
+    let width = register.width as usize;
+    if width < 4 && write.value >> (8 * width) != 0 {
+        return Err(serialport::Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "{:#x} doesn't fit into {}, which has {} bytes",
+                write.value, name, width
+            ),
+        )
+        .into());
+    }
+    let bytes = match map.big_endian {
+        true => write.value.to_be_bytes()[4 - width..].to_vec(),
+        false => write.value.to_le_bytes()[..width].to_vec(),
+    };
+
+    memory::write(session, register.address, &bytes)?;
+    Ok(())
+}
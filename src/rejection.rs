@@ -0,0 +1,81 @@
+//! Requests a device refused.
+//!
+//! Instead of the response a device may answer with the rejected opcode,
+//! which carries the opcode of the request in SDU byte 6 and the reason in
+//! SDU byte 7.
+
+use std::fmt::{self, Display};
+
+use crate::{sdu::Response, Opcode};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reason {
+    UnknownOpcode,
+    Busy,
+    BadParameter,
+    CrcError,
+    Other(u8),
+}
+
+impl From<u8> for Reason {
+    fn from(reason: u8) -> Self {
+        match reason {
+            1 => Reason::UnknownOpcode,
+            2 => Reason::Busy,
+            3 => Reason::BadParameter,
+            4 => Reason::CrcError,
+            reason => Reason::Other(reason),
+        }
+    }
+}
+
+impl Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Reason::UnknownOpcode => write!(f, "unknown opcode"),
+            Reason::Busy => write!(f, "busy, try again later"),
+            Reason::BadParameter => write!(f, "bad parameter"),
+            Reason::CrcError => write!(f, "the request arrived with a bad checksum"),
+            Reason::Other(reason) => write!(f, "reason {}", reason),
+        }
+    }
+}
+
+/// The answer of a device refusing a request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rejection {
+    pub device: u8,
+    pub opcode: u8,
+    pub reason: Reason,
+}
+
+impl Rejection {
+    /// The rejection `frame` carries, if it is one
+    pub fn parse(frame: &[u8; 16]) -> Option<Self> {
+        (Opcode::from_byte(frame[5]) == Opcode::Rejected).then(|| Rejection {
+            device: frame[2],
+            opcode: frame.sdu_u8(6),
+            reason: frame.sdu_u8(7).into(),
+        })
+    }
+
+    /// Exit code of the process, busy devices and corrupted requests are
+    /// worth a retry
+    pub fn exit_code(&self) -> u8 {
+        match self.reason {
+            Reason::Busy => 6,
+            Reason::CrcError => 7,
+            _ => 5,
+        }
+    }
+}
+
+impl Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Device {} rejected opcode {}", self.device, self.opcode)?;
+        if let Some(name) = Opcode::from_byte(self.opcode).name() {
+            write!(f, " ({})", name)?;
+        }
+        write!(f, ": {}", self.reason)
+    }
+}
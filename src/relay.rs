@@ -0,0 +1,266 @@
+//! Relay and routing of frames between serial bus segments, so a PC can act
+//! as a bridge or router during lab bring-up.
+//!
+//! Complete frames with a valid checksum are forwarded, everything else is
+//! dropped like a bridge node would. `relay` forwards between two ports,
+//! `route` between all segments of the routing table in the configuration:
+//!
+//! ```toml
+//! [routes.lab]
+//! port = "/dev/ttyUSB0"
+//! addresses = ["1-20", 0]
+//!
+//! [routes.field]
+//! port = "/dev/ttyUSB1"
+//! addresses = ["21-40"]
+//! ```
+//!
+//! Each frame goes to the segment holding its destination address, frames to
+//! an address of their own segment or of no segment are not forwarded.
+
+use std::{
+    ops::RangeInclusive,
+    sync::{mpsc, Arc, Mutex},
+    thread,
+};
+
+use clap::Args;
+use serialport::{ErrorKind, SerialPort};
+
+use crate::{
+    config::{Config, Value},
+    describe,
+    error::Error,
+    reader::FrameReader,
+    ChecksumAlgorithm, CliArgs,
+};
+
+#[derive(Args, Debug, Clone)]
+pub struct Relay {
+    /// Serial port of the first segment
+    port_a: String,
+    /// Serial port of the second segment
+    port_b: String,
+    /// Only forward frames to these addresses, e.g. `5` or `10-20`. Applies to
+    /// both directions, so responses need the address of their receiver too
+    #[arg(long = "to", value_parser = parse_address_range)]
+    to: Vec<RangeInclusive<u8>>,
+    #[command(flatten)]
+    forwarding: Forwarding,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct Route {
+    #[command(flatten)]
+    forwarding: Forwarding,
+}
+
+/// Options of relays and routes
+#[derive(Args, Debug, Clone, Copy)]
+pub struct Forwarding {
+    /// Increment the hop count of forwarded frames
+    #[arg(long)]
+    increment_hops: bool,
+    /// Drop frames with a higher hop count, protecting the bus from
+    /// forwarding loops
+    #[arg(long, value_parser = crate::parse_u8)]
+    max_hops: Option<u8>,
+}
+
+/// A bus segment of the routing table
+#[derive(Debug, Clone)]
+pub struct Segment {
+    pub name: String,
+    pub port: String,
+    pub addresses: Vec<RangeInclusive<u8>>,
+}
+
+pub fn run(args: &CliArgs, relay: &Relay) -> Result<(), Error> {
+    let filter = relay.to.clone();
+    bridge(
+        args,
+        &[relay.port_a.clone(), relay.port_b.clone()],
+        relay.forwarding,
+        move |from, frame| {
+            let allowed = filter.is_empty() || filter.iter().any(|r| r.contains(&frame[1]));
+            allowed.then_some(1 - from)
+        },
+    )
+}
+
+pub fn route(args: &CliArgs, route: &Route, config: &Config) -> Result<(), Error> {
+    let segments = segments(config)?;
+    let ports: Vec<String> = segments.iter().map(|s| s.port.clone()).collect();
+    for segment in &segments {
+        eprintln!(
+            "Segment {} on {}: {:?}",
+            segment.name, segment.port, segment.addresses
+        );
+    }
+
+    bridge(args, &ports, route.forwarding, move |from, frame| {
+        segments
+            .iter()
+            .position(|s| s.addresses.iter().any(|r| r.contains(&frame[1])))
+            .filter(|to| *to != from)
+    })
+}
+
+/// Read the segments of the routing table
+pub fn segments(config: &Config) -> Result<Vec<Segment>, serialport::Error> {
+    let invalid = |name: &str, msg: &str| {
+        serialport::Error::new(
+            ErrorKind::InvalidInput,
+            format!("Route `{}`: {}", name, msg),
+        )
+    };
+
+    let mut segments = Vec::new();
+    for (table, values) in &config.tables {
+        let name = match table.strip_prefix("routes.") {
+            Some(name) => name,
+            None => continue,
+        };
+        let port = values
+            .get("port")
+            .and_then(Value::as_str)
+            .ok_or_else(|| invalid(name, "`port` has to be the serial port of the segment"))?;
+        let addresses = values
+            .get("addresses")
+            .and_then(Value::as_array)
+            .ok_or_else(|| {
+                invalid(
+                    name,
+                    "`addresses` has to be an array of addresses and ranges",
+                )
+            })?
+            .iter()
+            .map(|a| match a {
+                Value::Integer(i) => parse_address_range(&i.to_string()),
+                Value::String(s) => parse_address_range(s),
+                _ => Err(format!("{:?} is no address", a)),
+            })
+            .collect::<Result<_, _>>()
+            .map_err(|e| invalid(name, &e))?;
+
+        segments.push(Segment {
+            name: name.to_owned(),
+            port: port.to_owned(),
+            addresses,
+        });
+    }
+
+    if segments.len() < 2 {
+        return Err(serialport::Error::new(
+            ErrorKind::InvalidInput,
+            "Routing needs at least two [routes.<name>] segments in the configuration",
+        ));
+    }
+
+    Ok(segments)
+}
+
+/// Forward frames between the ports, `target` picks the index of the port a
+/// frame read from port `from` goes to
+fn bridge<F>(
+    args: &CliArgs,
+    ports: &[String],
+    forwarding: Forwarding,
+    target: F,
+) -> Result<(), Error>
+where
+    F: Fn(usize, &[u8; 16]) -> Option<usize> + Send + Sync + 'static,
+{
+    let mut readers = Vec::new();
+    let mut writers = Vec::new();
+    for port in ports {
+        let mut args = args.clone();
+        args.device = Some(port.clone());
+        let serial = crate::open(&args)?;
+        writers.push(Mutex::new(serial.try_clone()?));
+        readers.push(serial);
+    }
+    eprintln!("Bridging {}", ports.join(" <-> "));
+
+    let writers = Arc::new(writers);
+    let target = Arc::new(target);
+    let names: Arc<[String]> = ports.into();
+    let (echo, checksum) = (args.echo, args.checksum());
+    let (done, finished) = mpsc::channel();
+    for (from, reader) in readers.into_iter().enumerate() {
+        let (writers, target, names, done) =
+            (writers.clone(), target.clone(), names.clone(), done.clone());
+        thread::spawn(move || {
+            let result = read_frames(reader, checksum, |mut frame| {
+                let to = match target(from, &frame) {
+                    Some(to) => to,
+                    None => return Ok(()),
+                };
+                if forwarding.max_hops.is_some_and(|max| frame[4] > max) {
+                    eprintln!(
+                        "Dropping frame over the hop limit from {}: {}",
+                        names[from],
+                        describe(&frame, checksum)
+                    );
+                    return Ok(());
+                }
+                if forwarding.increment_hops {
+                    frame[4] = frame[4].wrapping_add(1);
+                    frame[14] = checksum.compute(&frame[1..14]);
+                }
+                if echo {
+                    eprintln!(
+                        "{} > {}: {}",
+                        names[from],
+                        names[to],
+                        describe(&frame, checksum)
+                    );
+                }
+                writers[to]
+                    .lock()
+                    .expect("writer lock poisoned")
+                    .write_all(&frame)
+                    .map_err(Error::from)
+            });
+            let _ = done.send(result);
+        });
+    }
+
+    // The ports are forwarded until the first one fails
+    finished
+        .recv()
+        .expect("bridge threads ended without a result")
+}
+
+/// Hand every complete frame with a valid checksum of `algorithm` to `handle`
+fn read_frames(
+    serial: Box<dyn SerialPort>,
+    algorithm: ChecksumAlgorithm,
+    mut handle: impl FnMut([u8; 16]) -> Result<(), Error>,
+) -> Result<(), Error> {
+    for frame in FrameReader::new(serial).verify(algorithm) {
+        match frame {
+            Ok(frame) => handle(frame)?,
+            // The reader dropped the partial frame, if any
+            Err(e) if e.kind() == std::io::ErrorKind::TimedOut => (),
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    Ok(())
+}
+
+/// Parse an address or an inclusive range of addresses like `10-20`,
+/// special addresses may be given by name like `broadcast`
+pub fn parse_address_range(s: &str) -> Result<RangeInclusive<u8>, String> {
+    let (start, end) = s.split_once('-').unwrap_or((s, s));
+    let (start, end) = (
+        crate::parse_address(start.trim())?.0,
+        crate::parse_address(end.trim())?.0,
+    );
+    if start > end {
+        return Err(format!("`{}` is an empty range", s));
+    }
+
+    Ok(start..=end)
+}
@@ -0,0 +1,58 @@
+//! Relays of expansion nodes.
+//!
+//! The set relay opcode switches the relay of the channel in SDU byte 6, on
+//! if the last SDU byte is 1 and off if it is 0. The get relays opcode is
+//! answered with the states of all channels as bit mask in the last four
+//! SDU bytes (big endian), bit 0 for channel 0.
+
+use clap::{Args, ValueEnum};
+
+use crate::{
+    error::Error,
+    sdu::{Response, Sdu},
+    session::Session,
+    L7Sdu, Opcode,
+};
+
+/// Highest channel the bit mask has room for
+const MAX_CHANNEL: u8 = 31;
+
+#[derive(Args, Debug, Clone, Copy)]
+pub struct SetRelay {
+    /// Channel of the relay, from 0 to 31
+    #[arg(value_parser = clap::value_parser!(u8).range(..=MAX_CHANNEL as i64))]
+    channel: u8,
+    state: RelayState,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum RelayState {
+    On,
+    Off,
+}
+
+impl SetRelay {
+    pub fn as_sdu(&self) -> L7Sdu {
+        let mut sdu = L7Sdu::from_u8(7, (self.state == RelayState::On) as u8);
+        sdu[6] = self.channel;
+        sdu
+    }
+}
+
+pub fn get(session: &mut Session) -> Result<Vec<[u8; 16]>, Error> {
+    let mut msg = [0u8; 16];
+    session.transact(
+        session.builder(Opcode::GetRelays, L7Sdu::default()),
+        &mut msg,
+    )?;
+    Ok(vec![msg])
+}
+
+/// Channels of the relays switched on, as told by the answer of the get
+/// relays opcode
+pub fn on(msg: &[u8; 16]) -> Vec<u8> {
+    let mask = u32::from_be_bytes([msg.sdu_u8(4), msg.sdu_u8(5), msg.sdu_u8(6), msg.sdu_u8(7)]);
+    (0..=MAX_CHANNEL)
+        .filter(|channel| mask & 1 << channel != 0)
+        .collect()
+}
@@ -0,0 +1,124 @@
+//! Commands executed by a running daemon, see [`crate::daemon`], instead of
+//! opening the port.
+//!
+//! Opening a USB serial port may take longer than the exchange itself, a
+//! daemon keeps it open. The command given on the command line is sent over
+//! the socket of the daemon, without one every line of stdin is a command,
+//! all over one connection. The frames the daemon answers are printed, the
+//! first error ends the commands.
+
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    net::TcpStream,
+};
+
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+
+use clap::Args;
+use serialport::ErrorKind;
+
+use crate::{error::Error, output::Output};
+
+#[derive(Args, Debug, Clone)]
+pub struct Remote {
+    /// Address the daemon listens on, `host:port`, `unix:<path>` or on
+    /// Windows `pipe:<name>`
+    #[arg(long)]
+    connect: String,
+    /// Command to execute, e.g. `set-led on`. Commands are read from stdin
+    /// without it
+    #[arg(allow_hyphen_values = true)]
+    command: Vec<String>,
+}
+
+pub fn run(remote: &Remote, out: &mut Output) -> Result<(), Error> {
+    let error = |e: io::Error| {
+        serialport::Error::new(
+            ErrorKind::Io(e.kind()),
+            format!(
+                "Could not connect to the daemon at {}: {}",
+                remote.connect, e
+            ),
+        )
+    };
+
+    #[cfg(unix)]
+    if let Some(path) = remote.connect.strip_prefix("unix:") {
+        let stream = UnixStream::connect(path).map_err(error)?;
+        stream.set_read_timeout(crate::deadline::remaining())?;
+        return execute(remote, stream, out);
+    }
+    #[cfg(windows)]
+    if let Some(name) = remote.connect.strip_prefix("pipe:") {
+        let pipe = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(crate::pipe::path(name))
+            .map_err(error)?;
+        return execute(remote, pipe, out);
+    }
+    let stream = TcpStream::connect(&remote.connect).map_err(error)?;
+    stream.set_nodelay(true)?;
+    // The daemon may be busy with other clients for longer
+    stream.set_read_timeout(crate::deadline::remaining())?;
+    execute(remote, stream, out)
+}
+
+fn execute<S: Read + Write>(remote: &Remote, stream: S, out: &mut Output) -> Result<(), Error> {
+    let mut stream = BufReader::new(stream);
+    if !remote.command.is_empty() {
+        let words: Vec<String> = remote.command.iter().map(|w| quote(w)).collect();
+        return request(&mut stream, &words.join(" "), out);
+    }
+
+    for line in io::stdin().lock().lines() {
+        let line = line?;
+        if line.trim().is_empty() || line.trim_start().starts_with('#') {
+            continue;
+        }
+        request(&mut stream, line.trim(), out)?;
+    }
+    Ok(())
+}
+
+/// Send one command and print the frames of the answer
+fn request<S: Read + Write>(
+    stream: &mut BufReader<S>,
+    command: &str,
+    out: &mut Output,
+) -> Result<(), Error> {
+    writeln!(stream.get_mut(), "{}", command)?;
+
+    let mut line = String::new();
+    loop {
+        line.clear();
+        let read = stream.read_line(&mut line).map_err(|e| match e.kind() {
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => crate::deadline::error(),
+            _ => e.into(),
+        })?;
+        if read == 0 {
+            return Err(serialport::Error::new(
+                ErrorKind::Io(io::ErrorKind::UnexpectedEof),
+                format!("The daemon closed the connection during `{}`", command),
+            )
+            .into());
+        }
+
+        match line.trim_end() {
+            "ok" => return Ok(()),
+            reply => match reply.strip_prefix("error: ") {
+                Some(e) => return Err(serialport::Error::new(ErrorKind::InvalidInput, e).into()),
+                None => writeln!(out, "{}", reply)?,
+            },
+        }
+    }
+}
+
+/// A word of a command, quoted for the daemon if necessary
+fn quote(word: &str) -> String {
+    if !word.is_empty() && !word.contains(|c: char| c.is_whitespace() || "\"'\\".contains(c)) {
+        return word.to_owned();
+    }
+    format!("\"{}\"", word.replace('\\', "\\\\").replace('"', "\\\""))
+}
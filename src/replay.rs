@@ -0,0 +1,126 @@
+//! Host side frame counters for replay protection.
+//!
+//! For every device the counter of the last frame sent to it and the counter
+//! of the last frame accepted from it are persisted in a counter file, one
+//! `<id> <tx> <rx>` triple per line. The file is referenced by `counter_file`
+//! in the `[auth]` table of the configuration and defaults to `counters` next
+//! to the configuration.
+
+use std::{fs, path::PathBuf};
+
+use serialport::ErrorKind;
+
+use crate::config::Config;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Counters {
+    /// Counter of the last frame sent to the device
+    pub tx: u32,
+    /// Counter of the last frame accepted from the device
+    pub rx: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct CounterFile {
+    pub path: PathBuf,
+}
+
+impl CounterFile {
+    pub fn from_config(config: &Config) -> Result<Self, serialport::Error> {
+        let path = match config.get("auth", "counter_file").and_then(|v| v.as_str()) {
+            Some(path) => config.resolve_path(path),
+            None => crate::config::config_dir()
+                .ok_or_else(|| {
+                    serialport::Error::new(
+                        ErrorKind::InvalidInput,
+                        "No configuration directory found, set `counter_file` in the [auth] table",
+                    )
+                })?
+                .join("counters"),
+        };
+
+        Ok(Self { path })
+    }
+
+    pub fn get(&self, id: u8) -> Result<Counters, serialport::Error> {
+        Ok(self
+            .entries()?
+            .into_iter()
+            .find(|(entry_id, _)| *entry_id == id)
+            .map(|(_, counters)| counters)
+            .unwrap_or_default())
+    }
+
+    pub fn set(&self, id: u8, counters: Counters) -> Result<(), serialport::Error> {
+        let mut entries = self.entries()?;
+        entries.retain(|(entry_id, _)| *entry_id != id);
+        entries.push((id, counters));
+        entries.sort_by_key(|(id, _)| *id);
+
+        let contents: String = entries
+            .iter()
+            .map(|(id, c)| format!("{} {} {}\n", id, c.tx, c.rx))
+            .collect();
+
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir).map_err(|e| self.io_error(e))?;
+        }
+
+        // Write to a temporary file first, a torn write would reset counters
+        let tmp = self.path.with_extension("tmp");
+        fs::write(&tmp, contents)
+            .and_then(|_| fs::rename(&tmp, &self.path))
+            .map_err(|e| self.io_error(e))
+    }
+
+    /// Reserve the next counter for a frame sent to the device
+    pub fn next_tx(&self, id: u8) -> Result<u32, serialport::Error> {
+        let mut counters = self.get(id)?;
+        counters.tx = counters.tx.checked_add(1).ok_or_else(|| {
+            serialport::Error::new(
+                ErrorKind::InvalidInput,
+                format!("Frame counter of device {} exhausted, rotate its key", id),
+            )
+        })?;
+        self.set(id, counters)?;
+        Ok(counters.tx)
+    }
+
+    fn entries(&self) -> Result<Vec<(u8, Counters)>, serialport::Error> {
+        let contents = match fs::read_to_string(&self.path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(self.io_error(e)),
+        };
+
+        contents
+            .lines()
+            .enumerate()
+            .map(|(i, line)| (i, line.trim()))
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+            .map(|(i, line)| {
+                let mut fields = line.split_whitespace().map(|f| f.parse::<u32>().ok());
+                match (fields.next(), fields.next(), fields.next(), fields.next()) {
+                    (Some(Some(id)), Some(Some(tx)), Some(Some(rx)), None) if id <= 255 => {
+                        Ok((id as u8, Counters { tx, rx }))
+                    }
+                    _ => Err(serialport::Error::new(
+                        ErrorKind::InvalidInput,
+                        format!(
+                            "{}:{}: Expected `<id> <tx> <rx>`",
+                            self.path.display(),
+                            i + 1
+                        ),
+                    )),
+                }
+            })
+            .collect()
+    }
+
+    fn io_error(&self, e: std::io::Error) -> serialport::Error {
+        serialport::Error::new(
+            ErrorKind::Io(e.kind()),
+            format!("Counter file {}: {}", self.path.display(), e),
+        )
+    }
+}
@@ -0,0 +1,130 @@
+//! JUnit XML reports of script runs, for CI servers to display results of
+//! hardware tests.
+
+use std::{
+    fmt::Write as _,
+    fs,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use serialport::ErrorKind;
+
+use crate::{error::Error, trace};
+
+#[derive(Debug)]
+pub struct Report {
+    suite: String,
+    timestamp: String,
+    started: Instant,
+    cases: Vec<Case>,
+}
+
+#[derive(Debug)]
+struct Case {
+    name: String,
+    time: Duration,
+    outcome: Outcome,
+}
+
+#[derive(Debug)]
+enum Outcome {
+    Passed,
+    /// An assertion didn't hold
+    Failure(String),
+    /// The step couldn't be executed
+    Error(String),
+}
+
+impl Report {
+    pub fn new(suite: &str) -> Self {
+        Self {
+            suite: suite.to_owned(),
+            timestamp: trace::timestamp(),
+            started: Instant::now(),
+            cases: Vec::new(),
+        }
+    }
+
+    /// Record the result of one step as a test case
+    pub fn record(&mut self, name: String, time: Duration, result: &Result<(), Error>) {
+        let outcome = match result {
+            Ok(()) => Outcome::Passed,
+            Err(Error::Assertion(msg) | Error::Protocol(msg)) => Outcome::Failure(msg.clone()),
+            Err(e) => Outcome::Error(e.to_string()),
+        };
+        self.cases.push(Case {
+            name,
+            time,
+            outcome,
+        });
+    }
+
+    pub fn to_xml(&self) -> String {
+        let count = |f: fn(&Outcome) -> bool| self.cases.iter().filter(|c| f(&c.outcome)).count();
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<testsuites>\n");
+        let _ = writeln!(
+            xml,
+            "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" errors=\"{}\" time=\"{:.3}\" timestamp=\"{}\">",
+            escape(&self.suite),
+            self.cases.len(),
+            count(|o| matches!(o, Outcome::Failure(_))),
+            count(|o| matches!(o, Outcome::Error(_))),
+            self.started.elapsed().as_secs_f64(),
+            self.timestamp,
+        );
+
+        for case in &self.cases {
+            let _ = write!(
+                xml,
+                "    <testcase name=\"{}\" classname=\"{}\" time=\"{:.3}\"",
+                escape(&case.name),
+                escape(&self.suite),
+                case.time.as_secs_f64(),
+            );
+            let (tag, msg) = match &case.outcome {
+                Outcome::Passed => {
+                    xml.push_str("/>\n");
+                    continue;
+                }
+                Outcome::Failure(msg) => ("failure", msg),
+                Outcome::Error(msg) => ("error", msg),
+            };
+            let _ = writeln!(
+                xml,
+                ">\n      <{tag} message=\"{msg}\">{msg}</{tag}>\n    </testcase>",
+                tag = tag,
+                msg = escape(msg),
+            );
+        }
+
+        xml.push_str("  </testsuite>\n</testsuites>\n");
+        xml
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), serialport::Error> {
+        fs::write(path, self.to_xml()).map_err(|e| {
+            serialport::Error::new(
+                ErrorKind::Io(e.kind()),
+                format!("Could not write report {}: {}", path.display(), e),
+            )
+        })
+    }
+}
+
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            c if c.is_control() && c != '\n' && c != '\t' => (),
+            c => out.push(c),
+        }
+    }
+
+    out
+}
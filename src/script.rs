@@ -0,0 +1,831 @@
+//! Script files executed over a single session.
+//!
+//! Every non empty line that isn't a `#` comment is either a subcommand line,
+//! parsed like the subcommand part of the command line, or a directive:
+//!
+//! - `wait <command> <op> <value> [--timeout 60s] [--interval 200ms]` polls
+//!   the command until its decoded response compares to the value, `op` is
+//!   one of `==`, `!=`, `<`, `<=`, `>` and `>=`. Unanswered polls are
+//!   retried until the timeout.
+//! - `assert <command> <op> <value>` fails the script with exit code 3 unless
+//!   the decoded response compares to the value, `assert <command> matches
+//!   <sdu>` unless the SDU matches a pattern like `00..01` (see `--expect-sdu`).
+//! - `let <var> = <command>` stores the decoded response of the command in
+//!   the variable, `let <var> = <value>` stores the value itself.
+//! - `sleep <duration>` pauses, e.g. `sleep 250ms` to let a device settle.
+//! - `repeat <count> [as <var>] {` repeats the following lines up to a line
+//!   holding only `}`. The iteration, counted from 0, is available in the
+//!   variable `var`, which defaults to `i`.
+//!
+//! With `--report <path>` a JUnit XML report with a test case per executed
+//! line is written, whether the script succeeds or not.
+//!
+//! Variables are substituted into lines as `$var` or `${var}` in decimal and
+//! as `${var:x}` in hex with at least two digits, e.g. for SDU bytes. `$$` is
+//! a literal `$`.
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use clap::Args;
+use serialport::ErrorKind;
+
+use crate::{
+    config::split_words,
+    error::Error,
+    expect::{parse_sdu_pattern, SduPattern},
+    macros, parse_duration,
+    report::Report,
+    sdu::Response,
+    session::Session,
+    Command,
+};
+
+const DEFAULT_WAIT_TIMEOUT: Duration = Duration::from_secs(60);
+const DEFAULT_WAIT_INTERVAL: Duration = Duration::from_millis(200);
+
+#[derive(Args, Debug, Clone)]
+pub struct Script {
+    /// Path of the script file
+    path: PathBuf,
+}
+
+/// Values of the script variables by name
+pub type Vars = BTreeMap<String, u64>;
+
+/// Block structure of a script, lines are only parsed into steps once their
+/// variables are substituted
+#[derive(Debug, Clone)]
+pub enum Node {
+    Line {
+        line_no: usize,
+        text: String,
+    },
+    Repeat {
+        count: u64,
+        var: String,
+        body: Vec<Node>,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub enum Step {
+    Command {
+        cmd: Command,
+    },
+    Wait {
+        cmd: Command,
+        op: Comparison,
+        value: u64,
+        timeout: Duration,
+        interval: Duration,
+    },
+    Assert {
+        cmd: Command,
+        expectation: Expectation,
+    },
+    Let {
+        var: String,
+        value: LetValue,
+    },
+    Sleep(Duration),
+}
+
+#[derive(Debug, Clone)]
+pub enum Expectation {
+    Compare(Comparison, u64),
+    Sdu(SduPattern),
+}
+
+#[derive(Debug, Clone)]
+pub enum LetValue {
+    Value(u64),
+    Command(Box<Command>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Comparison {
+    pub fn parse(s: &str) -> Option<Self> {
+        Some(match s {
+            "==" => Self::Eq,
+            "!=" => Self::Ne,
+            "<" => Self::Lt,
+            "<=" => Self::Le,
+            ">" => Self::Gt,
+            ">=" => Self::Ge,
+            _ => return None,
+        })
+    }
+
+    pub fn eval(self, lhs: u64, rhs: u64) -> bool {
+        match self {
+            Self::Eq => lhs == rhs,
+            Self::Ne => lhs != rhs,
+            Self::Lt => lhs < rhs,
+            Self::Le => lhs <= rhs,
+            Self::Gt => lhs > rhs,
+            Self::Ge => lhs >= rhs,
+        }
+    }
+}
+
+pub fn run(session: &mut Session, script: &Script) -> Result<(), Error> {
+    let src = fs::read_to_string(&script.path).map_err(|e| {
+        serialport::Error::new(
+            ErrorKind::Io(e.kind()),
+            format!("Could not read script {}: {}", script.path.display(), e),
+        )
+    })?;
+
+    // Parse the whole script up front, so a typo doesn't abort it halfway
+    let nodes = parse(&src).map_err(|(line, msg)| {
+        serialport::Error::new(
+            ErrorKind::InvalidInput,
+            format!("{}:{}: {}", script.path.display(), line, msg),
+        )
+    })?;
+
+    // Frames of lines without a response go out together, up to the next
+    // line reading a response or sleeping
+    session.buffer_writes(true)?;
+    let mut report = Report::new(&script.path.display().to_string());
+    let result = execute(session, &nodes, &mut Vars::new(), &mut report);
+    session.buffer_writes(false)?;
+    if let Some(path) = &session.args.report {
+        report.write(path)?;
+    }
+
+    result
+}
+
+/// Parse the block structure and check every line parses with its variables set
+pub fn parse(src: &str) -> Result<Vec<Node>, (usize, String)> {
+    let mut lines = src
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'));
+
+    let nodes = parse_block(&mut lines, None)?;
+    check(&nodes, &mut Vars::new())?;
+    Ok(nodes)
+}
+
+fn parse_block<'a>(
+    lines: &mut impl Iterator<Item = (usize, &'a str)>,
+    opened_at: Option<usize>,
+) -> Result<Vec<Node>, (usize, String)> {
+    let mut nodes = Vec::new();
+    while let Some((line_no, line)) = lines.next() {
+        if line == "}" {
+            return match opened_at {
+                Some(_) => Ok(nodes),
+                None => Err((line_no, "`}` without an open block".to_owned())),
+            };
+        }
+
+        match line.strip_prefix("repeat ") {
+            Some(rest) => {
+                let (count, var) = parse_repeat(rest.trim()).ok_or_else(|| {
+                    (
+                        line_no,
+                        format!("`{}`: Expected `repeat <count> [as <var>] {{`", line),
+                    )
+                })?;
+                let body = parse_block(lines, Some(line_no))?;
+                nodes.push(Node::Repeat { count, var, body });
+            }
+            None => nodes.push(Node::Line {
+                line_no,
+                text: line.to_owned(),
+            }),
+        }
+    }
+
+    match opened_at {
+        Some(line_no) => Err((line_no, "Block is never closed with `}`".to_owned())),
+        None => Ok(nodes),
+    }
+}
+
+fn parse_repeat(rest: &str) -> Option<(u64, String)> {
+    let rest = rest.strip_suffix('{')?;
+    let mut words = rest.split_whitespace();
+    let count = parse_value(words.next()?)?;
+    let var = match (words.next(), words.next(), words.next()) {
+        (None, None, None) => "i".to_owned(),
+        (Some("as"), Some(var), None) if is_var_name(var) => var.to_owned(),
+        _ => return None,
+    };
+
+    Some((count, var))
+}
+
+/// Parse every line with all variables in scope set to 0
+fn check(nodes: &[Node], vars: &mut Vars) -> Result<(), (usize, String)> {
+    for node in nodes {
+        match node {
+            Node::Line { line_no, text } => {
+                let line = substitute(text, vars).map_err(|e| (*line_no, e))?;
+                if let Step::Let { var, .. } = parse_step(&line).map_err(|e| (*line_no, e))? {
+                    vars.insert(var, 0);
+                }
+            }
+            Node::Repeat { var, body, .. } => {
+                let outer = vars.insert(var.clone(), 0);
+                check(body, vars)?;
+                restore(vars, var, outer);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn restore(vars: &mut Vars, var: &str, outer: Option<u64>) {
+    match outer {
+        Some(value) => vars.insert(var.to_owned(), value),
+        None => vars.remove(var),
+    };
+}
+
+fn is_var_name(s: &str) -> bool {
+    let mut chars = s.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Replace `$var`, `${var}` and `${var:x}` by the variable values
+pub fn substitute(line: &str, vars: &Vars) -> Result<String, String> {
+    let lookup = |name: &str| {
+        vars.get(name)
+            .copied()
+            .ok_or_else(|| format!("`{}`: Unknown variable `{}`", line, name))
+    };
+
+    let mut out = String::with_capacity(line.len());
+    let mut rest = line;
+    while let Some(idx) = rest.find('$') {
+        out.push_str(&rest[..idx]);
+        rest = &rest[idx + 1..];
+
+        if let Some(r) = rest.strip_prefix('$') {
+            out.push('$');
+            rest = r;
+        } else if let Some(r) = rest.strip_prefix('{') {
+            let end = r
+                .find('}')
+                .ok_or_else(|| format!("`{}`: Unterminated `${{`", line))?;
+            let (name, hex) = match r[..end].split_once(':') {
+                Some((name, "x")) => (name, true),
+                Some(_) => return Err(format!("`{}`: Only the `:x` format is supported", line)),
+                None => (&r[..end], false),
+            };
+            let value = lookup(name)?;
+            if hex {
+                out.push_str(&format!("{:02x}", value));
+            } else {
+                out.push_str(&value.to_string());
+            }
+            rest = &r[end + 1..];
+        } else {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            out.push_str(&lookup(&rest[..end])?.to_string());
+            rest = &rest[end..];
+        }
+    }
+    out.push_str(rest);
+
+    Ok(out)
+}
+
+fn parse_step(line: &str) -> Result<Step, String> {
+    let words = split_words(line)?;
+    match words.first().map(String::as_str) {
+        Some("wait") => parse_wait(line, &words[1..]),
+        Some("assert") => parse_assert(line, &words[1..]),
+        Some("let") => parse_let(line, &words[1..]),
+        Some("sleep") => match &words[1..] {
+            [duration] => Ok(Step::Sleep(parse_duration(duration)?)),
+            _ => Err(format!("`{}`: Expected `sleep <duration>`", line)),
+        },
+        _ => Ok(Step::Command {
+            cmd: macros::parse(line)?,
+        }),
+    }
+}
+
+fn parse_let(line: &str, words: &[String]) -> Result<Step, String> {
+    match words {
+        [var, eq, rhs @ ..] if eq == "=" && is_var_name(var) && !rhs.is_empty() => {
+            let value = match (rhs.len(), parse_value(&rhs[0])) {
+                (1, Some(value)) => LetValue::Value(value),
+                _ => LetValue::Command(Box::new(macros::parse(&rhs.join(" "))?)),
+            };
+
+            Ok(Step::Let {
+                var: var.clone(),
+                value,
+            })
+        }
+        _ => Err(format!(
+            "`{}`: Expected `let <var> = <command>` or `let <var> = <value>`",
+            line
+        )),
+    }
+}
+
+fn parse_assert(line: &str, words: &[String]) -> Result<Step, String> {
+    let usage = || {
+        format!(
+            "`{}`: Expected `assert <command> <op> <value>` or `assert <command> matches <sdu>`",
+            line
+        )
+    };
+
+    let op_idx = words
+        .iter()
+        .position(|w| w == "matches" || Comparison::parse(w).is_some())
+        .ok_or_else(usage)?;
+    let expectation = match (Comparison::parse(&words[op_idx]), &words[op_idx + 1..]) {
+        (Some(op), [value]) => Expectation::Compare(op, parse_value(value).ok_or_else(usage)?),
+        (None, [pattern]) => Expectation::Sdu(parse_sdu_pattern(pattern)?),
+        _ => return Err(usage()),
+    };
+
+    Ok(Step::Assert {
+        cmd: macros::parse(&words[..op_idx].join(" "))?,
+        expectation,
+    })
+}
+
+fn parse_wait(line: &str, words: &[String]) -> Result<Step, String> {
+    let usage = || {
+        format!(
+            "`{}`: Expected `wait <command> <op> <value> \
+            [--timeout <duration>] [--interval <duration>]`",
+            line
+        )
+    };
+
+    let op_idx = words
+        .iter()
+        .position(|w| Comparison::parse(w).is_some())
+        .ok_or_else(usage)?;
+    let op = Comparison::parse(&words[op_idx]).ok_or_else(usage)?;
+    let value = words
+        .get(op_idx + 1)
+        .and_then(|v| parse_value(v))
+        .ok_or_else(usage)?;
+    let cmd = macros::parse(&words[..op_idx].join(" "))?;
+
+    let mut timeout = DEFAULT_WAIT_TIMEOUT;
+    let mut interval = DEFAULT_WAIT_INTERVAL;
+    let mut options = words[op_idx + 2..].iter();
+    while let Some(option) = options.next() {
+        let target = match option.as_str() {
+            "--timeout" => &mut timeout,
+            "--interval" => &mut interval,
+            _ => return Err(usage()),
+        };
+        *target = parse_duration(options.next().ok_or_else(usage)?)?;
+    }
+
+    Ok(Step::Wait {
+        cmd,
+        op,
+        value,
+        timeout,
+        interval,
+    })
+}
+
+fn parse_value(s: &str) -> Option<u64> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+pub fn execute(
+    session: &mut Session,
+    nodes: &[Node],
+    vars: &mut Vars,
+    report: &mut Report,
+) -> Result<(), Error> {
+    for node in nodes {
+        match node {
+            Node::Line { line_no, text } => {
+                let (line, step) = substitute(text, vars)
+                    .and_then(|line| parse_step(&line).map(|step| (line, step)))
+                    .map_err(|e| {
+                        serialport::Error::new(
+                            ErrorKind::InvalidInput,
+                            format!("line {}: {}", line_no, e),
+                        )
+                    })?;
+                let start = Instant::now();
+                let result = execute_step(session, &line, &step, vars);
+                if !matches!(step, Step::Sleep(_)) {
+                    report.record(
+                        format!("line {}: {}", line_no, line),
+                        start.elapsed(),
+                        &result,
+                    );
+                }
+                result?;
+            }
+            Node::Repeat {
+                count, var, body, ..
+            } => {
+                let outer = vars.get(var).copied();
+                for i in 0..*count {
+                    vars.insert(var.clone(), i);
+                    execute(session, body, vars, report)?;
+                }
+                restore(vars, var, outer);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn execute_step(
+    session: &mut Session,
+    line: &str,
+    step: &Step,
+    vars: &mut Vars,
+) -> Result<(), Error> {
+    match step {
+        Step::Sleep(duration) => {
+            if session.args.echo {
+                eprintln!("> {}", line);
+            }
+            Ok(session.sleep(*duration)?)
+        }
+        Step::Let { var, value } => {
+            let value = match value {
+                LetValue::Value(value) => *value,
+                LetValue::Command(cmd) => {
+                    let responses = crate::perform(session, cmd)?;
+                    first_value(line, cmd, &responses)?
+                }
+            };
+            if session.args.echo {
+                eprintln!("> {} ({} = {})", line, var, value);
+            }
+            vars.insert(var.clone(), value);
+            Ok(())
+        }
+        Step::Command { cmd } => {
+            if session.args.echo {
+                eprintln!("> {}", line);
+            }
+            crate::execute(session, cmd).map(|_| ())
+        }
+        Step::Assert { cmd, expectation } => {
+            if session.args.echo {
+                eprintln!("> {}", line);
+            }
+            let responses = crate::perform(session, cmd)?;
+            let result = match expectation {
+                Expectation::Compare(op, value) => {
+                    let current = first_value(line, cmd, &responses)?;
+                    match op.eval(current, *value) {
+                        true => Ok(()),
+                        false => Err(format!("got {}", current)),
+                    }
+                }
+                Expectation::Sdu(pattern) => pattern.check(responses.first()),
+            };
+
+            result.map_err(|e| Error::Assertion(format!("`{}`: {}", line, e)))
+        }
+        Step::Wait {
+            cmd,
+            op,
+            value,
+            timeout,
+            interval,
+        } => {
+            eprintln!("Waiting for `{}`", line.trim_start_matches("wait").trim());
+            // Polls are paced by the clock of the session, virtual time
+            // included
+            let start = session.clock.now();
+            let elapsed = |session: &Session| {
+                session
+                    .clock
+                    .now()
+                    .duration_since(start)
+                    .unwrap_or_default()
+            };
+            loop {
+                let responses = match crate::perform(session, cmd) {
+                    // A device busy or restarting is asked again
+                    Err(Error::Serial(e))
+                        if e.kind == ErrorKind::Io(std::io::ErrorKind::TimedOut)
+                            && elapsed(session) < *timeout =>
+                    {
+                        session.stats.record_retries(1);
+                        session.sleep(*interval)?;
+                        continue;
+                    }
+                    result => result?,
+                };
+                let current = first_value(line, cmd, &responses)?;
+
+                if op.eval(current, *value) {
+                    eprintln!("Done after {:.1?} (value {})", elapsed(session), current);
+                    return Ok(());
+                }
+
+                if elapsed(session) >= *timeout {
+                    return Err(serialport::Error::new(
+                        ErrorKind::Io(std::io::ErrorKind::TimedOut),
+                        format!(
+                            "`{}` timed out after {:?}, last value {}",
+                            line, timeout, current
+                        ),
+                    )
+                    .into());
+                }
+                session.sleep(*interval)?;
+            }
+        }
+    }
+}
+
+fn first_value(
+    line: &str,
+    cmd: &Command,
+    responses: &[[u8; 16]],
+) -> Result<u64, serialport::Error> {
+    responses
+        .first()
+        .map(|r| decoded_value(cmd, r))
+        .ok_or_else(|| {
+            serialport::Error::new(
+                ErrorKind::InvalidInput,
+                format!("`{}`: The command gave no response", line),
+            )
+        })
+}
+
+/// The value a command's response decodes to, the SDU as big endian number
+/// unless the command defines otherwise
+pub fn decoded_value(cmd: &Command, frame: &[u8; 16]) -> u64 {
+    match cmd {
+        Command::ReadButtonPresses => frame.sdu_u8(7) as u64,
+        _ => frame.sdu_u64_be(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn step(line: &str) -> Step {
+        parse_step(line).unwrap()
+    }
+
+    fn error(src: &str) -> (usize, String) {
+        parse(src).unwrap_err()
+    }
+
+    #[test]
+    fn commands() {
+        assert!(matches!(
+            step("status"),
+            Step::Command {
+                cmd: Command::Status
+            }
+        ));
+        assert!(parse_step("no-such-command").is_err());
+        assert!(parse_step("run other").is_err());
+    }
+
+    #[test]
+    fn sleeps() {
+        assert!(matches!(step("sleep 250ms"), Step::Sleep(d) if d == Duration::from_millis(250)));
+        assert!(matches!(step("sleep 1m30s"), Step::Sleep(d) if d == Duration::from_secs(90)));
+        assert!(parse_step("sleep")
+            .unwrap_err()
+            .contains("Expected `sleep <duration>`"));
+        assert!(parse_step("sleep 1s 2s").is_err());
+        assert!(parse_step("sleep soon").is_err());
+    }
+
+    #[test]
+    fn waits() {
+        match step("wait uptime >= 0x10") {
+            Step::Wait {
+                cmd: Command::Uptime(_),
+                op: Comparison::Ge,
+                value: 16,
+                timeout,
+                interval,
+            } => {
+                assert_eq!(timeout, DEFAULT_WAIT_TIMEOUT);
+                assert_eq!(interval, DEFAULT_WAIT_INTERVAL);
+            }
+            other => panic!("{:?}", other),
+        }
+
+        match step("wait status != 3 --interval 1s --timeout 5m") {
+            Step::Wait {
+                op: Comparison::Ne,
+                value: 3,
+                timeout,
+                interval,
+                ..
+            } => {
+                assert_eq!(timeout, Duration::from_secs(300));
+                assert_eq!(interval, Duration::from_secs(1));
+            }
+            other => panic!("{:?}", other),
+        }
+
+        for line in [
+            "wait uptime",
+            "wait uptime >=",
+            "wait uptime >= many",
+            "wait uptime >= 1 --timeout",
+            "wait uptime >= 1 --every 1s",
+        ] {
+            let err = parse_step(line).unwrap_err();
+            assert!(
+                err.contains("Expected `wait <command> <op> <value>"),
+                "{}",
+                err
+            );
+        }
+        assert!(parse_step("wait uptime >= 1 --timeout never").is_err());
+    }
+
+    #[test]
+    fn asserts() {
+        assert!(matches!(
+            step("assert uptime < 100"),
+            Step::Assert {
+                cmd: Command::Uptime(_),
+                expectation: Expectation::Compare(Comparison::Lt, 100),
+            }
+        ));
+        assert!(matches!(
+            step("assert status matches ..03"),
+            Step::Assert {
+                cmd: Command::Status,
+                expectation: Expectation::Sdu(_),
+            }
+        ));
+
+        for line in ["assert uptime", "assert uptime ==", "assert uptime == 1 2"] {
+            let err = parse_step(line).unwrap_err();
+            assert!(
+                err.contains("Expected `assert <command> <op> <value>`"),
+                "{}",
+                err
+            );
+        }
+        assert!(parse_step("assert status matches zz").is_err());
+    }
+
+    #[test]
+    fn lets() {
+        assert!(matches!(
+            step("let n = 0x2a"),
+            Step::Let { var, value: LetValue::Value(42) } if var == "n"
+        ));
+        assert!(matches!(
+            step("let up = uptime"),
+            Step::Let { var, value: LetValue::Command(cmd) }
+                if var == "up" && matches!(*cmd, Command::Uptime(_))
+        ));
+
+        for line in ["let n", "let n 1", "let n =", "let 1n = 1"] {
+            let err = parse_step(line).unwrap_err();
+            assert!(err.contains("Expected `let <var> = <command>`"), "{}", err);
+        }
+        assert!(parse_step("let n = no-such-command").is_err());
+    }
+
+    #[test]
+    fn comparisons() {
+        assert!(Comparison::parse("==").unwrap().eval(1, 1));
+        assert!(Comparison::parse("!=").unwrap().eval(1, 2));
+        assert!(Comparison::parse("<").unwrap().eval(1, 2));
+        assert!(Comparison::parse("<=").unwrap().eval(2, 2));
+        assert!(Comparison::parse(">").unwrap().eval(3, 2));
+        assert!(Comparison::parse(">=").unwrap().eval(2, 2));
+        assert!(!Comparison::parse(">=").unwrap().eval(1, 2));
+        assert_eq!(Comparison::parse("=<"), None);
+    }
+
+    #[test]
+    fn substitution() {
+        let vars = Vars::from([("n".to_owned(), 42), ("i_2".to_owned(), 7)]);
+        assert_eq!(substitute("a $n b", &vars).unwrap(), "a 42 b");
+        assert_eq!(substitute("${n}0 $i_2", &vars).unwrap(), "420 7");
+        assert_eq!(substitute("0x${n:x}", &vars).unwrap(), "0x2a");
+        assert_eq!(substitute("$$n", &vars).unwrap(), "$n");
+        assert!(substitute("$m", &vars)
+            .unwrap_err()
+            .contains("Unknown variable `m`"));
+        assert!(substitute("${n", &vars)
+            .unwrap_err()
+            .contains("Unterminated"));
+        assert!(substitute("${n:b}", &vars).unwrap_err().contains("`:x`"));
+    }
+
+    #[test]
+    fn blocks() {
+        let nodes = parse(
+            "# setup\n\
+            let n = 1\n\
+            \n\
+            repeat 3 {\n\
+            repeat 0x2 as j {\n\
+            assert uptime >= $i$j$n\n\
+            }\n\
+            }\n\
+            sleep 10ms\n",
+        )
+        .unwrap();
+
+        match &nodes[..] {
+            [Node::Line { line_no: 2, .. }, Node::Repeat {
+                count: 3,
+                var,
+                body,
+            }, Node::Line { line_no: 9, text }] => {
+                assert_eq!(var, "i");
+                assert_eq!(text, "sleep 10ms");
+                match &body[..] {
+                    [Node::Repeat {
+                        count: 2,
+                        var,
+                        body,
+                    }] => {
+                        assert_eq!(var, "j");
+                        assert!(matches!(&body[..], [Node::Line { line_no: 6, .. }]));
+                    }
+                    other => panic!("{:?}", other),
+                }
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn block_errors() {
+        assert_eq!(
+            error("status\n}"),
+            (2, "`}` without an open block".to_owned())
+        );
+        assert_eq!(
+            error("status\nrepeat 2 {\nstatus"),
+            (2, "Block is never closed with `}`".to_owned())
+        );
+        for src in [
+            "repeat {\n}",
+            "repeat 2\n}",
+            "repeat 2 as {\n}",
+            "repeat 2 as 1 {\n}",
+        ] {
+            assert!(
+                error(src)
+                    .1
+                    .contains("Expected `repeat <count> [as <var>] {`"),
+                "{}",
+                src
+            );
+        }
+    }
+
+    #[test]
+    fn variables_are_scoped() {
+        assert_eq!(error("status\nassert uptime == $n").0, 2);
+        assert!(parse("let n = 1\nassert uptime == $n").is_ok());
+        assert!(parse("repeat 2 as k {\nassert uptime == $k\n}").is_ok());
+        let (line, msg) = error("repeat 2 as k {\n}\nassert uptime == $k");
+        assert_eq!(line, 3);
+        assert!(msg.contains("Unknown variable `k`"), "{}", msg);
+        assert_eq!(error("status\n\nsleep").0, 3);
+    }
+}
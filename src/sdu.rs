@@ -0,0 +1,119 @@
+//! Construction of SDUs and extraction of values from responses, so payloads
+//! are built and read by the values they carry instead of byte by byte.
+//!
+//! Offsets count from the first SDU byte. Values which don't fit into the 8
+//! bytes at the given offset are a bug of the caller and panic.
+
+use crate::{parse_hex, L7Sdu};
+
+/// Constructors of SDUs holding a single value, all other bytes are zero
+pub trait Sdu: Sized {
+    fn from_u8(offset: usize, value: u8) -> Self;
+    fn from_u64_be(value: u64) -> Self;
+    fn from_u32_le(offset: usize, value: u32) -> Self;
+    /// The UTF-8 bytes of a string of up to 8 bytes, padded with zeros
+    fn from_str_padded(s: &str) -> Result<Self, String>;
+    /// A hex string of 8 bytes, e.g. `00:00:00:00:00:00:00:01`
+    fn from_hex(s: &str) -> Result<Self, String>;
+}
+
+impl Sdu for L7Sdu {
+    fn from_u8(offset: usize, value: u8) -> Self {
+        let mut sdu = L7Sdu::default();
+        sdu[offset] = value;
+        sdu
+    }
+
+    fn from_u64_be(value: u64) -> Self {
+        value.to_be_bytes()
+    }
+
+    fn from_u32_le(offset: usize, value: u32) -> Self {
+        let mut sdu = L7Sdu::default();
+        sdu[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+        sdu
+    }
+
+    fn from_str_padded(s: &str) -> Result<Self, String> {
+        if s.len() > 8 {
+            return Err(format!(
+                "`{}` doesn't fit into the SDU, it has {} of 8 bytes",
+                s,
+                s.len()
+            ));
+        }
+        let mut sdu = L7Sdu::default();
+        sdu[..s.len()].copy_from_slice(s.as_bytes());
+        Ok(sdu)
+    }
+
+    fn from_hex(s: &str) -> Result<Self, String> {
+        let bytes = parse_hex(s)?;
+        L7Sdu::try_from(bytes.as_slice())
+            .map_err(|_| format!("The SDU consists of 8 bytes, got {}", bytes.len()))
+    }
+}
+
+/// Values carried by the SDU of a received frame
+pub trait Response {
+    fn sdu(&self) -> L7Sdu;
+
+    fn sdu_u8(&self, offset: usize) -> u8 {
+        self.sdu()[offset]
+    }
+
+    fn sdu_u64_be(&self) -> u64 {
+        u64::from_be_bytes(self.sdu())
+    }
+
+    fn sdu_u32_le(&self, offset: usize) -> u32 {
+        let mut bytes = [0u8; 4];
+        bytes.copy_from_slice(&self.sdu()[offset..offset + 4]);
+        u32::from_le_bytes(bytes)
+    }
+
+    /// The SDU as string up to the first zero byte, invalid UTF-8 is replaced
+    fn sdu_str(&self) -> String {
+        let sdu = self.sdu();
+        let len = sdu.iter().position(|b| *b == 0).unwrap_or(sdu.len());
+        String::from_utf8_lossy(&sdu[..len]).into_owned()
+    }
+}
+
+impl Response for [u8; 16] {
+    fn sdu(&self) -> L7Sdu {
+        self[6..14].try_into().expect("SDU is 8 bytes")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn constructors() {
+        assert_eq!(L7Sdu::from_u8(6, 2), [0, 0, 0, 0, 0, 0, 2, 0]);
+        assert_eq!(L7Sdu::from_u64_be(0x0102), [0, 0, 0, 0, 0, 0, 1, 2]);
+        assert_eq!(
+            L7Sdu::from_u32_le(4, 0xdeadbeef),
+            [0, 0, 0, 0, 0xef, 0xbe, 0xad, 0xde]
+        );
+        assert_eq!(L7Sdu::from_str_padded("MMCP"), Ok(*b"MMCP\0\0\0\0"));
+        assert!(L7Sdu::from_str_padded("too long!").is_err());
+        assert_eq!(
+            L7Sdu::from_hex("00:00:00:00:00:00:00:01"),
+            Ok([0, 0, 0, 0, 0, 0, 0, 1])
+        );
+        assert!(L7Sdu::from_hex("0001").is_err());
+    }
+
+    #[test]
+    fn responses() {
+        let mut frame = [0u8; 16];
+        frame[6..14].copy_from_slice(&[b'o', b'k', 0, 0, 0xef, 0xbe, 0xad, 0xde]);
+        assert_eq!(frame.sdu_u8(1), b'k');
+        assert_eq!(frame.sdu_u32_le(4), 0xdeadbeef);
+        assert_eq!(frame.sdu_u64_be(), 0x6f6b_0000_efbe_adde);
+        assert_eq!(frame.sdu_str(), "ok");
+    }
+}
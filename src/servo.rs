@@ -0,0 +1,131 @@
+//! Positions of the servos of a device.
+//!
+//! The set servo opcode carries the channel of the servo in SDU byte 5 and
+//! its position in degrees from 0 to 180 in the last two SDU bytes (big
+//! endian). Sweeps step a servo through a range of positions, to exercise
+//! the mechanics during bring-up.
+
+use std::{cmp::Ordering, ops::RangeInclusive, time::Duration};
+
+use clap::Args;
+
+use crate::{parse_duration, session::Session, L7Sdu, Opcode};
+
+/// Highest position in degrees
+const MAX_POSITION: u16 = 180;
+
+#[derive(Args, Debug, Clone)]
+pub struct SetServo {
+    /// Channel of the servo
+    channel: u8,
+    /// Position in degrees, from 0 to 180
+    #[arg(
+        value_parser = clap::value_parser!(u16).range(..=MAX_POSITION as i64),
+        required_unless_present = "sweep",
+        conflicts_with_all = ["sweep", "step", "dwell"]
+    )]
+    position: Option<u16>,
+    /// Step through the positions of a range instead, e.g. `0..180`
+    #[arg(long, value_parser = parse_sweep)]
+    sweep: Option<RangeInclusive<u16>>,
+    /// Degrees between the positions of a sweep, 1 by default
+    #[arg(long, value_parser = clap::value_parser!(u16).range(1..), requires = "sweep")]
+    step: Option<u16>,
+    /// Time to hold each position of a sweep, e.g. `100ms`, 20ms by default
+    #[arg(long, value_parser = parse_duration, requires = "sweep")]
+    dwell: Option<Duration>,
+}
+
+/// Move the servo, returning the answers to every position
+pub fn run(session: &mut Session, servo: &SetServo) -> Result<Vec<[u8; 16]>, serialport::Error> {
+    let mut responses = Vec::new();
+    let sweep = match (&servo.sweep, servo.position) {
+        (Some(sweep), _) => sweep.clone(),
+        (None, Some(position)) => {
+            set(session, servo.channel, position, &mut responses)?;
+            return Ok(responses);
+        }
+        (None, None) => unreachable!("clap requires a position or a sweep"),
+    };
+
+    let dwell = servo.dwell.unwrap_or(Duration::from_millis(20));
+    for (i, position) in positions(sweep, servo.step.unwrap_or(1)).enumerate() {
+        if i > 0 {
+            session.sleep(dwell)?;
+        }
+        set(session, servo.channel, position, &mut responses)?;
+    }
+    Ok(responses)
+}
+
+/// The positions of a sweep, from the start to the end of the range
+fn positions(sweep: RangeInclusive<u16>, step: u16) -> impl Iterator<Item = u16> {
+    let (from, to) = sweep.into_inner();
+    // The last step may be shorter, so the sweep ends at the end of the range
+    std::iter::successors(Some(from), move |&position| match position.cmp(&to) {
+        Ordering::Less => Some(to.min(position.saturating_add(step))),
+        Ordering::Greater => Some(to.max(position.saturating_sub(step))),
+        Ordering::Equal => None,
+    })
+}
+
+/// Move the servo to the position, the answer goes to `responses`
+fn set(
+    session: &mut Session,
+    channel: u8,
+    position: u16,
+    responses: &mut Vec<[u8; 16]>,
+) -> Result<(), serialport::Error> {
+    let mut sdu = L7Sdu::default();
+    sdu[5] = channel;
+    sdu[6..].copy_from_slice(&position.to_be_bytes());
+    let mut msg = [0u8; 16];
+    session.transact(session.builder(Opcode::SetServo, sdu), &mut msg)?;
+    if !session.args.no_response {
+        responses.push(msg);
+    }
+    Ok(())
+}
+
+fn parse_sweep(s: &str) -> Result<RangeInclusive<u16>, String> {
+    let invalid = || format!("`{}` is no range of positions like `0..180`", s);
+    let (from, to) = s.split_once("..").ok_or_else(invalid)?;
+    let from: u16 = from.trim().parse().map_err(|_| invalid())?;
+    let to: u16 = to.trim().parse().map_err(|_| invalid())?;
+    match from.max(to) {
+        0..=MAX_POSITION => Ok(from..=to),
+        _ => Err(format!(
+            "`{}` exceeds the position of {} degrees",
+            s, MAX_POSITION
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sweep(range: &str, step: u16) -> Vec<u16> {
+        positions(parse_sweep(range).unwrap(), step).collect()
+    }
+
+    #[test]
+    fn sweeps() {
+        assert_eq!(sweep("0..3", 1), [0, 1, 2, 3]);
+        assert_eq!(sweep("0..180", 60), [0, 60, 120, 180]);
+        assert_eq!(sweep("10..100", 40), [10, 50, 90, 100]);
+        assert_eq!(sweep("180..0", 100), [180, 80, 0]);
+        assert_eq!(sweep("5..1", 3), [5, 2, 1]);
+        assert_eq!(sweep("90..90", 1), [90]);
+        assert_eq!(sweep("0..180", 1).len(), 181);
+    }
+
+    #[test]
+    fn ranges() {
+        assert_eq!(parse_sweep(" 0 .. 180 "), Ok(0..=180));
+        assert_eq!(parse_sweep("180..0").unwrap().into_inner(), (180, 0));
+        assert!(parse_sweep("0..181").is_err());
+        assert!(parse_sweep("0-180").is_err());
+        assert!(parse_sweep("..180").is_err());
+    }
+}
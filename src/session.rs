@@ -0,0 +1,444 @@
+//! State shared by all exchanges of one invocation.
+//!
+//! Sending and receiving a frame allocates nothing unless it fails, long
+//! runs like transfers and the BER test reuse the buffers of the session.
+
+use std::{
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use serialport::{ErrorKind, SerialPort};
+
+use crate::{
+    auth,
+    capabilities::Capabilities,
+    clock::Clock,
+    config::Config,
+    keys::KeyFile,
+    output::{self, Output},
+    rejection::Rejection,
+    replay::CounterFile,
+    sdu::Response,
+    snapshot::Snapshots,
+    stats::Stats,
+    trace::{Direction, Trace},
+    transfer,
+    writer::FrameWriter,
+    Address, AuthKey, CliArgs, Frame, L7Sdu, MsgBuilder, Opcode,
+};
+
+pub struct Session {
+    /// Id of the device this invocation talks to
+    pub id: u8,
+    pub args: CliArgs,
+    pub config: Config,
+    pub serial: Box<dyn SerialPort>,
+    /// Frames written to `serial`, buffered while `buffer_writes` is set
+    writer: FrameWriter<Box<dyn SerialPort>>,
+    buffer_writes: bool,
+    /// When the last frame was written, to keep to `--rate`
+    last_write: Option<SystemTime>,
+    counters: Option<CounterFile>,
+    trace: Option<Trace>,
+    pub snapshots: Option<Snapshots>,
+    /// Clock of delays and trace timestamps, virtual with `--virtual-time`
+    pub clock: Clock,
+    pub stats: Stats,
+    /// Whether a command was already executed, to apply `--delay` before the next one
+    executed_command: bool,
+    /// Bytes of the frame read last, if it was cut short
+    pub received: Vec<u8>,
+    /// Capabilities of the device once queried, `Some(None)` if it didn't
+    /// answer
+    pub capabilities: Option<Option<Capabilities>>,
+    /// Rejection received last, if the device refused the request
+    pub rejection: Option<Rejection>,
+    /// Opcode of the frame received last, if it doesn't answer the request
+    pub stray: Option<u8>,
+    /// Destination of the results
+    pub out: Output,
+}
+
+impl Session {
+    pub fn new(
+        mut args: CliArgs,
+        config: Config,
+        serial: Box<dyn SerialPort>,
+    ) -> Result<Self, serialport::Error> {
+        let id = match args.group {
+            // Devices don't respond to messages to a group
+            Some(group) => {
+                args.no_response = true;
+                let address = Address::group(group).ok_or_else(|| {
+                    serialport::Error::new(
+                        ErrorKind::InvalidInput,
+                        format!(
+                            "There is no group {}, there are {} of them",
+                            group,
+                            Address::GROUPS
+                        ),
+                    )
+                })?;
+                address.0
+            }
+            None => args.id.ok_or_else(|| {
+                serialport::Error::new(ErrorKind::InvalidInput, "This command needs a device id")
+            })?,
+        };
+
+        if args.auth_key.is_none() {
+            args.auth_key = KeyFile::from_config(&config)?.get(id)?.map(AuthKey);
+        }
+
+        let counters = if args.replay_protection {
+            if args.auth_key.is_none() {
+                return Err(serialport::Error::new(
+                    ErrorKind::InvalidInput,
+                    format!(
+                        "--replay-protection needs a key, none is known for device {}",
+                        id
+                    ),
+                ));
+            }
+            Some(CounterFile::from_config(&config)?)
+        } else {
+            None
+        };
+
+        let trace = Trace::from_args(&args)?;
+        let snapshots = args
+            .snapshot
+            .as_deref()
+            .map(|dir| Snapshots::open(dir, args.update_snapshots, args.checksum()))
+            .transpose()?;
+
+        let clock = Clock::new(args.virtual_time);
+        let out = Output::open(&args)?;
+        let writer = FrameWriter::new(serial.try_clone()?);
+
+        Ok(Self {
+            id,
+            args,
+            config,
+            serial,
+            writer,
+            buffer_writes: false,
+            last_write: None,
+            counters,
+            trace,
+            snapshots,
+            clock,
+            stats: Stats::default(),
+            executed_command: false,
+            received: Vec::new(),
+            capabilities: None,
+            rejection: None,
+            stray: None,
+            out,
+        })
+    }
+
+    /// Wait for `--delay` if this isn't the first command of the session
+    pub fn pace(&mut self) -> Result<(), serialport::Error> {
+        if let (true, Some(delay)) = (self.executed_command, self.args.delay) {
+            self.sleep(delay)?;
+        }
+        self.executed_command = true;
+        Ok(())
+    }
+
+    /// Send buffered frames, then wait on the session's clock
+    pub fn sleep(&mut self, duration: Duration) -> Result<(), serialport::Error> {
+        self.flush()?;
+        self.clock.sleep(duration);
+        crate::deadline::check()
+    }
+
+    /// Keep written frames in a buffer until the next read, sleep or flush
+    /// instead of writing each one right away
+    pub fn buffer_writes(&mut self, on: bool) -> Result<(), serialport::Error> {
+        self.buffer_writes = on;
+        if !on {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Write all buffered frames to the port. On a half-duplex bus the
+    /// driver is enabled around them, until they are sent completely.
+    pub fn flush(&mut self) -> Result<(), serialport::Error> {
+        if !self.args.half_duplex || self.writer.is_empty() {
+            return Ok(self.writer.flush()?);
+        }
+
+        // The bus turns around in real time, also with --virtual-time
+        thread::sleep(self.args.tx_guard);
+        if self.args.rts {
+            self.serial.write_request_to_send(true)?;
+        }
+        // Flushing the port waits until the last byte is out
+        let written = self.writer.flush();
+        thread::sleep(self.args.rx_guard);
+        if self.args.rts {
+            self.serial.write_request_to_send(false)?;
+        }
+        Ok(written?)
+    }
+
+    /// Builder of a message to the device with the session's protocol version
+    pub fn builder(&self, opcode: Opcode, l7_sdu: L7Sdu) -> MsgBuilder {
+        MsgBuilder {
+            version: self.args.protocol_version,
+            ..MsgBuilder::new(self.id, opcode, l7_sdu)
+        }
+    }
+
+    /// Send a single message and read the response into `msg`, applying frame
+    /// authentication if a key is known. With `--no-response` `msg` is left
+    /// untouched.
+    pub fn transact(
+        &mut self,
+        builder: MsgBuilder,
+        msg: &mut [u8; 16],
+    ) -> Result<(), serialport::Error> {
+        let bytes = self.frame(builder)?;
+        self.write(&bytes)?;
+        if self.args.no_response {
+            return Ok(());
+        }
+
+        self.receive(builder.opcode, msg)
+    }
+
+    /// Build the frame of a message, signed if a key is known and the opcode
+    /// is authenticated, and with the selected checksum
+    pub fn frame(&mut self, mut builder: MsgBuilder) -> Result<[u8; 16], serialport::Error> {
+        let id = self.id;
+        if self.args.auth_key.is_some() && auth::unsigned_write(builder.opcode) {
+            return Err(serialport::Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Opcode {} ({}) changes the device but can't carry a tag, it isn't sent \
+                    while a key is known for device {}",
+                    u8::from(builder.opcode),
+                    builder.opcode.name().unwrap_or("unknown"),
+                    id
+                ),
+            ));
+        }
+        let key = self
+            .args
+            .auth_key
+            .as_ref()
+            .filter(|_| auth::authenticates(builder.opcode));
+        if let Some(AuthKey(key)) = key {
+            let counter = match &self.counters {
+                Some(counters) => Some(counters.next_tx(id)?),
+                None => None,
+            };
+            auth::sign(&mut builder, key, counter)?;
+        }
+
+        builder.validate()?;
+        let algorithm = self.args.checksum();
+        let bytes = builder.build_with(algorithm);
+        if self.args.echo {
+            // The checksum of a message built here is always valid
+            eprintln!(
+                "MSG: {}\n{}",
+                Frame::from(bytes),
+                output::table(&bytes, algorithm, transfer::seq_offset(&self.args))
+            );
+        }
+        Ok(bytes)
+    }
+
+    /// Read the response to a request of `opcode` into `msg`, verifying its
+    /// checksum if one is selected and its tag if a key is known and the
+    /// request is authenticated. Frames of other opcodes, other than the
+    /// rejection of the request, are refused without a look at their tag.
+    pub fn receive(&mut self, opcode: Opcode, msg: &mut [u8; 16]) -> Result<(), serialport::Error> {
+        self.rejection = None;
+        self.stray = None;
+        self.read_frame(msg)?;
+        self.check_response(opcode, msg)
+    }
+
+    /// Verify a frame read otherwise as response to a request of `opcode`,
+    /// like [`Session::receive`] does
+    pub fn check_response(
+        &mut self,
+        opcode: Opcode,
+        msg: &[u8; 16],
+    ) -> Result<(), serialport::Error> {
+        let id = self.id;
+        self.rejection = None;
+        self.stray = None;
+        if let Some(algorithm) = self.args.checksum {
+            let expected = algorithm.compute(&msg[1..14]);
+            if msg[14] != expected {
+                return Err(serialport::Error::new(
+                    ErrorKind::Io(std::io::ErrorKind::InvalidData),
+                    format!(
+                        "The response has checksum {:#04x}, {:?} expects {:#04x}",
+                        msg[14], algorithm, expected
+                    ),
+                ));
+            }
+        }
+
+        let answers = match Opcode::from_byte(msg[5]) {
+            Opcode::Rejected => msg.sdu_u8(6) == u8::from(opcode),
+            response => response == opcode,
+        };
+        if !answers {
+            self.stray = Some(msg[5]);
+            return Err(serialport::Error::new(
+                ErrorKind::Io(std::io::ErrorKind::InvalidData),
+                format!(
+                    "The response has opcode {}, the request {}",
+                    msg[5],
+                    u8::from(opcode)
+                ),
+            ));
+        }
+
+        let key = self
+            .args
+            .auth_key
+            .as_ref()
+            .filter(|_| auth::authenticates(opcode));
+        if let Some(AuthKey(key)) = key {
+            match &self.counters {
+                Some(counters) => {
+                    let mut current = counters.get(id)?;
+                    if let Some(rx) = auth::verify(msg, key, Some(current.rx))? {
+                        current.rx = rx;
+                        counters.set(id, current)?;
+                    }
+                }
+                None => {
+                    auth::verify(msg, key, None)?;
+                }
+            }
+        }
+
+        if let Some(rejection) = Rejection::parse(msg) {
+            self.rejection = Some(rejection);
+            return Err(serialport::Error::new(
+                ErrorKind::InvalidInput,
+                rejection.to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Write raw bytes to the port, waiting as long as `--rate` requires
+    pub fn write(&mut self, bytes: &[u8]) -> Result<(), serialport::Error> {
+        crate::deadline::check()?;
+        if let (Some(rate), Some(last)) = (self.args.rate, self.last_write) {
+            let interval = Duration::from_secs_f64(1.0 / rate);
+            let elapsed = self.clock.now().duration_since(last).unwrap_or_default();
+            if elapsed < interval {
+                self.sleep(interval - elapsed)?;
+            }
+        }
+        self.last_write = Some(self.clock.now());
+
+        if let Some(trace) = &mut self.trace {
+            trace.log(self.clock.now(), Direction::Tx, bytes)?;
+        }
+
+        self.writer.queue(bytes)?;
+        if !self.buffer_writes {
+            self.flush()?;
+        }
+        self.stats.record_tx();
+        Ok(())
+    }
+
+    /// Read a single frame into `msg` without any verification.
+    ///
+    /// The first byte is awaited for the response timeout, after that each
+    /// further byte has to arrive within the inter byte timeout.
+    pub fn read_frame(&mut self, msg: &mut [u8; 16]) -> Result<(), serialport::Error> {
+        // A response can only follow its request
+        self.flush()?;
+        match self.read_bytes(msg) {
+            Ok(()) => self.stats.record_rx(msg, self.args.checksum()),
+            Err(e) => {
+                if e.kind == ErrorKind::Io(std::io::ErrorKind::TimedOut) {
+                    self.stats.record_timeout();
+                }
+                return Err(e);
+            }
+        }
+
+        if let Some(trace) = &mut self.trace {
+            trace.log(self.clock.now(), Direction::Rx, msg)?;
+        }
+
+        Ok(())
+    }
+
+    fn read_bytes(&mut self, msg: &mut [u8; 16]) -> Result<(), serialport::Error> {
+        let response_timeout = self.args.response_timeout();
+        let inter_byte_timeout = self.args.inter_byte_timeout();
+
+        // Whether the deadline shortened the timeout of the read
+        let mut cut = self.limit_timeout(response_timeout)?;
+        self.received.clear();
+        let mut read = 0;
+        while read < msg.len() {
+            match self.serial.read(&mut msg[read..]) {
+                Ok(0) => {
+                    return Err(serialport::Error::new(
+                        ErrorKind::Io(std::io::ErrorKind::UnexpectedEof),
+                        "The port was closed while reading a frame",
+                    ))
+                }
+                Ok(n) => {
+                    if read == 0 && response_timeout != inter_byte_timeout {
+                        cut = self.limit_timeout(inter_byte_timeout)?;
+                    }
+                    read += n;
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => (),
+                // Cut short by the deadline rather than the device
+                Err(e) if e.kind() == std::io::ErrorKind::TimedOut && cut => {
+                    return Err(crate::deadline::error())
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::TimedOut && read > 0 => {
+                    self.received.extend_from_slice(&msg[..read]);
+                    return Err(serialport::Error::new(
+                        ErrorKind::Io(e.kind()),
+                        format!("Timed out after receiving {} of 16 bytes", read),
+                    ));
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Set the read timeout of the port, shortened to the deadline if it is
+    /// closer. Returns whether it was
+    fn limit_timeout(&mut self, timeout: Duration) -> Result<bool, serialport::Error> {
+        let limited = crate::deadline::limit(timeout);
+        self.serial.set_timeout(limited)?;
+        Ok(limited < timeout)
+    }
+
+    /// Forget the frame counters of the device, done whenever its key changes
+    pub fn reset_counters(&self) -> Result<(), serialport::Error> {
+        let counters = CounterFile::from_config(&self.config)?;
+        if counters.path.exists() {
+            counters.set(self.id, Default::default())?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,36 @@
+//! SIGTERM and SIGINT ending long running modes gracefully.
+//!
+//! Once [`handle`] was called, the signals only set a flag which loops check
+//! with [`stopping`], so ports are closed and output files completed.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Set by SIGTERM and SIGINT
+static STOP: AtomicBool = AtomicBool::new(false);
+
+/// Whether SIGTERM or SIGINT arrived since [`handle`], or a failure of
+/// another thread or the deadline ended the invocation
+pub fn stopping() -> bool {
+    STOP.load(Ordering::SeqCst) || crate::deadline::expired()
+}
+
+/// Make loops end like on SIGTERM, e.g. when a thread they rely on failed
+pub fn stop() {
+    STOP.store(true, Ordering::SeqCst);
+}
+
+/// Let SIGTERM and SIGINT set [`stopping`] instead of ending the process
+pub fn handle() {
+    #[cfg(unix)]
+    {
+        extern "C" fn stop(_: libc::c_int) {
+            STOP.store(true, Ordering::SeqCst);
+        }
+        let handler = stop as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        // SAFETY: The handler only stores to an atomic, which is signal safe
+        unsafe {
+            libc::signal(libc::SIGTERM, handler);
+            libc::signal(libc::SIGINT, handler);
+        }
+    }
+}
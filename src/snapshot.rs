@@ -0,0 +1,178 @@
+//! Golden response snapshots.
+//!
+//! With `--snapshot <dir>` the decoded responses of every command are stored
+//! in the directory the first time and compared against on later runs, so
+//! firmware changes altering responses are noticed. A command executed several
+//! times in one run gets a snapshot per execution.
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serialport::ErrorKind;
+
+use crate::{describe, error::Error, ChecksumAlgorithm, Command};
+
+#[derive(Debug)]
+pub struct Snapshots {
+    dir: PathBuf,
+    /// Overwrite snapshots which don't match instead of failing
+    update: bool,
+    /// Number of executions of each snapshot name so far
+    seen: BTreeMap<String, usize>,
+    /// Algorithm the checksums of the decoded responses are verified with
+    checksum: ChecksumAlgorithm,
+}
+
+impl Snapshots {
+    pub fn open(
+        dir: &Path,
+        update: bool,
+        checksum: ChecksumAlgorithm,
+    ) -> Result<Self, serialport::Error> {
+        fs::create_dir_all(dir).map_err(|e| io_error(dir, e))?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            update,
+            seen: BTreeMap::new(),
+            checksum,
+        })
+    }
+
+    /// Compare the responses of the command with its snapshot, or store them
+    /// if there is none yet
+    pub fn check(&mut self, cmd: &Command, responses: &[[u8; 16]]) -> Result<(), Error> {
+        let name = match name(cmd) {
+            Some(name) => name,
+            None => return Ok(()),
+        };
+        let count = self.seen.entry(name.clone()).or_default();
+        *count += 1;
+        let name = format!("{}.{}", name, count);
+        let path = self.dir.join(format!("{}.snap", name));
+
+        let current: String = responses
+            .iter()
+            .map(|frame| format!("{}\n", describe(frame, self.checksum)))
+            .collect();
+        let stored = match fs::read_to_string(&path) {
+            Ok(stored) => stored,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                eprintln!("Stored new snapshot `{}`", name);
+                return write(&path, &current);
+            }
+            Err(e) => return Err(io_error(&path, e).into()),
+        };
+
+        if stored == current {
+            return Ok(());
+        }
+        if self.update {
+            eprintln!("Updated snapshot `{}`", name);
+            return write(&path, &current);
+        }
+
+        let mut msg = format!("Response drifted from snapshot `{}`\n", name);
+        for line in stored.lines() {
+            msg.push_str(&format!("- {}\n", line));
+        }
+        for line in current.lines() {
+            msg.push_str(&format!("+ {}\n", line));
+        }
+        Err(Error::Assertion(msg.trim_end().to_owned()))
+    }
+}
+
+/// File name of the snapshot of a command, commands which are not compared
+/// have none
+fn name(cmd: &Command) -> Option<String> {
+    let hex = |bytes: &[u8]| {
+        bytes
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>()
+    };
+    Some(match cmd {
+        Command::Raw(raw) => format!("raw-{}", hex(&raw.bytes.concat())),
+        Command::SetLed(set_led) => match set_led.index {
+            0 => format!("set-led-{:?}", set_led.on).to_lowercase(),
+            index => format!("set-led{}-{:?}", index, set_led.on).to_lowercase(),
+        },
+        Command::GetLed(get_led) => match get_led.index {
+            0 => "get-led".to_owned(),
+            index => format!("get-led{}", index),
+        },
+        Command::ReadButtonPresses => "read-button-presses".to_owned(),
+        Command::ConfigureButton(configure) => {
+            format!("configure-button-{}", hex(&configure.as_sdu()))
+        }
+        Command::Beep(beep) => format!("beep-{}", hex(&beep.as_sdu())),
+        Command::SetRelay(set_relay) => format!("set-relay-{}", hex(&set_relay.as_sdu())),
+        Command::ReadUid => "read-uid".to_owned(),
+        Command::Capabilities => "capabilities".to_owned(),
+        Command::Status => "status".to_owned(),
+        Command::Uptime(_) => "uptime".to_owned(),
+        Command::Stats(stats) => match stats.clear {
+            false => "stats".to_owned(),
+            true => "stats-clear".to_owned(),
+        },
+        Command::GetRelays => "get-relays".to_owned(),
+        Command::DisplayNumber(display) => format!("display-number-{}", display.number),
+        Command::Send(send) => format!(
+            "send-to{}-from{}-v{}-hops{}-op{}-{}",
+            send.to.map_or("-id".to_owned(), |to| to.0.to_string()),
+            send.from.0,
+            send.version
+                .map_or("-default".to_owned(), |v| v.to_string()),
+            send.hops,
+            send.opcode,
+            hex(&send.sdu.unwrap_or_default()),
+        ),
+        // Key exchanges carry secrets, the others consist of further commands
+        // or of many frames
+        Command::Key(_)
+        | Command::Run(_)
+        | Command::Script(_)
+        | Command::Group(_)
+        | Command::LcdWrite(_)
+        | Command::SetServo(_)
+        | Command::Subscribe(_)
+        | Command::ListOpcodes(_)
+        | Command::Replay(_)
+        | Command::BerTest(_)
+        | Command::MeshFlood(_)
+        | Command::Upload(_)
+        | Command::Download(_)
+        | Command::Peek(_)
+        | Command::Poke(_)
+        | Command::DumpFlash(_)
+        | Command::Verify(_)
+        | Command::ReadReg(_)
+        | Command::WriteReg(_)
+        | Command::Diff(_)
+        | Command::Explain(_)
+        | Command::Checksum(_)
+        | Command::ListPorts
+        | Command::Emulate(_)
+        | Command::ChaosProxy(_)
+        | Command::Relay(_)
+        | Command::Route(_)
+        | Command::Monitor(_)
+        | Command::MqttBridge(_)
+        | Command::Remote(_)
+        | Command::Daemon(_) => return None,
+    })
+}
+
+fn write(path: &Path, contents: &str) -> Result<(), Error> {
+    fs::write(path, contents).map_err(|e| io_error(path, e).into())
+}
+
+fn io_error(path: &Path, e: std::io::Error) -> serialport::Error {
+    serialport::Error::new(
+        ErrorKind::Io(e.kind()),
+        format!("Could not access snapshot {}: {}", path.display(), e),
+    )
+}
@@ -0,0 +1,104 @@
+//! Link statistics of one invocation.
+
+use std::time::{Duration, Instant};
+
+use clap::ValueEnum;
+
+use crate::ChecksumAlgorithm;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum StatsFormat {
+    Human,
+    Json,
+}
+
+#[derive(Debug, Default)]
+pub struct Stats {
+    pub sent: u64,
+    pub received: u64,
+    /// Requests sent again after their response timed out
+    pub retries: u64,
+    pub checksum_errors: u64,
+    pub timeouts: u64,
+    rtt_total: Duration,
+    rtt_count: u32,
+    /// Time of the last transmission not answered yet
+    pending: Option<Instant>,
+}
+
+impl Stats {
+    pub fn record_tx(&mut self) {
+        self.sent += 1;
+        self.pending = Some(Instant::now());
+    }
+
+    /// Count a received frame, whose checksum is verified with `algorithm`,
+    /// the first one after a transmission gives the round trip time
+    pub fn record_rx(&mut self, frame: &[u8; 16], algorithm: ChecksumAlgorithm) {
+        self.received += 1;
+        if algorithm.compute(&frame[1..14]) != frame[14] {
+            self.checksum_errors += 1;
+        }
+        if let Some(sent) = self.pending.take() {
+            self.rtt_total += sent.elapsed();
+            self.rtt_count += 1;
+        }
+    }
+
+    pub fn record_timeout(&mut self) {
+        self.timeouts += 1;
+    }
+
+    /// Count `n` requests sent again
+    pub fn record_retries(&mut self, n: u64) {
+        self.retries += n;
+    }
+
+    pub fn average_rtt(&self) -> Option<Duration> {
+        (self.rtt_count > 0).then(|| self.rtt_total / self.rtt_count)
+    }
+
+    pub fn render(&self, format: StatsFormat) -> String {
+        let rtt_ms = self.average_rtt().map(|d| d.as_secs_f64() * 1_000.0);
+        match format {
+            StatsFormat::Human => format!(
+                "Frames: {} sent, {} received\n\
+                Retries: {}, checksum errors: {}, timeouts: {}\n\
+                Average RTT: {}",
+                self.sent,
+                self.received,
+                self.retries,
+                self.checksum_errors,
+                self.timeouts,
+                rtt_ms.map_or("-".to_owned(), |ms| format!("{:.3}ms", ms)),
+            ),
+            StatsFormat::Json => format!(
+                "{{\"sent\":{},\"received\":{},\"retries\":{},\"checksum_errors\":{},\
+                \"timeouts\":{},\"average_rtt_ms\":{}}}",
+                self.sent,
+                self.received,
+                self.retries,
+                self.checksum_errors,
+                self.timeouts,
+                rtt_ms.map_or("null".to_owned(), |ms| format!("{:.3}", ms)),
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retries_are_rendered() {
+        let mut stats = Stats::default();
+        stats.record_tx();
+        stats.record_timeout();
+        stats.record_retries(2);
+        assert!(stats
+            .render(StatsFormat::Human)
+            .contains("Retries: 2, checksum errors: 0, timeouts: 1"));
+        assert!(stats.render(StatsFormat::Json).contains("\"retries\":2"));
+    }
+}
@@ -0,0 +1,112 @@
+//! Telemetry pushed by the device.
+//!
+//! The subscribe opcode carries the opcode to push in SDU byte 5 and the
+//! period in milliseconds in bytes 6 and 7 (big endian), a period of 0 ends
+//! the subscription. The device answers it with a status in the last SDU
+//! byte, non zero if it can't push the opcode. Afterwards it sends a frame
+//! like the response to the opcode every period, without being asked.
+
+use std::{
+    io::Write,
+    time::{Duration, SystemTime},
+};
+
+use clap::Args;
+use serialport::ErrorKind;
+
+use crate::{
+    describe, error::Error, output::Format, parse_duration, parse_u8, sdu::Response,
+    session::Session, signals, trace::format_timestamp, L7Sdu, Opcode,
+};
+
+#[derive(Args, Debug, Clone, Copy)]
+pub struct Subscribe {
+    /// Opcode of the values to push, e.g. `101` for the button presses
+    #[arg(value_parser = parse_u8)]
+    opcode: u8,
+    /// Time between two pushed frames, from 1ms to 65s, e.g. `500ms`
+    #[arg(long, default_value = "1s", value_parser = parse_period)]
+    period: Duration,
+}
+
+pub fn subscribe(session: &mut Session, subscribe: &Subscribe) -> Result<(), Error> {
+    request(session, subscribe.opcode, subscribe.period)?;
+    eprintln!(
+        "Subscribed to opcode {} of device {}, stop with Ctrl-C",
+        subscribe.opcode, session.id
+    );
+
+    signals::handle();
+    while !signals::stopping() {
+        let mut frame = [0u8; 16];
+        match session.receive(Opcode::from_byte(subscribe.opcode), &mut frame) {
+            Ok(()) => print(session, &frame)?,
+            // Frames of other opcodes are none of the pushed ones
+            Err(_) if session.stray.is_some() => (),
+            Err(e) if e.kind == ErrorKind::Io(std::io::ErrorKind::TimedOut) => (),
+            // A signal interrupting the read
+            Err(e) if e.kind == ErrorKind::Io(std::io::ErrorKind::Interrupted) => (),
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    request(session, subscribe.opcode, Duration::ZERO)?;
+    Ok(())
+}
+
+/// Subscribe to the opcode, or end the subscription with a zero period
+fn request(session: &mut Session, opcode: u8, period: Duration) -> Result<(), Error> {
+    let mut sdu = L7Sdu::default();
+    sdu[5] = opcode;
+    sdu[6..].copy_from_slice(&(period.as_millis() as u16).to_be_bytes());
+    let frame = session.frame(session.builder(Opcode::Subscribe, sdu))?;
+    session.write(&frame)?;
+
+    // Pushed frames may still arrive before the answer
+    loop {
+        let mut msg = [0u8; 16];
+        match session.receive(Opcode::Subscribe, &mut msg) {
+            Ok(()) => (),
+            Err(_) if session.stray.is_some() => continue,
+            Err(e) => return Err(e.into()),
+        }
+        return match msg.sdu_u8(7) {
+            0 => Ok(()),
+            status => Err(serialport::Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Device {} can't push opcode {} (status {})",
+                    session.id, opcode, status
+                ),
+            )
+            .into()),
+        };
+    }
+}
+
+/// Print the value of a pushed frame
+fn print(session: &mut Session, frame: &[u8; 16]) -> Result<(), Error> {
+    if session.out.format() != Format::Text {
+        return Ok(session.out.frame(frame)?);
+    }
+
+    let value = match Opcode::from_byte(frame[5]) {
+        Opcode::ReadButtonPresses => format!("{} button presses", frame.sdu_u8(7)),
+        _ => describe(frame, session.args.checksum()),
+    };
+    writeln!(
+        session.out,
+        "{}  {}",
+        format_timestamp(SystemTime::now()),
+        value
+    )?;
+    Ok(())
+}
+
+fn parse_period(s: &str) -> Result<Duration, String> {
+    let period = parse_duration(s)?;
+    match period.as_millis() {
+        1..=65_535 => Ok(period),
+        _ => Err(format!("`{}` is no period from 1ms to 65.535s", s)),
+    }
+}
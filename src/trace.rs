@@ -0,0 +1,418 @@
+//! Persistent log of every transmitted and received frame.
+//!
+//! With `--rotate` a new file is started once the current one reaches a size
+//! or an age. The old file is renamed to `<name>.1`, shifting older ones to
+//! `<name>.2` and so on, and with `--keep` only that many old files are kept.
+//!
+//! With `--compress gzip` every line is compressed and written right away,
+//! continuing a gzip member of up to 64 KiB or 10 seconds of traffic.
+//! Concatenated members are a valid gzip file, so a trace can be continued
+//! and read with `zcat`. The member of a killed process is never finished,
+//! `zcat` then warns about an unexpected end, but no line of it is lost.
+
+use std::{
+    fmt::{self, Display, Write as _},
+    fs::{self, File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use clap::ValueEnum;
+use serialport::ErrorKind;
+
+use crate::{gzip, ChecksumAlgorithm, CliArgs, FrameText, Hex};
+
+/// Uncompressed bytes of a gzip member before the next one is started
+const BLOCK_SIZE: usize = 64 * 1024;
+/// Longest time a gzip member is continued
+const BLOCK_AGE: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Tx,
+    Rx,
+}
+
+impl Direction {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Direction::Tx => "TX",
+            Direction::Rx => "RX",
+        }
+    }
+}
+
+/// A single line of a trace file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub timestamp: String,
+    pub direction: Direction,
+    pub bytes: Vec<u8>,
+}
+
+/// Read all entries of a trace file
+pub fn read(path: &Path) -> Result<Vec<Entry>, serialport::Error> {
+    let invalid = |msg: String| {
+        serialport::Error::new(
+            ErrorKind::InvalidInput,
+            format!("Could not read trace file {}: {}", path.display(), msg),
+        )
+    };
+    let mut contents = fs::read(path).map_err(|e| {
+        serialport::Error::new(
+            ErrorKind::Io(e.kind()),
+            format!("Could not read trace file {}: {}", path.display(), e),
+        )
+    })?;
+    if contents.starts_with(&[0x1f, 0x8b]) {
+        contents = gzip::decompress(&contents).map_err(invalid)?;
+    }
+    let contents = String::from_utf8(contents).map_err(|e| invalid(e.to_string()))?;
+
+    contents
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            parse_line(line).ok_or_else(|| {
+                serialport::Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("{}:{}: Not a trace line", path.display(), i + 1),
+                )
+            })
+        })
+        .collect()
+}
+
+fn parse_line(line: &str) -> Option<Entry> {
+    // Everything after `|` is the decode, which is derived from the bytes
+    let line = line.split('|').next()?;
+    let mut fields = line.split_whitespace();
+    let timestamp = fields.next()?.to_owned();
+    let direction = match fields.next()? {
+        "TX" => Direction::Tx,
+        "RX" => Direction::Rx,
+        _ => return None,
+    };
+    let bytes = fields
+        .map(|b| u8::from_str_radix(b, 16).ok())
+        .collect::<Option<_>>()?;
+
+    Some(Entry {
+        timestamp,
+        direction,
+        bytes,
+    })
+}
+
+/// When to start a new trace file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rotation {
+    Size(u64),
+    Age(Duration),
+}
+
+/// Parse a size like `100MB` or a duration like `1h`, KB, MB and GB are
+/// multiples of 1024
+pub fn parse_rotation(s: &str) -> Result<Rotation, String> {
+    let units = [("GB", 1 << 30), ("MB", 1 << 20), ("KB", 1 << 10), ("B", 1)];
+    for (unit, factor) in units {
+        if let Some(value) = s.strip_suffix(unit) {
+            return value
+                .trim()
+                .parse::<u64>()
+                .ok()
+                .filter(|v| *v > 0)
+                .and_then(|v| v.checked_mul(factor))
+                .map(Rotation::Size)
+                .ok_or_else(|| format!("`{}` is not a size like `100MB`", s));
+        }
+    }
+
+    match crate::parse_duration(s) {
+        Ok(age) if !age.is_zero() => Ok(Rotation::Age(age)),
+        _ => Err(format!(
+            "`{}` is neither a size like `100MB` nor a duration like `1h`",
+            s
+        )),
+    }
+}
+
+/// Compression of trace files
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Compression {
+    Gzip,
+}
+
+pub struct Trace {
+    file: File,
+    path: PathBuf,
+    rotation: Option<Rotation>,
+    /// Number of old files to keep, all with `None`
+    keep: Option<usize>,
+    /// Size of the current file
+    written: u64,
+    /// Time of the first line of the current file
+    started: Option<SystemTime>,
+    compression: Option<Compression>,
+    /// Algorithm the checksums of decoded frames are verified with
+    checksum: ChecksumAlgorithm,
+    /// The gzip member being written and since when
+    member: Option<gzip::Member>,
+    member_since: Option<Instant>,
+    /// The line being logged, kept to reuse its memory
+    line: String,
+}
+
+impl Trace {
+    /// Open the trace file of `--trace-file`, if any, rotated as requested
+    pub fn from_args(args: &CliArgs) -> Result<Option<Self>, serialport::Error> {
+        let path = match &args.trace_file {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+        let mut trace = Self::open(path)?;
+        trace.rotation = args.rotate;
+        trace.keep = args.keep;
+        trace.compression = args.compress;
+        trace.checksum = args.checksum();
+        Ok(Some(trace))
+    }
+
+    /// Open the trace file for appending, creating it if necessary
+    pub fn open(path: &Path) -> Result<Self, serialport::Error> {
+        let file = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(path)
+            .map_err(|e| io_error(path, e))?;
+        let written = file.metadata().map_err(|e| io_error(path, e))?.len();
+
+        Ok(Self {
+            file,
+            path: path.to_path_buf(),
+            rotation: None,
+            keep: None,
+            written,
+            started: None,
+            compression: None,
+            checksum: ChecksumAlgorithm::Sum,
+            member: None,
+            member_since: None,
+            line: String::new(),
+        })
+    }
+
+    /// Append one line with the timestamp of `time`, direction, the bytes and
+    /// their decode
+    pub fn log(
+        &mut self,
+        time: SystemTime,
+        direction: Direction,
+        bytes: &[u8],
+    ) -> Result<(), serialport::Error> {
+        let mut line = std::mem::take(&mut self.line);
+        line.clear();
+        let _ = write!(
+            line,
+            "{} {} {}",
+            Timestamp(time),
+            direction.as_str(),
+            Hex(bytes)
+        );
+        if let Ok(frame) = <[u8; 16]>::try_from(bytes) {
+            let _ = write!(line, " | {}", FrameText(&frame, self.checksum));
+        }
+        line.push('\n');
+        let logged = self.append(time, &line);
+        self.line = line;
+        logged
+    }
+
+    fn append(&mut self, time: SystemTime, line: &str) -> Result<(), serialport::Error> {
+        let due = match (self.rotation, self.started) {
+            (Some(Rotation::Size(size)), _) => {
+                self.written > 0 && self.written + line.len() as u64 > size
+            }
+            (Some(Rotation::Age(age)), Some(started)) => {
+                time.duration_since(started).unwrap_or_default() >= age
+            }
+            _ => false,
+        };
+        if due {
+            self.rotate()?;
+        }
+        self.started.get_or_insert(time);
+
+        match self.compression {
+            None => self.write(line.as_bytes()),
+            Some(Compression::Gzip) => {
+                let member = self.member.get_or_insert_with(gzip::Member::new);
+                let bytes = member.append(line.as_bytes());
+                let full = member.len >= BLOCK_SIZE;
+                self.write(&bytes)?;
+                let since = *self.member_since.get_or_insert_with(Instant::now);
+                if full || since.elapsed() >= BLOCK_AGE {
+                    self.finish()?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Write the end of the gzip member, the next line starts another one
+    fn finish(&mut self) -> Result<(), serialport::Error> {
+        self.member_since = None;
+        match self.member.take() {
+            Some(member) => self.write(&member.finish()),
+            None => Ok(()),
+        }
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<(), serialport::Error> {
+        // One write per line, so concurrent invocations don't interleave
+        // them
+        self.file.write_all(bytes).map_err(|e| {
+            serialport::Error::new(
+                ErrorKind::Io(e.kind()),
+                format!("Could not write trace file: {}", e),
+            )
+        })?;
+        self.written += bytes.len() as u64;
+        Ok(())
+    }
+
+    /// Move the current file aside and start a new one
+    fn rotate(&mut self) -> Result<(), serialport::Error> {
+        self.finish()?;
+        let old = |n: usize| {
+            let mut name = self.path.clone().into_os_string();
+            name.push(format!(".{}", n));
+            PathBuf::from(name)
+        };
+
+        let last = match self.keep {
+            Some(keep) => keep,
+            // The first free suffix
+            None => (1..).find(|n| !old(*n).exists()).unwrap_or(1),
+        };
+        if last > 0 && old(last).exists() {
+            fs::remove_file(old(last)).map_err(|e| io_error(&old(last), e))?;
+        }
+        for n in (1..last).rev() {
+            if old(n).exists() {
+                fs::rename(old(n), old(n + 1)).map_err(|e| io_error(&old(n), e))?;
+            }
+        }
+        match last {
+            0 => fs::remove_file(&self.path),
+            _ => fs::rename(&self.path, old(1)),
+        }
+        .map_err(|e| io_error(&self.path, e))?;
+
+        let mut next = Self::open(&self.path)?;
+        (next.rotation, next.keep, next.compression) = (self.rotation, self.keep, self.compression);
+        *self = next;
+        Ok(())
+    }
+}
+
+impl Drop for Trace {
+    fn drop(&mut self) {
+        if let Err(e) = self.finish() {
+            eprintln!("{}", e);
+        }
+    }
+}
+
+fn io_error(path: &Path, e: std::io::Error) -> serialport::Error {
+    serialport::Error::new(
+        ErrorKind::Io(e.kind()),
+        format!("Could not access trace file {}: {}", path.display(), e),
+    )
+}
+
+/// Current UTC time formatted as RFC 3339 with milli seconds
+pub fn timestamp() -> String {
+    format_timestamp(SystemTime::now())
+}
+
+pub fn format_timestamp(time: SystemTime) -> String {
+    Timestamp(time).to_string()
+}
+
+/// A time formatted as RFC 3339 with milli seconds, see [`format_timestamp`],
+/// written without allocating
+pub struct Timestamp(pub SystemTime);
+
+impl Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let since_epoch = self.0.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let secs = since_epoch.as_secs();
+        let (days, secs_of_day) = ((secs / 86_400) as i64, secs % 86_400);
+
+        // Civil date from days since the epoch, after Howard Hinnant's algorithm
+        let z = days + 719_468;
+        let era = z.div_euclid(146_097);
+        let doe = z.rem_euclid(146_097);
+        let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+            year,
+            month,
+            day,
+            secs_of_day / 3_600,
+            secs_of_day / 60 % 60,
+            secs_of_day % 60,
+            since_epoch.subsec_millis()
+        )
+    }
+}
+
+/// Parse a timestamp as written by `format_timestamp`
+pub fn parse_timestamp(s: &str) -> Option<SystemTime> {
+    let s = s.strip_suffix('Z')?;
+    let (date, time) = s.split_once('T')?;
+    let mut date = date.splitn(3, '-').map(|p| p.parse::<i64>().ok());
+    let (year, month, day) = (date.next()??, date.next()??, date.next()??);
+    let (hms, millis) = time.split_once('.').unwrap_or((time, "0"));
+    let mut hms = hms.splitn(3, ':').map(|p| p.parse::<u64>().ok());
+    let (hour, minute, second) = (hms.next()??, hms.next()??, hms.next()??);
+    let millis: u64 = format!("{:0<3}", millis).get(..3)?.parse().ok()?;
+
+    // Days since the epoch from the civil date, the inverse of the above
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = u64::try_from(era * 146_097 + doe - 719_468).ok()?;
+
+    let secs = days * 86_400 + hour * 3_600 + minute * 60 + second;
+    Some(UNIX_EPOCH + Duration::from_secs(secs) + Duration::from_millis(millis))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotations() {
+        assert_eq!(parse_rotation("100MB"), Ok(Rotation::Size(100 << 20)));
+        assert_eq!(
+            parse_rotation("1h"),
+            Ok(Rotation::Age(Duration::from_secs(3_600)))
+        );
+        assert!(parse_rotation("0B").is_err());
+        assert!(parse_rotation(&format!("{}GB", u64::MAX)).is_err());
+    }
+}
@@ -0,0 +1,480 @@
+//! Reliable transfer of files between the host and slots of the device
+//! storage.
+//!
+//! A transfer is opened with the transfer open opcode, the direction (0 to
+//! upload, 1 to download) in SDU byte 4, the slot in byte 5 and for uploads
+//! the number of chunks in bytes 6 and 7. The device answers an upload with
+//! the chunks it already holds of an interrupted upload of the same length
+//! into the slot in bytes 4 and 5, a download with the length of the slot in
+//! bytes 4 to 6. The last SDU byte of the answer is a status, non zero if the
+//! slot can't be used.
+//!
+//! Uploaded data is cut into chunks, each sent with the transfer data opcode and
+//! its sequence number in front of it, big endian in two SDU bytes. The
+//! device answers every data frame with the sequence number it expects next
+//! in the same place, which acknowledges all chunks before it, and with a
+//! non zero status in the last SDU byte if it can't store a chunk. Chunks
+//! arriving out of order are dropped by the device. In authenticated frames
+//! the sequence number follows the tag, leaving fewer bytes for the chunk.
+//!
+//! Up to `--window` chunks are sent ahead of the last acknowledged one
+//! (go-back-N, see [`crate::window`]). If no acknowledgement arrives within
+//! the response timeout, all chunks from the last acknowledged one on are
+//! sent again, up to `--retries` times in a row. Finally the transfer end frame carries the
+//! length of the data in its last four SDU bytes, the device answers it with
+//! a status once the data is stored.
+//!
+//! A delta upload only sends the chunks which differ from the data in the
+//! slot, taken from a base image or downloaded from the slot first. Its
+//! transfer is opened with direction 2, the device keeps the chunks not sent
+//! and answers every data frame with the sequence number following the
+//! chunk it stored, whatever the order they arrive in. The transfer end frame
+//! sets the length of the slot as for uploads.
+//!
+//! Downloads request chunks by their sequence number with the transfer read
+//! opcode, the device answers with the chunk in the layout of a data frame.
+//! Requests run ahead the same way. Received chunks are appended to
+//! `<file>.partial`, which is renamed to the file once complete, so an
+//! interrupted upload or download resumes from the last acknowledged chunk
+//! when started again.
+
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    ops::Range,
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+use clap::Args;
+use serialport::{ClearBuffer, ErrorKind};
+
+use crate::{
+    auth,
+    error::Error,
+    parse_u8,
+    sdu::Response,
+    session::Session,
+    window::{self, Exchange, Window},
+    CliArgs, L7Sdu, Opcode,
+};
+
+#[derive(Args, Debug, Clone)]
+pub struct Upload {
+    /// File to store on the device
+    file: PathBuf,
+    /// Slot of the device storage to store it in
+    #[arg(value_parser = parse_u8)]
+    slot: u8,
+    /// Only send the chunks which differ from the data in the slot, which is
+    /// downloaded first
+    #[arg(long)]
+    delta: bool,
+    /// Only send the chunks which differ from this image of the data in the
+    /// slot, instead of downloading it
+    #[arg(long, value_name = "FILE")]
+    base: Option<PathBuf>,
+    #[command(flatten)]
+    options: TransferOptions,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct Download {
+    /// Slot of the device storage to read
+    #[arg(value_parser = parse_u8)]
+    slot: u8,
+    /// File to write the data to
+    file: PathBuf,
+    #[command(flatten)]
+    options: TransferOptions,
+}
+
+#[derive(Args, Debug, Clone, Copy)]
+pub struct TransferOptions {
+    /// Chunks to send ahead of the last acknowledged one
+    #[arg(long, default_value_t = 8, value_parser = clap::value_parser!(u16).range(1..))]
+    window: u16,
+    /// Times to send unacknowledged chunks again before giving up
+    #[arg(long, default_value_t = 5)]
+    retries: u8,
+}
+
+/// Direction of a transfer in its open frame
+const UPLOAD: u8 = 0;
+const DOWNLOAD: u8 = 1;
+const DELTA: u8 = 2;
+
+/// How the device acknowledges the data frames of a transfer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Acks {
+    /// By the sequence number it expects next, all chunks before it are
+    /// stored
+    Cumulative,
+    /// By the sequence number following the chunk it stored
+    Each,
+}
+
+pub fn upload(session: &mut Session, upload: &Upload) -> Result<(), Error> {
+    let data = read(&upload.file)?;
+    let base = match (&upload.base, upload.delta) {
+        (Some(base), _) => Some(read(base)?),
+        (None, true) => {
+            let base = read_slot(session, upload.slot, upload.options)?;
+            eprintln!("Read back {} bytes of slot {}", base.len(), upload.slot);
+            Some(base)
+        }
+        (None, false) => None,
+    };
+    if let Some(base) = base {
+        return delta(session, upload, &data, &base);
+    }
+
+    let chunk_len = chunk_len(session);
+    let chunks = data.len().div_ceil(chunk_len);
+    if chunks > u16::MAX as usize {
+        return Err(serialport::Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "{} bytes don't fit into a transfer, it holds up to {} bytes",
+                data.len(),
+                u16::MAX as usize * chunk_len
+            ),
+        )
+        .into());
+    }
+
+    let [n0, n1] = (chunks as u16).to_be_bytes();
+    let reply = open(session, [UPLOAD, upload.slot, n0, n1], upload.options)?;
+    let first = u16::from_be_bytes([reply.sdu_u8(4), reply.sdu_u8(5)]) as usize;
+    if first > 0 {
+        eprintln!("Resuming after chunk {} of {}", first, chunks);
+    }
+
+    let start = Instant::now();
+    let seqs: Vec<usize> = (first..chunks).collect();
+    let resent = send(session, &data, &seqs, Acks::Cumulative, upload.options)?;
+    writeln!(
+        session.out,
+        "Uploaded {} bytes to slot {} in {:.1?}, {} chunks sent again",
+        data.len() - (first * chunk_len).min(data.len()),
+        upload.slot,
+        start.elapsed(),
+        resent
+    )?;
+
+    Ok(())
+}
+
+/// Send the chunks of `data` which differ from `base`
+fn delta(session: &mut Session, upload: &Upload, data: &[u8], base: &[u8]) -> Result<(), Error> {
+    let chunk_len = chunk_len(session);
+    let chunks = data.len().div_ceil(chunk_len);
+    let chunk_count = u16::try_from(chunks).map_err(|_| {
+        serialport::Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "{} bytes don't fit into a transfer, it holds up to {} bytes",
+                data.len(),
+                u16::MAX as usize * chunk_len
+            ),
+        )
+    })?;
+
+    let [n0, n1] = chunk_count.to_be_bytes();
+    open(session, [DELTA, upload.slot, n0, n1], upload.options)?;
+    let changed = changed_chunks(base, data, chunk_len);
+    let start = Instant::now();
+    let resent = send(session, data, &changed, Acks::Each, upload.options)?;
+    writeln!(
+        session.out,
+        "Uploaded {} of {} chunks to slot {} in {:.1?}, {} chunks sent again",
+        changed.len(),
+        chunks,
+        upload.slot,
+        start.elapsed(),
+        resent
+    )?;
+
+    Ok(())
+}
+
+/// Sequence numbers of the chunks of `data` which differ from `base`
+fn changed_chunks(base: &[u8], data: &[u8], chunk_len: usize) -> Vec<usize> {
+    data.chunks(chunk_len)
+        .enumerate()
+        .filter(|(seq, chunk)| {
+            base.get(seq * chunk_len..seq * chunk_len + chunk.len()) != Some(chunk)
+        })
+        .map(|(seq, _)| seq)
+        .collect()
+}
+
+/// Read the data of a slot
+pub fn read_slot(
+    session: &mut Session,
+    slot: u8,
+    options: TransferOptions,
+) -> Result<Vec<u8>, serialport::Error> {
+    let reply = open(session, [DOWNLOAD, slot, 0, 0], options)?;
+    let len = u32::from_be_bytes([0, reply.sdu_u8(4), reply.sdu_u8(5), reply.sdu_u8(6)]) as usize;
+    let mut data = Vec::with_capacity(len);
+    receive(session, len, 0, &mut data, options)?;
+    Ok(data)
+}
+
+fn read(path: &Path) -> Result<Vec<u8>, serialport::Error> {
+    fs::read(path).map_err(|e| {
+        serialport::Error::new(
+            ErrorKind::Io(e.kind()),
+            format!("Could not read {}: {}", path.display(), e),
+        )
+    })
+}
+
+pub fn download(session: &mut Session, download: &Download) -> Result<(), Error> {
+    let reply = open(session, [DOWNLOAD, download.slot, 0, 0], download.options)?;
+    let len = u32::from_be_bytes([0, reply.sdu_u8(4), reply.sdu_u8(5), reply.sdu_u8(6)]) as usize;
+
+    let mut partial = download.file.clone().into_os_string();
+    partial.push(".partial");
+    let partial = PathBuf::from(partial);
+    let io_error = |e: std::io::Error| {
+        serialport::Error::new(
+            ErrorKind::Io(e.kind()),
+            format!("Could not write {}: {}", partial.display(), e),
+        )
+    };
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&partial)
+        .map_err(io_error)?;
+
+    // Bytes of a chunk cut short by the interruption are fetched again
+    let chunk_len = chunk_len(session);
+    let first = file.metadata().map_err(io_error)?.len() as usize / chunk_len;
+    file.set_len((first * chunk_len) as u64).map_err(io_error)?;
+    if first > 0 {
+        eprintln!("Resuming after chunk {}", first);
+    }
+
+    let start = Instant::now();
+    let resent = receive(session, len, first, &mut file, download.options)?;
+    file.sync_all().map_err(io_error)?;
+    fs::rename(&partial, &download.file).map_err(io_error)?;
+    writeln!(
+        session.out,
+        "Downloaded {} bytes from slot {} in {:.1?}, {} chunks requested again",
+        len,
+        download.slot,
+        start.elapsed(),
+        resent
+    )?;
+
+    Ok(())
+}
+
+/// Open a transfer, returning the answer of the device
+fn open(
+    session: &mut Session,
+    request: [u8; 4],
+    options: TransferOptions,
+) -> Result<[u8; 16], serialport::Error> {
+    let mut sdu = L7Sdu::default();
+    sdu[4..].copy_from_slice(&request);
+    let (msg, _) = exchange(session, Opcode::TransferOpen, sdu, options)?;
+    status(&msg, "slot")?;
+    Ok(msg)
+}
+
+/// Send the chunks of `data` with the sequence numbers `seqs`, in order, and
+/// end the transfer, returning how many chunks were sent again
+fn send(
+    session: &mut Session,
+    data: &[u8],
+    seqs: &[usize],
+    acks: Acks,
+    options: TransferOptions,
+) -> Result<u64, serialport::Error> {
+    let seq_at = seq_offset(&session.args);
+    let chunks: Vec<&[u8]> = data.chunks(chunk_len(session)).collect();
+
+    // Chunks are counted by their index in `seqs`
+    let request = |i: usize| window::chunk(seq_at, seqs[i] as u16, chunks[seqs[i]]);
+    let answer = |msg: &[u8; 16], done: usize| {
+        status(msg, "chunk")?;
+        let acked = window::seq(msg, seq_at);
+        Ok(match acks {
+            Acks::Cumulative => seqs.partition_point(|seq| *seq < acked),
+            // Chunks behind a lost one don't complete any
+            Acks::Each if seqs.get(done).is_some_and(|seq| seq + 1 == acked) => done + 1,
+            Acks::Each => done,
+        })
+    };
+    let mut resent = go_back_n(
+        session,
+        Opcode::TransferData,
+        0..seqs.len(),
+        options,
+        request,
+        answer,
+    )?;
+
+    let mut sdu = L7Sdu::default();
+    sdu[4..].copy_from_slice(&(data.len() as u32).to_be_bytes());
+    let (msg, tries) = exchange(session, Opcode::TransferEnd, sdu, options)?;
+    status(&msg, "data")?;
+    resent += tries;
+
+    Ok(resent)
+}
+
+/// Receive `len` bytes from chunk `first` on into `file`, returning how many
+/// chunks were requested again
+fn receive(
+    session: &mut Session,
+    len: usize,
+    first: usize,
+    file: &mut impl Write,
+    options: TransferOptions,
+) -> Result<u64, serialport::Error> {
+    let seq_at = seq_offset(&session.args);
+    let chunk_len = chunk_len(session);
+
+    let request = |seq: usize| window::chunk(seq_at, seq as u16, &[]);
+    let answer = |msg: &[u8; 16], done: usize| {
+        // Chunks behind a lost one are dropped, they are requested again
+        if window::seq(msg, seq_at) != done {
+            return Ok(done);
+        }
+        let bytes = chunk_len.min(len - done * chunk_len);
+        file.write_all(&msg[8 + seq_at..8 + seq_at + bytes])?;
+        Ok(done + 1)
+    };
+    go_back_n(
+        session,
+        Opcode::TransferRead,
+        first..len.div_ceil(chunk_len),
+        options,
+        request,
+        answer,
+    )
+}
+
+/// Exchange frames for the chunks in `range` through the sliding window of
+/// the library, see [`window::go_back_n`]
+fn go_back_n(
+    session: &mut Session,
+    opcode: Opcode,
+    range: Range<usize>,
+    options: TransferOptions,
+    request: impl Fn(usize) -> L7Sdu,
+    answer: impl FnMut(&[u8; 16], usize) -> io::Result<usize>,
+) -> Result<u64, serialport::Error> {
+    // Responses are awaited after every window, not after every chunk
+    session.buffer_writes(true)?;
+    let window = Window {
+        size: options.window,
+        retries: options.retries,
+    };
+    let resent = window::go_back_n(session, opcode, range, window, request, answer)?;
+    session.buffer_writes(false)?;
+
+    Ok(resent)
+}
+
+impl Exchange for Session {
+    fn send(&mut self, opcode: Opcode, sdu: L7Sdu) -> io::Result<()> {
+        let frame = self.frame(self.builder(opcode, sdu))?;
+        Ok(self.write(&frame)?)
+    }
+
+    fn receive(&mut self, opcode: Opcode) -> io::Result<Option<[u8; 16]>> {
+        let mut msg = [0u8; 16];
+        match Session::receive(self, opcode, &mut msg) {
+            Ok(()) => Ok(Some(msg)),
+            Err(_) if self.stray.is_some() => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn resend(&mut self, chunks: u64) -> io::Result<()> {
+        self.serial.clear(ClearBuffer::Input)?;
+        self.stats.record_retries(chunks);
+        Ok(())
+    }
+}
+
+/// Send a single message until its response arrives, returning the response
+/// and how often the message was sent again
+fn exchange(
+    session: &mut Session,
+    opcode: Opcode,
+    sdu: L7Sdu,
+    options: TransferOptions,
+) -> Result<([u8; 16], u64), serialport::Error> {
+    let mut msg = [0u8; 16];
+    let mut tries = 0;
+    loop {
+        let builder = session.builder(opcode, sdu);
+        match session.transact(builder, &mut msg) {
+            Ok(()) => return Ok((msg, tries)),
+            // A late response to a chunk
+            Err(_) if session.stray.is_some() => (),
+            Err(e)
+                if e.kind == ErrorKind::Io(std::io::ErrorKind::TimedOut)
+                    && tries < options.retries as u64 =>
+            {
+                tries += 1;
+                session.stats.record_retries(1);
+                session.serial.clear(ClearBuffer::Input)?;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Offset of the sequence number in the SDU, behind the tag in
+/// authenticated frames
+pub fn seq_offset(args: &CliArgs) -> usize {
+    match args.auth_key {
+        Some(_) => auth::TAG_LEN,
+        None => 0,
+    }
+}
+
+/// Data bytes of a chunk, behind the sequence number
+fn chunk_len(session: &Session) -> usize {
+    window::chunk_len(seq_offset(&session.args))
+}
+
+fn status(msg: &[u8; 16], what: &str) -> Result<(), serialport::Error> {
+    match msg.sdu_u8(7) {
+        0 => Ok(()),
+        status => Err(serialport::Error::new(
+            ErrorKind::InvalidInput,
+            format!("Device rejected the {} (status {})", what, status),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn changed_chunks_of_an_image() {
+        let base = b"abcdefghijklmnopqrst";
+        assert_eq!(changed_chunks(base, base, 6), Vec::<usize>::new());
+        assert_eq!(changed_chunks(base, b"abcdefghijkLmnopqrst", 6), [1]);
+        assert_eq!(changed_chunks(base, b"Abcdefghijklmnopqrsx", 6), [0, 3]);
+        // A longer image sends its new chunks, a shorter one is cut by the
+        // transfer end
+        assert_eq!(
+            changed_chunks(base, b"abcdefghijklmnopqrstuvwxyz", 6),
+            [3, 4]
+        );
+        assert_eq!(changed_chunks(base, b"abcdefgh", 6), Vec::<usize>::new());
+        assert_eq!(changed_chunks(b"", b"abcdefgh", 2), [0, 1, 2, 3]);
+    }
+}
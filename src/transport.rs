@@ -0,0 +1,120 @@
+//! Ports the clients talk to devices over, each behind a feature of its own.
+//!
+//! Any `Read + Write` stream is a port, its read timeout is the one of
+//! responses and ends reads with [`io::ErrorKind::TimedOut`]. The `serial`
+//! feature opens serial ports, `tcp` connects to serial servers bridging a
+//! bus to TCP and `mock` provides a device in memory for tests.
+
+#[cfg(feature = "mock")]
+use std::collections::VecDeque;
+#[cfg(any(feature = "mock", feature = "tcp"))]
+use std::io;
+#[cfg(feature = "mock")]
+use std::io::{Read, Write};
+#[cfg(feature = "tcp")]
+use std::net::{TcpStream, ToSocketAddrs};
+#[cfg(any(feature = "serial", feature = "tcp"))]
+use std::time::Duration;
+
+#[cfg(feature = "mock")]
+use crate::{Address, ChecksumAlgorithm, L7Sdu, MsgBuilder, Opcode};
+
+/// Open the serial port at `path`
+#[cfg(feature = "serial")]
+pub fn serial(
+    path: &str,
+    baud_rate: u32,
+    timeout: Duration,
+) -> serialport::Result<Box<dyn serialport::SerialPort>> {
+    serialport::new(path, baud_rate).timeout(timeout).open()
+}
+
+/// Connect to a serial server at `address`, e.g. `ser2net` in raw mode
+#[cfg(feature = "tcp")]
+pub fn tcp<A: ToSocketAddrs>(address: A, timeout: Duration) -> io::Result<TcpStream> {
+    let stream = TcpStream::connect(address)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_nodelay(true)?;
+    Ok(stream)
+}
+
+/// A device in memory, answering each written frame with the frame
+/// `respond` returns for it, if any. Reads without an answer pending time
+/// out at once.
+#[cfg(feature = "mock")]
+pub struct Mock<F> {
+    respond: F,
+    request: Vec<u8>,
+    answers: VecDeque<u8>,
+}
+
+#[cfg(feature = "mock")]
+impl<F: FnMut(&[u8; 16]) -> Option<[u8; 16]>> Mock<F> {
+    pub fn new(respond: F) -> Self {
+        Mock {
+            respond,
+            request: Vec::new(),
+            answers: VecDeque::new(),
+        }
+    }
+
+    /// Queue a frame the device sends by itself, like pushed telemetry, in
+    /// front of the answers to later requests
+    pub fn push(&mut self, frame: [u8; 16]) {
+        self.answers.extend(frame);
+    }
+}
+
+/// The response of the addressed device to `request`, carrying `sdu` and
+/// the checksum of `algorithm`, for the answers of a [`Mock`]
+#[cfg(feature = "mock")]
+pub fn answer(request: &[u8; 16], sdu: L7Sdu, algorithm: ChecksumAlgorithm) -> [u8; 16] {
+    MsgBuilder {
+        to: Address::HOST,
+        from: Address(request[1]),
+        hops: 0,
+        version: request[3],
+        opcode: Opcode::from_byte(request[5]),
+        l7_sdu: sdu,
+    }
+    .build_with(algorithm)
+}
+
+#[cfg(feature = "mock")]
+impl<F: FnMut(&[u8; 16]) -> Option<[u8; 16]>> Write for Mock<F> {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        for b in bytes {
+            self.request.push(*b);
+            if self.request.len() == 16 {
+                let mut request = [0u8; 16];
+                request.copy_from_slice(&self.request);
+                self.request.clear();
+                if let Some(answer) = (self.respond)(&request) {
+                    self.answers.extend(answer);
+                }
+            }
+        }
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(feature = "mock")]
+impl<F> Read for Mock<F> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.answers.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "The mock device didn't answer",
+            ));
+        }
+        let n = buf.len().min(self.answers.len());
+        for (to, b) in buf.iter_mut().zip(self.answers.drain(..n)) {
+            *to = b;
+        }
+        Ok(n)
+    }
+}
@@ -0,0 +1,174 @@
+//! HTTP notifications of frames.
+//!
+//! Frames are POSTed one request per frame, as JSON to webhooks, see
+//! [`crate::describe_json`]. Only plain `http://` endpoints are supported,
+//! put a reverse proxy in front of HTTPS backends. Requests are sent in the
+//! background in the order of the frames, a failed request is reported and
+//! not retried. Frames arriving while [`QUEUE`] requests are waiting are
+//! dropped and counted.
+
+use std::{
+    fmt::{self, Display},
+    io::{self, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    sync::mpsc::{self, SyncSender, TrySendError},
+    thread,
+    time::Duration,
+};
+
+/// Requests waiting to be sent, further frames are dropped
+pub const QUEUE: usize = 256;
+/// Longest time to connect, to send the request and to wait for the response
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Url {
+    host: String,
+    port: u16,
+    path: String,
+}
+
+pub fn parse_url(s: &str) -> Result<Url, String> {
+    let rest = s
+        .strip_prefix("http://")
+        .ok_or_else(|| format!("`{}` is no http:// URL", s))?;
+    let (authority, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+    };
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) => (
+            host,
+            port.parse()
+                .map_err(|_| format!("`{}` has an invalid port", s))?,
+        ),
+        None => (authority, 80),
+    };
+    if host.is_empty() {
+        return Err(format!("`{}` has no host", s));
+    }
+
+    Ok(Url {
+        host: host.to_owned(),
+        port,
+        path: path.to_owned(),
+    })
+}
+
+impl Display for Url {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "http://{}:{}{}", self.host, self.port, self.path)
+    }
+}
+
+/// Sender of notifications to one endpoint
+pub struct Webhook {
+    pub url: Url,
+    bodies: SyncSender<String>,
+    /// Frames dropped as [`QUEUE`] requests were waiting
+    pub dropped: u64,
+}
+
+impl Webhook {
+    pub fn start(url: Url, content_type: &'static str) -> Self {
+        let (bodies, pending) = mpsc::sync_channel::<String>(QUEUE);
+        let endpoint = url.clone();
+        thread::spawn(move || {
+            for body in pending {
+                if let Err(e) = post(&endpoint, content_type, &body) {
+                    eprintln!("POST to {} failed: {}", endpoint, e);
+                }
+            }
+        });
+
+        Self {
+            url,
+            bodies,
+            dropped: 0,
+        }
+    }
+
+    pub fn send(&mut self, body: String) {
+        match self.bodies.try_send(body) {
+            Err(TrySendError::Full(_)) => {
+                if self.dropped == 0 {
+                    eprintln!("POSTs to {} fall behind, dropping frames", self.url);
+                }
+                self.dropped += 1;
+            }
+            // The worker only ends with the sender
+            Ok(()) | Err(TrySendError::Disconnected(_)) => (),
+        }
+    }
+}
+
+fn post(url: &Url, content_type: &str, body: &str) -> Result<(), String> {
+    let mut stream = connect(url).map_err(|e| e.to_string())?;
+    stream
+        .set_read_timeout(Some(TIMEOUT))
+        .and_then(|()| stream.set_write_timeout(Some(TIMEOUT)))
+        .map_err(|e| e.to_string())?;
+    stream
+        .write_all(request(url, content_type, body).as_bytes())
+        .map_err(|e| e.to_string())?;
+
+    let mut response = String::new();
+    stream
+        .read_to_string(&mut response)
+        .map_err(|e| e.to_string())?;
+    let status = response.lines().next().unwrap_or_default();
+    match status.split_whitespace().nth(1) {
+        Some(code) if code.starts_with('2') => Ok(()),
+        _ => Err(format!("Unexpected response `{}`", status)),
+    }
+}
+
+/// Connect to the first address of the host which answers within the
+/// timeout
+fn connect(url: &Url) -> io::Result<TcpStream> {
+    let mut last = None;
+    for address in (url.host.as_str(), url.port).to_socket_addrs()? {
+        match TcpStream::connect_timeout(&address, TIMEOUT) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last = Some(e),
+        }
+    }
+    Err(last.unwrap_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("`{}` has no address", url.host),
+        )
+    }))
+}
+
+fn request(url: &Url, content_type: &str, body: &str) -> String {
+    // The port is part of the host unless it is the default one
+    let host = match url.port {
+        80 => url.host.clone(),
+        port => format!("{}:{}", url.host, port),
+    };
+    format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: {}\r\n\
+        Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+        url.path,
+        host,
+        content_type,
+        body.len(),
+        body
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn host_header_with_port() {
+        let url = parse_url("http://localhost:8086/write?db=mmcp").unwrap();
+        assert_eq!(url.to_string(), "http://localhost:8086/write?db=mmcp");
+        assert!(request(&url, "text/plain", "").contains("\r\nHost: localhost:8086\r\n"));
+        let url = parse_url("http://example.com").unwrap();
+        assert_eq!((url.port, url.path.as_str()), (80, "/"));
+        assert!(request(&url, "text/plain", "").contains("\r\nHost: example.com\r\n"));
+    }
+}
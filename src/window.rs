@@ -0,0 +1,119 @@
+//! Transfers with a sliding window of requests in flight (go-back-N), shared
+//! by the clients of the library and the transfers of the command line.
+//!
+//! Up to [`Window::size`] requests are sent ahead of the last completed one.
+//! If no response arrives in time, all requests from the last completed one
+//! on are sent again, up to [`Window::retries`] times in a row. The frames
+//! of a transfer carry the sequence number of their chunk big endian in two
+//! SDU bytes, in authenticated frames behind the tag, followed by the data.
+
+use std::{io, ops::Range};
+
+use crate::{L7Sdu, Opcode};
+
+/// Requests in flight and retries of a transfer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Window {
+    /// Requests sent ahead of the last completed one
+    pub size: u16,
+    /// Times to send the requests in flight again before giving up
+    pub retries: u8,
+}
+
+impl Default for Window {
+    fn default() -> Self {
+        Window {
+            size: 8,
+            retries: 5,
+        }
+    }
+}
+
+/// The frames of one device a transfer is exchanged with
+pub trait Exchange {
+    /// Send a request, it may be buffered until the next
+    /// [`Exchange::receive`]
+    fn send(&mut self, opcode: Opcode, sdu: L7Sdu) -> io::Result<()>;
+
+    /// The next response to a request of `opcode`, `None` for a frame
+    /// answering another one. Fails with [`io::ErrorKind::TimedOut`] if none
+    /// arrived in time
+    fn receive(&mut self, opcode: Opcode) -> io::Result<Option<[u8; 16]>>;
+
+    /// Called before `chunks` requests in flight are sent again, e.g. to drop
+    /// the input
+    fn resend(&mut self, chunks: u64) -> io::Result<()> {
+        let _ = chunks;
+        Ok(())
+    }
+}
+
+/// Data bytes of a chunk, behind the sequence number at `seq_at`
+pub fn chunk_len(seq_at: usize) -> usize {
+    8 - seq_at - 2
+}
+
+/// The SDU carrying the chunk `seq`, with its sequence number at `seq_at`
+pub fn chunk(seq_at: usize, seq: u16, data: &[u8]) -> L7Sdu {
+    let mut sdu = L7Sdu::default();
+    sdu[seq_at..seq_at + 2].copy_from_slice(&seq.to_be_bytes());
+    sdu[seq_at + 2..seq_at + 2 + data.len()].copy_from_slice(data);
+    sdu
+}
+
+/// The sequence number of a frame of a transfer
+pub fn seq(frame: &[u8; 16], seq_at: usize) -> usize {
+    u16::from_be_bytes([frame[6 + seq_at], frame[7 + seq_at]]) as usize
+}
+
+/// Exchange frames for the chunks in `range` with up to [`Window::size`] of
+/// them in flight. `request` returns the SDU of a chunk, `answer` handles a
+/// response given the number of completed chunks and returns the new one.
+/// Returns how many chunks were sent again.
+pub fn go_back_n(
+    device: &mut impl Exchange,
+    opcode: Opcode,
+    range: Range<usize>,
+    window: Window,
+    request: impl Fn(usize) -> L7Sdu,
+    mut answer: impl FnMut(&[u8; 16], usize) -> io::Result<usize>,
+) -> io::Result<u64> {
+    let (mut done, mut next, mut retries, mut resent) = (range.start, range.start, 0, 0);
+    while done < range.end {
+        while next < range.end && next - done < window.size as usize {
+            device.send(opcode, request(next))?;
+            next += 1;
+        }
+
+        match device.receive(opcode) {
+            Ok(Some(msg)) => {
+                let completed = answer(&msg, done)?;
+                // Responses to chunks sent again may arrive late
+                if completed > done && completed <= next {
+                    done = completed;
+                    retries = 0;
+                }
+            }
+            // A late response to another request
+            Ok(None) => (),
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => {
+                retries += 1;
+                if retries > window.retries {
+                    return Err(io::Error::new(
+                        e.kind(),
+                        format!(
+                            "Chunk {} of {} got no response after {} retries",
+                            done, range.end, window.retries
+                        ),
+                    ));
+                }
+                device.resend((next - done) as u64)?;
+                resent += (next - done) as u64;
+                next = done;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(resent)
+}
@@ -0,0 +1,55 @@
+//! Buffered writing of frames.
+//!
+//! Frames queued in a [`FrameWriter`] stay in memory until they are flushed
+//! with a single write, explicitly or once the buffer is full. USB serial
+//! adapters spend more time on a write call than on the 16 bytes of a frame,
+//! so sending many frames without waiting for responses gets much faster.
+
+use std::io::{self, Write};
+
+/// Frames buffered before they are written without an explicit flush
+const CAPACITY: usize = 64;
+
+pub struct FrameWriter<W: Write> {
+    inner: W,
+    buf: Vec<u8>,
+}
+
+impl<W: Write> FrameWriter<W> {
+    pub fn new(inner: W) -> Self {
+        FrameWriter {
+            inner,
+            buf: Vec::with_capacity(CAPACITY * 16),
+        }
+    }
+
+    /// Add a frame to the buffer, writing the buffer if it is full
+    pub fn queue(&mut self, frame: &[u8]) -> io::Result<()> {
+        self.buf.extend_from_slice(frame);
+        if self.buf.len() >= CAPACITY * 16 {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Whether no frames are queued
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    /// Write all queued frames at once
+    pub fn flush(&mut self) -> io::Result<()> {
+        if !self.buf.is_empty() {
+            self.inner.write_all(&self.buf)?;
+            self.buf.clear();
+        }
+        self.inner.flush()
+    }
+}
+
+/// Queued frames are written like by a `BufWriter`, errors are ignored
+impl<W: Write> Drop for FrameWriter<W> {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
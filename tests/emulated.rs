@@ -0,0 +1,1474 @@
+//! End to end tests of the subcommands, the client talking to the emulator
+//! through a null modem of two pseudo terminals.
+//!
+//! Every test starts its own emulator with the rules of a device, so tests
+//! run in parallel. The null modem records the bytes the emulator receives,
+//! tests compare the frames of a command with the bytes the protocol
+//! specifies. Exchanges which need a device keeping state, like transfers,
+//! are scripted with rules matching their sequence numbers. Every subcommand
+//! of the help is run by some test, which a test of its own checks.
+
+#![cfg(unix)]
+
+use std::{
+    ffi::CStr,
+    fs::{self, File},
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    os::fd::{AsRawFd, FromRawFd, OwnedFd},
+    os::unix::net::UnixStream,
+    path::PathBuf,
+    process::{Child, Command, Output, Stdio},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Rules of a device with LED and buttons, answering the application,
+/// health, group, debugging and peripheral opcodes
+const RULES: &str = "
+opcode 100 => echo
+opcode 101 => sdu 0000000000000003
+opcode 102 => echo
+opcode 103 => sdu 0000000000000003
+opcode 104 => sdu 0000000001000500
+opcode 105 => sdu 00000000000003e8
+opcode 106 => sdu 000000000000002a
+opcode 107 => sdu 0000000000000000
+opcode 108 => sdu 0000000000000000
+opcode 113 => sdu 00000000c0ffee01
+opcode 130 => sdu 20000000deadbeef
+opcode 131 => echo
+opcode 132 => echo
+opcode 133 => echo
+opcode 150 => sdu 0000000000000001
+opcode 151 => echo
+opcode 152 => echo
+opcode 153 => echo
+opcode 154 => echo
+opcode 155 => echo
+opcode 156 => echo
+opcode 157 => echo
+opcode 158 => sdu 0000000000000009
+";
+
+const LED_ON: &str = "00 05 00 04 00 64 00 00 00 00 00 00 00 01 91 00";
+const READ_UID: &str = "00 05 00 04 00 71 00 00 00 00 00 00 00 00 85 00";
+const STATUS: &str = "00 05 00 04 00 68 00 00 00 00 00 00 00 00 8e 00";
+
+/// Transfer rules of a device whose slot 3 holds `hello, world`, in two
+/// chunks of six bytes
+const TRANSFER_RULES: &str = "
+opcode 122 sdu 0000000001.. => sdu 0000000000000c00
+opcode 122 => sdu 0000000000000000
+opcode 120 sdu 0000.. => sdu 0001000000000000
+opcode 120 sdu 0001.. => sdu 0002000000000000
+opcode 121 => sdu 0000000000000000
+opcode 123 sdu 0000.. => sdu 000068656c6c6f2c
+opcode 123 sdu 0001.. => sdu 000120776f726c64
+";
+
+/// Two pseudo terminals whose bytes are copied to each other, like serial
+/// ports connected by a null modem cable
+struct NullModem {
+    paths: [String; 2],
+    /// Bytes written to the second port, not taken yet
+    received: Arc<Mutex<Vec<u8>>>,
+    // The pseudo terminals end with their last open slave
+    _slaves: [OwnedFd; 2],
+}
+
+impl NullModem {
+    fn new() -> Self {
+        let (a, a_slave, a_path) = open_pty();
+        let (b, b_slave, b_path) = open_pty();
+        let (a_in, b_in) = (a.try_clone().unwrap(), b.try_clone().unwrap());
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let record = received.clone();
+        thread::spawn(move || copy(a_in, b, |_| ()));
+        thread::spawn(move || copy(b_in, a, |bytes| record.lock().unwrap().extend(bytes)));
+        NullModem {
+            paths: [a_path, b_path],
+            received,
+            _slaves: [a_slave, b_slave],
+        }
+    }
+}
+
+fn open_pty() -> (File, OwnedFd, String) {
+    let (mut master, mut slave) = (0, 0);
+    // SAFETY: openpty only writes the descriptors, the name is left out
+    let result = unsafe {
+        libc::openpty(
+            &mut master,
+            &mut slave,
+            std::ptr::null_mut(),
+            std::ptr::null(),
+            std::ptr::null(),
+        )
+    };
+    assert_eq!(result, 0, "openpty: {}", io::Error::last_os_error());
+
+    // SAFETY: both descriptors were just opened and are owned from here on
+    let (master, slave) = unsafe { (File::from_raw_fd(master), OwnedFd::from_raw_fd(slave)) };
+    // SAFETY: the termios is filled by tcgetattr, the name is copied before
+    // another call of ttyname
+    let path = unsafe {
+        let mut termios = std::mem::zeroed();
+        libc::tcgetattr(slave.as_raw_fd(), &mut termios);
+        libc::cfmakeraw(&mut termios);
+        libc::tcsetattr(slave.as_raw_fd(), libc::TCSANOW, &termios);
+        CStr::from_ptr(libc::ttyname(slave.as_raw_fd()))
+            .to_string_lossy()
+            .into_owned()
+    };
+    (master, slave, path)
+}
+
+/// Copy the bytes of `from` to `to`, passing them to `record` first, so they
+/// are recorded once the other end can read them
+fn copy(mut from: File, mut to: File, record: impl Fn(&[u8])) {
+    let mut buf = [0u8; 256];
+    while let Ok(n) = from.read(&mut buf) {
+        if n == 0 {
+            return;
+        }
+        record(&buf[..n]);
+        if to.write_all(&buf[..n]).is_err() {
+            return;
+        }
+    }
+}
+
+/// The emulator on one end of a null modem, killed when dropped
+struct Device {
+    modem: NullModem,
+    emulator: Child,
+    config: PathBuf,
+}
+
+impl Device {
+    fn new(rules: &str) -> Self {
+        Self::with_args(rules, &[])
+    }
+
+    /// The emulator started with the global options `args`
+    fn with_args(rules: &str, args: &[&str]) -> Self {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let config = std::env::temp_dir().join(format!(
+            "mmcp-test-{}-{}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::create_dir_all(&config).unwrap();
+        let rules_path = config.join("rules");
+        fs::write(&rules_path, rules).unwrap();
+
+        let modem = NullModem::new();
+        let mut emulator = mmcp(&config)
+            .arg(&modem.paths[0])
+            .args(args)
+            .arg("emulate")
+            .arg(&rules_path)
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+
+        // The port is open once the rules are read
+        let mut line = String::new();
+        BufReader::new(emulator.stderr.as_mut().unwrap())
+            .read_line(&mut line)
+            .unwrap();
+        assert!(line.starts_with("Emulating"), "{}", line);
+
+        Device {
+            modem,
+            emulator,
+            config,
+        }
+    }
+
+    /// Run the client with `args` for device 5
+    fn run(&self, args: &[&str]) -> Output {
+        mmcp(&self.config)
+            .arg(&self.modem.paths[1])
+            .arg("5")
+            .args(args)
+            .output()
+            .unwrap()
+    }
+
+    /// Run the client with `args`, which has to succeed, returning its output
+    fn ok(&self, args: &[&str]) -> String {
+        let output = self.run(args);
+        assert!(
+            output.status.success(),
+            "{:?} failed: {}",
+            args,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    /// Frames the emulator received since the last call, in hex. Only
+    /// frames which were answered are sure to be recorded.
+    fn received(&self) -> Vec<String> {
+        let bytes = std::mem::take(&mut *self.modem.received.lock().unwrap());
+        bytes
+            .chunks(16)
+            .map(|frame| {
+                let hex: Vec<String> = frame.iter().map(|b| format!("{:02x}", b)).collect();
+                hex.join(" ")
+            })
+            .collect()
+    }
+}
+
+impl Drop for Device {
+    fn drop(&mut self) {
+        let _ = self.emulator.kill();
+        let _ = self.emulator.wait();
+        let _ = fs::remove_dir_all(&self.config);
+    }
+}
+
+/// The client with an empty configuration
+fn mmcp(config: &PathBuf) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_mmcp_client_cli"));
+    command
+        .env("XDG_CONFIG_HOME", config)
+        .env("XDG_RUNTIME_DIR", config)
+        .env("HOME", config);
+    command
+}
+
+#[test]
+fn led_and_buttons() {
+    let device = Device::new(RULES);
+    device.ok(&["set-led", "on"]);
+    assert_eq!(device.received(), [LED_ON]);
+    device.ok(&["set-led", "off"]);
+    device.ok(&["set-led", "toggle"]);
+    assert!(device.ok(&["get-led"]).contains("LED: on"));
+    device.ok(&["set-led", "--index", "2", "on"]);
+    assert!(device
+        .ok(&["get-led", "--index", "2"])
+        .contains("LED 2: on"));
+    assert!(device.ok(&["read-button-presses"]).contains('3'));
+    device.ok(&["configure-button", "--debounce-ms", "20"]);
+    device.ok(&["configure-button", "--trigger", "both", "--pull", "up"]);
+    // A setting has to be given
+    assert_eq!(device.run(&["configure-button"]).status.code(), Some(2));
+}
+
+#[test]
+fn read_uid() {
+    let device = Device::new(RULES);
+    assert!(device.ok(&["read-uid"]).contains("UID: c0ffee01"));
+    assert_eq!(device.received(), [READ_UID]);
+    let script = device.config.join("script");
+    fs::write(
+        &script,
+        "let uid = read-uid\nassert read-uid == $uid\nsend --opcode 102 --sdu u64:$uid\n",
+    )
+    .unwrap();
+    device.ok(&["script", script.to_str().unwrap()]);
+    // The variable holds the UID as read
+    assert_eq!(
+        device.received(),
+        [
+            READ_UID,
+            READ_UID,
+            "00 05 00 04 00 66 00 00 00 00 c0 ff ee 01 e2 00"
+        ]
+    );
+}
+
+#[test]
+fn macros() {
+    let device = Device::new(RULES);
+    fs::create_dir_all(device.config.join("mmcp")).unwrap();
+    fs::write(
+        device.config.join("mmcp/config.toml"),
+        "[macros]\nprovision = [\"set-led on\", \"read-button-presses\"]\n",
+    )
+    .unwrap();
+    assert!(device.ok(&["run", "provision"]).contains('3'));
+    assert_eq!(
+        device.received(),
+        [LED_ON, "00 05 00 04 00 65 00 00 00 00 00 00 00 00 91 00"]
+    );
+    let output = device.run(&["run", "deploy"]);
+    assert!(!output.status.success());
+}
+
+#[test]
+fn keys() {
+    let device =
+        Device::new("opcode 110 => sdu 0000000000000000\nopcode 112 => sdu 0000000000000000\n");
+    let status = device.ok(&["key", "status"]);
+    assert_eq!(status, "Local key: none\nDevice: unpaired\n");
+
+    let key = "000102030405060708090a0b0c0d0e0f";
+    device.ok(&["key", "set", "--key", key]);
+    // Three key bytes behind their offset, in the clear
+    assert_eq!(
+        device.received()[1..],
+        [
+            "00 05 00 04 00 6e 00 00 00 00 00 00 01 02 85 00",
+            "00 05 00 04 00 6e 00 00 00 00 03 03 04 05 79 00",
+            "00 05 00 04 00 6e 00 00 00 00 06 06 07 08 6d 00",
+            "00 05 00 04 00 6e 00 00 00 00 09 09 0a 0b 61 00",
+            "00 05 00 04 00 6e 00 00 00 00 0c 0c 0d 0e 55 00",
+            "00 05 00 04 00 6e 00 00 00 00 0f 0f 00 00 6a 00",
+        ]
+    );
+    let keys = fs::read_to_string(device.config.join("mmcp/keys")).unwrap();
+    assert_eq!(keys, format!("5 {}\n", key));
+
+    // Later requests are signed with the stored key, the emulator doesn't
+    // sign its answers
+    let output = device.run(&["key", "status"]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("authentication failed"));
+}
+
+#[test]
+fn crc8_checksums() {
+    let device = Device::with_args(RULES, &["--checksum", "crc8"]);
+    let crc8 = ["--checksum", "crc8"];
+    let json = device.ok(&[&crc8[..], &["--format", "json", "get-led"]].concat());
+    assert!(json.contains("\"checksum_ok\":true"), "{}", json);
+    let csv = device.ok(&[&crc8[..], &["--format", "csv", "get-led"]].concat());
+    assert!(csv.trim_end().ends_with(",true"), "{}", csv);
+    let table = device.ok(&[&crc8[..], &["--format", "table", "get-led"]].concat());
+    let row = table.lines().find(|l| l.starts_with("checksum"));
+    assert!(row.is_some_and(|row| row.ends_with(" ok")), "{}", table);
+
+    let trace = device.config.join("trace");
+    let output = device.run(
+        &[
+            &crc8[..],
+            &[
+                "--stats",
+                "--trace-file",
+                trace.to_str().unwrap(),
+                "get-led",
+            ],
+        ]
+        .concat(),
+    );
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("checksum errors: 0"), "{}", stderr);
+    assert!(fs::read_to_string(&trace).unwrap().contains(" crc=OK"));
+
+    // Requests with the other checksum are ignored like by a device
+    let output = device.run(&["--timeout", "100", "get-led"]);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn raw_and_send() {
+    let device = Device::new(RULES);
+    let bytes = "0 5 0 4 0 102 1 2 3 4 5 6 7 8".split(' ');
+    device.ok(&["raw", "--auto-checksum"]
+        .into_iter()
+        .chain(bytes)
+        .collect::<Vec<_>>());
+    device.ok(&["send", "--opcode", "102", "--sdu", "0102030405060708"]);
+}
+
+#[test]
+fn features_and_health() {
+    let device = Device::new(RULES);
+    let capabilities = device.ok(&["capabilities"]);
+    assert!(capabilities.contains("yes"), "{}", capabilities);
+    let status = device.ok(&["status"]);
+    assert!(status.contains("buffer overruns  5"), "{}", status);
+    assert!(device.ok(&["uptime"]).contains("1.000s"));
+    // Ticks too long to add up in a duration are shown alone
+    let tick = ["uptime", "--tick", "18446744073709552s"];
+    assert_eq!(device.ok(&tick), "1000 ticks\n");
+    let json = [&["--format", "json"][..], &tick].concat();
+    assert_eq!(device.ok(&json), "{\"ticks\":1000}\n");
+    assert!(device.ok(&["stats"]).contains("42"));
+
+    // The responses are checked like the ones of other commands
+    device.ok(&["--expect-sdu", "..03e8", "uptime"]);
+    device.ok(&["--expect-sdu", "..0500", "status"]);
+    device.ok(&["--expect-sdu", "..2a", "stats"]);
+    device.ok(&["--expect-sdu", "..09", "get-relays"]);
+    device.ok(&["--expect-sdu", "..03", "capabilities"]);
+    let output = device.run(&["--expect-sdu", "..01", "uptime"]);
+    assert_eq!(output.status.code(), Some(3));
+    let script = device.config.join("script");
+    fs::write(&script, "assert uptime == 1000\nlet up = uptime\n").unwrap();
+    device.ok(&["script", script.to_str().unwrap()]);
+    let snapshots = device.config.join("snapshots");
+    let snapshot = [
+        "--snapshot",
+        snapshots.to_str().unwrap(),
+        "display-number",
+        "42",
+    ];
+    device.ok(&snapshot);
+    device.ok(&snapshot);
+    let stored = fs::read_dir(&snapshots)
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap()
+        .path();
+    assert!(fs::read_to_string(stored).unwrap().contains("crc=OK"));
+}
+
+#[test]
+fn health_in_every_format() {
+    let device = Device::new(RULES);
+    let status = |format| device.ok(&["--format", format, "status"]);
+    assert_eq!(
+        status("json"),
+        "{\"brown_out\":true,\"watchdog_reset\":false,\"buffer_overruns\":5,\"last_error\":0}\n"
+    );
+    assert_eq!(
+        status("csv"),
+        "brown_out,watchdog_reset,buffer_overruns,last_error\ntrue,false,5,0\n"
+    );
+    assert_eq!(
+        status("yaml"),
+        "- {brown_out: true, watchdog_reset: false, buffer_overruns: 5, last_error: 0}\n"
+    );
+    assert_eq!(
+        status("hex"),
+        "00 00 05 04 00 68 00 00 00 00 01 00 05 00 88 00\n"
+    );
+    let cbor = device.run(&["--format", "cbor", "get-relays"]).stdout;
+    // {"on": [0, 3]}
+    assert_eq!(cbor, b"\xa1\x62on\x82\x00\x03");
+    assert_eq!(
+        device.ok(&["--format", "json", "uptime"]),
+        "{\"ticks\":1000,\"seconds\":1}\n"
+    );
+    assert!(device
+        .ok(&["--format", "yaml", "stats"])
+        .starts_with("- {received: 42,"));
+    assert!(device
+        .ok(&["--format", "json", "capabilities"])
+        .starts_with("{\"led\":true,\"buttons\":true,\"adc\":false,"));
+
+    // Dumps are written for humans only
+    let output = device.run(&["--format", "json", "peek", "0x20000000", "4"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--format json"));
+}
+
+#[test]
+fn beep() {
+    let device = Device::new(RULES);
+    device.ok(&["beep"]);
+    device.ok(&["beep", "--freq", "440", "--duration", "1s"]);
+    assert_eq!(
+        device.run(&["beep", "--duration", "70s"]).status.code(),
+        Some(2)
+    );
+}
+
+#[test]
+fn display_number() {
+    let device = Device::new(RULES);
+    device.ok(&["display-number", "42"]);
+    // Wider than a frame
+    device.ok(&["display-number", "-123456789012"]);
+}
+
+#[test]
+fn lcd_write() {
+    let device = Device::new(RULES);
+    device.ok(&["lcd-write", "--line", "1", "Hello, world!"]);
+    assert_eq!(device.run(&["lcd-write", "Grüße"]).status.code(), Some(2));
+}
+
+#[test]
+fn set_servo() {
+    let device = Device::new(RULES);
+    device.ok(&["set-servo", "1", "90"]);
+    device.ok(&["set-servo", "1", "--sweep", "180..0", "--step", "45"]);
+    assert_eq!(
+        device.run(&["set-servo", "1", "181"]).status.code(),
+        Some(2)
+    );
+}
+
+#[test]
+fn relays() {
+    let device = Device::new(RULES);
+    device.ok(&["set-relay", "3", "on"]);
+    assert!(device.ok(&["get-relays"]).contains("Relays on: 0, 3"));
+    assert_eq!(
+        device.run(&["set-relay", "32", "on"]).status.code(),
+        Some(2)
+    );
+}
+
+#[test]
+fn groups() {
+    let device = Device::new(RULES);
+    assert!(device
+        .ok(&["group", "join", "3"])
+        .contains("joined group 3"));
+    assert!(device.ok(&["group", "leave", "3"]).contains("left group 3"));
+}
+
+#[test]
+fn list_opcodes() {
+    let device = Device::new(RULES);
+    // Unanswered opcodes take the whole timeout
+    let opcodes = device.ok(&["--timeout", "50", "list-opcodes"]);
+    assert!(opcodes.contains("102  echo"), "{}", opcodes);
+    assert!(!opcodes.contains("110"), "{}", opcodes);
+}
+
+#[test]
+fn memory() {
+    let device = Device::new(RULES);
+    assert!(device.ok(&["peek", "0x20000000"]).contains("de ad be ef"));
+    device.ok(&["poke", "--width", "1", "0x20000000", "ff"]);
+    let beyond = device.run(&["peek", "0xffffff00", "0x200"]);
+    assert!(String::from_utf8_lossy(&beyond.stderr).contains("beyond the address space"));
+
+    let dump = device.config.join("flash.bin");
+    let range = "0x20000000..0x20000004";
+    device.ok(&["dump-flash", "--range", range, dump.to_str().unwrap()]);
+    assert_eq!(fs::read(&dump).unwrap(), [0xde, 0xad, 0xbe, 0xef]);
+    let verified = device.ok(&["verify", "--address", "0x20000000", dump.to_str().unwrap()]);
+    assert!(verified.starts_with("Verified 4 bytes at 0x20000000"));
+
+    fs::write(&dump, [0xde, 0xad, 0xbe, 0x00]).unwrap();
+    let output = device.run(&["verify", "--address", "0x20000000", dump.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(3));
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("First mismatch at 0x20000003: the device holds 0xef, the image 0x00"));
+}
+
+#[test]
+fn registers() {
+    let device = Device::new(RULES);
+    let map = device.config.join("registers.toml");
+    fs::write(
+        &map,
+        "byte_order = \"little\"\n\n[registers.STATUS]\naddress = 0x20000000\n\n\
+        [registers.STATUS.fields]\nREADY = 0\nMODE = \"5:4\"\n",
+    )
+    .unwrap();
+    let map = map.to_str().unwrap();
+
+    assert_eq!(
+        device.ok(&["read-reg", "--map", map, "STATUS"]),
+        "STATUS (0x20000000) = 0xefbeadde\n  READY = 0x0 (bit 0)\n  MODE  = 0x1 (bits 5:4)\n"
+    );
+    assert_eq!(
+        device.received(),
+        ["00 05 00 04 00 82 20 00 00 00 04 00 00 00 50 00"]
+    );
+    device.ok(&["write-reg", "--map", map, "STATUS", "0x03"]);
+    // The value in the byte order of the map
+    assert_eq!(
+        device.received(),
+        ["00 05 00 04 00 85 20 00 00 00 03 00 00 00 4e 00"]
+    );
+    let unknown = device.run(&["read-reg", "--map", map, "CONTROL"]);
+    assert!(!unknown.status.success());
+}
+
+#[test]
+fn transfers() {
+    let device = Device::new(TRANSFER_RULES);
+    let file = device.config.join("file");
+    let path = file.to_str().unwrap();
+    let open_download = "00 05 00 04 00 7a 00 00 00 00 01 03 00 00 78 00";
+    let reads = [
+        "00 05 00 04 00 7b 00 00 00 00 00 00 00 00 7b 00",
+        "00 05 00 04 00 7b 00 01 00 00 00 00 00 00 7a 00",
+    ];
+    let end = "00 05 00 04 00 79 00 00 00 00 00 00 00 0c 71 00";
+
+    fs::write(&file, "hello, world").unwrap();
+    let uploaded = device.ok(&["upload", path, "3"]);
+    assert!(
+        uploaded.starts_with("Uploaded 12 bytes to slot 3"),
+        "{}",
+        uploaded
+    );
+    // Opened with the direction, slot and chunk count, chunks carry their
+    // sequence number, the end the length
+    assert_eq!(
+        device.received(),
+        [
+            "00 05 00 04 00 7a 00 00 00 00 00 03 00 02 77 00",
+            "00 05 00 04 00 78 00 00 68 65 6c 6c 6f 2c 3e 00",
+            "00 05 00 04 00 78 00 01 20 77 6f 72 6c 64 35 00",
+            end,
+        ]
+    );
+
+    fs::remove_file(&file).unwrap();
+    let downloaded = device.ok(&["download", "3", path]);
+    assert!(
+        downloaded.starts_with("Downloaded 12 bytes from slot 3"),
+        "{}",
+        downloaded
+    );
+    assert_eq!(fs::read_to_string(&file).unwrap(), "hello, world");
+    assert_eq!(device.received(), [open_download, reads[0], reads[1]]);
+
+    // Only the second chunk differs
+    let open_delta = "00 05 00 04 00 7a 00 00 00 00 02 03 00 02 75 00";
+    let base = device.config.join("base");
+    fs::write(&base, "hello, there").unwrap();
+    let uploaded = device.ok(&["upload", "--base", base.to_str().unwrap(), path, "3"]);
+    assert!(
+        uploaded.starts_with("Uploaded 1 of 2 chunks to slot 3"),
+        "{}",
+        uploaded
+    );
+    assert_eq!(
+        device.received(),
+        [
+            open_delta,
+            "00 05 00 04 00 78 00 01 20 77 6f 72 6c 64 35 00",
+            end
+        ]
+    );
+    fs::write(&file, "hello, WORLD").unwrap();
+    let uploaded = device.ok(&["upload", "--delta", path, "3"]);
+    assert!(
+        uploaded.starts_with("Uploaded 1 of 2 chunks to slot 3"),
+        "{}",
+        uploaded
+    );
+    assert_eq!(
+        device.received(),
+        [
+            open_download,
+            reads[0],
+            reads[1],
+            open_delta,
+            "00 05 00 04 00 78 00 01 20 57 4f 52 4c 44 d5 00",
+            end
+        ]
+    );
+
+    let dump = device.config.join("dump");
+    device.ok(&["dump-flash", "--slot", "3", dump.to_str().unwrap()]);
+    assert_eq!(fs::read_to_string(&dump).unwrap(), "hello, world");
+    assert_eq!(device.received(), [open_download, reads[0], reads[1]]);
+    assert!(device
+        .ok(&["verify", "--slot", "3", dump.to_str().unwrap()])
+        .starts_with("Verified 12 bytes at slot 3"));
+    let output = device.run(&["verify", "--slot", "3", path]);
+    assert_eq!(output.status.code(), Some(3));
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("First mismatch at offset 0x7 of slot 3: the device holds 0x77, the image 0x57"));
+}
+
+#[test]
+fn replay_and_diff() {
+    let device = Device::new(RULES);
+    let (status, uptime) = (device.config.join("status"), device.config.join("uptime"));
+    device.ok(&["--trace-file", status.to_str().unwrap(), "status"]);
+    device.ok(&["--trace-file", uptime.to_str().unwrap(), "uptime"]);
+    device.received();
+
+    let replayed = device.ok(&["replay", status.to_str().unwrap()]);
+    assert_eq!(replayed, "Replayed 2 frames, all 1 responses match\n");
+    assert_eq!(device.received(), [STATUS]);
+
+    let output = device.run(&["diff", status.to_str().unwrap(), uptime.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1));
+    let diff = String::from_utf8_lossy(&output.stdout);
+    assert!(diff.starts_with("@ frame 1\n"), "{}", diff);
+    assert!(diff.contains("@ frame 2\n"), "{}", diff);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("differ in 2 of 2 frames"));
+    assert!(device
+        .ok(&["diff", status.to_str().unwrap(), status.to_str().unwrap()])
+        .contains("identical (2 frames)"));
+}
+
+#[test]
+fn monitor() {
+    let device = Device::new(RULES);
+    let mut monitor = mmcp(&device.config)
+        .args([&device.modem.paths[1], "monitor"])
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let (tx, rx) = mpsc::channel();
+    let stdout = monitor.stdout.take().unwrap();
+    thread::spawn(move || {
+        for line in BufReader::new(stdout).lines() {
+            let _ = tx.send(line.unwrap());
+        }
+    });
+
+    // A response of the device, sent until the monitor has opened the port
+    let mut port = File::options()
+        .write(true)
+        .open(&device.modem.paths[0])
+        .unwrap();
+    let frame = [0, 0, 5, 4, 0, 101, 0, 0, 0, 0, 0, 0, 0, 3, 0x8e, 0];
+    let line = (0..50)
+        .find_map(|_| {
+            port.write_all(&frame).unwrap();
+            rx.recv_timeout(Duration::from_millis(100)).ok()
+        })
+        .expect("the monitor shows no frame");
+    assert!(
+        line.contains("00 00 05 04 00 65 00 00 00 00 00 00 00 03 8e 00"),
+        "{}",
+        line
+    );
+
+    // Ctrl-C ends the monitor normally
+    // SAFETY: the monitor is a child still waited for
+    unsafe { libc::kill(monitor.id() as i32, libc::SIGINT) };
+    assert!(monitor.wait().unwrap().success());
+}
+
+#[test]
+fn monitor_hooks() {
+    let device = Device::new(RULES);
+    let mut monitor = mmcp(&device.config)
+        .args([
+            &device.modem.paths[1],
+            "monitor",
+            "--exec",
+            "sleep 1",
+            "--max-hooks",
+            "1",
+        ])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let (tx, rx) = mpsc::channel();
+    let stdout = monitor.stdout.take().unwrap();
+    thread::spawn(move || {
+        for line in BufReader::new(stdout).lines() {
+            let _ = tx.send(line.unwrap());
+        }
+    });
+
+    let mut port = File::options()
+        .write(true)
+        .open(&device.modem.paths[0])
+        .unwrap();
+    let frame = [0, 0, 5, 4, 0, 101, 0, 0, 0, 0, 0, 0, 0, 3, 0x8e, 0];
+    (0..50)
+        .find_map(|_| {
+            port.write_all(&frame).unwrap();
+            rx.recv_timeout(Duration::from_millis(100)).ok()
+        })
+        .expect("the monitor shows no frame");
+    // Shown while the hook of the first one still runs
+    for _ in 0..2 {
+        port.write_all(&frame).unwrap();
+        rx.recv_timeout(Duration::from_secs(1)).unwrap();
+    }
+
+    // SAFETY: the monitor is a child still waited for
+    unsafe { libc::kill(monitor.id() as i32, libc::SIGINT) };
+    let mut stderr = String::new();
+    // The hook holds the pipe until it finished
+    monitor
+        .stderr
+        .take()
+        .unwrap()
+        .read_to_string(&mut stderr)
+        .unwrap();
+    assert!(monitor.wait().unwrap().success());
+    // Frames sent again until the monitor showed one may add to them
+    assert!(stderr.contains("while 1 are running"), "{}", stderr);
+    assert!(stderr.contains("Skipped the hook for "), "{}", stderr);
+}
+
+#[test]
+fn relay() {
+    let device = Device::new(RULES);
+    let segment = NullModem::new();
+    let mut relay = mmcp(&device.config)
+        .args([
+            "relay",
+            &device.modem.paths[1],
+            &segment.paths[0],
+            "--increment-hops",
+        ])
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut line = String::new();
+    BufReader::new(relay.stderr.as_mut().unwrap())
+        .read_line(&mut line)
+        .unwrap();
+    assert!(line.starts_with("Bridging"), "{}", line);
+
+    let output = mmcp(&device.config)
+        .args([&segment.paths[1], "5", "set-led", "on"])
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    // Forwarded with one more hop
+    assert_eq!(
+        device.received(),
+        ["00 05 00 04 01 64 00 00 00 00 00 00 00 01 90 00"]
+    );
+
+    let _ = relay.kill();
+    let _ = relay.wait();
+}
+
+/// Wait for the line of a bridging or proxying process which tells its
+/// ports are open
+fn await_line(child: &mut Child, start: &str) {
+    let mut lines = BufReader::new(child.stderr.as_mut().unwrap()).lines();
+    let line = lines
+        .find(|line| line.as_ref().map_or(true, |line| line.starts_with(start)))
+        .expect("no output")
+        .unwrap();
+    assert!(line.starts_with(start), "{}", line);
+}
+
+#[test]
+fn route() {
+    let device = Device::new(RULES);
+    let segment = NullModem::new();
+    fs::create_dir_all(device.config.join("mmcp")).unwrap();
+    fs::write(
+        device.config.join("mmcp/config.toml"),
+        format!(
+            "[routes.lab]\nport = \"{}\"\naddresses = [\"1-20\"]\n\n\
+            [routes.field]\nport = \"{}\"\naddresses = [0]\n",
+            device.modem.paths[1], segment.paths[0]
+        ),
+    )
+    .unwrap();
+    let mut route = mmcp(&device.config)
+        .arg("route")
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    await_line(&mut route, "Bridging");
+
+    let output = mmcp(&device.config)
+        .args([&segment.paths[1], "5", "set-led", "on"])
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    // Routed to the segment of address 5, the response back to the host
+    assert_eq!(device.received(), [LED_ON]);
+
+    let _ = route.kill();
+    let _ = route.wait();
+}
+
+#[test]
+fn chaos_proxy() {
+    let device = Device::new(RULES);
+    let client = NullModem::new();
+    let mut proxy = mmcp(&device.config)
+        .args([
+            &client.paths[0],
+            "chaos-proxy",
+            &device.modem.paths[1],
+            "--duplicate",
+            "100",
+            "--seed",
+            "1",
+        ])
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    await_line(&mut proxy, "Proxying");
+
+    let output = mmcp(&device.config)
+        .args([&client.paths[1], "5", "set-led", "on"])
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    // Every frame is sent twice
+    assert_eq!(device.received(), [LED_ON, LED_ON]);
+
+    let _ = proxy.kill();
+    let _ = proxy.wait();
+}
+
+#[test]
+fn ber_test() {
+    let device = Device::new(RULES);
+    let report = device.ok(&["ber-test", "--duration", "200ms", "--window", "4"]);
+    assert!(report.contains(" 0 with errors, 0 lost"), "{}", report);
+    assert!(report.contains("Bit error rate: 0.000e0"), "{}", report);
+    let sent = device.received();
+    assert!(!sent.is_empty());
+    // Echoes of pseudorandom SDUs
+    assert!(sent
+        .iter()
+        .all(|frame| frame.starts_with("00 05 00 04 00 66")));
+}
+
+#[test]
+fn mesh_flood() {
+    let device = Device::new(RULES);
+    let report = device.ok(&["--timeout", "100", "mesh-flood", "--max-hops", "1"]);
+    assert!(
+        report.contains(
+            "to=5 hops=0: 1/1 delivered, 0 duplicates, responders (from@hops x count): 5@0x1"
+        ),
+        "{}",
+        report
+    );
+    assert!(
+        report.contains(
+            "to=5 hops=1: 1/1 delivered, 0 duplicates, responders (from@hops x count): 5@1x1"
+        ),
+        "{}",
+        report
+    );
+    assert_eq!(
+        device.received(),
+        [
+            "00 05 00 04 00 66 05 00 00 00 00 00 00 00 8b 00",
+            "00 05 00 04 01 66 05 01 00 00 00 00 00 00 89 00",
+        ]
+    );
+}
+
+#[test]
+fn subscribe() {
+    let device = Device::new(&format!("opcode 140 => sdu 0000000000000000\n{}", RULES));
+    let mut subscribe = mmcp(&device.config)
+        .args([
+            &device.modem.paths[1],
+            "5",
+            "subscribe",
+            "101",
+            "--period",
+            "100ms",
+        ])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    await_line(&mut subscribe, "Subscribed");
+    let (tx, rx) = mpsc::channel();
+    let stdout = subscribe.stdout.take().unwrap();
+    thread::spawn(move || {
+        for line in BufReader::new(stdout).lines() {
+            let _ = tx.send(line.unwrap());
+        }
+    });
+
+    // Button presses pushed by the device, a frame of another opcode is
+    // skipped
+    let mut port = File::options()
+        .write(true)
+        .open(&device.modem.paths[0])
+        .unwrap();
+    port.write_all(&[0, 0, 5, 4, 0, 105, 0, 0, 0, 0, 0, 0, 0, 7, 0x87, 0])
+        .unwrap();
+    let frame = [0, 0, 5, 4, 0, 101, 0, 0, 0, 0, 0, 0, 0, 3, 0x8e, 0];
+    let line = (0..50)
+        .find_map(|_| {
+            port.write_all(&frame).unwrap();
+            rx.recv_timeout(Duration::from_millis(100)).ok()
+        })
+        .expect("no pushed frame shown");
+    assert!(line.ends_with("3 button presses"), "{}", line);
+
+    // Ctrl-C ends the subscription
+    // SAFETY: the subscription is a child still waited for
+    unsafe { libc::kill(subscribe.id() as i32, libc::SIGINT) };
+    assert!(subscribe.wait().unwrap().success());
+    assert_eq!(
+        device.received(),
+        [
+            "00 05 00 04 00 8c 00 00 00 00 00 65 00 64 a1 00",
+            "00 05 00 04 00 8c 00 00 00 00 00 65 00 00 05 00",
+        ]
+    );
+}
+
+/// A packet of the fake broker, fixed header byte and body
+fn mqtt_packet(stream: &mut TcpStream) -> (u8, Vec<u8>) {
+    let mut header = [0u8; 2];
+    stream.read_exact(&mut header).unwrap();
+    // The remaining length takes up to four bytes
+    let (mut len, mut shift, mut byte) = (0, 0, header[1]);
+    loop {
+        len |= ((byte & 0x7f) as usize) << shift;
+        if byte & 0x80 == 0 {
+            break;
+        }
+        shift += 7;
+        let mut next = [0u8; 1];
+        stream.read_exact(&mut next).unwrap();
+        byte = next[0];
+    }
+    let mut body = vec![0u8; len];
+    stream.read_exact(&mut body).unwrap();
+    (header[0], body)
+}
+
+/// Topic and payload of a publish packet
+fn mqtt_publish(body: &[u8]) -> (String, String) {
+    let len = u16::from_be_bytes([body[0], body[1]]) as usize;
+    (
+        String::from_utf8_lossy(&body[2..2 + len]).into_owned(),
+        String::from_utf8_lossy(&body[2 + len..]).into_owned(),
+    )
+}
+
+#[test]
+fn mqtt_bridge() {
+    let device = Device::new(RULES);
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let broker = listener.local_addr().unwrap().to_string();
+    let mut bridge = mmcp(&device.config)
+        .args([&device.modem.paths[1], "mqtt-bridge", &broker])
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    let (mut stream, _) = listener.accept().unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(10)))
+        .unwrap();
+    assert_eq!(mqtt_packet(&mut stream).0, 0x10);
+    stream.write_all(&[0x20, 2, 0, 0]).unwrap();
+    let (header, subscribe) = mqtt_packet(&mut stream);
+    assert_eq!(header, 0x82);
+    assert!(String::from_utf8_lossy(&subscribe).contains("mmcp/+/led/set"));
+    await_line(&mut bridge, "Bridging");
+
+    let mut command = vec![0x30, 18, 0, 14];
+    command.extend_from_slice(b"mmcp/5/led/setON");
+    stream.write_all(&command).unwrap();
+    // The state is published once the device answered, like its frame
+    let (header, body) = mqtt_packet(&mut stream);
+    assert_eq!(header, 0x31);
+    assert_eq!(
+        mqtt_publish(&body),
+        ("mmcp/5/led/state".to_owned(), "ON".to_owned())
+    );
+    let (header, body) = mqtt_packet(&mut stream);
+    assert_eq!(header, 0x30);
+    let (topic, frame) = mqtt_publish(&body);
+    assert_eq!(topic, "mmcp/5/frame");
+    assert!(frame.contains("\"opcode\":100"), "{}", frame);
+    assert_eq!(device.received(), [LED_ON]);
+
+    // Ctrl-C disconnects from the broker
+    // SAFETY: the bridge is a child still waited for
+    unsafe { libc::kill(bridge.id() as i32, libc::SIGINT) };
+    assert!(bridge.wait().unwrap().success());
+    assert_eq!(mqtt_packet(&mut stream).0, 0xe0);
+}
+
+#[test]
+fn every_subcommand_is_tested() {
+    let help = Command::new(env!("CARGO_BIN_EXE_mmcp_client_cli"))
+        .arg("-h")
+        .output()
+        .unwrap();
+    let help = String::from_utf8(help.stdout).unwrap();
+    let tests = include_str!("emulated.rs");
+    let untested: Vec<&str> = help
+        .lines()
+        .skip_while(|line| *line != "Commands:")
+        .skip(1)
+        .take_while(|line| !line.is_empty())
+        .filter_map(|line| line.split_whitespace().next())
+        .filter(|command| *command != "help" && !tests.contains(&format!("\"{}\"", command)))
+        .collect();
+    assert!(
+        untested.is_empty(),
+        "Subcommands without a test: {:?}",
+        untested
+    );
+}
+
+#[test]
+fn authentication_by_opcode() {
+    let device = Device::new(RULES);
+    // Peeks need the whole SDU, so they go out without a tag
+    let peek = device.ok(&["--auth-key", "00112233", "peek", "0x20000000"]);
+    assert!(peek.contains("de ad be ef"));
+    // The emulator doesn't sign its status
+    let output = device.run(&["--auth-key", "00112233", "status"]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("authentication failed"), "{}", stderr);
+
+    // Writes which can't be signed aren't sent at all
+    let output = device.run(&[
+        "--auth-key",
+        "00112233",
+        "poke",
+        "--width",
+        "1",
+        "0x20000000",
+        "ff",
+    ]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("can't carry a tag"), "{}", stderr);
+    // Only the peek and the status reached the device
+    assert_eq!(device.received().len(), 2);
+}
+
+#[test]
+fn responses_answer_their_request() {
+    // The status answered by an echo, the uptime by a rejection of a peek,
+    // both of which carry no tag
+    let device = Device::new(
+        "opcode 104 => raw 00000504006600000000000000009000
+        opcode 105 => raw 00000504006d00000000000082010600",
+    );
+    for command in ["status", "uptime"] {
+        let output = device.run(&["--auth-key", "00112233", command]);
+        assert_eq!(output.status.code(), Some(1));
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("The response has opcode"), "{}", stderr);
+    }
+}
+
+#[test]
+fn compressed_traces() {
+    let config = std::env::temp_dir().join(format!("mmcp-test-{}-emulator", std::process::id()));
+    fs::create_dir_all(&config).unwrap();
+    let emulated = config.join("trace.gz");
+    let emulated = emulated.to_str().unwrap();
+    let device = Device::with_args(RULES, &["--trace-file", emulated, "--compress", "gzip"]);
+    let (plain, compressed) = (device.config.join("plain"), device.config.join("trace.gz"));
+    for _ in 0..2 {
+        device.ok(&["--trace-file", plain.to_str().unwrap(), "status"]);
+        let compressed = compressed.to_str().unwrap();
+        device.ok(&["--trace-file", compressed, "--compress", "gzip", "status"]);
+    }
+
+    // A member per invocation
+    let diff = device.ok(&[
+        "diff",
+        plain.to_str().unwrap(),
+        compressed.to_str().unwrap(),
+    ]);
+    assert!(diff.contains("identical (4 frames)"), "{}", diff);
+    // The emulator is still running, its member isn't finished
+    let diff = device.ok(&["diff", emulated, emulated]);
+    assert!(diff.contains("identical (8 frames)"), "{}", diff);
+    fs::remove_dir_all(&config).unwrap();
+}
+
+#[test]
+fn unanswered_requests_time_out() {
+    let device = Device::new("opcode 102 => none");
+    let output = device.run(&["--timeout", "100", "send", "--opcode", "102"]);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn unanswered_polls_are_retried() {
+    let device = Device::new("opcode 101 => none");
+    let script = device.config.join("script");
+    fs::write(
+        &script,
+        "wait read-button-presses == 3 --timeout 300ms --interval 10ms\n",
+    )
+    .unwrap();
+    let output = device.run(&[
+        "--timeout",
+        "50",
+        "--stats",
+        "script",
+        script.to_str().unwrap(),
+    ]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let retries: u64 = stderr
+        .split("Retries: ")
+        .nth(1)
+        .and_then(|rest| rest.split(',').next())
+        .and_then(|n| n.parse().ok())
+        .unwrap_or_else(|| panic!("{}", stderr));
+    assert!(retries >= 2, "{}", stderr);
+}
+
+#[test]
+fn waits_follow_the_virtual_clock() {
+    let device = Device::new(RULES);
+    let script = device.config.join("script");
+    fs::write(
+        &script,
+        "wait read-button-presses > 3 --timeout 10m --interval 1m\n",
+    )
+    .unwrap();
+    let start = Instant::now();
+    let output = device.run(&["--virtual-time", "script", script.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("timed out after 600s, last value 3"),
+        "{}",
+        stderr
+    );
+    // Ten polls a minute apart took no real time
+    assert!(start.elapsed() < Duration::from_secs(30));
+}
+
+#[test]
+fn deadline_ends_the_invocation_normally() {
+    let device = Device::new("opcode 102 => none");
+    let trace = device.config.join("trace");
+    let output = device.run(&[
+        "--deadline",
+        "300ms",
+        "--response-timeout",
+        "10s",
+        "--stats",
+        "--trace-file",
+        trace.to_str().unwrap(),
+        "send",
+        "--opcode",
+        "102",
+    ]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("The deadline of 300ms expired"),
+        "{}",
+        stderr
+    );
+    // Statistics and the trace are written by the shutdown
+    assert!(stderr.contains("timeouts: 1"), "{}", stderr);
+    assert!(fs::read_to_string(&trace).unwrap().contains(" TX "));
+}
+
+#[test]
+fn lock_files_are_not_followed() {
+    let device = Device::new(RULES);
+    let victim = device.config.join("victim");
+    fs::write(&victim, "keep").unwrap();
+    let name: String = device.modem.paths[1]
+        .trim_start_matches('/')
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect();
+    let lock = lock_dir().join(format!("mmcp-{}.lock", name));
+    // The file of an earlier pseudo terminal of the same name
+    let _ = fs::remove_file(&lock);
+    std::os::unix::fs::symlink(&victim, &lock).unwrap();
+
+    assert!(!device.run(&["set-led", "on"]).status.success());
+    assert_eq!(fs::read_to_string(&victim).unwrap(), "keep");
+    fs::remove_file(&lock).unwrap();
+}
+
+/// The directory the client locks ports in, shared by all users
+fn lock_dir() -> PathBuf {
+    // SAFETY: The path is a null terminated string
+    match unsafe { libc::access(c"/run/lock".as_ptr(), libc::W_OK) } {
+        0 => "/run/lock".into(),
+        _ => std::env::temp_dir(),
+    }
+}
+
+#[test]
+fn ports_are_locked_for_all_users() {
+    let device = Device::new(RULES);
+    let mut monitor = mmcp(&device.config)
+        .args([&device.modem.paths[1], "--wait-for-port=5s", "monitor"])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let other = device.config.join("other");
+    fs::create_dir_all(&other).unwrap();
+    // Monitoring starts once the port is locked and open, it waits for the
+    // clients started before
+    let started = Instant::now();
+    let output = loop {
+        // Another user has a runtime directory of its own
+        let output = mmcp(&device.config)
+            .env("XDG_RUNTIME_DIR", &other)
+            .args([&device.modem.paths[1], "5", "set-led", "on"])
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+        if stderr.contains("in use by PID") || started.elapsed() > Duration::from_secs(5) {
+            break stderr;
+        }
+        thread::sleep(Duration::from_millis(50));
+    };
+    assert!(
+        output.contains(&format!("in use by PID {}", monitor.id())),
+        "{}",
+        output
+    );
+
+    let _ = monitor.kill();
+    let _ = monitor.wait();
+}
+
+#[test]
+fn offline_commands() {
+    let device = Device::new(RULES);
+    let frame = "00 05 00 04 00 64 00 00 00 00 00 00 00 01 91 00";
+    let explained = mmcp(&device.config)
+        .args(["explain", frame])
+        .output()
+        .unwrap();
+    assert!(explained.status.success());
+    let checksum = mmcp(&device.config)
+        .args(["checksum", frame])
+        .output()
+        .unwrap();
+    assert!(checksum.status.success());
+    let checksum = mmcp(&device.config)
+        .args(["--format", "csv", "checksum", frame])
+        .output()
+        .unwrap();
+    assert_eq!(
+        String::from_utf8_lossy(&checksum.stdout),
+        "sum,crc8\n145,69\n"
+    );
+    // The sequence number of an authenticated chunk follows the tag
+    let chunk = "00 05 00 04 00 78 aa bb cc dd 00 01 68 69 00 00";
+    let explained = mmcp(&device.config)
+        .args(["--auth-key", "00112233", "explain", chunk])
+        .output()
+        .unwrap();
+    let table = String::from_utf8_lossy(&explained.stdout);
+    assert!(
+        table.contains("sdu[4]        10  00      0  sequence number 1"),
+        "{}",
+        table
+    );
+    let listed = mmcp(&device.config)
+        .args(["--format", "json", "list-ports"])
+        .output()
+        .unwrap();
+    assert_eq!(listed.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&listed.stderr).contains("writes text only"));
+}
+
+#[test]
+fn daemon_serves_only_bus_commands() {
+    let device = Device::new(RULES);
+    let socket = format!("unix:{}", device.config.join("daemon.sock").display());
+    let mut daemon = mmcp(&device.config)
+        .args([&device.modem.paths[1], "5", "daemon", "--listen", &socket])
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut line = String::new();
+    BufReader::new(daemon.stderr.as_mut().unwrap())
+        .read_line(&mut line)
+        .unwrap();
+    assert!(line.starts_with("Serving"), "{}", line);
+
+    let remote = |command: &[&str]| {
+        mmcp(&device.config)
+            .args(["remote", "--connect", &socket])
+            .args(command)
+            .output()
+            .unwrap()
+    };
+    assert!(remote(&["set-led", "on"]).status.success());
+    assert_eq!(device.received(), [LED_ON]);
+    assert!(remote(&["send", "--opcode", "102"]).status.success());
+    let download = remote(&["download", "1", "/tmp/mmcp-daemon-test"]);
+    assert!(!download.status.success());
+    assert!(String::from_utf8_lossy(&download.stderr).contains("not served by the daemon"));
+    assert!(!remote(&["key", "status"]).status.success());
+    // Peek by its opcode
+    assert!(!remote(&["send", "--opcode", "131"]).status.success());
+
+    let _ = daemon.kill();
+    let _ = daemon.wait();
+}
+
+#[test]
+fn daemon_limits_clients_and_lines() {
+    let device = Device::new(RULES);
+    let path = device.config.join("daemon.sock");
+    let socket = format!("unix:{}", path.display());
+    let mut daemon = mmcp(&device.config)
+        .args([&device.modem.paths[1], "5", "daemon", "--listen", &socket])
+        .args(["--max-clients", "1"])
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    await_line(&mut daemon, "Serving");
+
+    let mut client = UnixStream::connect(&path).unwrap();
+    client.write_all(b"set-led on\n").unwrap();
+    let mut answer = BufReader::new(client.try_clone().unwrap());
+    let mut line = String::new();
+    answer.read_line(&mut line).unwrap();
+    assert!(line.contains("op=SetLed"), "{}", line);
+    // The only client is served, a further one is turned away
+    let turned_away = mmcp(&device.config)
+        .args(["remote", "--connect", &socket, "set-led", "on"])
+        .output()
+        .unwrap();
+    assert!(!turned_away.status.success());
+    assert!(
+        String::from_utf8_lossy(&turned_away.stderr).contains("1 clients at most"),
+        "{}",
+        String::from_utf8_lossy(&turned_away.stderr)
+    );
+
+    // A line without end disconnects the client
+    client.write_all(&[b'x'; 5000]).unwrap();
+    let mut rest = String::new();
+    answer.read_to_string(&mut rest).unwrap();
+    assert!(
+        rest.contains("error: Lines are limited to 4096 bytes"),
+        "{}",
+        rest
+    );
+
+    let _ = daemon.kill();
+    let _ = daemon.wait();
+}
+
+#[test]
+fn daemon_listens_locally() {
+    let device = Device::new(RULES);
+    let output = mmcp(&device.config)
+        .args([
+            &device.modem.paths[1],
+            "5",
+            "daemon",
+            "--listen",
+            "0.0.0.0:0",
+        ])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--allow-remote"));
+}